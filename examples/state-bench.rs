@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+
+use bifrost::error::ApiResult;
+use bifrost::hue::api::{
+    Device, DeviceArchetype, DeviceProductData, GroupedLight, Light, Metadata, RType, Resource,
+    Room, RoomArchetype, RoomMetadata,
+};
+use bifrost::model::state::{State, StateFormat};
+use bifrost::resource::Resources;
+
+/// Measure load and save times of the state file formats
+#[derive(Parser, Debug)]
+struct Args {
+    /// Number of rooms to generate
+    #[arg(long, default_value_t = 25)]
+    rooms: u32,
+
+    /// Number of lights to generate in each room
+    #[arg(long, default_value_t = 10)]
+    lights: u32,
+
+    /// Number of times to repeat each measurement
+    #[arg(long, default_value_t = 20)]
+    rounds: u32,
+}
+
+fn generate(args: &Args) -> ApiResult<State> {
+    let mut res = Resources::new(State::new());
+    res.init("0011223344556677")?;
+
+    for r in 0..args.rooms {
+        let link_room = RType::Room.deterministic(("bench", r));
+        let link_grouped = RType::GroupedLight.deterministic(("bench", r));
+        let mut children = vec![];
+
+        for l in 0..args.lights {
+            let name = format!("Light {r}.{l}");
+            let link_device = RType::Device.deterministic(("bench", r, l));
+            let link_light = RType::Light.deterministic(("bench", r, l));
+
+            let dev = Device {
+                product_data: DeviceProductData::virtual_light(),
+                metadata: Metadata::new(DeviceArchetype::SpotBulb, &name),
+                services: vec![link_light],
+            };
+            let light = Light::new(link_device, dev.metadata.clone());
+
+            res.add(&link_device, Resource::Device(dev))?;
+            res.add(&link_light, Resource::Light(light))?;
+            children.push(link_device);
+        }
+
+        let room = Room {
+            children,
+            metadata: RoomMetadata::new(RoomArchetype::Other, &format!("Room {r}")),
+            services: vec![link_grouped],
+        };
+        res.add(&link_room, Resource::Room(room))?;
+        res.add(
+            &link_grouped,
+            Resource::GroupedLight(GroupedLight::new(link_room)),
+        )?;
+    }
+
+    Ok(res.snapshot())
+}
+
+fn measure(rounds: u32, mut func: impl FnMut() -> ApiResult<()>) -> ApiResult<Duration> {
+    let start = Instant::now();
+    for _ in 0..rounds {
+        func()?;
+    }
+    Ok(start.elapsed() / rounds)
+}
+
+fn main() -> ApiResult<()> {
+    let args = Args::parse();
+
+    let state = generate(&args)?;
+    println!("Generated state with {} resources", state.res.len());
+    println!();
+    println!(
+        "{:8} {:>10} {:>10} {:>10}",
+        "format", "size", "save", "load"
+    );
+
+    for format in [StateFormat::Yaml, StateFormat::Json, StateFormat::JsonGz] {
+        let data = state.to_vec(format)?;
+
+        let save = measure(args.rounds, || state.to_vec(format).map(drop))?;
+        let load = measure(args.rounds, || State::from_slice(&data).map(drop))?;
+
+        println!(
+            "{:8} {:>10} {:>10.2?} {:>10.2?}",
+            format!("{format:?}"),
+            data.len(),
+            save,
+            load
+        );
+    }
+
+    Ok(())
+}
@@ -63,6 +63,20 @@ async fn main() -> ApiResult<()> {
                 Message::BridgeEvent(ref obj) => {
                     println!("{obj:#?}");
                 },
+                Message::TouchlinkScan(ref obj) => {
+                    println!("{obj:#?}");
+                },
+                Message::TouchlinkIdentify(ref obj)
+                | Message::TouchlinkFactoryReset(ref obj)
+                | Message::DeviceOptions(ref obj)
+                | Message::DeviceConfigureReporting(ref obj)
+                | Message::GroupAdd(ref obj)
+                | Message::GroupRemove(ref obj)
+                | Message::GroupMembersAdd(ref obj)
+                | Message::GroupMembersRemove(ref obj)
+                | Message::HealthCheck(ref obj) => {
+                    println!("{obj:#?}");
+                },
             }
 
             continue;
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use serde::Deserialize;
+use serde_json::Value;
+
+use bifrost::z2m::update::DeviceUpdate;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(payload) = serde_json::from_slice::<Value>(data) else {
+        return;
+    };
+
+    let _ = DeviceUpdate::deserialize(&payload);
+});
@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use serde::Deserialize;
+
+use bifrost::z2m::api::{Message, RawMessage};
+use bifrost::z2m::update::DeviceUpdate;
+
+/* Parse websocket messages the same way the z2m client does */
+fuzz_target!(|data: &[u8]| {
+    let Ok(txt) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let Ok(raw) = serde_json::from_str::<RawMessage>(txt) else {
+        return;
+    };
+
+    if raw.topic.starts_with("bridge/") {
+        let _ = serde_json::from_str::<Message>(txt);
+    } else {
+        let _ = DeviceUpdate::deserialize(&raw.payload);
+    }
+});
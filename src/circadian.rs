@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::config::CircadianConfig;
+use crate::error::ApiResult;
+use crate::hue::api::{Device, Light, RType, ResourceLink, Room};
+use crate::model::brightness::Brightness;
+use crate::resource::Resources;
+use crate::z2m::request::ClientRequest;
+use crate::z2m::update::DeviceUpdate;
+
+/* sun elevation (in degrees) where the warmest and coolest settings apply */
+const NIGHT_ELEVATION: f64 = -6.0;
+const DAY_ELEVATION: f64 = 30.0;
+
+/* differences smaller than this are not worth an update, and are not taken
+ * as a sign that somebody else changed the light */
+const MIREK_TOLERANCE: u32 = 5;
+const BRIGHTNESS_TOLERANCE: f64 = 2.0;
+
+/// Circadian settings of a room
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircadianRoom {
+    pub enabled: bool,
+    /// Adjust brightness, as well as color temperature
+    #[serde(default)]
+    pub brightness: bool,
+}
+
+/// Color temperature (in mirek) and brightness (in percent) to use, when the
+/// sun is at the given elevation
+#[must_use]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn target(conf: &CircadianConfig, elevation: f64) -> (u32, f64) {
+    let day = ((elevation - NIGHT_ELEVATION) / (DAY_ELEVATION - NIGHT_ELEVATION)).clamp(0.0, 1.0);
+
+    let kelvin = day.mul_add(
+        f64::from(conf.coolest) - f64::from(conf.warmest),
+        f64::from(conf.warmest),
+    );
+    let brightness = day.mul_add(100.0 - conf.min_brightness, conf.min_brightness);
+
+    ((1_000_000.0 / kelvin).round() as u32, brightness)
+}
+
+/// Settings of all rooms: those from the config, unless changed through the
+/// admin api since
+#[must_use]
+pub fn room_settings(conf: &CircadianConfig, res: &Resources) -> BTreeMap<Uuid, CircadianRoom> {
+    let mut rooms: BTreeMap<Uuid, CircadianRoom> = conf
+        .rooms
+        .iter()
+        .flat_map(|(name, room)| {
+            let settings = CircadianRoom {
+                enabled: true,
+                brightness: room.brightness,
+            };
+            res.find_rooms(name)
+                .into_iter()
+                .map(move |link| (link.rid, settings))
+        })
+        .collect();
+
+    rooms.extend(res.get_circadian());
+    rooms
+}
+
+/* what was last sent to a light */
+#[derive(Debug)]
+struct Applied {
+    mirek: u32,
+    brightness: Option<f64>,
+}
+
+impl Applied {
+    fn changed(&self, light: &Light) -> bool {
+        let mirek = light.color_temperature.as_ref().and_then(|ct| ct.mirek);
+        let Some(mirek) = mirek else {
+            /* light has been set to a color */
+            return true;
+        };
+
+        let brightness = light.dimming.map(|dim| dim.brightness);
+        let dimmed = matches!(
+            (self.brightness, brightness),
+            (Some(old), Some(new)) if (old - new).abs() > BRIGHTNESS_TOLERANCE
+        );
+
+        mirek.abs_diff(self.mirek) > MIREK_TOLERANCE || dimmed
+    }
+}
+
+/// Periodically adjusts the lights in enabled rooms to the position of the
+/// sun, as seen from the location set through the geolocation resource.
+///
+/// Lights that are off are left alone, and so are lights that were changed
+/// by anybody else, until `override_mins` have passed.
+pub struct Circadian {
+    conf: CircadianConfig,
+    state: Arc<Mutex<Resources>>,
+    applied: HashMap<Uuid, Applied>,
+    manual: HashMap<Uuid, DateTime<Utc>>,
+}
+
+impl Circadian {
+    #[must_use]
+    pub fn new(conf: CircadianConfig, state: Arc<Mutex<Resources>>) -> Self {
+        Self {
+            conf,
+            state,
+            applied: HashMap::new(),
+            manual: HashMap::new(),
+        }
+    }
+
+    fn room_lights(res: &Resources, room: &Uuid) -> Vec<ResourceLink> {
+        res.get::<Room>(&RType::Room.link_to(*room))
+            .map(|room| {
+                room.children
+                    .iter()
+                    .filter_map(|rl| res.get::<Device>(rl).ok()?.light_service().copied())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /* light was changed by somebody else recently */
+    fn is_overridden(&mut self, light: &Uuid, now: DateTime<Utc>) -> bool {
+        let window = Duration::minutes(i64::from(self.conf.override_mins));
+        match self.manual.get(light) {
+            Some(time) if now - *time < window => true,
+            Some(_) => {
+                self.manual.remove(light);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Adjust all lights in enabled rooms, for the sun position at `now`
+    pub async fn tick(&mut self, now: DateTime<Utc>) -> ApiResult<()> {
+        let state = self.state.clone();
+        let res = state.lock().await;
+
+        let Some(location) = res.get_location() else {
+            log::trace!("Circadian: no location configured, skipping");
+            return Ok(());
+        };
+
+        let (mirek, brightness) = target(&self.conf, location.sun_elevation(now));
+        let mut seen = HashSet::new();
+
+        for (room, settings) in room_settings(&self.conf, &res) {
+            if !settings.enabled {
+                continue;
+            }
+
+            for link in Self::room_lights(&res, &room) {
+                if !seen.insert(link.rid) || self.is_overridden(&link.rid, now) {
+                    continue;
+                }
+
+                let Ok(light) = res.get::<Light>(&link) else {
+                    continue;
+                };
+
+                let Some(ct) = &light.color_temperature else {
+                    continue;
+                };
+
+                if !light.on.on {
+                    continue;
+                }
+
+                if self
+                    .applied
+                    .get(&link.rid)
+                    .is_some_and(|a| a.changed(light))
+                {
+                    log::debug!("Circadian: {link:?} was changed, leaving it alone for now");
+                    self.applied.remove(&link.rid);
+                    self.manual.insert(link.rid, now);
+                    continue;
+                }
+
+                let schema = &ct.mirek_schema;
+                let applied = Applied {
+                    mirek: mirek.clamp(schema.mirek_minimum, schema.mirek_maximum),
+                    brightness: settings.brightness.then_some(brightness),
+                };
+
+                /* already there */
+                if !applied.changed(light) {
+                    self.applied.insert(link.rid, applied);
+                    continue;
+                }
+
+                let upd = DeviceUpdate::new()
+                    .with_color_temp(Some(applied.mirek))
+                    .with_brightness(
+                        applied
+                            .brightness
+                            .map(|bri| Brightness::from_percent(bri).z2m()),
+                    );
+
+                log::debug!("Circadian: adjusting {link:?} to {upd:?}");
+                res.z2m_request(ClientRequest::light_update(link, upd))?;
+                self.applied.insert(link.rid, applied);
+            }
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    pub async fn run_forever(mut self) -> ApiResult<()> {
+        let interval = std::time::Duration::from_secs(self.conf.interval_secs.max(1));
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+            self.tick(Utc::now()).await?;
+        }
+    }
+}
@@ -1,33 +1,126 @@
-use std::{collections::HashMap, net::Ipv4Addr};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
 use camino::{Utf8Path, Utf8PathBuf};
+use chrono::NaiveTime;
 use config::{Config, ConfigError};
 use mac_address::MacAddress;
-use serde::{Deserialize, Serialize};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
 
 use crate::hue::api::RoomArchetype;
+use crate::hue::scene_presets::ScenePreset;
+use crate::z2m::update::PowerOnBehavior;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BridgeConfig {
     pub name: String,
     pub mac: MacAddress,
     pub ipaddress: Ipv4Addr,
+    #[serde(default)]
+    pub ipv6address: Option<Ipv6Addr>,
+    #[serde(default)]
+    pub interface: Option<String>,
     pub http_port: u16,
     pub https_port: u16,
+    /// Accept both http and https on each port (which may then be the same)
+    #[serde(default)]
+    pub single_port: bool,
     pub netmask: Ipv4Addr,
     pub gateway: Ipv4Addr,
     pub timezone: String,
 }
 
+impl BridgeConfig {
+    /// All addresses to advertise
+    #[must_use]
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        let mut res = vec![IpAddr::V4(self.ipaddress)];
+        res.extend(self.ipv6address.map(IpAddr::V6));
+        res
+    }
+
+    /// All addresses to listen on
+    ///
+    /// When following a network interface, the ipv4 address can change at
+    /// any time, so listen on all addresses instead.
+    #[must_use]
+    pub fn listen_addresses(&self) -> Vec<IpAddr> {
+        let mut res = self.addresses();
+        if self.interface.is_some() {
+            res[0] = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        }
+        res
+    }
+}
+
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BifrostConfig {
     pub state_file: Utf8PathBuf,
     pub cert_file: Utf8PathBuf,
+    pub enable_v1: bool,
+    pub v1_pairing: bool,
+    pub fake_portal: bool,
+    pub stable_v1_ids: bool,
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+    /// Reverse proxies allowed to report the client address and protocol
+    /// (using `X-Forwarded-For` and `X-Forwarded-Proto`)
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Path prefix to also serve the api under (e.g. `/bifrost`)
+    #[serde(default, deserialize_with = "BifrostConfig::deserialize_base_path")]
+    pub base_path: Option<String>,
+    /// Wait for changes to settle this long, before saving state
+    #[serde(default = "BifrostConfig::default_save_debounce_ms")]
+    pub save_debounce_ms: u64,
+    /// Save state at most this long after a change, even if changes keep coming
+    #[serde(default = "BifrostConfig::default_save_max_delay_ms")]
+    pub save_max_delay_ms: u64,
+    /// Commands per second allowed for each v1 client, like a real bridge
+    /// (default: no limit)
+    #[serde(default)]
+    pub v1_rate_limit: Option<u32>,
+    /// Append all resource changes to this file (default: no journal)
+    #[serde(default)]
+    pub journal_file: Option<Utf8PathBuf>,
+    /// Only pair new v1 apps while the link button is pressed
+    #[serde(default)]
+    pub require_linkbutton: bool,
+    /// Touching this file presses the link button
+    #[serde(default)]
+    pub linkbutton_file: Option<Utf8PathBuf>,
+    /// The link button is pressed for this long after startup
+    #[serde(default)]
+    pub linkbutton_startup_secs: u64,
+    /// Bearer token required for the admin api (default: none required)
+    #[serde(default)]
+    pub admin_token: Option<String>,
+}
+
+impl BifrostConfig {
+    const fn default_save_debounce_ms() -> u64 {
+        1000
+    }
+
+    const fn default_save_max_delay_ms() -> u64 {
+        10000
+    }
+
+    /* axum needs a leading slash, and no trailing slash */
+    fn deserialize_base_path<'de, D: Deserializer<'de>>(de: D) -> Result<Option<String>, D::Error> {
+        let path = Option::<String>::deserialize(de)?;
+        Ok(path
+            .map(|path| format!("/{}", path.trim_matches('/')))
+            .filter(|path| path != "/"))
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Z2mConfig {
-    #[serde(flatten)]
     pub servers: HashMap<String, Z2mServer>,
 }
 
@@ -35,6 +128,46 @@ pub struct Z2mConfig {
 pub struct Z2mServer {
     pub url: String,
     pub group_prefix: Option<String>,
+    /// Room settings for groups on this server, taking precedence over the
+    /// global rooms section
+    #[serde(default)]
+    pub rooms: HashMap<String, RoomConfig>,
+    /// Check on the connection after this many seconds without any message
+    /// from z2m, and reconnect if it stays silent (0: never)
+    #[serde(default = "Z2mServer::default_watchdog_secs")]
+    pub watchdog_secs: u64,
+    /// Forward warnings and errors logged by z2m into the bifrost log
+    #[serde(default)]
+    pub forward_logging: bool,
+    /// Ask z2m for the actual state of every light this often (in seconds),
+    /// to find and correct states that diverged (0: never)
+    #[serde(default)]
+    pub reconcile_secs: u64,
+    /// Serve the z2m frontend through bifrost, under `/z2m/<server>/`
+    #[serde(default)]
+    pub frontend: Option<Z2mFrontend>,
+}
+
+/// Reverse proxy settings for the frontend of a z2m server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Z2mFrontend {
+    /// Address of the frontend (default: the z2m websocket url, as http,
+    /// without the `/api` path)
+    pub url: Option<String>,
+    /// Credentials required to access the frontend through bifrost
+    pub username: String,
+    pub password: String,
+    /// Also accept the credentials over plain http (where anyone on the
+    /// network can read them), instead of only over https
+    #[serde(default)]
+    pub allow_insecure: bool,
+}
+
+impl Z2mServer {
+    #[must_use]
+    pub const fn default_watchdog_secs() -> u64 {
+        120
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
@@ -43,6 +176,284 @@ pub struct RoomConfig {
     pub icon: Option<RoomArchetype>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct ZoneConfig {
+    #[serde(default)]
+    pub zigbee_group: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SwitchConfig {
+    pub room: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct DefaultScenesConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub names: HashMap<ScenePreset, String>,
+}
+
+impl DefaultScenesConfig {
+    #[must_use]
+    pub fn name(&self, preset: ScenePreset) -> &str {
+        self.names
+            .get(&preset)
+            .map_or_else(|| preset.default_name(), String::as_str)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VirtualConfig {
+    pub rooms: u32,
+    pub lights: u32,
+    pub latency_ms: u64,
+}
+
+impl Default for VirtualConfig {
+    fn default() -> Self {
+        Self {
+            rooms: 1,
+            lights: 3,
+            latency_ms: 0,
+        }
+    }
+}
+
+/// A light defined in the config, controlled by a webhook or a command
+/// instead of zigbee2mqtt
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct StaticLightConfig {
+    pub dimming: bool,
+    pub color_temperature: bool,
+    pub color: bool,
+    /// Url (plain http) to post the new light state to, as json
+    pub webhook: Option<String>,
+    /// Shell command to run with the new light state in its environment
+    pub command: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircadianConfig {
+    /// Seconds between adjustments
+    pub interval_secs: u64,
+    /// Minutes to leave a light alone, after it was changed by anybody else
+    pub override_mins: u32,
+    /// Color temperature (in kelvin) at night
+    pub warmest: u32,
+    /// Color temperature (in kelvin) in broad daylight
+    pub coolest: u32,
+    /// Brightness (in percent) at night, for rooms that adjust brightness
+    pub min_brightness: f64,
+    /// Rooms to adjust from startup, by zigbee2mqtt group "friendly name"
+    pub rooms: HashMap<String, CircadianRoomConfig>,
+}
+
+impl Default for CircadianConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 60,
+            override_mins: 30,
+            warmest: 2200,
+            coolest: 5000,
+            min_brightness: 30.0,
+            rooms: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct CircadianRoomConfig {
+    #[serde(default)]
+    pub brightness: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct MotionSensorConfig {
+    /// Sensitivity to switch to at given times of day
+    #[serde(default)]
+    pub sensitivity_schedule: Vec<SensitivityPeriod>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SensitivityPeriod {
+    /// Local time of day this sensitivity applies from (until the next period)
+    pub at: NaiveTime,
+    /// Sensitivity, as in the motion resource (`0..=sensitivity_max`)
+    pub sensitivity: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReportingConfig {
+    /// Devices to configure attribute reporting for, by zigbee2mqtt
+    /// "friendly name"
+    pub devices: Vec<String>,
+    /// Minimum time (in seconds) between reports
+    pub min_interval: u32,
+    /// Maximum time (in seconds) between reports, even without changes
+    pub max_interval: u32,
+}
+
+impl Default for ReportingConfig {
+    fn default() -> Self {
+        Self {
+            devices: vec![],
+            min_interval: 1,
+            max_interval: 300,
+        }
+    }
+}
+
+/// Regular expression replacement for device and room names
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NameReplaceRule {
+    #[serde(with = "regex_pattern")]
+    pub pattern: Regex,
+    /// Replacement text (may refer to groups in the pattern, e.g. "$1")
+    pub replace: String,
+}
+
+/// Rules for turning z2m friendly names into the names shown in the Hue app
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct NamesConfig {
+    /// Prefixes to remove (only the first matching one is removed)
+    pub strip_prefixes: Vec<String>,
+    /// Suffixes to remove (only the first matching one is removed)
+    pub strip_suffixes: Vec<String>,
+    /// Replacements, applied in order
+    pub replace: Vec<NameReplaceRule>,
+    /// Start every word with an uppercase letter
+    pub title_case: bool,
+}
+
+impl NamesConfig {
+    /// The name to show for the z2m friendly name `name`
+    ///
+    /// If the rules leave nothing of the name, it is used as is.
+    #[must_use]
+    pub fn apply(&self, name: &str) -> String {
+        let mut res = name;
+        if let Some(rest) = self
+            .strip_prefixes
+            .iter()
+            .find_map(|prefix| res.strip_prefix(prefix.as_str()))
+        {
+            res = rest;
+        }
+        if let Some(rest) = self
+            .strip_suffixes
+            .iter()
+            .find_map(|suffix| res.strip_suffix(suffix.as_str()))
+        {
+            res = rest;
+        }
+
+        let mut res = res.to_string();
+        for rule in &self.replace {
+            res = rule
+                .pattern
+                .replace_all(&res, rule.replace.as_str())
+                .into_owned();
+        }
+
+        if self.title_case {
+            res = res
+                .split(' ')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    chars.next().map_or_else(String::new, |first| {
+                        first.to_uppercase().chain(chars).collect()
+                    })
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+
+        let res = res.trim();
+        if res.is_empty() {
+            name.to_string()
+        } else {
+            res.to_string()
+        }
+    }
+}
+
+/// Settings pushed to every new light, so they all behave the same
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
+#[serde(default)]
+pub struct LightDefaultsConfig {
+    /// State to return to, after a power outage
+    pub power_on_behavior: Option<PowerOnBehavior>,
+    /// Transition time (in seconds) for changes without one
+    pub transition: Option<f64>,
+    /// Keep color temperature and color (xy) in sync
+    pub color_sync: Option<bool>,
+}
+
+impl LightDefaultsConfig {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.power_on_behavior.is_none() && self.transition.is_none() && self.color_sync.is_none()
+    }
+
+    /// Settings that were not applied yet, according to `applied`
+    #[must_use]
+    pub fn not_in(&self, applied: Option<&Self>) -> Self {
+        Self {
+            power_on_behavior: self
+                .power_on_behavior
+                .filter(|pob| applied.map_or(true, |old| old.power_on_behavior != Some(*pob))),
+            transition: self
+                .transition
+                .filter(|tr| applied.map_or(true, |old| old.transition != Some(*tr))),
+            color_sync: self
+                .color_sync
+                .filter(|cs| applied.map_or(true, |old| old.color_sync != Some(*cs))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HaConfig {
+    /// Lock file, on storage shared by all instances
+    pub lock_file: Utf8PathBuf,
+    /// Name of this instance (for logging)
+    pub name: String,
+    /// Address to send state to standby instances from, while active
+    #[serde(default)]
+    pub listen: Option<SocketAddr>,
+    /// Replication addresses (`host:port`) of the other instances
+    #[serde(default)]
+    pub peers: Vec<String>,
+    #[serde(default = "HaConfig::default_heartbeat_secs")]
+    pub heartbeat_secs: u64,
+    #[serde(default = "HaConfig::default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl HaConfig {
+    const fn default_heartbeat_secs() -> u64 {
+        5
+    }
+
+    const fn default_timeout_secs() -> u64 {
+        20
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SceneIconRule {
+    #[serde(with = "regex_pattern")]
+    pub pattern: Regex,
+    pub icon: Uuid,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub bridge: BridgeConfig,
@@ -50,16 +461,125 @@ pub struct AppConfig {
     pub bifrost: BifrostConfig,
     #[serde(default)]
     pub rooms: HashMap<String, RoomConfig>,
+    #[serde(default)]
+    pub zones: HashMap<String, ZoneConfig>,
+    #[serde(default)]
+    pub scene_icons: Vec<SceneIconRule>,
+    #[serde(default)]
+    pub default_scenes: DefaultScenesConfig,
+    #[serde(default)]
+    pub switches: HashMap<String, SwitchConfig>,
+    #[serde(default)]
+    pub motion_sensors: HashMap<String, MotionSensorConfig>,
+    #[serde(default, rename = "virtual")]
+    pub virtual_devices: Option<VirtualConfig>,
+    #[serde(default)]
+    pub static_lights: HashMap<String, StaticLightConfig>,
+    #[serde(default)]
+    pub circadian: CircadianConfig,
+    #[serde(default)]
+    pub reporting: ReportingConfig,
+    #[serde(default)]
+    pub light_defaults: LightDefaultsConfig,
+    #[serde(default)]
+    pub names: NamesConfig,
+    #[serde(default)]
+    pub ha: Option<HaConfig>,
 }
 
-pub fn parse(filename: &Utf8Path) -> Result<AppConfig, ConfigError> {
-    let settings = Config::builder()
-        .set_default("bifrost.state_file", "state.yaml")?
-        .set_default("bifrost.cert_file", "cert.pem")?
+impl AppConfig {
+    /// A hidden z2m group is maintained for the named zone
+    #[must_use]
+    pub fn zone_zigbee_group(&self, name: &str) -> bool {
+        self.zones.get(name).is_some_and(|zone| zone.zigbee_group)
+    }
+}
+
+mod regex_pattern {
+    use regex::Regex;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(regex: &Regex, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(regex.as_str())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Regex::new(&s).map_err(Error::custom)
+    }
+}
+
+/// Configuration file used when there is no `config.yaml` in the working
+/// directory (e.g. in containers)
+pub const SYSTEM_CONFIG_FILE: &str = "/etc/bifrost/config.yaml";
+
+/// Default state file, when using [`SYSTEM_CONFIG_FILE`]
+pub const SYSTEM_STATE_FILE: &str = "/var/lib/bifrost/state.yaml";
+
+/// Default certificate file, when using [`SYSTEM_CONFIG_FILE`]
+pub const SYSTEM_CERT_FILE: &str = "/var/lib/bifrost/cert.pem";
+
+/// The configuration file to use when none is given: `config.yaml` in the
+/// working directory, or [`SYSTEM_CONFIG_FILE`] if only that one exists.
+#[must_use]
+pub fn default_file() -> Utf8PathBuf {
+    let local = Utf8PathBuf::from("config.yaml");
+    if !local.exists() && Utf8Path::new(SYSTEM_CONFIG_FILE).exists() {
+        Utf8PathBuf::from(SYSTEM_CONFIG_FILE)
+    } else {
+        local
+    }
+}
+
+fn read(filename: &Utf8Path) -> Result<Config, ConfigError> {
+    let (state_file, cert_file) = if filename == SYSTEM_CONFIG_FILE {
+        (SYSTEM_STATE_FILE, SYSTEM_CERT_FILE)
+    } else {
+        ("state.yaml", "cert.pem")
+    };
+
+    Config::builder()
+        .set_default("bifrost.state_file", state_file)?
+        .set_default("bifrost.cert_file", cert_file)?
+        .set_default("bifrost.enable_v1", true)?
+        .set_default("bifrost.v1_pairing", true)?
+        .set_default("bifrost.fake_portal", false)?
+        .set_default("bifrost.stable_v1_ids", false)?
         .set_default("bridge.http_port", 80)?
         .set_default("bridge.https_port", 443)?
         .add_source(config::File::with_name(filename.as_str()))
-        .build()?;
+        .build()
+}
+
+pub fn parse(filename: &Utf8Path) -> Result<AppConfig, ConfigError> {
+    read(filename)?.try_deserialize()
+}
+
+/// Load a configuration file, with the state file optionally overridden
+/// (e.g. from the command line)
+pub fn load(filename: &Utf8Path, state_file: Option<&Utf8Path>) -> Result<AppConfig, ConfigError> {
+    let mut config = parse(filename)?;
+    if let Some(state_file) = state_file {
+        config.bifrost.state_file = state_file.to_path_buf();
+    }
+    Ok(config)
+}
 
-    settings.try_deserialize()
+/// Keys in a configuration file that are not part of the configuration,
+/// which usually means they are misspelled (and are ignored when loading it)
+///
+/// Keys are returned as dotted paths, e.g. `bifrost.enable_v2`.
+pub fn unknown_keys(filename: &Utf8Path) -> Result<Vec<String>, ConfigError> {
+    let mut res = vec![];
+    serde_ignored::deserialize::<_, _, AppConfig>(read(filename)?, |path| {
+        res.push(path.to_string());
+    })?;
+    res.sort();
+    Ok(res)
 }
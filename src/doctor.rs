@@ -0,0 +1,431 @@
+use std::fs::File;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::time::Duration;
+
+use camino::Utf8Path;
+use futures::StreamExt;
+use mdns_sd::ServiceDaemon;
+use tokio::time::timeout;
+use tokio_tungstenite::connect_async;
+
+use crate::config::{self, AppConfig, Z2mServer};
+use crate::server::certificate;
+
+/* Time allowed for a z2m server to accept a connection, and send a message */
+const Z2M_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// Collects and prints the results of each check
+struct Report {
+    worst: Status,
+    results: Vec<(Status, String)>,
+}
+
+impl Report {
+    const fn new() -> Self {
+        Self {
+            worst: Status::Ok,
+            results: vec![],
+        }
+    }
+
+    fn result(&mut self, status: Status, check: &str, hint: Option<&str>) {
+        let label = match status {
+            Status::Ok => " OK ",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        println!("[{label}] {check}");
+        if let Some(hint) = hint {
+            println!("       -> {hint}");
+        }
+        self.worst = self.worst.max(status);
+        self.results.push((status, check.to_string()));
+    }
+
+    fn ok(&mut self, check: &str) {
+        self.result(Status::Ok, check, None);
+    }
+
+    fn warn(&mut self, check: &str, hint: &str) {
+        self.result(Status::Warn, check, Some(hint));
+    }
+
+    fn fail(&mut self, check: &str, hint: &str) {
+        self.result(Status::Fail, check, Some(hint));
+    }
+}
+
+fn check_certificate(report: &mut Report, config: &AppConfig) {
+    let certpath = &config.bifrost.cert_file;
+    let id = certificate::hue_bridge_id(config.bridge.mac);
+
+    if !certpath.is_file() {
+        report.warn(
+            &format!("Certificate [{certpath}] not found"),
+            "A new certificate will be generated on startup",
+        );
+        return;
+    }
+
+    match File::open(certpath).map(certificate::extract_common_name) {
+        Ok(Ok(Some(cn))) if cn == id => {
+            report.ok(&format!("Certificate matches bridge id [{id}]"));
+        }
+        Ok(Ok(Some(cn))) => report.fail(
+            &format!("Certificate is for bridge id [{cn}], but mac address gives [{id}]"),
+            &format!("Fix bridge.mac, or remove [{certpath}] to generate a new certificate"),
+        ),
+        Ok(Ok(None)) => report.fail(
+            &format!("Certificate [{certpath}] has no common name"),
+            &format!("Remove [{certpath}] to generate a new certificate"),
+        ),
+        Ok(Err(err)) => report.fail(
+            &format!("Cannot parse certificate [{certpath}]: {err}"),
+            &format!("Remove [{certpath}] to generate a new certificate"),
+        ),
+        Err(err) => report.fail(
+            &format!("Cannot read certificate [{certpath}]: {err}"),
+            "Check file permissions",
+        ),
+    }
+}
+
+fn check_keys(report: &mut Report, config_file: &Utf8Path) {
+    let Ok(unknown) = config::unknown_keys(config_file) else {
+        report.warn(
+            "Cannot check configuration for unknown keys",
+            "Unknown keys are ignored, so check for misspelled keys by hand",
+        );
+        return;
+    };
+
+    if unknown.is_empty() {
+        report.ok("Configuration has no unknown keys");
+    }
+    for key in unknown {
+        report.fail(
+            &format!("Unknown configuration key [{key}]"),
+            "This key is ignored. Check the spelling, see doc/config-reference.md",
+        );
+    }
+}
+
+fn check_mac(report: &mut Report, config: &AppConfig) {
+    let mac = config.bridge.mac;
+    let bytes = mac.bytes();
+
+    if bytes == [0x00; 6] || bytes == [0xFF; 6] {
+        report.fail(
+            &format!("Mac address [{mac}] is not a real mac address"),
+            "Set bridge.mac to the mac address of the interface bifrost runs on",
+        );
+    } else if bytes[0] & 0x01 != 0 {
+        report.fail(
+            &format!("Mac address [{mac}] is a multicast address"),
+            "Set bridge.mac to the mac address of the interface bifrost runs on",
+        );
+    } else {
+        report.ok(&format!("Mac address [{mac}] is valid"));
+    }
+}
+
+fn check_port_numbers(report: &mut Report, config: &AppConfig) {
+    let bconf = &config.bridge;
+
+    if bconf.http_port == bconf.https_port && !bconf.single_port {
+        report.fail(
+            &format!("http and https both use port {}", bconf.http_port),
+            "Set bridge.http_port and bridge.https_port to different ports, or enable bridge.single_port",
+        );
+    } else if bconf.http_port == 0 || bconf.https_port == 0 {
+        report.fail(
+            "Port 0 is not a valid port",
+            "Set bridge.http_port and bridge.https_port to fixed ports",
+        );
+    } else if bconf.http_port == bconf.https_port {
+        report.ok(&format!(
+            "Port {} serves both http and https (single port mode)",
+            bconf.http_port
+        ));
+    } else {
+        report.ok(&format!(
+            "Ports {} (http) and {} (https) do not overlap",
+            bconf.http_port, bconf.https_port
+        ));
+    }
+}
+
+/* permission bits do not tell the whole story (acls, read-only mounts,
+ * running as root), so actually create a file to see if we can */
+fn probe_dir(dir: &Utf8Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".bifrost-doctor-{}", std::process::id()));
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
+/* files bifrost writes must be writable if present, or creatable if not */
+fn check_writable(report: &mut Report, key: &str, path: &Utf8Path) {
+    if path.is_file() {
+        match std::fs::OpenOptions::new().append(true).open(path) {
+            Ok(_) => report.ok(&format!("{key} [{path}] is writable")),
+            Err(err) => report.fail(
+                &format!("{key} [{path}] is not writable: {err}"),
+                "Check file permissions",
+            ),
+        }
+        return;
+    }
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_str().is_empty() => dir,
+        _ => Utf8Path::new("."),
+    };
+
+    match std::fs::metadata(dir) {
+        Ok(meta) if !meta.is_dir() => report.fail(
+            &format!("{key} [{path}] cannot be created, [{dir}] is not a directory"),
+            &format!("Change {key}, or create the directory"),
+        ),
+        Ok(_) => match probe_dir(dir) {
+            Ok(()) => report.ok(&format!("{key} [{path}] can be created")),
+            Err(err) => report.fail(
+                &format!("{key} [{path}] cannot be created in [{dir}]: {err}"),
+                "Check directory permissions",
+            ),
+        },
+        Err(err) => report.fail(
+            &format!("{key} [{path}] cannot be created: {err}"),
+            &format!("Change {key}, or create the directory"),
+        ),
+    }
+}
+
+fn check_subnet(report: &mut Report, config: &AppConfig) {
+    let bconf = &config.bridge;
+    let mask = u32::from(bconf.netmask);
+
+    if mask.leading_ones() + mask.trailing_zeros() != 32 {
+        report.warn(
+            &format!("Netmask [{}] is not a valid netmask", bconf.netmask),
+            "Apps may fail to connect. Set bridge.netmask to e.g. 255.255.255.0",
+        );
+        return;
+    }
+
+    let network = |ip: Ipv4Addr| u32::from(ip) & mask;
+    if network(bconf.ipaddress) == network(bconf.gateway) {
+        report.ok(&format!(
+            "Gateway [{}] is on the same network as [{}]",
+            bconf.gateway, bconf.ipaddress
+        ));
+    } else {
+        report.warn(
+            &format!(
+                "Gateway [{}] is not on the network of [{}/{}]",
+                bconf.gateway,
+                bconf.ipaddress,
+                mask.leading_ones()
+            ),
+            "Check bridge.ipaddress, bridge.netmask and bridge.gateway",
+        );
+    }
+}
+
+fn check_network(report: &mut Report, config: &AppConfig) {
+    let bconf = &config.bridge;
+
+    let iface = mac_address::name_by_mac_address(&bconf.mac).ok().flatten();
+    if let Some(iface) = &iface {
+        report.ok(&format!(
+            "Mac address [{}] belongs to interface [{iface}]",
+            bconf.mac
+        ));
+    } else {
+        report.warn(
+            &format!("Mac address [{}] not found on any interface", bconf.mac),
+            "bridge.mac should be the mac address of the interface bifrost runs on",
+        );
+    }
+
+    let addrs = if_addrs::get_if_addrs().unwrap_or_default();
+    let ip = IpAddr::from(bconf.ipaddress);
+    match addrs.iter().find(|addr| addr.ip() == ip) {
+        Some(addr) if iface.as_ref().map_or(true, |name| *name == addr.name) => {
+            report.ok(&format!("Address [{ip}] is assigned to [{}]", addr.name));
+        }
+        Some(addr) => report.warn(
+            &format!(
+                "Address [{ip}] is assigned to [{}], not the mac address interface",
+                addr.name
+            ),
+            "bridge.ipaddress and bridge.mac should belong to the same interface",
+        ),
+        None if bconf.interface.is_some() => {
+            report.ok(&format!("Address [{ip}] follows the configured interface"));
+        }
+        None => report.fail(
+            &format!("Address [{ip}] is not assigned to any interface"),
+            "Set bridge.ipaddress to an address of this host",
+        ),
+    }
+}
+
+fn check_ports(report: &mut Report, config: &AppConfig) {
+    let bconf = &config.bridge;
+
+    let mut ports = vec![bconf.http_port, bconf.https_port];
+    ports.dedup();
+
+    for addr in bconf.listen_addresses() {
+        for &port in &ports {
+            match TcpListener::bind(SocketAddr::new(addr, port)) {
+                Ok(_) => report.ok(&format!("Port {port} is available on [{addr}]")),
+                Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => report.fail(
+                    &format!("Not allowed to listen on port {port}"),
+                    "Run as root, or grant CAP_NET_BIND_SERVICE (e.g. with setcap)",
+                ),
+                Err(err) if err.kind() == std::io::ErrorKind::AddrNotAvailable => report.fail(
+                    &format!("Cannot listen on [{addr}] port {port}: {err}"),
+                    "Set bridge.ipaddress to an address of this host",
+                ),
+                Err(err) => report.fail(
+                    &format!("Cannot listen on [{addr}] port {port}: {err}"),
+                    "Is bifrost (or another web server) already running?",
+                ),
+            }
+        }
+    }
+}
+
+fn check_mdns(report: &mut Report) {
+    match ServiceDaemon::new() {
+        Ok(mdns) => {
+            let _ = mdns.shutdown();
+            report.ok("mDNS service can be started");
+        }
+        Err(err) => report.fail(
+            &format!("Cannot start mDNS service: {err}"),
+            "Apps will not discover the bridge. Check that udp port 5353 is not blocked",
+        ),
+    }
+}
+
+async fn check_z2m(report: &mut Report, name: &str, server: &Z2mServer) {
+    let url = &server.url;
+
+    let connect = async {
+        let (mut socket, _) = connect_async(url).await.map_err(|err| err.to_string())?;
+        match socket.next().await {
+            Some(Ok(_)) => Ok(()),
+            Some(Err(err)) => Err(err.to_string()),
+            None => Err("connection closed".to_string()),
+        }
+    };
+
+    match timeout(Z2M_TIMEOUT, connect).await {
+        Ok(Ok(())) => report.ok(&format!("z2m server [{name}] at {url} is reachable")),
+        Ok(Err(err)) => report.fail(
+            &format!("z2m server [{name}] at {url}: {err}"),
+            "Check the url, and that the zigbee2mqtt frontend (websocket) is enabled",
+        ),
+        Err(_) => report.fail(
+            &format!("z2m server [{name}] at {url} did not respond"),
+            "Check the url, and that no firewall is blocking the connection",
+        ),
+    }
+}
+
+/* load the configuration, and run all checks that do not depend on the
+ * environment (network, other services) */
+fn check_static(
+    report: &mut Report,
+    config_file: &Utf8Path,
+    state_file: Option<&Utf8Path>,
+) -> Option<AppConfig> {
+    let config = match config::load(config_file, state_file) {
+        Ok(config) => {
+            report.ok(&format!("Configuration [{config_file}] is valid"));
+            config
+        }
+        Err(err) => {
+            report.fail(
+                &format!("Cannot load configuration [{config_file}]: {err}"),
+                "See doc/config-reference.md",
+            );
+            return None;
+        }
+    };
+
+    check_keys(report, config_file);
+    check_mac(report, &config);
+    check_port_numbers(report, &config);
+    check_subnet(report, &config);
+    check_writable(report, "bifrost.cert_file", &config.bifrost.cert_file);
+    check_writable(report, "bifrost.state_file", &config.bifrost.state_file);
+    check_certificate(report, &config);
+
+    Some(config)
+}
+
+/// Validate the configuration file, without looking at the environment
+/// (network interfaces, ports in use, z2m servers), and print the results.
+///
+/// Returns the outcome of each check.
+#[must_use]
+pub fn config_checks(
+    config_file: &Utf8Path,
+    state_file: Option<&Utf8Path>,
+) -> Vec<(Status, String)> {
+    let mut report = Report::new();
+    check_static(&mut report, config_file, state_file);
+    report.results
+}
+
+/// Validate the configuration file, like [`config_checks`].
+///
+/// Returns true if no check failed.
+#[must_use]
+pub fn check_config(config_file: &Utf8Path, state_file: Option<&Utf8Path>) -> bool {
+    config_checks(config_file, state_file)
+        .iter()
+        .all(|(status, _)| *status != Status::Fail)
+}
+
+/// Check configuration and environment for common problems, and print the
+/// results.
+///
+/// Returns true if no check failed.
+pub async fn run(config_file: &Utf8Path, state_file: Option<&Utf8Path>) -> bool {
+    let mut report = Report::new();
+
+    let Some(config) = check_static(&mut report, config_file, state_file) else {
+        return false;
+    };
+
+    check_network(&mut report, &config);
+    check_ports(&mut report, &config);
+    check_mdns(&mut report);
+
+    if config.z2m.servers.is_empty() && config.virtual_devices.is_none() {
+        report.warn(
+            "No z2m servers configured",
+            "Add at least one server to the z2m section",
+        );
+    }
+    for (name, server) in &config.z2m.servers {
+        check_z2m(&mut report, name, server).await;
+    }
+
+    report.worst != Status::Fail
+}
@@ -2,16 +2,20 @@ use std::num::ParseIntError;
 use std::sync::Arc;
 
 use camino::Utf8PathBuf;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
 use thiserror::Error;
 use tokio::task::JoinError;
 use uuid::Uuid;
 
 use crate::{
     hue::{
-        api::{RType, ResourceLink},
+        api::{LightEffect, RType, ResourceLink, TimedEffect},
+        behavior_scripts::BundledScript,
         event::EventBlock,
         legacy_api::ApiResourceType,
     },
+    journal::TraceId,
     z2m::request::ClientRequest,
 };
 
@@ -46,7 +50,9 @@ pub enum ApiError {
     SendErrorHue(#[from] tokio::sync::broadcast::error::SendError<EventBlock>),
 
     #[error(transparent)]
-    SendErrorZ2m(#[from] tokio::sync::broadcast::error::SendError<Arc<ClientRequest>>),
+    SendErrorZ2m(
+        #[from] tokio::sync::broadcast::error::SendError<(Option<TraceId>, Arc<ClientRequest>)>,
+    ),
 
     #[error(transparent)]
     SetLoggerError(#[from] log::SetLoggerError),
@@ -57,6 +63,12 @@ pub enum ApiError {
     #[error(transparent)]
     TokioRecvError(#[from] tokio::sync::broadcast::error::RecvError),
 
+    #[error(transparent)]
+    TokioWatchRecvError(#[from] tokio::sync::watch::error::RecvError),
+
+    #[error(transparent)]
+    TimeoutError(#[from] tokio::time::error::Elapsed),
+
     #[error(transparent)]
     AxumError(#[from] axum::Error),
 
@@ -78,10 +90,17 @@ pub enum ApiError {
     #[error(transparent)]
     P256Pkcs8Error(#[from] p256::pkcs8::Error),
 
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    SqliteError(#[from] rusqlite::Error),
+
     /* zigbee2mqtt errors */
     #[error("Unexpected eof on z2m socket")]
     UnexpectedZ2mEof,
 
+    #[error("No messages from z2m for {0} seconds")]
+    Z2mStalled(u64),
+
     #[error("Unexpected z2m message: {0:?}")]
     UnexpectedZ2mReply(tokio_tungstenite::tungstenite::Message),
 
@@ -89,9 +108,15 @@ pub enum ApiError {
     #[error("Cannot create resources of type: {0:?}")]
     V1CreateUnsupported(ApiResourceType),
 
+    #[error("Cannot delete resources of type: {0:?}")]
+    V1DeleteUnsupported(ApiResourceType),
+
     #[error("Resource {0} not found")]
     V1NotFound(u32),
 
+    #[error("Cannot allocate any more {0:?}")]
+    V1Full(ApiResourceType),
+
     /* hue api v2 errors */
     #[error("State changes not supported for: {0:?}")]
     UpdateUnsupported(RType),
@@ -99,6 +124,12 @@ pub enum ApiError {
     #[error("Resource {0} could not be deleted")]
     DeleteDenied(Uuid),
 
+    #[error("Resource {0} is locked until {1}")]
+    Locked(Uuid, DateTime<Utc>),
+
+    #[error("Z2m server {0} is not connected")]
+    ServerOffline(String),
+
     #[error("Resource {0} not found")]
     NotFound(Uuid),
 
@@ -108,18 +139,88 @@ pub enum ApiError {
     #[error("Cannot allocate any more {0:?}")]
     Full(RType),
 
+    #[error("Effect not supported by light: {0:?}")]
+    EffectUnsupported(LightEffect),
+
+    #[error("Timed effect not supported by light: {0:?}")]
+    TimedEffectUnsupported(TimedEffect),
+
+    #[error("Timed effect duration of {0}ms is out of range (at most 6 hours)")]
+    TimedEffectDuration(u32),
+
+    #[error("Motion sensitivity {0} not supported by sensor")]
+    MotionSensitivityUnsupported(u32),
+
+    #[error("Gradient not supported by light")]
+    GradientUnsupported,
+
+    #[error("Dimming not supported by light")]
+    DimmingUnsupported,
+
+    #[error("Color not supported by light")]
+    ColorUnsupported,
+
+    #[error("Color temperature not supported by light")]
+    ColorTemperatureUnsupported,
+
+    #[error("Gradient has {0} points, but light supports at most {1}")]
+    GradientTooLong(usize, u32),
+
+    #[error("Invalid behavior configuration: {}", .0.join(", "))]
+    BehaviorConfigInvalid(Vec<String>),
+
+    #[error("Invalid configuration schema for behavior script {0:?}: {1}")]
+    BehaviorSchemaInvalid(BundledScript, String),
+
+    #[error("Invalid location: latitude {0}, longitude {1}")]
+    LocationInvalid(f64, f64),
+
+    #[error("Unknown time zone: {0:?}")]
+    TimeZoneInvalid(String),
+
+    #[error("Invalid name: {0:?}")]
+    NameInvalid(String),
+
+    /* bifrost admin api errors */
+    #[error("Unknown device option: {0:?}")]
+    DeviceOptionUnknown(String),
+
+    #[error("Device options must be an object, not: {0}")]
+    DeviceOptionsInvalid(Value),
+
+    #[error("Invalid log filter directive: {0:?}")]
+    LogFilterInvalid(String),
+
     /* bifrost errors */
     #[error("Cannot parse state file: no version field found")]
     StateVersionNotFound,
 
+    #[error("State file {0:?} needs sqlite support, which is not enabled in this build")]
+    StorageUnsupported(Utf8PathBuf),
+
+    #[error("No journal_file set in {0:?}")]
+    JournalNotConfigured(Utf8PathBuf),
+
     #[error("Missing auxiliary data resource {0:?}")]
     AuxNotFound(ResourceLink),
 
+    #[error("Missing or wrong admin token")]
+    AdminTokenInvalid,
+
+    #[error("Scene {0} has no index in the z2m group of its room")]
+    SceneIndexMissing(Uuid),
+
     #[error("Cannot load certificate: {0:?}")]
     Certificate(Utf8PathBuf, std::io::Error),
 
     #[error("Cannot parse certificate: {0:?}")]
     CertificateInvalid(Utf8PathBuf),
+
+    #[error("Leadership lost to instance [{0}]")]
+    LeadershipLost(String),
+
+    #[error("Static light {0:?} failed: {1}")]
+    StaticLightFailed(String, String),
 }
 
 pub type ApiResult<T> = Result<T, ApiError>;
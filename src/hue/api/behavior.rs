@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::{uuid, Uuid};
+
+use crate::hue::api::ResourceLink;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DollarRef {
+    #[serde(rename = "$ref")]
+    pub dref: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BehaviorScript {
+    pub configuration_schema: DollarRef,
+    pub description: String,
+    pub max_number_instances: Option<u32>,
+    pub metadata: Value,
+    pub state_schema: DollarRef,
+    pub supported_features: Vec<String>,
+    pub trigger_schema: DollarRef,
+    pub version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BehaviorInstanceMetadata {
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BehaviorInstance {
+    #[serde(default)]
+    pub script_id: Uuid,
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub configuration: Value,
+    #[serde(default)]
+    pub dependees: Vec<Value>,
+    #[serde(default)]
+    pub metadata: BehaviorInstanceMetadata,
+}
+
+impl BehaviorInstance {
+    /// Script id used for switch behaviors implemented by Bifrost itself
+    pub const SWITCH_SCENE_CYCLE: Uuid = uuid!("771e65fe-1742-4639-9918-586e19dad850");
+
+    #[must_use]
+    pub fn switch_scene_cycle(name: &str, device: ResourceLink, room: ResourceLink) -> Self {
+        let config = SwitchConfiguration {
+            device,
+            location: vec![SwitchLocation { group: room }],
+        };
+
+        Self {
+            script_id: Self::SWITCH_SCENE_CYCLE,
+            enabled: true,
+            configuration: serde_json::to_value(config).unwrap_or_default(),
+            dependees: vec![],
+            metadata: BehaviorInstanceMetadata {
+                name: name.to_string(),
+            },
+        }
+    }
+
+    /// Parse configuration as a switch behavior, if possible
+    #[must_use]
+    pub fn switch_configuration(&self) -> Option<SwitchConfiguration> {
+        serde_json::from_value(self.configuration.clone()).ok()
+    }
+}
+
+/// Configuration of a switch behavior, linking a switch device to a room.
+///
+/// This matches the fields used by the Hue app for dimmer switch behaviors.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SwitchConfiguration {
+    pub device: ResourceLink,
+    #[serde(rename = "where")]
+    pub location: Vec<SwitchLocation>,
+}
+
+impl SwitchConfiguration {
+    #[must_use]
+    pub fn room(&self) -> Option<&ResourceLink> {
+        self.location.first().map(|loc| &loc.group)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SwitchLocation {
+    pub group: ResourceLink,
+}
@@ -0,0 +1,75 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::hue::api::ResourceLink;
+use crate::hue::date_format;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Contact {
+    pub owner: ResourceLink,
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact_report: Option<ContactReport>,
+}
+
+impl Contact {
+    #[must_use]
+    pub const fn new(owner: ResourceLink) -> Self {
+        Self {
+            owner,
+            enabled: true,
+            contact_report: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContactState {
+    Contact,
+    NoContact,
+}
+
+impl From<bool> for ContactState {
+    fn from(contact: bool) -> Self {
+        if contact {
+            Self::Contact
+        } else {
+            Self::NoContact
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContactReport {
+    #[serde(with = "date_format::utc")]
+    pub changed: DateTime<Utc>,
+    pub state: ContactState,
+}
+
+impl ContactReport {
+    #[must_use]
+    pub fn new(state: ContactState) -> Self {
+        Self {
+            changed: Utc::now(),
+            state,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ContactUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact_report: Option<ContactReport>,
+}
+
+impl From<&Contact> for ContactUpdate {
+    fn from(contact: &Contact) -> Self {
+        Self {
+            enabled: Some(contact.enabled),
+            contact_report: contact.contact_report.clone(),
+        }
+    }
+}
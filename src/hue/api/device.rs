@@ -15,6 +15,74 @@ impl Device {
     pub fn light_service(&self) -> Option<&ResourceLink> {
         self.services.iter().find(|rl| rl.rtype == RType::Light)
     }
+
+    #[must_use]
+    pub fn motion_service(&self) -> Option<&ResourceLink> {
+        self.services.iter().find(|rl| rl.rtype == RType::Motion)
+    }
+
+    #[must_use]
+    pub fn contact_service(&self) -> Option<&ResourceLink> {
+        self.services.iter().find(|rl| rl.rtype == RType::Contact)
+    }
+
+    #[must_use]
+    pub fn tamper_service(&self) -> Option<&ResourceLink> {
+        self.services.iter().find(|rl| rl.rtype == RType::Tamper)
+    }
+
+    #[must_use]
+    pub fn relative_rotary_service(&self) -> Option<&ResourceLink> {
+        self.services
+            .iter()
+            .find(|rl| rl.rtype == RType::RelativeRotary)
+    }
+
+    #[must_use]
+    pub fn entertainment_service(&self) -> Option<&ResourceLink> {
+        self.services
+            .iter()
+            .find(|rl| rl.rtype == RType::Entertainment)
+    }
+
+    #[must_use]
+    pub fn zigbee_connectivity_service(&self) -> Option<&ResourceLink> {
+        self.services
+            .iter()
+            .find(|rl| rl.rtype == RType::ZigbeeConnectivity)
+    }
+
+    #[must_use]
+    pub fn software_update_service(&self) -> Option<&ResourceLink> {
+        self.services
+            .iter()
+            .find(|rl| rl.rtype == RType::DeviceSoftwareUpdate)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DeviceUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<DeviceMetadataUpdate>,
+}
+
+impl From<&Device> for DeviceUpdate {
+    fn from(dev: &Device) -> Self {
+        Self {
+            metadata: Some(DeviceMetadataUpdate {
+                name: Some(dev.metadata.name.clone()),
+                archetype: Some(dev.metadata.archetype.clone()),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DeviceMetadataUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archetype: Option<DeviceArchetype>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -42,6 +110,44 @@ impl DeviceProductData {
         }
     }
 
+    #[must_use]
+    pub fn zigbee2mqtt_coordinator(coord: &z2m::api::Coordinator) -> Self {
+        Self {
+            certified: false,
+            manufacturer_name: "Zigbee2MQTT".to_string(),
+            model_id: coord.coordinator_type.clone(),
+            product_archetype: DeviceArchetype::BridgeV2,
+            product_name: "Zigbee2MQTT coordinator".to_string(),
+            software_version: coord
+                .firmware_revision()
+                .unwrap_or_else(|| String::from("<unknown>")),
+        }
+    }
+
+    #[must_use]
+    pub fn virtual_light() -> Self {
+        Self {
+            certified: false,
+            manufacturer_name: "Bifrost".to_string(),
+            model_id: "virtual".to_string(),
+            product_archetype: DeviceArchetype::SpotBulb,
+            product_name: "Virtual light".to_string(),
+            software_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    #[must_use]
+    pub fn static_light() -> Self {
+        Self {
+            certified: false,
+            manufacturer_name: "Bifrost".to_string(),
+            model_id: "static".to_string(),
+            product_archetype: DeviceArchetype::ClassicBulb,
+            product_name: "Static light".to_string(),
+            software_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
     #[must_use]
     pub fn guess_from_device(dev: &z2m::api::Device) -> Self {
         fn str_or_unknown(name: &Option<String>) -> String {
@@ -65,6 +171,25 @@ impl DeviceProductData {
             software_version,
         }
     }
+
+    /// Replace the fields that are still unknown with those from `other`.
+    /// Returns true if anything changed.
+    pub fn fill_unknown(&mut self, other: Self) -> bool {
+        let mut changed = false;
+        for (field, value) in [
+            (&mut self.model_id, other.model_id),
+            (&mut self.manufacturer_name, other.manufacturer_name),
+            (&mut self.product_name, other.product_name),
+            (&mut self.software_version, other.software_version),
+        ] {
+            if field == "<unknown>" && value != "<unknown>" {
+                *field = value;
+                changed = true;
+            }
+        }
+        self.certified = self.manufacturer_name == Self::SIGNIFY_MANUFACTURER_NAME;
+        changed
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
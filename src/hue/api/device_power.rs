@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hue::api::ResourceLink;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DevicePower {
+    pub owner: ResourceLink,
+    pub power_state: PowerState,
+}
+
+impl DevicePower {
+    /// Power status of a device running on mains power
+    #[must_use]
+    pub fn mains(owner: ResourceLink) -> Self {
+        Self {
+            owner,
+            power_state: PowerState::default(),
+        }
+    }
+}
+
+/// Battery status, which is left out for devices on mains power
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct PowerState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery_state: Option<BatteryState>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery_level: Option<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryState {
+    Normal,
+    Low,
+    Critical,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DevicePowerUpdate {
+    pub power_state: PowerState,
+}
+
+impl From<&DevicePower> for DevicePowerUpdate {
+    fn from(power: &DevicePower) -> Self {
+        Self {
+            power_state: power.power_state.clone(),
+        }
+    }
+}
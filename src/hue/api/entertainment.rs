@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hue::api::{Light, ResourceLink};
+use crate::model::types::XY;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Entertainment {
+    pub equalizer: bool,
+    pub owner: ResourceLink,
+    pub proxy: bool,
+    pub renderer: bool,
+    pub renderer_reference: ResourceLink,
+    pub segments: EntertainmentSegments,
+}
+
+impl Entertainment {
+    /// Entertainment service of a device, rendering through `light`
+    #[must_use]
+    pub fn new(owner: ResourceLink, light_link: ResourceLink, light: &Light) -> Self {
+        let segments = light
+            .gradient
+            .as_ref()
+            .map_or(1, |grad| grad.points_capable.max(1));
+
+        Self {
+            equalizer: true,
+            owner,
+            proxy: true,
+            renderer: true,
+            renderer_reference: light_link,
+            segments: EntertainmentSegments::new(segments),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EntertainmentSegments {
+    pub configurable: bool,
+    pub max_segments: u32,
+    pub segments: Vec<EntertainmentSegment>,
+}
+
+impl EntertainmentSegments {
+    /// Fixed segments of one pixel each (a gradient light has one segment
+    /// for each gradient point, and all other lights have just one)
+    #[must_use]
+    pub fn new(count: u32) -> Self {
+        Self {
+            configurable: false,
+            max_segments: count,
+            segments: (0..count)
+                .map(|start| EntertainmentSegment { length: 1, start })
+                .collect(),
+        }
+    }
+
+    /// Number of pixels covered by all segments
+    #[must_use]
+    pub fn pixels(&self) -> u32 {
+        self.segments
+            .iter()
+            .map(|seg| seg.start + seg.length)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Map the colors of entertainment channels onto the pixels of the light,
+    /// where channel `n` drives segment `n`.
+    ///
+    /// Pixels of segments without a channel keep the color of the last
+    /// channel, so a light driven by a single channel shows a single color.
+    #[must_use]
+    pub fn pixel_colors(&self, channels: &[XY]) -> Vec<XY> {
+        let Some(last) = channels.last() else {
+            return vec![];
+        };
+
+        let mut pixels = vec![*last; self.pixels() as usize];
+        for (seg, color) in self.segments.iter().zip(channels) {
+            let start = seg.start as usize;
+            let end = start + seg.length as usize;
+            pixels[start..end].fill(*color);
+        }
+        pixels
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EntertainmentSegment {
+    pub length: u32,
+    pub start: u32,
+}
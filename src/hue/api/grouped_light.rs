@@ -9,6 +9,10 @@ pub struct GroupedLight {
     pub alert: Value,
     pub dimming: Option<DimmingUpdate>,
     pub on: Option<On>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorUpdate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_temperature: Option<ColorTemperatureUpdate>,
     pub owner: ResourceLink,
     pub signaling: Value,
 }
@@ -20,6 +24,8 @@ impl GroupedLight {
             alert: Value::Null,
             dimming: None,
             on: None,
+            color: None,
+            color_temperature: None,
             owner: room,
             signaling: Value::Null,
         }
@@ -29,6 +35,21 @@ impl GroupedLight {
     pub fn as_brightness_opt(&self) -> Option<f64> {
         self.dimming.as_ref().map(|br| br.brightness)
     }
+
+    #[must_use]
+    pub fn as_mirek_opt(&self) -> Option<u32> {
+        self.color_temperature.as_ref().map(|ct| ct.mirek)
+    }
+
+    #[must_use]
+    pub fn as_color_opt(&self) -> Option<XY> {
+        self.color.as_ref().map(|col| col.xy)
+    }
+
+    #[must_use]
+    pub fn as_signaling_opt(&self) -> Option<&Value> {
+        Some(&self.signaling).filter(|sig| !sig.is_null())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -41,6 +62,8 @@ pub struct GroupedLightUpdate {
     pub color: Option<ColorUpdate>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub color_temperature: Option<ColorTemperatureUpdate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signaling: Option<Value>,
 }
 
 impl GroupedLightUpdate {
@@ -58,23 +81,28 @@ impl GroupedLightUpdate {
     }
 
     #[must_use]
-    pub const fn with_on(self, on: Option<On>) -> Self {
+    pub fn with_on(self, on: Option<On>) -> Self {
         Self { on, ..self }
     }
 
     #[must_use]
-    pub const fn with_color_temperature(self, mirek: u32) -> Self {
+    pub fn with_color_temperature(self, mirek: impl Into<Option<u32>>) -> Self {
         Self {
-            color_temperature: Some(ColorTemperatureUpdate::new(mirek)),
+            color_temperature: mirek.into().map(ColorTemperatureUpdate::new),
             ..self
         }
     }
 
     #[must_use]
-    pub const fn with_color_xy(self, xy: XY) -> Self {
+    pub fn with_color_xy(self, xy: impl Into<Option<XY>>) -> Self {
         Self {
-            color: Some(ColorUpdate { xy }),
+            color: xy.into().map(ColorUpdate::new),
             ..self
         }
     }
+
+    #[must_use]
+    pub fn with_signaling(self, signaling: Option<Value>) -> Self {
+        Self { signaling, ..self }
+    }
 }
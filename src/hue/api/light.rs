@@ -3,6 +3,7 @@ use std::ops::{AddAssign, Sub};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::error::{ApiError, ApiResult};
 use crate::hue::api::{Metadata, ResourceLink};
 use crate::model::types::XY;
 use crate::z2m::api::Expose;
@@ -18,6 +19,8 @@ pub struct Light {
     pub dimming: Option<Dimming>,
     pub dynamics: Option<LightDynamics>,
     pub effects: Option<LightEffects>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gradient: Option<LightGradient>,
     pub timed_effects: Option<LightTimedEffects>,
     pub mode: LightMode,
     pub on: On,
@@ -35,6 +38,7 @@ impl Light {
             dimming: None,
             dynamics: None,
             effects: None,
+            gradient: None,
             timed_effects: None,
             mode: LightMode::Normal,
             on: On { on: true },
@@ -61,6 +65,146 @@ impl Light {
     pub fn as_color_opt(&self) -> Option<XY> {
         self.color.as_ref().map(|col| col.xy)
     }
+
+    #[must_use]
+    pub fn as_effect_opt(&self) -> Option<LightEffect> {
+        self.effects.as_ref().map(|eff| eff.status)
+    }
+
+    #[must_use]
+    pub fn as_timed_effect_opt(&self) -> Option<LightTimedEffectsUpdate> {
+        self.timed_effects
+            .as_ref()
+            .map(|te| LightTimedEffectsUpdate {
+                effect: Some(te.status),
+                duration: te.duration,
+            })
+    }
+
+    #[must_use]
+    pub fn as_gradient_opt(&self) -> Option<LightGradientUpdate> {
+        self.gradient.as_ref().map(|grad| LightGradientUpdate {
+            points: grad.points.clone(),
+        })
+    }
+
+    /// What this light can do, as detected from its z2m exposes
+    #[must_use]
+    pub fn capabilities(&self) -> LightCapabilities {
+        LightCapabilities {
+            dimmable: self.dimming.is_some(),
+            color_temperature: self.color_temperature.as_ref().map(|ct| ct.mirek_schema),
+            color: self.color.as_ref().map(|col| ColorCapability {
+                gamut: col.gamut.clone().unwrap_or(ColorGamut::GAMUT_C),
+                gamut_type: col.gamut_type,
+            }),
+            effects: self
+                .effects
+                .as_ref()
+                .map(|eff| eff.effect_values.clone())
+                .unwrap_or_default(),
+            gradient_points: self.gradient.as_ref().map(|grad| grad.points_capable),
+        }
+    }
+}
+
+/// The features of a light (or a group of lights), in one place.
+///
+/// The light resource spreads these over a number of optional fields, which
+/// also carry the current state. This is only what the light is capable of.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LightCapabilities {
+    pub dimmable: bool,
+    /// Color temperature range, if the light has tunable white
+    pub color_temperature: Option<MirekSchema>,
+    pub color: Option<ColorCapability>,
+    /// Supported effects, including `no_effect`, or empty if none
+    pub effects: Vec<LightEffect>,
+    /// Number of gradient points, for gradient lights
+    pub gradient_points: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ColorCapability {
+    pub gamut: ColorGamut,
+    pub gamut_type: GamutType,
+}
+
+impl LightCapabilities {
+    #[must_use]
+    pub fn supports_effect(&self, effect: LightEffect) -> bool {
+        self.effects.contains(&effect)
+    }
+
+    /// Clamp a color temperature to the range of the light, if it has one
+    #[must_use]
+    pub fn clamp_mirek(&self, mirek: u32) -> u32 {
+        self.color_temperature.map_or(mirek, |schema| {
+            mirek.clamp(schema.mirek_minimum, schema.mirek_maximum)
+        })
+    }
+
+    /// Fit a requested color and/or color temperature to the light.
+    ///
+    /// Tunable white lights get the nearest color temperature for a color,
+    /// and color lights without tunable white get the color of a color
+    /// temperature. Only if the light can show neither, this is an error.
+    pub fn fit_color(
+        &self,
+        xy: Option<XY>,
+        mirek: Option<u32>,
+    ) -> ApiResult<(Option<XY>, Option<u32>)> {
+        let has_color = self.color.is_some();
+        let has_ct = self.color_temperature.is_some();
+
+        match (xy, mirek) {
+            (None, None) => Ok((None, None)),
+            (xy, Some(mirek)) if has_ct => {
+                Ok((xy.filter(|_| has_color), Some(self.clamp_mirek(mirek))))
+            }
+            (Some(xy), _) if has_color => Ok((Some(xy), None)),
+            (Some(xy), None) if has_ct => Ok((None, Some(self.clamp_mirek(xy.to_mirek())))),
+            (None, Some(mirek)) if has_color => Ok((Some(XY::from_mirek(mirek)), None)),
+            (Some(_), _) => Err(ApiError::ColorUnsupported),
+            (None, Some(_)) => Err(ApiError::ColorTemperatureUnsupported),
+        }
+    }
+
+    /// Entertainment areas can only use lights that can show colors
+    #[must_use]
+    pub const fn streamable(&self) -> bool {
+        self.color.is_some()
+    }
+
+    /// Combine the capabilities of two lights, into what a group of them can
+    /// do (i.e., anything at least one of them can)
+    #[must_use]
+    pub fn union(mut self, other: &Self) -> Self {
+        self.dimmable |= other.dimmable;
+        self.color_temperature = match (self.color_temperature, other.color_temperature) {
+            (Some(a), Some(b)) => Some(MirekSchema {
+                mirek_minimum: a.mirek_minimum.min(b.mirek_minimum),
+                mirek_maximum: a.mirek_maximum.max(b.mirek_maximum),
+            }),
+            (a, b) => a.or(b),
+        };
+        if self.color.is_none() {
+            self.color.clone_from(&other.color);
+        }
+        for effect in &other.effects {
+            if !self.effects.contains(effect) {
+                self.effects.push(*effect);
+            }
+        }
+        self.gradient_points = self.gradient_points.max(other.gradient_points);
+        self
+    }
+}
+
+impl<'a> FromIterator<&'a Self> for LightCapabilities {
+    fn from_iter<T: IntoIterator<Item = &'a Self>>(iter: T) -> Self {
+        iter.into_iter().fold(Self::default(), Self::union)
+    }
 }
 
 impl AddAssign<LightUpdate> for Light {
@@ -87,6 +231,26 @@ impl AddAssign<LightUpdate> for Light {
                 ct.mirek = None;
             }
         }
+
+        if let Some(eff) = upd.effects {
+            if let Some(effects) = &mut self.effects {
+                effects.status = eff.effect;
+            }
+        }
+
+        if let Some(grad) = upd.gradient {
+            if let Some(gradient) = &mut self.gradient {
+                gradient.points = grad.points;
+            }
+        }
+
+        if let Some(te) = upd.timed_effects {
+            if let (Some(timed), Some(effect)) = (&mut self.timed_effects, te.effect) {
+                timed.effect = effect;
+                timed.status = effect;
+                timed.duration = te.duration.filter(|_| effect != TimedEffect::NoEffect);
+            }
+        }
     }
 }
 
@@ -99,6 +263,10 @@ impl Sub<&Light> for &Light {
             dimming: None,
             color: None,
             color_temperature: None,
+            effects: None,
+            gradient: None,
+            timed_effects: None,
+            dynamics: None,
         };
 
         if self.on != rhs.on {
@@ -117,6 +285,18 @@ impl Sub<&Light> for &Light {
             upd = upd.with_color_xy(rhs.as_color_opt());
         }
 
+        if self.as_effect_opt() != rhs.as_effect_opt() {
+            upd = upd.with_effect(rhs.as_effect_opt());
+        }
+
+        if self.as_gradient_opt() != rhs.as_gradient_opt() {
+            upd = upd.with_gradient(rhs.as_gradient_opt());
+        }
+
+        if self.as_timed_effect_opt() != rhs.as_timed_effect_opt() {
+            upd.timed_effects = rhs.as_timed_effect_opt();
+        }
+
         upd
     }
 }
@@ -176,18 +356,230 @@ pub struct LightDynamics {
     pub speed_valid: bool,
 }
 
+#[derive(Copy, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LightEffect {
+    NoEffect,
+    Candle,
+    Fire,
+    Prism,
+    Sparkle,
+    Opal,
+    Glisten,
+    Underwater,
+    Cosmos,
+    Sunbeam,
+    Enchant,
+}
+
+impl LightEffect {
+    /* Hue effects, and their names in zigbee2mqtt */
+    const Z2M_NAMES: &'static [(Self, &'static str)] = &[
+        (Self::NoEffect, "stop_hue_effect"),
+        (Self::Candle, "candle"),
+        (Self::Fire, "fireplace"),
+        (Self::Prism, "colorloop"),
+        (Self::Sparkle, "sparkle"),
+        (Self::Opal, "opal"),
+        (Self::Glisten, "glisten"),
+        (Self::Underwater, "underwater"),
+        (Self::Cosmos, "cosmos"),
+        (Self::Sunbeam, "sunbeam"),
+        (Self::Enchant, "enchant"),
+    ];
+
+    #[must_use]
+    pub fn from_z2m(name: &str) -> Option<Self> {
+        Self::Z2M_NAMES
+            .iter()
+            .find(|(_, z2m)| *z2m == name)
+            .map(|(effect, _)| *effect)
+    }
+
+    #[must_use]
+    pub fn z2m_name(self) -> &'static str {
+        Self::Z2M_NAMES
+            .iter()
+            .find(|(effect, _)| *effect == self)
+            .map_or("stop_hue_effect", |(_, z2m)| z2m)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LightEffects {
-    pub status_values: Value,
-    pub status: Value,
-    pub effect_values: Value,
+    pub status_values: Vec<LightEffect>,
+    pub status: LightEffect,
+    pub effect_values: Vec<LightEffect>,
+}
+
+impl LightEffects {
+    /// Build the supported effects from the values of a z2m `effect` expose.
+    ///
+    /// Returns `None` if the device supports none of the hue effects.
+    #[must_use]
+    pub fn from_z2m_values(values: &[String]) -> Option<Self> {
+        let mut effect_values = vec![LightEffect::NoEffect];
+        effect_values.extend(
+            values
+                .iter()
+                .filter_map(|name| LightEffect::from_z2m(name))
+                .filter(|effect| *effect != LightEffect::NoEffect),
+        );
+
+        if effect_values.len() == 1 {
+            return None;
+        }
+
+        Some(Self {
+            status_values: effect_values.clone(),
+            status: LightEffect::NoEffect,
+            effect_values,
+        })
+    }
+
+    #[must_use]
+    pub fn supports(&self, effect: LightEffect) -> bool {
+        self.effect_values.contains(&effect)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LightEffectsUpdate {
+    pub effect: LightEffect,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LightGradientPoint {
+    pub color: ColorUpdate,
+}
+
+impl LightGradientPoint {
+    #[must_use]
+    pub const fn new(xy: XY) -> Self {
+        Self {
+            color: ColorUpdate::new(xy),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LightGradientMode {
+    InterpolatedPalette,
+    InterpolatedPaletteMirrored,
+    RandomPixelated,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LightGradient {
+    pub points: Vec<LightGradientPoint>,
+    pub points_capable: u32,
+    pub mode: LightGradientMode,
+    pub mode_values: Vec<LightGradientMode>,
+    pub pixel_count: Option<u32>,
+}
+
+impl LightGradient {
+    #[must_use]
+    pub fn new(points_capable: u32) -> Self {
+        Self {
+            points: vec![],
+            points_capable,
+            mode: LightGradientMode::InterpolatedPalette,
+            mode_values: vec![LightGradientMode::InterpolatedPalette],
+            pixel_count: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LightGradientUpdate {
+    pub points: Vec<LightGradientPoint>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LightTimedEffects {
-    pub status_values: Value,
-    pub status: Value,
-    pub effect_values: Value,
+    pub effect: TimedEffect,
+    pub status_values: Vec<TimedEffect>,
+    pub status: TimedEffect,
+    pub effect_values: Vec<TimedEffect>,
+    /// Duration of the running effect, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+}
+
+impl LightTimedEffects {
+    /// Longest duration of a timed effect accepted by hue, in milliseconds
+    pub const MAX_DURATION: u32 = 6 * 60 * 60 * 1000;
+
+    /// Timed effects of a dimmable light (which can show all of them)
+    #[allow(clippy::new_without_default)]
+    #[must_use]
+    pub fn new() -> Self {
+        let values = vec![
+            TimedEffect::NoEffect,
+            TimedEffect::Sunrise,
+            TimedEffect::Sunset,
+        ];
+        Self {
+            effect: TimedEffect::NoEffect,
+            status_values: values.clone(),
+            status: TimedEffect::NoEffect,
+            effect_values: values,
+            duration: None,
+        }
+    }
+
+    #[must_use]
+    pub fn supports(&self, effect: TimedEffect) -> bool {
+        self.effect_values.contains(&effect)
+    }
+}
+
+#[derive(Copy, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimedEffect {
+    NoEffect,
+    Sunrise,
+    Sunset,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct LightTimedEffectsUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effect: Option<TimedEffect>,
+    /// Duration of the effect, in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+}
+
+impl LightTimedEffectsUpdate {
+    /// Check the duration against the range accepted by hue
+    pub const fn validate(&self) -> ApiResult<()> {
+        match self.duration {
+            Some(ms) if ms == 0 || ms > LightTimedEffects::MAX_DURATION => {
+                Err(ApiError::TimedEffectDuration(ms))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LightDynamicsUpdate {
+    /// Duration of the transition to the new state, in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+}
+
+impl LightDynamicsUpdate {
+    /// Transition time in seconds, as used by z2m
+    #[must_use]
+    pub fn transition(&self) -> Option<f64> {
+        self.duration.map(|ms| f64::from(ms) / 1000.0)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -200,6 +592,14 @@ pub struct LightUpdate {
     pub color: Option<ColorUpdate>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub color_temperature: Option<ColorTemperatureUpdate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effects: Option<LightEffectsUpdate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gradient: Option<LightGradientUpdate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timed_effects: Option<LightTimedEffectsUpdate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dynamics: Option<LightDynamicsUpdate>,
 }
 
 impl LightUpdate {
@@ -239,6 +639,33 @@ impl LightUpdate {
             ..self
         }
     }
+
+    #[must_use]
+    pub fn with_effect(self, effect: impl Into<Option<LightEffect>>) -> Self {
+        Self {
+            effects: effect.into().map(|effect| LightEffectsUpdate { effect }),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_gradient(self, gradient: impl Into<Option<LightGradientUpdate>>) -> Self {
+        Self {
+            gradient: gradient.into(),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_timed_effect(self, effect: Option<TimedEffect>, duration: Option<u32>) -> Self {
+        Self {
+            timed_effects: effect.map(|effect| LightTimedEffectsUpdate {
+                effect: Some(effect),
+                duration,
+            }),
+            ..self
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -276,7 +703,7 @@ impl On {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ColorUpdate {
     pub xy: XY,
 }
@@ -288,7 +715,7 @@ impl ColorUpdate {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct ColorTemperatureUpdate {
     pub mirek: u32,
 }
@@ -300,7 +727,7 @@ impl ColorTemperatureUpdate {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ColorGamut {
     pub red: XY,
     pub green: XY,
@@ -339,7 +766,7 @@ impl ColorGamut {
     };
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Copy, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum GamutType {
     A,
     B,
@@ -379,7 +806,7 @@ impl LightColor {
     }
 }
 
-#[derive(Copy, Debug, Serialize, Deserialize, Clone)]
+#[derive(Copy, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct MirekSchema {
     pub mirek_minimum: u32,
     pub mirek_maximum: u32,
@@ -1,17 +1,42 @@
+mod behavior;
+mod contact;
 mod device;
+mod device_power;
+mod entertainment;
 mod grouped_light;
 mod light;
+mod motion;
+mod relative_rotary;
 mod resource;
 mod room;
 mod scene;
+mod software_update;
 mod stubs;
+mod tamper;
 mod update;
 
-pub use device::{Device, DeviceArchetype, DeviceProductData};
+pub use behavior::{
+    BehaviorInstance, BehaviorInstanceMetadata, BehaviorScript, DollarRef, SwitchConfiguration,
+    SwitchLocation,
+};
+pub use contact::{Contact, ContactReport, ContactState, ContactUpdate};
+pub use device::{Device, DeviceArchetype, DeviceMetadataUpdate, DeviceProductData, DeviceUpdate};
+pub use device_power::{BatteryState, DevicePower, DevicePowerUpdate, PowerState};
+pub use entertainment::{Entertainment, EntertainmentSegment, EntertainmentSegments};
 pub use grouped_light::{GroupedLight, GroupedLightUpdate};
 pub use light::{
-    ColorGamut, ColorTemperature, ColorTemperatureUpdate, ColorUpdate, Delta, Dimming,
-    DimmingUpdate, GamutType, Light, LightColor, LightUpdate, MirekSchema, On,
+    ColorCapability, ColorGamut, ColorTemperature, ColorTemperatureUpdate, ColorUpdate, Delta,
+    Dimming, DimmingUpdate, GamutType, Light, LightCapabilities, LightColor, LightDynamicsUpdate,
+    LightEffect, LightEffects, LightEffectsUpdate, LightGradient, LightGradientMode,
+    LightGradientPoint, LightGradientUpdate, LightTimedEffects, LightTimedEffectsUpdate,
+    LightUpdate, MirekSchema, On, TimedEffect,
+};
+pub use motion::{
+    Motion, MotionData, MotionReport, MotionSensitivity, MotionSensitivityStatus, MotionUpdate,
+};
+pub use relative_rotary::{
+    RelativeRotary, RelativeRotaryData, RelativeRotaryUpdate, RotaryAction, RotaryDirection,
+    RotaryEvent, RotaryReport, RotaryRotation,
 };
 pub use resource::{RType, ResourceLink, ResourceRecord};
 pub use room::{Room, RoomArchetype, RoomMetadata};
@@ -19,12 +44,14 @@ pub use scene::{
     Scene, SceneAction, SceneActionElement, SceneMetadata, SceneRecall, SceneStatus,
     SceneStatusUpdate, SceneUpdate,
 };
+pub use software_update::{DeviceSoftwareUpdate, DeviceSoftwareUpdateUpdate, SoftwareUpdateState};
 pub use stubs::{
-    BehaviorInstance, BehaviorScript, Bridge, BridgeHome, Button, ButtonData, ButtonMetadata,
-    ButtonReport, DollarRef, Entertainment, EntertainmentSegment, EntertainmentSegments,
-    GeofenceClient, Geolocation, Homekit, Matter, Metadata, PublicImage, SmartScene, TimeZone,
-    ZigbeeConnectivity, ZigbeeConnectivityStatus, ZigbeeDeviceDiscovery, Zone,
+    Bridge, BridgeHome, BridgeUpdate, Button, ButtonData, ButtonMetadata, ButtonReport,
+    ButtonUpdate, GeofenceClient, Geolocation, Homekit, Matter, Metadata, PublicImage, SmartScene,
+    TimeZone, ZigbeeConnectivity, ZigbeeConnectivityStatus, ZigbeeConnectivityUpdate,
+    ZigbeeDeviceDiscovery, Zone, ZoneUpdate,
 };
+pub use tamper::{Tamper, TamperReport, TamperSource, TamperState, TamperUpdate};
 pub use update::{Update, UpdateRecord};
 
 use std::fmt::Debug;
@@ -43,7 +70,10 @@ pub enum Resource {
     Bridge(Bridge),
     BridgeHome(BridgeHome),
     Button(Button),
+    Contact(Contact),
     Device(Device),
+    DevicePower(DevicePower),
+    DeviceSoftwareUpdate(DeviceSoftwareUpdate),
     Entertainment(Entertainment),
     GeofenceClient(GeofenceClient),
     Geolocation(Geolocation),
@@ -51,10 +81,13 @@ pub enum Resource {
     Homekit(Homekit),
     Light(Light),
     Matter(Matter),
+    Motion(Motion),
     PublicImage(PublicImage),
+    RelativeRotary(RelativeRotary),
     Room(Room),
     Scene(Scene),
     SmartScene(SmartScene),
+    Tamper(Tamper),
     ZigbeeConnectivity(ZigbeeConnectivity),
     ZigbeeDeviceDiscovery(ZigbeeDeviceDiscovery),
     Zone(Zone),
@@ -69,7 +102,10 @@ impl Resource {
             Self::Bridge(_) => RType::Bridge,
             Self::BridgeHome(_) => RType::BridgeHome,
             Self::Button(_) => RType::Button,
+            Self::Contact(_) => RType::Contact,
             Self::Device(_) => RType::Device,
+            Self::DevicePower(_) => RType::DevicePower,
+            Self::DeviceSoftwareUpdate(_) => RType::DeviceSoftwareUpdate,
             Self::Entertainment(_) => RType::Entertainment,
             Self::GeofenceClient(_) => RType::GeofenceClient,
             Self::Geolocation(_) => RType::Geolocation,
@@ -77,10 +113,13 @@ impl Resource {
             Self::Homekit(_) => RType::Homekit,
             Self::Light(_) => RType::Light,
             Self::Matter(_) => RType::Matter,
+            Self::Motion(_) => RType::Motion,
             Self::PublicImage(_) => RType::PublicImage,
+            Self::RelativeRotary(_) => RType::RelativeRotary,
             Self::Room(_) => RType::Room,
             Self::Scene(_) => RType::Scene,
             Self::SmartScene(_) => RType::SmartScene,
+            Self::Tamper(_) => RType::Tamper,
             Self::ZigbeeConnectivity(_) => RType::ZigbeeConnectivity,
             Self::ZigbeeDeviceDiscovery(_) => RType::ZigbeeDeviceDiscovery,
             Self::Zone(_) => RType::Zone,
@@ -94,7 +133,10 @@ impl Resource {
             RType::Bridge => Self::Bridge(from_value(obj)?),
             RType::BridgeHome => Self::BridgeHome(from_value(obj)?),
             RType::Button => Self::Button(from_value(obj)?),
+            RType::Contact => Self::Contact(from_value(obj)?),
             RType::Device => Self::Device(from_value(obj)?),
+            RType::DevicePower => Self::DevicePower(from_value(obj)?),
+            RType::DeviceSoftwareUpdate => Self::DeviceSoftwareUpdate(from_value(obj)?),
             RType::Entertainment => Self::Entertainment(from_value(obj)?),
             RType::GeofenceClient => Self::GeofenceClient(from_value(obj)?),
             RType::Geolocation => Self::Geolocation(from_value(obj)?),
@@ -102,10 +144,13 @@ impl Resource {
             RType::Homekit => Self::Homekit(from_value(obj)?),
             RType::Light => Self::Light(from_value(obj)?),
             RType::Matter => Self::Matter(from_value(obj)?),
+            RType::Motion => Self::Motion(from_value(obj)?),
             RType::PublicImage => Self::PublicImage(from_value(obj)?),
+            RType::RelativeRotary => Self::RelativeRotary(from_value(obj)?),
             RType::Room => Self::Room(from_value(obj)?),
             RType::Scene => Self::Scene(from_value(obj)?),
             RType::SmartScene => Self::SmartScene(from_value(obj)?),
+            RType::Tamper => Self::Tamper(from_value(obj)?),
             RType::ZigbeeConnectivity => Self::ZigbeeConnectivity(from_value(obj)?),
             RType::ZigbeeDeviceDiscovery => Self::ZigbeeDeviceDiscovery(from_value(obj)?),
             RType::Zone => Self::Zone(from_value(obj)?),
@@ -166,7 +211,10 @@ resource_conversion_impl!(BehaviorInstance);
 resource_conversion_impl!(Bridge);
 resource_conversion_impl!(BridgeHome);
 resource_conversion_impl!(Button);
+resource_conversion_impl!(Contact);
 resource_conversion_impl!(Device);
+resource_conversion_impl!(DevicePower);
+resource_conversion_impl!(DeviceSoftwareUpdate);
 resource_conversion_impl!(Entertainment);
 resource_conversion_impl!(GeofenceClient);
 resource_conversion_impl!(Geolocation);
@@ -174,10 +222,13 @@ resource_conversion_impl!(GroupedLight);
 resource_conversion_impl!(Homekit);
 resource_conversion_impl!(Light);
 resource_conversion_impl!(Matter);
+resource_conversion_impl!(Motion);
 resource_conversion_impl!(PublicImage);
+resource_conversion_impl!(RelativeRotary);
 resource_conversion_impl!(Room);
 resource_conversion_impl!(Scene);
 resource_conversion_impl!(SmartScene);
+resource_conversion_impl!(Tamper);
 resource_conversion_impl!(ZigbeeConnectivity);
 resource_conversion_impl!(ZigbeeDeviceDiscovery);
 resource_conversion_impl!(Zone);
@@ -0,0 +1,86 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::hue::api::ResourceLink;
+use crate::hue::date_format;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Motion {
+    pub owner: ResourceLink,
+    pub enabled: bool,
+    pub motion: MotionData,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensitivity: Option<MotionSensitivity>,
+}
+
+impl Motion {
+    #[must_use]
+    pub const fn new(owner: ResourceLink, sensitivity: Option<MotionSensitivity>) -> Self {
+        Self {
+            owner,
+            enabled: true,
+            motion: MotionData {
+                motion: false,
+                motion_valid: false,
+                motion_report: None,
+            },
+            sensitivity,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MotionData {
+    pub motion: bool,
+    pub motion_valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motion_report: Option<MotionReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MotionReport {
+    #[serde(with = "date_format::utc")]
+    pub changed: DateTime<Utc>,
+    pub motion: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MotionSensitivityStatus {
+    #[default]
+    Set,
+    Changing,
+}
+
+/// Sensitivity, as an index into the levels supported by the sensor
+/// (`0..=sensitivity_max`)
+///
+/// Only `sensitivity` is given in PUT requests, so the rest is optional.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct MotionSensitivity {
+    #[serde(default)]
+    pub status: MotionSensitivityStatus,
+    pub sensitivity: u32,
+    #[serde(default)]
+    pub sensitivity_max: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MotionUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motion: Option<MotionData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensitivity: Option<MotionSensitivity>,
+}
+
+impl From<&Motion> for MotionUpdate {
+    fn from(motion: &Motion) -> Self {
+        Self {
+            enabled: Some(motion.enabled),
+            motion: Some(motion.motion.clone()),
+            sensitivity: motion.sensitivity,
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::hue::api::ResourceLink;
+use crate::hue::date_format;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RelativeRotary {
+    pub owner: ResourceLink,
+    pub relative_rotary: RelativeRotaryData,
+}
+
+impl RelativeRotary {
+    #[must_use]
+    pub const fn new(owner: ResourceLink) -> Self {
+        Self {
+            owner,
+            relative_rotary: RelativeRotaryData {
+                last_event: None,
+                rotary_report: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RelativeRotaryData {
+    /// Deprecated by hue in favor of `rotary_report`, but still reported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_event: Option<RotaryEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotary_report: Option<RotaryReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RotaryAction {
+    /// First event of a rotation
+    Start,
+    /// Rotation continues
+    Repeat,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RotaryDirection {
+    ClockWise,
+    CounterClockWise,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct RotaryRotation {
+    pub direction: RotaryDirection,
+    /// Amount of rotation
+    pub steps: u32,
+    /// Duration of the rotation (in milliseconds)
+    pub duration: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RotaryEvent {
+    pub action: RotaryAction,
+    pub rotation: RotaryRotation,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RotaryReport {
+    #[serde(with = "date_format::utc")]
+    pub updated: DateTime<Utc>,
+    pub action: RotaryAction,
+    pub rotation: RotaryRotation,
+}
+
+impl RotaryReport {
+    #[must_use]
+    pub fn new(action: RotaryAction, rotation: RotaryRotation) -> Self {
+        Self {
+            updated: Utc::now(),
+            action,
+            rotation,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RelativeRotaryUpdate {
+    pub relative_rotary: RelativeRotaryData,
+}
+
+impl From<&RelativeRotary> for RelativeRotaryUpdate {
+    fn from(rotary: &RelativeRotary) -> Self {
+        Self {
+            relative_rotary: rotary.relative_rotary.clone(),
+        }
+    }
+}
@@ -22,6 +22,7 @@ pub enum RType {
     Homekit,
     Light,
     Matter,
+    Motion,
     PublicImage,
     Room,
     Scene,
@@ -29,6 +30,13 @@ pub enum RType {
     ZigbeeConnectivity,
     ZigbeeDeviceDiscovery,
     Zone,
+    /* new types go last, since the variant index is hashed into
+     * deterministic resource ids */
+    RelativeRotary,
+    Contact,
+    Tamper,
+    DeviceSoftwareUpdate,
+    DevicePower,
 }
 
 fn hash<T: Hash + ?Sized>(t: &T) -> u64 {
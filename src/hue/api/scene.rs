@@ -1,7 +1,9 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::hue::api::{ColorTemperatureUpdate, ColorUpdate, DimmingUpdate, On, ResourceLink};
+use crate::hue::api::{
+    ColorTemperatureUpdate, ColorUpdate, DimmingUpdate, Light, On, ResourceLink,
+};
 
 #[derive(Copy, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(tag = "active", rename_all = "snake_case")]
@@ -58,6 +60,22 @@ pub struct SceneAction {
     pub on: Option<On>,
 }
 
+impl From<&Light> for SceneAction {
+    /// The current state of a light, as a scene action
+    fn from(light: &Light) -> Self {
+        let mirek = light.as_mirek_opt();
+        Self {
+            color: mirek
+                .is_none()
+                .then(|| light.as_color_opt().map(|xy| ColorUpdate { xy }))
+                .flatten(),
+            color_temperature: mirek.map(|mirek| ColorTemperatureUpdate { mirek }),
+            dimming: light.as_dimming_opt(),
+            on: Some(light.on),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SceneActionElement {
     pub action: SceneAction,
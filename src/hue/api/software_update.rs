@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hue::api::ResourceLink;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceSoftwareUpdate {
+    pub owner: ResourceLink,
+    pub state: SoftwareUpdateState,
+    pub problems: Vec<String>,
+    /// Progress (in percent) of an update being installed (Bifrost extension)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f64>,
+    /// Estimated seconds until the update is installed (Bifrost extension)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<u64>,
+}
+
+impl DeviceSoftwareUpdate {
+    #[must_use]
+    pub const fn new(owner: ResourceLink) -> Self {
+        Self {
+            owner,
+            state: SoftwareUpdateState::NoUpdate,
+            problems: vec![],
+            progress: None,
+            remaining: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SoftwareUpdateState {
+    NoUpdate,
+    UpdatePending,
+    ReadyToInstall,
+    Installing,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceSoftwareUpdateUpdate {
+    pub state: SoftwareUpdateState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<u64>,
+}
+
+impl From<&DeviceSoftwareUpdate> for DeviceSoftwareUpdateUpdate {
+    fn from(swu: &DeviceSoftwareUpdate) -> Self {
+        Self {
+            state: swu.state,
+            progress: swu.progress,
+            remaining: swu.remaining,
+        }
+    }
+}
@@ -2,7 +2,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::hue::api::{DeviceArchetype, ResourceLink, SceneMetadata};
+use crate::hue::api::{DeviceArchetype, RType, ResourceLink, RoomMetadata, SceneMetadata};
 use crate::hue::{best_guess_timezone, date_format};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -12,12 +12,35 @@ pub struct Bridge {
     pub time_zone: TimeZone,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BridgeUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<TimeZone>,
+}
+
+impl From<&Bridge> for BridgeUpdate {
+    fn from(bridge: &Bridge) -> Self {
+        Self {
+            time_zone: Some(bridge.time_zone.clone()),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BridgeHome {
     pub children: Vec<ResourceLink>,
     pub services: Vec<ResourceLink>,
 }
 
+impl BridgeHome {
+    #[must_use]
+    pub fn grouped_light_service(&self) -> Option<&ResourceLink> {
+        self.services
+            .iter()
+            .find(|rl| rl.rtype == RType::GroupedLight)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Button {
     pub owner: ResourceLink,
@@ -40,6 +63,11 @@ pub struct ButtonData {
     pub event_values: Option<Value>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ButtonUpdate {
+    pub button: ButtonData,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ButtonReport {
     #[serde(with = "date_format::utc")]
@@ -47,50 +75,6 @@ pub struct ButtonReport {
     pub event: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct DollarRef {
-    #[serde(rename = "$ref")]
-    pub dref: String,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct BehaviorScript {
-    pub configuration_schema: DollarRef,
-    pub description: String,
-    pub max_number_instances: Option<u32>,
-    pub metadata: Value,
-    pub state_schema: DollarRef,
-    pub supported_features: Vec<String>,
-    pub trigger_schema: DollarRef,
-    pub version: String,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct BehaviorInstance {}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Entertainment {
-    pub equalizer: bool,
-    pub owner: ResourceLink,
-    pub proxy: bool,
-    pub renderer: bool,
-    pub renderer_reference: ResourceLink,
-    pub segments: EntertainmentSegments,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct EntertainmentSegments {
-    pub configurable: bool,
-    pub max_segments: u32,
-    pub segments: Vec<EntertainmentSegment>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct EntertainmentSegment {
-    pub length: u32,
-    pub start: u32,
-}
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GeofenceClient {
     pub name: String,
@@ -143,9 +127,10 @@ pub struct SmartScene {
     pub week_timeslots: Value,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum ZigbeeConnectivityStatus {
+    Connected,
     ConnectivityIssue,
 }
 
@@ -158,6 +143,11 @@ pub struct ZigbeeConnectivity {
     pub status: ZigbeeConnectivityStatus,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ZigbeeConnectivityUpdate {
+    pub status: ZigbeeConnectivityStatus,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ZigbeeDeviceDiscovery {
     pub owner: ResourceLink,
@@ -166,12 +156,29 @@ pub struct ZigbeeDeviceDiscovery {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Zone {
-    pub metadata: Metadata,
+    pub metadata: RoomMetadata,
     pub children: Vec<ResourceLink>,
     #[serde(default)]
     pub services: Vec<ResourceLink>,
 }
 
+impl Zone {
+    #[must_use]
+    pub fn grouped_light_service(&self) -> Option<&ResourceLink> {
+        self.services
+            .iter()
+            .find(|rl| rl.rtype == RType::GroupedLight)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ZoneUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<RoomMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub children: Option<Vec<ResourceLink>>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TimeZone {
     pub time_zone: String,
@@ -0,0 +1,77 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::hue::api::ResourceLink;
+use crate::hue::date_format;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Tamper {
+    pub owner: ResourceLink,
+    /// Latest report from each tamper source
+    pub tamper_reports: Vec<TamperReport>,
+}
+
+impl Tamper {
+    #[must_use]
+    pub const fn new(owner: ResourceLink) -> Self {
+        Self {
+            owner,
+            tamper_reports: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TamperSource {
+    BatteryDoor,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TamperState {
+    Tampered,
+    NotTampered,
+}
+
+impl From<bool> for TamperState {
+    fn from(tampered: bool) -> Self {
+        if tampered {
+            Self::Tampered
+        } else {
+            Self::NotTampered
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TamperReport {
+    #[serde(with = "date_format::utc")]
+    pub changed: DateTime<Utc>,
+    pub source: TamperSource,
+    pub state: TamperState,
+}
+
+impl TamperReport {
+    #[must_use]
+    pub fn new(source: TamperSource, state: TamperState) -> Self {
+        Self {
+            changed: Utc::now(),
+            source,
+            state,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TamperUpdate {
+    pub tamper_reports: Vec<TamperReport>,
+}
+
+impl From<&Tamper> for TamperUpdate {
+    fn from(tamper: &Tamper) -> Self {
+        Self {
+            tamper_reports: tamper.tamper_reports.clone(),
+        }
+    }
+}
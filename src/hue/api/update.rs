@@ -1,16 +1,24 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::hue::api::{GroupedLightUpdate, LightUpdate, RType, SceneUpdate};
+use crate::hue::api::{
+    BridgeUpdate, ButtonUpdate, ContactUpdate, DevicePowerUpdate, DeviceSoftwareUpdateUpdate,
+    DeviceUpdate, GroupedLightUpdate, LightUpdate, MotionUpdate, RType, RelativeRotaryUpdate,
+    SceneUpdate, TamperUpdate, ZigbeeConnectivityUpdate,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Update {
     /* BehaviorScript(BehaviorScriptUpdate), */
     /* BehaviorInstance(BehaviorInstanceUpdate), */
-    /* Bridge(BridgeUpdate), */
+    Bridge(BridgeUpdate),
     /* BridgeHome(BridgeHomeUpdate), */
-    /* Device(DeviceUpdate), */
+    Button(ButtonUpdate),
+    Contact(ContactUpdate),
+    Device(DeviceUpdate),
+    DevicePower(DevicePowerUpdate),
+    DeviceSoftwareUpdate(DeviceSoftwareUpdateUpdate),
     /* Entertainment(EntertainmentUpdate), */
     /* GeofenceClient(GeofenceClientUpdate), */
     /* Geolocation(GeolocationUpdate), */
@@ -18,11 +26,14 @@ pub enum Update {
     /* Homekit(HomekitUpdate), */
     Light(LightUpdate),
     /* Matter(MatterUpdate), */
+    Motion(MotionUpdate),
     /* PublicImage(PublicImageUpdate), */
+    RelativeRotary(RelativeRotaryUpdate),
     /* Room(RoomUpdate), */
     Scene(SceneUpdate),
     /* SmartScene(SmartSceneUpdate), */
-    /* ZigbeeConnectivity(ZigbeeConnectivityUpdate), */
+    Tamper(TamperUpdate),
+    ZigbeeConnectivity(ZigbeeConnectivityUpdate),
     /* ZigbeeDeviceDiscovery(ZigbeeDeviceDiscoveryUpdate), */
     /* Zone(ZoneUpdate), */
 }
@@ -31,9 +42,19 @@ impl Update {
     #[must_use]
     pub const fn rtype(&self) -> RType {
         match self {
+            Self::Bridge(_) => RType::Bridge,
+            Self::Button(_) => RType::Button,
+            Self::Contact(_) => RType::Contact,
+            Self::Device(_) => RType::Device,
+            Self::DevicePower(_) => RType::DevicePower,
+            Self::DeviceSoftwareUpdate(_) => RType::DeviceSoftwareUpdate,
             Self::GroupedLight(_) => RType::GroupedLight,
             Self::Light(_) => RType::Light,
+            Self::Motion(_) => RType::Motion,
+            Self::RelativeRotary(_) => RType::RelativeRotary,
             Self::Scene(_) => RType::Scene,
+            Self::Tamper(_) => RType::Tamper,
+            Self::ZigbeeConnectivity(_) => RType::ZigbeeConnectivity,
         }
     }
 
@@ -43,6 +64,16 @@ impl Update {
             Self::GroupedLight(_) => Some(format!("/groups/{id}")),
             Self::Light(_) => Some(format!("/lights/{id}")),
             Self::Scene(_) => Some(format!("/scenes/{uuid}")),
+            Self::Bridge(_)
+            | Self::Button(_)
+            | Self::Contact(_)
+            | Self::Device(_)
+            | Self::DevicePower(_)
+            | Self::DeviceSoftwareUpdate(_)
+            | Self::Motion(_)
+            | Self::RelativeRotary(_)
+            | Self::Tamper(_)
+            | Self::ZigbeeConnectivity(_) => None,
         }
     }
 }
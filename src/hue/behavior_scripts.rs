@@ -0,0 +1,216 @@
+use serde_json::{json, Value};
+use uuid::{uuid, Uuid};
+
+use crate::error::{ApiError, ApiResult};
+use crate::hue::api::{BehaviorScript, DollarRef};
+
+/// The standard behavior scripts bundled with Bifrost, which posted
+/// behavior instances are validated against
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BundledScript {
+    WakeUp,
+    GoToSleep,
+    MotionSensor,
+}
+
+impl BundledScript {
+    pub const ALL: [Self; 3] = [Self::WakeUp, Self::GoToSleep, Self::MotionSensor];
+
+    #[must_use]
+    pub const fn id(self) -> Uuid {
+        match self {
+            Self::WakeUp => uuid!("ff8957e3-2eb9-4699-a0c8-ad2cb3ede704"),
+            Self::GoToSleep => uuid!("7e571ac6-f363-42e1-809a-4cbf6523ed72"),
+            Self::MotionSensor => uuid!("3a4d6b15-09c5-4e33-9b58-8e2ae4b8c2f1"),
+        }
+    }
+
+    #[must_use]
+    pub fn from_id(id: &Uuid) -> Option<Self> {
+        Self::ALL.into_iter().find(|script| script.id() == *id)
+    }
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::WakeUp => "Basic wake up routine",
+            Self::GoToSleep => "Basic go to sleep routine",
+            Self::MotionSensor => "Motion sensor",
+        }
+    }
+
+    #[must_use]
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::WakeUp => {
+                "Get your body in the mood to wake up by fading on the lights in the morning."
+            }
+            Self::GoToSleep => "Get ready for nice sleep by fading the lights off in the evening.",
+            Self::MotionSensor => "Turn on lights when motion is detected.",
+        }
+    }
+
+    #[must_use]
+    pub const fn schema_name(self) -> &'static str {
+        match self {
+            Self::WakeUp => "basic_wake_up",
+            Self::GoToSleep => "basic_go_to_sleep",
+            Self::MotionSensor => "motion_sensor",
+        }
+    }
+
+    #[must_use]
+    pub fn script(self) -> BehaviorScript {
+        let dref = |kind: &str| DollarRef {
+            dref: format!("{}_{kind}.json#", self.schema_name()),
+        };
+
+        BehaviorScript {
+            configuration_schema: dref("config"),
+            description: self.description().to_string(),
+            max_number_instances: None,
+            metadata: json!({
+                "name": self.name(),
+                "category": "automation",
+            }),
+            state_schema: dref("state"),
+            supported_features: vec![],
+            trigger_schema: dref("trigger"),
+            version: String::from("0.0.1"),
+        }
+    }
+
+    /// JSON schema for the `configuration` of behavior instances using this script
+    #[must_use]
+    pub fn configuration_schema(self) -> Value {
+        let (required, properties) = match self {
+            Self::WakeUp => (
+                json!(["end_brightness", "fade_in_duration", "when", "where"]),
+                json!({
+                    "end_brightness": {"type": "number", "minimum": 0, "maximum": 100},
+                    "fade_in_duration": {"$ref": "#/definitions/duration"},
+                    "turn_lights_off_after": {"$ref": "#/definitions/duration"},
+                    "style": {"enum": ["basic", "sunrise"]},
+                    "when": {"$ref": "#/definitions/when"},
+                    "where": {"$ref": "#/definitions/where"},
+                }),
+            ),
+            Self::GoToSleep => (
+                json!(["fade_out_duration", "when", "where"]),
+                json!({
+                    "fade_out_duration": {"$ref": "#/definitions/duration"},
+                    "end_state": {"enum": ["turn_off", "keep"]},
+                    "when": {"$ref": "#/definitions/when"},
+                    "where": {"$ref": "#/definitions/where"},
+                }),
+            ),
+            Self::MotionSensor => (
+                json!(["source", "where"]),
+                json!({
+                    "source": {"$ref": "#/definitions/resource_link"},
+                    "timeout": {"$ref": "#/definitions/duration"},
+                    "when": {"type": "object"},
+                    "where": {"$ref": "#/definitions/where"},
+                }),
+            ),
+        };
+
+        json!({
+            "type": "object",
+            "required": required,
+            "properties": properties,
+            "definitions": Self::definitions(),
+        })
+    }
+
+    /* schema definitions shared by all bundled scripts */
+    fn definitions() -> Value {
+        json!({
+            "resource_link": {
+                "type": "object",
+                "required": ["rid", "rtype"],
+                "properties": {
+                    "rid": {
+                        "type": "string",
+                        "pattern": "^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$",
+                    },
+                    "rtype": {"type": "string"},
+                },
+            },
+            "duration": {
+                "type": "object",
+                "required": ["seconds"],
+                "properties": {
+                    "seconds": {"type": "integer", "minimum": 0},
+                },
+            },
+            "when": {
+                "type": "object",
+                "required": ["time_point"],
+                "properties": {
+                    "recurrence_days": {
+                        "type": "array",
+                        "uniqueItems": true,
+                        "items": {
+                            "enum": [
+                                "monday", "tuesday", "wednesday", "thursday",
+                                "friday", "saturday", "sunday",
+                            ],
+                        },
+                    },
+                    "time_point": {
+                        "type": "object",
+                        "required": ["type", "time"],
+                        "properties": {
+                            "type": {"enum": ["time"]},
+                            "time": {
+                                "type": "object",
+                                "required": ["hour", "minute"],
+                                "properties": {
+                                    "hour": {"type": "integer", "minimum": 0, "maximum": 23},
+                                    "minute": {"type": "integer", "minimum": 0, "maximum": 59},
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "where": {
+                "type": "array",
+                "minItems": 1,
+                "items": {
+                    "type": "object",
+                    "required": ["group"],
+                    "properties": {
+                        "group": {"$ref": "#/definitions/resource_link"},
+                        "items": {
+                            "type": "array",
+                            "items": {"$ref": "#/definitions/resource_link"},
+                        },
+                    },
+                },
+            },
+        })
+    }
+
+    /// Validate a behavior instance configuration against the schema of this
+    /// script, reporting every problem found (prefixed by its json pointer)
+    pub fn validate(self, configuration: &Value) -> ApiResult<()> {
+        let validator = jsonschema::validator_for(&self.configuration_schema())
+            .map_err(|err| ApiError::BehaviorSchemaInvalid(self, err.to_string()))?;
+
+        let errors: Vec<String> = validator
+            .iter_errors(configuration)
+            .map(|err| match err.instance_path.to_string() {
+                path if path.is_empty() => format!("(root): {err}"),
+                path => format!("{path}: {err}"),
+            })
+            .collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::BehaviorConfigInvalid(errors))
+        }
+    }
+}
@@ -8,6 +8,7 @@ use uuid::Uuid;
 
 use crate::error::ApiResult;
 use crate::hue::{api, best_guess_timezone};
+use crate::model::brightness::Brightness;
 use crate::resource::Resources;
 
 use super::date_format;
@@ -20,6 +21,73 @@ pub struct HueError {
     description: String,
 }
 
+impl HueError {
+    /// Error type used by a real bridge, when it is too busy to handle a
+    /// command
+    pub const INTERNAL_ERROR: u32 = 901;
+
+    /// Error type used by a real bridge, when a parameter cannot be changed
+    /// in the current state
+    pub const PARAMETER_NOT_MODIFIABLE: u32 = 201;
+
+    /// Error type used by a real bridge, when a resource does not have a
+    /// parameter at all
+    pub const PARAMETER_NOT_AVAILABLE: u32 = 6;
+
+    /// Error type used by a real bridge, when a resource does not support
+    /// the request method
+    pub const METHOD_NOT_AVAILABLE: u32 = 4;
+
+    /// Error type used by a real bridge, when an app tries to pair without
+    /// the link button being pressed
+    pub const LINK_BUTTON_NOT_PRESSED: u32 = 101;
+
+    #[must_use]
+    pub fn internal_error(address: &str, code: u16) -> Self {
+        Self {
+            typ: Self::INTERNAL_ERROR,
+            address: address.to_string(),
+            description: format!("Internal error, {code}"),
+        }
+    }
+
+    #[must_use]
+    pub fn not_modifiable(address: &str, reason: &str) -> Self {
+        Self {
+            typ: Self::PARAMETER_NOT_MODIFIABLE,
+            address: address.to_string(),
+            description: format!("parameter, {address}, is not modifiable. {reason}"),
+        }
+    }
+
+    #[must_use]
+    pub fn link_button_not_pressed() -> Self {
+        Self {
+            typ: Self::LINK_BUTTON_NOT_PRESSED,
+            address: String::new(),
+            description: "link button not pressed".to_string(),
+        }
+    }
+
+    #[must_use]
+    pub fn not_available(address: &str, parameter: &str) -> Self {
+        Self {
+            typ: Self::PARAMETER_NOT_AVAILABLE,
+            address: address.to_string(),
+            description: format!("parameter, {parameter}, not available"),
+        }
+    }
+
+    #[must_use]
+    pub fn method_not_available(address: &str, method: &str) -> Self {
+        Self {
+            typ: Self::METHOD_NOT_AVAILABLE,
+            address: address.to_string(),
+            description: format!("method, {method}, not available for resource, {address}"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum HueResult<T> {
@@ -28,37 +96,65 @@ pub enum HueResult<T> {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+/// Unauthenticated bridge config.
+///
+/// Fields are in the order a real bridge sends them, since some apps are
+/// picky about it.
 pub struct ApiShortConfig {
+    pub name: String,
+    pub datastoreversion: String,
+    pub swversion: String,
     pub apiversion: String,
+    #[serde(with = "mac_lowercase")]
+    pub mac: MacAddress,
     pub bridgeid: String,
-    pub datastoreversion: String,
     pub factorynew: bool,
-    pub mac: MacAddress,
-    pub modelid: String,
-    pub name: String,
     pub replacesbridgeid: Option<String>,
+    pub modelid: String,
     pub starterkitid: String,
-    pub swversion: String,
+}
+
+/* real bridges report their mac address in lower case */
+mod mac_lowercase {
+    use mac_address::MacAddress;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn serialize<S>(mac: &MacAddress, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&mac.to_string().to_lowercase())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<MacAddress, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(Error::custom)
+    }
 }
 
 impl Default for ApiShortConfig {
     fn default() -> Self {
         Self {
-            apiversion: "1.66.0".to_string(),
+            name: "Bifrost Bridge".to_string(),
+            datastoreversion: crate::hue::HUE_BRIDGE_V2_DATASTORE_VERSION.to_string(),
+            swversion: crate::hue::HUE_BRIDGE_V2_SW_VERSION.to_string(),
+            apiversion: crate::hue::HUE_BRIDGE_V2_API_VERSION.to_string(),
+            mac: MacAddress::default(),
             bridgeid: "0000000000000000".to_string(),
-            datastoreversion: "163".to_string(),
             factorynew: false,
-            mac: MacAddress::default(),
-            modelid: crate::hue::HUE_BRIDGE_V2_MODEL_ID.to_string(),
-            name: "Bifrost Bridge".to_string(),
             replacesbridgeid: None,
+            modelid: crate::hue::HUE_BRIDGE_V2_MODEL_ID.to_string(),
             starterkitid: String::new(),
-            swversion: "1966060010".to_string(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum ApiResourceType {
     Config,
@@ -74,7 +170,7 @@ pub enum ApiResourceType {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NewUser {
-    devicetype: String,
+    pub devicetype: String,
     generateclientkey: Option<bool>,
 }
 
@@ -125,6 +221,19 @@ pub struct PortalState {
     signedon: bool,
 }
 
+impl PortalState {
+    /// Portal state as reported by a bridge that is signed on to the hue cloud
+    #[must_use]
+    pub const fn connected() -> Self {
+        Self {
+            communication: ConnectionState::Connected,
+            incoming: true,
+            outgoing: true,
+            signedon: true,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiBackup {
     pub errorcode: u32,
@@ -196,7 +305,7 @@ impl SoftwareUpdate2 {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct Whitelist {
     #[serde(with = "date_format::utc")]
     pub create_date: DateTime<Utc>,
@@ -233,6 +342,11 @@ pub struct ApiConfig {
     pub whitelist: HashMap<Uuid, Whitelist>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ApiConfigUpdate {
+    pub portalservices: Option<bool>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ApiEffect {
@@ -261,6 +375,7 @@ pub struct ApiGroupAction {
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ApiGroupType {
     Room,
+    Zone,
     LightGroup,
 }
 
@@ -269,6 +384,7 @@ pub struct ApiGroup {
     name: String,
     lights: Vec<String>,
     action: ApiGroupAction,
+    state: ApiGroupState,
 
     #[serde(rename = "type")]
     group_type: ApiGroupType,
@@ -276,21 +392,59 @@ pub struct ApiGroup {
 }
 
 impl ApiGroup {
-    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
     #[must_use]
     pub fn from_lights_and_room(
         glight: api::GroupedLight,
         lights: Vec<String>,
         room: api::Room,
+        state: ApiGroupState,
+        caps: &api::LightCapabilities,
+    ) -> Self {
+        Self::new(
+            glight,
+            lights,
+            room.metadata,
+            ApiGroupType::Room,
+            state,
+            caps,
+        )
+    }
+
+    #[must_use]
+    pub fn from_lights_and_zone(
+        glight: api::GroupedLight,
+        lights: Vec<String>,
+        zone: api::Zone,
+        state: ApiGroupState,
+        caps: &api::LightCapabilities,
+    ) -> Self {
+        Self::new(
+            glight,
+            lights,
+            zone.metadata,
+            ApiGroupType::Zone,
+            state,
+            caps,
+        )
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn new(
+        glight: api::GroupedLight,
+        lights: Vec<String>,
+        metadata: api::RoomMetadata,
+        group_type: ApiGroupType,
+        state: ApiGroupState,
+        caps: &api::LightCapabilities,
     ) -> Self {
         Self {
-            name: room.metadata.name,
+            name: metadata.name,
             lights,
             action: ApiGroupAction {
                 on: glight.on.is_some_and(|on| on.on),
                 bri: glight
                     .dimming
-                    .map(|dim| (dim.brightness * 2.54) as u32)
+                    .map(|dim| Brightness::from_percent(dim.brightness).v1())
                     .unwrap_or_default(),
                 hue: 0,
                 sat: 0,
@@ -298,20 +452,90 @@ impl ApiGroup {
                 xy: [0.0, 0.0],
                 ct: 0,
                 alert: ApiAlert::None,
-                colormode: LightColorMode::Xy,
+                colormode: LightColorMode::for_capabilities(caps),
             },
-            class: "Bedroom".to_string(),
-            group_type: ApiGroupType::Room,
+            state,
+            class: group_class(metadata.archetype).to_string(),
+            group_type,
         }
     }
 }
 
+/// Group class used by the v1 api for each room archetype
+const fn group_class(archetype: api::RoomArchetype) -> &'static str {
+    use api::RoomArchetype as RA;
+
+    match archetype {
+        RA::LivingRoom => "Living room",
+        RA::Kitchen => "Kitchen",
+        RA::Dining => "Dining",
+        RA::Bedroom => "Bedroom",
+        RA::KidsBedroom => "Kids bedroom",
+        RA::Bathroom => "Bathroom",
+        RA::Nursery => "Nursery",
+        RA::Recreation => "Recreation",
+        RA::Office => "Office",
+        RA::Gym => "Gym",
+        RA::Hallway => "Hallway",
+        RA::Toilet => "Toilet",
+        RA::FrontDoor => "Front door",
+        RA::Garage => "Garage",
+        RA::Terrace => "Terrace",
+        RA::Garden => "Garden",
+        RA::Driveway => "Driveway",
+        RA::Carport => "Carport",
+        RA::Home => "Home",
+        RA::Downstairs => "Downstairs",
+        RA::Upstairs => "Upstairs",
+        RA::TopFloor => "Top floor",
+        RA::Attic => "Attic",
+        RA::GuestRoom => "Guest room",
+        RA::Staircase => "Staircase",
+        RA::Lounge => "Lounge",
+        RA::ManCave => "Man cave",
+        RA::Computer => "Computer",
+        RA::Studio => "Studio",
+        RA::Music => "Music",
+        RA::Tv => "TV",
+        RA::Reading => "Reading",
+        RA::Closet => "Closet",
+        RA::Storage => "Storage",
+        RA::LaundryRoom => "Laundry room",
+        RA::Balcony => "Balcony",
+        RA::Porch => "Porch",
+        RA::Barbecue => "Barbecue",
+        RA::Pool => "Pool",
+        RA::Other => "Other",
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiGroupState {
     pub all_on: bool,
     pub any_on: bool,
 }
 
+impl ApiGroupState {
+    /// Group state from the on/off state of each member light
+    ///
+    /// Like a real bridge, an empty group is reported as neither all on nor
+    /// any on.
+    #[must_use]
+    pub fn from_lights(lights: impl IntoIterator<Item = bool>) -> Self {
+        let mut count = 0;
+        let mut on = 0;
+        for light in lights {
+            count += 1;
+            on += usize::from(light);
+        }
+
+        Self {
+            all_on: count > 0 && on == count,
+            any_on: on > 0,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum LightColorMode {
@@ -319,6 +543,57 @@ pub enum LightColorMode {
     Xy,
 }
 
+impl LightColorMode {
+    #[must_use]
+    pub const fn for_capabilities(caps: &api::LightCapabilities) -> Self {
+        if caps.color.is_some() {
+            Self::Xy
+        } else {
+            Self::Ct
+        }
+    }
+}
+
+/* the v1 light type, which is how v1 clients tell what a light can do */
+const fn v1_light_type(caps: &api::LightCapabilities) -> &'static str {
+    match (caps.color.is_some(), caps.color_temperature.is_some()) {
+        (true, true) => "Extended color light",
+        (true, false) => "Color light",
+        (false, true) => "Color temperature light",
+        (false, false) if caps.dimmable => "Dimmable light",
+        (false, false) => "On/Off plug-in unit",
+    }
+}
+
+fn v1_control(caps: &api::LightCapabilities) -> Value {
+    let mut control = json!({
+        "maxlumen": 300,
+    });
+
+    if caps.dimmable {
+        control["mindimlevel"] = json!(200);
+    }
+
+    if let Some(color) = &caps.color {
+        let gamut = &color.gamut;
+        control["colorgamut"] = json!([
+            [gamut.red.x, gamut.red.y],
+            [gamut.green.x, gamut.green.y],
+            [gamut.blue.x, gamut.blue.y],
+        ]);
+        control["colorgamuttype"] = json!(color.gamut_type);
+    }
+
+    if let Some(schema) = caps.color_temperature {
+        control["ct"] = json!({
+            "max": schema.mirek_maximum,
+            "min": schema.mirek_minimum,
+        });
+    }
+
+    control
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiLightState {
     on: bool,
@@ -358,12 +633,54 @@ pub enum ApiGroupActionUpdate {
     LightUpdate(ApiLightStateUpdate),
 }
 
+impl ApiLightStateUpdate {
+    /// Leave out anything the light is not capable of, since some clients
+    /// send scene lightstates back as they are, when recalling a scene
+    #[must_use]
+    pub fn for_light(self, caps: &api::LightCapabilities) -> Self {
+        Self {
+            on: self.on,
+            bri: self.bri.filter(|_| caps.dimmable),
+            xy: self.xy.filter(|_| caps.color.is_some()),
+            ct: self.ct.filter(|_| caps.color_temperature.is_some()),
+        }
+    }
+
+    /// Split off the parameters the light cannot show at all. Colors and
+    /// color temperatures are kept if the light can show either, since
+    /// [`api::LightCapabilities::fit_color`] translates between them.
+    #[must_use]
+    pub fn split_unsupported(self, caps: &api::LightCapabilities) -> (Self, Vec<&'static str>) {
+        let colors = caps.color.is_some() || caps.color_temperature.is_some();
+        let mut unsupported = vec![];
+        if self.bri.is_some() && !caps.dimmable {
+            unsupported.push("bri");
+        }
+        if self.xy.is_some() && !colors {
+            unsupported.push("xy");
+        }
+        if self.ct.is_some() && !colors {
+            unsupported.push("ct");
+        }
+
+        let upd = Self {
+            on: self.on,
+            bri: self.bri.filter(|_| caps.dimmable),
+            xy: self.xy.filter(|_| colors),
+            ct: self.ct.filter(|_| colors),
+        };
+        (upd, unsupported)
+    }
+}
+
 impl From<api::SceneAction> for ApiLightStateUpdate {
     #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
     fn from(action: api::SceneAction) -> Self {
         Self {
             on: action.on.map(|on| on.on),
-            bri: action.dimming.map(|dim| (dim.brightness * 2.54) as u32),
+            bri: action
+                .dimming
+                .map(|dim| Brightness::from_percent(dim.brightness).v1()),
             xy: action.color.map(|col| col.xy.into()),
             ct: action.color_temperature.map(|ct| ct.mirek),
         }
@@ -389,14 +706,17 @@ pub struct ApiLight {
 }
 
 impl ApiLight {
+    #[must_use]
+    pub const fn with_reachable(mut self, reachable: bool) -> Self {
+        self.state.reachable = reachable;
+        self
+    }
+
     #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
     #[must_use]
     pub fn from_dev_and_light(uuid: &Uuid, dev: &api::Device, light: &api::Light) -> Self {
-        let colormode = if light.color.is_some() {
-            LightColorMode::Xy
-        } else {
-            LightColorMode::Ct
-        };
+        let caps = light.capabilities();
+        let colormode = LightColorMode::for_capabilities(&caps);
 
         let product_data = dev.product_data.clone();
 
@@ -405,7 +725,7 @@ impl ApiLight {
                 on: light.on.on,
                 bri: light
                     .dimming
-                    .map(|dim| (dim.brightness * 2.54) as u32)
+                    .map(|dim| Brightness::from_percent(dim.brightness).v1())
                     .unwrap_or_default(),
                 hue: 0,
                 sat: 0,
@@ -433,23 +753,10 @@ impl ApiLight {
             productid: product_data.model_id,
             capabilities: json!({
                 "certified": true,
-                "control": {
-                    "colorgamut": [
-                        [0.6915, 0.3083 ],
-                        [0.17,   0.7    ],
-                        [0.1532, 0.0475 ],
-                    ],
-                    "colorgamuttype": "C",
-                    "ct": {
-                        "max": 500,
-                        "min": 153
-                    },
-                    "maxlumen": 300,
-                    "mindimlevel": 200
-                },
+                "control": v1_control(&caps),
                 "streaming": {
-                    "proxy": true,
-                    "renderer": true
+                    "proxy": caps.streamable(),
+                    "renderer": caps.streamable()
                 }
             }),
             config: json!({
@@ -461,7 +768,7 @@ impl ApiLight {
                     "configured": true
                 }
             }),
-            light_type: "Extended color light".to_string(),
+            light_type: v1_light_type(&caps).to_string(),
             uniqueid: uuid.as_simple().to_string(),
             swversion: product_data.software_version,
             swconfigid: String::new(),
@@ -469,8 +776,65 @@ impl ApiLight {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApiResourceLinkType {
+    #[default]
+    Link,
+}
+
+/// A bundle of v1 resources, that apps use to keep track of the resources
+/// they created together (e.g. the rules and sensors of a formula)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ApiResourceLink {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(rename = "type", default)]
+    pub link_type: ApiResourceLinkType,
+    pub classid: u32,
+    pub owner: Uuid,
+    /// Delete this resourcelink once no other resourcelink refers to it
+    #[serde(default)]
+    pub recycle: bool,
+    /// Addresses of the linked resources (e.g. "/scenes/3")
+    #[serde(default)]
+    pub links: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ApiResourceLink {}
+pub struct NewResourceLink {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub classid: u32,
+    #[serde(default)]
+    pub recycle: bool,
+    #[serde(default)]
+    pub links: Vec<String>,
+}
+
+impl NewResourceLink {
+    #[must_use]
+    pub fn with_owner(self, owner: Uuid) -> ApiResourceLink {
+        ApiResourceLink {
+            name: self.name,
+            description: self.description,
+            link_type: ApiResourceLinkType::Link,
+            classid: self.classid,
+            owner,
+            recycle: self.recycle,
+            links: self.links,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ApiResourceLinkUpdate {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub classid: Option<u32>,
+    pub links: Option<Vec<String>>,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiRule {}
@@ -523,10 +887,11 @@ impl ApiScene {
             .actions
             .iter()
             .map(|sae| {
-                Ok((
-                    res.get_id_v1(sae.target.rid)?,
-                    ApiLightStateUpdate::from(sae.action.clone()),
-                ))
+                let mut state = ApiLightStateUpdate::from(sae.action.clone());
+                if let Ok(light) = res.get::<api::Light>(&sae.target) {
+                    state = state.for_light(&light.capabilities());
+                }
+                Ok((res.get_id_v1(sae.target.rid)?, state))
             })
             .collect::<ApiResult<_>>()?;
 
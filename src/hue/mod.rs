@@ -1,12 +1,56 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
 pub mod api;
+pub mod behavior_scripts;
 pub mod date_format;
 pub mod event;
 pub mod legacy_api;
 pub mod scene_icons;
+pub mod scene_presets;
 
 pub const HUE_BRIDGE_V2_MODEL_ID: &str = "BSB002";
 
+/* firmware emulated by bifrost. The datastore version goes up along with
+ * the firmware, so these have to be changed together (the reported datastore
+ * version also counts changes to the state, on top of this) */
+pub const HUE_BRIDGE_V2_API_VERSION: &str = "1.66.0";
+pub const HUE_BRIDGE_V2_SW_VERSION: &str = "1966060010";
+pub const HUE_BRIDGE_V2_DATASTORE_VERSION: u64 = 163;
+
 #[must_use]
 pub fn best_guess_timezone() -> String {
     iana_time_zone::get_timezone().unwrap_or_else(|_| "none".to_string())
 }
+
+/// Check a time zone name (e.g. `Europe/Copenhagen`) against the IANA time
+/// zone database of the system, found in `$TZDIR` or `/usr/share/zoneinfo`.
+///
+/// Systems without the database (e.g. minimal containers) only get the
+/// name checked for valid characters.
+#[must_use]
+pub fn is_valid_timezone(name: &str) -> bool {
+    let wellformed = name.split('/').all(|part| {
+        !part.is_empty()
+            && !part.starts_with('.')
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_+-".contains(c))
+    });
+    if !wellformed {
+        return false;
+    }
+
+    let dir = std::env::var_os("TZDIR")
+        .map_or_else(|| PathBuf::from("/usr/share/zoneinfo"), PathBuf::from);
+    if !dir.is_dir() {
+        return true;
+    }
+
+    /* zone files start with a magic marker, which rules out anything else
+     * found in the database directory */
+    let mut magic = [0; 4];
+    File::open(dir.join(name))
+        .is_ok_and(|mut file| file.read_exact(&mut magic).is_ok() && &magic == b"TZif")
+}
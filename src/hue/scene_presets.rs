@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::hue::api::{ColorTemperatureUpdate, ColorUpdate, DimmingUpdate, On, SceneAction};
+use crate::hue::scene_icons;
+use crate::model::types::XY;
+
+/// The standard scenes created by a real Hue bridge for new rooms
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScenePreset {
+    Bright,
+    Relax,
+    Read,
+    Concentrate,
+    Energize,
+    Dimmed,
+    Rest,
+    NightLight,
+}
+
+impl ScenePreset {
+    pub const ALL: [Self; 8] = [
+        Self::Bright,
+        Self::Relax,
+        Self::Read,
+        Self::Concentrate,
+        Self::Energize,
+        Self::Dimmed,
+        Self::Rest,
+        Self::NightLight,
+    ];
+
+    #[must_use]
+    pub const fn default_name(self) -> &'static str {
+        match self {
+            Self::Bright => "Bright",
+            Self::Relax => "Relax",
+            Self::Read => "Read",
+            Self::Concentrate => "Concentrate",
+            Self::Energize => "Energize",
+            Self::Dimmed => "Dimmed",
+            Self::Rest => "Rest",
+            Self::NightLight => "Nightlight",
+        }
+    }
+
+    #[must_use]
+    pub const fn icon(self) -> Uuid {
+        match self {
+            Self::Bright => scene_icons::BRIGHT,
+            Self::Relax => scene_icons::RELAX,
+            Self::Read => scene_icons::READ,
+            Self::Concentrate => scene_icons::CONCENTRATE,
+            Self::Energize => scene_icons::ENERGIZE,
+            Self::Dimmed => scene_icons::DIMMED,
+            Self::Rest => scene_icons::REST,
+            Self::NightLight => scene_icons::NIGHT_LIGHT,
+        }
+    }
+
+    /// Brightness (in percent) used by this preset
+    #[must_use]
+    pub const fn brightness(self) -> f64 {
+        match self {
+            Self::Bright | Self::Read | Self::Concentrate | Self::Energize => 100.0,
+            Self::Relax => 56.3,
+            Self::Dimmed => 30.3,
+            Self::Rest => 34.6,
+            Self::NightLight => 0.4,
+        }
+    }
+
+    /// Color temperature (in mirek) used by this preset, if any
+    #[must_use]
+    pub const fn mirek(self) -> Option<u32> {
+        match self {
+            Self::Bright | Self::Dimmed => Some(366),
+            Self::Relax | Self::Rest => Some(447),
+            Self::Read => Some(346),
+            Self::Concentrate => Some(233),
+            Self::Energize => Some(156),
+            Self::NightLight => None,
+        }
+    }
+
+    /// Color (in xy coordinates) used by this preset, if any
+    #[must_use]
+    pub const fn color_xy(self) -> Option<XY> {
+        match self {
+            Self::NightLight => Some(XY::new(0.5612, 0.4042)),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn action(self) -> SceneAction {
+        let color_temperature = match self.mirek() {
+            Some(mirek) => Some(ColorTemperatureUpdate::new(mirek)),
+            None => None,
+        };
+
+        let color = match self.color_xy() {
+            Some(xy) => Some(ColorUpdate::new(xy)),
+            None => None,
+        };
+
+        SceneAction {
+            color,
+            color_temperature,
+            dimming: Some(DimmingUpdate::new(self.brightness())),
+            on: Some(On::new(true)),
+        }
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+#[cfg(not(feature = "sqlite"))]
+use crate::error::ApiError;
+use crate::error::ApiResult;
+use crate::hue::api::RType;
+use crate::resource::Resources;
+use crate::storage;
+#[cfg(feature = "sqlite")]
+use crate::storage::sqlite::SqliteStore;
+
+/* Journal of resource changes, for finding out who changed what (and when),
+ * e.g. when a light keeps getting "overridden". Each change is appended to
+ * the journal file as a line of json, or as a row of the journal table, when
+ * the journal is a sqlite database (which may be the state database). */
+
+tokio::task_local! {
+    static SOURCE: Source;
+    static TRACE: TraceId;
+}
+
+/// Where a resource change came from
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    /// A hue (or admin) api client
+    Api,
+    /// A state update from zigbee2mqtt
+    Z2m,
+    /// Actions learned when recalling a scene
+    SceneLearn,
+    /// The virtual devices backend
+    Virtual,
+    /// The static lights backend
+    Static,
+    /// Scheduled settings from the config, and scheduled scene recalls
+    Scheduler,
+}
+
+impl Source {
+    const ALL: [Self; 6] = [
+        Self::Api,
+        Self::Z2m,
+        Self::SceneLearn,
+        Self::Virtual,
+        Self::Static,
+        Self::Scheduler,
+    ];
+
+    /// The source of changes made by the current task: [`Source::Api`],
+    /// unless running inside [`Source::scope`]
+    #[must_use]
+    pub fn current() -> Self {
+        SOURCE.try_with(|source| *source).unwrap_or(Self::Api)
+    }
+
+    /// Run `fut`, attributing any resource changes it makes to this source
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        SOURCE.scope(self, fut).await
+    }
+
+    /// Like [`Source::scope`], for synchronous code
+    pub fn sync_scope<R>(self, func: impl FnOnce() -> R) -> R {
+        SOURCE.sync_scope(self, func)
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Api => "api",
+            Self::Z2m => "z2m",
+            Self::SceneLearn => "scene-learn",
+            Self::Virtual => "virtual",
+            Self::Static => "static",
+            Self::Scheduler => "scheduler",
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|source| source.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|source| source.name()).collect();
+                format!("expected one of: {}", names.join(", "))
+            })
+    }
+}
+
+/// Identifies a single api request, so the z2m commands, resource changes
+/// and events it causes can be followed through the logs and the journal
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct TraceId(u64);
+
+#[allow(clippy::new_without_default)]
+impl TraceId {
+    #[must_use]
+    pub fn new() -> Self {
+        Self(rand::random())
+    }
+
+    /// The trace id of the api request the current task is working on, if any
+    #[must_use]
+    pub fn current() -> Option<Self> {
+        TRACE.try_with(|trace| *trace).ok()
+    }
+
+    /// Run `fut` on behalf of the api request with this trace id
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        TRACE.scope(self, fut).await
+    }
+
+    /// Like [`TraceId::scope`], with no trace id running `fut` as is
+    pub async fn scope_opt<F: Future>(trace: Option<Self>, fut: F) -> F::Output {
+        match trace {
+            Some(trace) => trace.scope(fut).await,
+            None => fut.await,
+        }
+    }
+}
+
+impl Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for TraceId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16)
+            .map(Self)
+            .map_err(|_| format!("invalid trace id {s:?}"))
+    }
+}
+
+impl From<TraceId> for String {
+    fn from(trace: TraceId) -> Self {
+        trace.to_string()
+    }
+}
+
+impl TryFrom<String> for TraceId {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Add,
+    Update,
+    Delete,
+}
+
+/// A single change to a resource.
+///
+/// For updates, `before` and `after` only hold the fields that changed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub time: DateTime<Utc>,
+    pub source: Source,
+    /// The api request that caused the change, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceId>,
+    pub op: Operation,
+    pub id: Uuid,
+    pub rtype: RType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<Value>,
+}
+
+impl JournalEntry {
+    #[must_use]
+    pub fn add(id: Uuid, rtype: RType, obj: Value) -> Self {
+        Self::new(Operation::Add, id, rtype, None, Some(obj))
+    }
+
+    /// Entry for an update, or `None` if nothing actually changed
+    #[must_use]
+    pub fn update(id: Uuid, rtype: RType, before: &Value, after: &Value) -> Option<Self> {
+        let (before, after) = diff(before, after)?;
+        Some(Self::new(
+            Operation::Update,
+            id,
+            rtype,
+            Some(before),
+            Some(after),
+        ))
+    }
+
+    #[must_use]
+    pub fn delete(id: Uuid, rtype: RType, obj: Value) -> Self {
+        Self::new(Operation::Delete, id, rtype, Some(obj), None)
+    }
+
+    fn new(
+        op: Operation,
+        id: Uuid,
+        rtype: RType,
+        before: Option<Value>,
+        after: Option<Value>,
+    ) -> Self {
+        Self {
+            time: Utc::now(),
+            source: Source::current(),
+            trace: TraceId::current(),
+            op,
+            id,
+            rtype,
+            before,
+            after,
+        }
+    }
+}
+
+/// The parts of `before` and `after` that differ, or `None` if they are equal.
+///
+/// Objects are compared field by field (recursively), anything else as a
+/// whole. Fields missing on one side show up as `null` there.
+#[must_use]
+pub fn diff(before: &Value, after: &Value) -> Option<(Value, Value)> {
+    if before == after {
+        return None;
+    }
+
+    let (Value::Object(old), Value::Object(new)) = (before, after) else {
+        return Some((before.clone(), after.clone()));
+    };
+
+    let mut old_delta = Map::new();
+    let mut new_delta = Map::new();
+
+    for key in old
+        .keys()
+        .chain(new.keys().filter(|key| !old.contains_key(*key)))
+    {
+        let a = old.get(key).unwrap_or(&Value::Null);
+        let b = new.get(key).unwrap_or(&Value::Null);
+        if let Some((a, b)) = diff(a, b) {
+            old_delta.insert(key.clone(), a);
+            new_delta.insert(key.clone(), b);
+        }
+    }
+
+    Some((Value::Object(old_delta), Value::Object(new_delta)))
+}
+
+/* apply the "after" side of a delta */
+fn merge(target: &mut Value, delta: &Value) {
+    match (target, delta) {
+        (Value::Object(target), Value::Object(delta)) => {
+            for (key, value) in delta {
+                merge(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (target, delta) => *target = delta.clone(),
+    }
+}
+
+enum Output {
+    File(LineWriter<File>),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteStore),
+}
+
+impl Output {
+    fn open(path: &Utf8Path) -> ApiResult<Self> {
+        if storage::is_sqlite(path) {
+            #[cfg(feature = "sqlite")]
+            return Ok(Self::Sqlite(SqliteStore::open(path)?));
+
+            #[cfg(not(feature = "sqlite"))]
+            return Err(ApiError::StorageUnsupported(path.to_path_buf()));
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::File(LineWriter::new(file)))
+    }
+
+    fn append(&mut self, entry: &JournalEntry) -> ApiResult<()> {
+        match self {
+            Self::File(out) => {
+                serde_json::to_writer(&mut *out, entry)?;
+                out.write_all(b"\n")?;
+            }
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(db) => db.append_journal(entry)?,
+        }
+        Ok(())
+    }
+}
+
+/// Append all resource changes to the journal at `path`
+pub async fn writer(res: Arc<Mutex<Resources>>, path: Utf8PathBuf) -> ApiResult<()> {
+    let mut out = Output::open(&path)?;
+
+    let mut rx = res.lock().await.journal_channel();
+    log::info!("Writing journal of resource changes to [{path}]");
+
+    while let Some(entry) = rx.recv().await {
+        out.append(&entry)?;
+    }
+
+    Ok(())
+}
+
+/// Read all entries from a journal, skipping lines of a journal file that
+/// cannot be parsed (e.g. a partial line written during a crash)
+pub fn read(path: &Utf8Path) -> ApiResult<Vec<JournalEntry>> {
+    if storage::is_sqlite(path) {
+        #[cfg(feature = "sqlite")]
+        return SqliteStore::open(path)?.read_journal();
+
+        #[cfg(not(feature = "sqlite"))]
+        return Err(ApiError::StorageUnsupported(path.to_path_buf()));
+    }
+
+    let mut entries = vec![];
+
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        match serde_json::from_str(&line?) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => eprintln!("{path}:{}: Skipping invalid entry: {err}", index + 1),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Selection of journal entries, for inspection
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    pub id: Option<Uuid>,
+    pub source: Option<Source>,
+    pub trace: Option<TraceId>,
+}
+
+impl Filter {
+    #[must_use]
+    pub fn matches(&self, entry: &JournalEntry) -> bool {
+        self.id.map_or(true, |id| id == entry.id)
+            && self.source.map_or(true, |source| source == entry.source)
+            && self.trace.map_or(true, |trace| Some(trace) == entry.trace)
+    }
+}
+
+/// Print the entries of a journal file, one per line
+pub fn inspect(path: &Utf8Path, filter: &Filter) -> ApiResult<()> {
+    for entry in read(path)?.iter().filter(|entry| filter.matches(entry)) {
+        let rtype = serde_json::to_value(entry.rtype)?;
+        let change = match entry.op {
+            Operation::Add => format!("added {}", entry.after.clone().unwrap_or_default()),
+            Operation::Update => format!(
+                "{} -> {}",
+                entry.before.clone().unwrap_or_default(),
+                entry.after.clone().unwrap_or_default()
+            ),
+            Operation::Delete => String::from("deleted"),
+        };
+
+        let trace = entry
+            .trace
+            .map_or_else(|| String::from("-"), |trace| trace.to_string());
+
+        println!(
+            "{}  {:<11} {trace:<16} {} {:<20} {change}",
+            entry.time.format("%Y-%m-%d %H:%M:%S%.3f"),
+            entry.source,
+            entry.id,
+            rtype.as_str().unwrap_or_default(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Rebuild resources by applying the journal entries in order.
+///
+/// Resources that existed before the journal was started only have the
+/// fields that were changed since.
+#[must_use]
+pub fn replay(entries: &[JournalEntry]) -> BTreeMap<Uuid, Value> {
+    let mut res = BTreeMap::new();
+
+    for entry in entries {
+        match entry.op {
+            Operation::Add | Operation::Update => {
+                let obj = res
+                    .entry(entry.id)
+                    .or_insert_with(|| Value::Object(Map::new()));
+                if let Some(after) = &entry.after {
+                    merge(obj, after);
+                }
+            }
+            Operation::Delete => {
+                res.remove(&entry.id);
+            }
+        }
+    }
+
+    res
+}
+
+/// Print the resources (matching `filter`) as rebuilt from a journal file
+pub fn print_replay(path: &Utf8Path, filter: &Filter) -> ApiResult<()> {
+    let entries: Vec<JournalEntry> = read(path)?
+        .into_iter()
+        .filter(|entry| filter.matches(entry))
+        .collect();
+
+    for (id, obj) in replay(&entries) {
+        println!("{id}: {}", serde_json::to_string_pretty(&obj)?);
+    }
+
+    Ok(())
+}
@@ -14,12 +14,21 @@
     clippy::module_name_repetitions
 )]
 
+pub mod circadian;
 pub mod config;
+pub mod doctor;
 pub mod error;
 pub mod hue;
+pub mod journal;
+pub mod logging;
 pub mod mdns;
 pub mod model;
 pub mod resource;
 pub mod routes;
+pub mod scheduler;
 pub mod server;
+pub mod statefile;
+pub mod static_lights;
+pub mod storage;
+pub mod virt;
 pub mod z2m;
@@ -0,0 +1,264 @@
+use std::io::Write;
+use std::sync::RwLock;
+
+use chrono::{SecondsFormat, Utc};
+use log::kv::{self, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use pretty_env_logger::env_logger::{self, filter};
+use serde_json::{Map, Value};
+
+use crate::error::{ApiError, ApiResult};
+use crate::journal::TraceId;
+
+/* Try to provide reasonable default filters, when RUST_LOG is not specified */
+const DEFAULT_LOG_FILTERS: &[&str] = &[
+    "debug",
+    "mdns_sd=off",
+    "tower_http::trace::on_request=info",
+    "h2=info",
+    "axum::rejection=trace",
+];
+
+/// Log filters in effect, and the ones bifrost was started with
+struct LogFilters {
+    initial: String,
+    current: String,
+    filter: filter::Filter,
+}
+
+static FILTERS: RwLock<Option<LogFilters>> = RwLock::new(None);
+
+/*
+ * Logger that applies the (runtime adjustable) filters in [`FILTERS`], before
+ * passing records on to the actual env_logger, which accepts everything.
+ */
+struct FilteredLogger {
+    inner: env_logger::Logger,
+    format: LogFormat,
+}
+
+/// The structured fields of a log record, with the trace id of the api
+/// request it was logged for
+struct WithTrace<'a> {
+    kvs: &'a dyn kv::Source,
+    trace: TraceId,
+}
+
+impl kv::Source for WithTrace<'_> {
+    fn visit<'kvs>(&'kvs self, visitor: &mut dyn VisitSource<'kvs>) -> Result<(), kv::Error> {
+        self.kvs.visit(visitor)?;
+        visitor.visit_pair(kv::Key::from("trace"), kv::Value::from_display(&self.trace))
+    }
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        FILTERS
+            .read()
+            .is_ok_and(|filters| filters.as_ref().is_some_and(|f| f.filter.enabled(metadata)))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let Some(trace) = TraceId::current() else {
+            self.inner.log(record);
+            return;
+        };
+
+        /* json output has the trace id as a field, the others in the message */
+        let kvs = WithTrace {
+            kvs: record.key_values(),
+            trace,
+        };
+        if self.format == LogFormat::Json {
+            self.inner
+                .log(&record.to_builder().key_values(&kvs).build());
+        } else {
+            self.inner.log(
+                &record
+                    .to_builder()
+                    .args(format_args!("{} [trace {trace}]", record.args()))
+                    .key_values(&kvs)
+                    .build(),
+            );
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/*
+ * Formatter function to output in syslog format. This makes sense when running
+ * as a service (where output might go to a log file, or the system journal)
+ */
+fn syslog_format(buf: &mut env_logger::fmt::Formatter, record: &Record) -> std::io::Result<()> {
+    writeln!(
+        buf,
+        "<{}>{}: {}",
+        match record.level() {
+            log::Level::Error => 3,
+            log::Level::Warn => 4,
+            log::Level::Info => 6,
+            log::Level::Debug | log::Level::Trace => 7,
+        },
+        record.target(),
+        record.args()
+    )
+}
+
+/// Collects the structured fields of a log record (like `server`, `topic`
+/// or `uuid`) into a json object
+struct JsonFields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = value
+            .to_bool()
+            .map(Value::from)
+            .or_else(|| value.to_u64().map(Value::from))
+            .or_else(|| value.to_i64().map(Value::from))
+            .unwrap_or_else(|| Value::from(value.to_string()));
+
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/*
+ * Formatter function to output one json object per line. This makes sense
+ * when shipping logs to a log aggregator (like Loki or Elasticsearch)
+ */
+fn json_format(buf: &mut env_logger::fmt::Formatter, record: &Record) -> std::io::Result<()> {
+    let mut obj = Map::new();
+    obj.insert(
+        "time".into(),
+        Utc::now()
+            .to_rfc3339_opts(SecondsFormat::Micros, true)
+            .into(),
+    );
+    obj.insert("level".into(), record.level().as_str().into());
+    obj.insert("module".into(), record.target().into());
+    obj.insert("message".into(), record.args().to_string().into());
+
+    let _ = record.key_values().visit(&mut JsonFields(&mut obj));
+
+    serde_json::to_writer(&mut *buf, &obj)?;
+    writeln!(buf)
+}
+
+/// Log output format, selected with the `BIFROST_LOG_FORMAT` environment
+/// variable (`pretty`, `syslog` or `json`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    Pretty,
+    Syslog,
+    Json,
+}
+
+impl LogFormat {
+    fn detect() -> Self {
+        match std::env::var("BIFROST_LOG_FORMAT").as_deref() {
+            Ok("json") => return Self::Json,
+            Ok("syslog") => return Self::Syslog,
+            Ok("pretty") => return Self::Pretty,
+            Ok(other) => eprintln!("Unknown BIFROST_LOG_FORMAT {other:?}, detecting instead"),
+            Err(_) => {}
+        }
+
+        /* Detect if we need syslog or human-readable formatting */
+        if std::env::var("SYSTEMD_EXEC_PID").is_ok_and(|pid| pid == std::process::id().to_string())
+        {
+            Self::Syslog
+        } else {
+            Self::Pretty
+        }
+    }
+}
+
+/// Check that every `module=level` directive names a valid level, since
+/// `env_logger` silently ignores the ones it cannot parse.
+fn validate_filters(filters: &str) -> ApiResult<()> {
+    let directives = filters.split('/').next().unwrap_or_default();
+
+    for directive in directives.split(',').map(str::trim) {
+        if let Some((_, level)) = directive.split_once('=') {
+            if level.parse::<LevelFilter>().is_err() {
+                return Err(ApiError::LogFilterInvalid(directive.to_string()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_filters(filters: &str) {
+    let filter = filter::Builder::new().parse(filters).build();
+    log::set_max_level(filter.filter());
+
+    if let Ok(mut lock) = FILTERS.write() {
+        let initial = lock
+            .as_ref()
+            .map_or_else(|| filters.to_string(), |f| f.initial.clone());
+
+        *lock = Some(LogFilters {
+            initial,
+            current: filters.to_string(),
+            filter,
+        });
+    }
+}
+
+pub fn init_logging() -> ApiResult<()> {
+    let log_filters = std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LOG_FILTERS.join(","));
+
+    let format = LogFormat::detect();
+    let mut builder = match format {
+        LogFormat::Pretty => pretty_env_logger::formatted_timed_builder(),
+        LogFormat::Syslog => {
+            let mut builder = env_logger::builder();
+            builder.format(syslog_format);
+            builder
+        }
+        LogFormat::Json => {
+            let mut builder = env_logger::builder();
+            builder.format(json_format);
+            builder
+        }
+    };
+
+    let inner = builder.filter_level(LevelFilter::Trace).build();
+
+    log::set_boxed_logger(Box::new(FilteredLogger { inner, format }))?;
+    apply_filters(&log_filters);
+
+    Ok(())
+}
+
+/// Returns the log filters in effect, and the ones set at startup
+#[must_use]
+pub fn log_filters() -> Option<(String, String)> {
+    FILTERS
+        .read()
+        .ok()?
+        .as_ref()
+        .map(|f| (f.current.clone(), f.initial.clone()))
+}
+
+/// Replace the log filters (in `RUST_LOG` syntax) until the next restart
+pub fn set_log_filters(filters: &str) -> ApiResult<()> {
+    validate_filters(filters)?;
+    apply_filters(filters);
+    Ok(())
+}
+
+/// Go back to the log filters set at startup
+pub fn reset_log_filters() {
+    if let Some((_, initial)) = log_filters() {
+        apply_filters(&initial);
+    }
+}
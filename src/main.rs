@@ -1,64 +1,29 @@
-use std::io::Write;
+use std::time::Duration;
 
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{Parser, Subcommand};
+use tokio::select;
 use tokio::task::JoinSet;
+use uuid::Uuid;
 
+use bifrost::circadian::Circadian;
 use bifrost::config;
-use bifrost::error::ApiResult;
+use bifrost::doctor;
+use bifrost::error::{ApiError, ApiResult};
+use bifrost::journal;
+use bifrost::logging::init_logging;
 use bifrost::mdns;
-use bifrost::server::{self, appstate::AppState, banner};
+use bifrost::scheduler::Scheduler;
+use bifrost::server::replication::{self, Follower};
+use bifrost::server::{self, appstate::AppState, banner, ha::LeaderLock, netwatch};
+use bifrost::statefile;
+use bifrost::static_lights::StaticLights;
+use bifrost::virt;
 use bifrost::z2m;
 
-/*
- * Formatter function to output in syslog format. This makes sense when running
- * as a service (where output might go to a log file, or the system journal)
- */
-fn syslog_format(
-    buf: &mut pretty_env_logger::env_logger::fmt::Formatter,
-    record: &log::Record,
-) -> std::io::Result<()> {
-    writeln!(
-        buf,
-        "<{}>{}: {}",
-        match record.level() {
-            log::Level::Error => 3,
-            log::Level::Warn => 4,
-            log::Level::Info => 6,
-            log::Level::Debug => 7,
-            log::Level::Trace => 7,
-        },
-        record.target(),
-        record.args()
-    )
-}
-
-fn init_logging() -> ApiResult<()> {
-    /* Try to provide reasonable default filters, when RUST_LOG is not specified */
-    const DEFAULT_LOG_FILTERS: &[&str] = &[
-        "debug",
-        "mdns_sd=off",
-        "tower_http::trace::on_request=info",
-        "h2=info",
-        "axum::rejection=trace",
-    ];
-
-    let log_filters = std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LOG_FILTERS.join(","));
-
-    /* Detect if we need syslog or human-readable formatting */
-    if std::env::var("SYSTEMD_EXEC_PID").is_ok_and(|pid| pid == std::process::id().to_string()) {
-        Ok(pretty_env_logger::env_logger::builder()
-            .format(syslog_format)
-            .parse_filters(&log_filters)
-            .try_init()?)
-    } else {
-        Ok(pretty_env_logger::formatted_timed_builder()
-            .parse_filters(&log_filters)
-            .try_init()?)
-    }
-}
-
 async fn build_tasks(appstate: AppState) -> ApiResult<JoinSet<ApiResult<()>>> {
     let bconf = &appstate.config().bridge;
-    let _mdns = mdns::register_mdns(bconf.mac, bconf.ipaddress);
+    let mdns = mdns::register_mdns(bconf.mac, &bconf.addresses());
 
     let mut tasks = JoinSet::new();
 
@@ -67,20 +32,38 @@ async fn build_tasks(appstate: AppState) -> ApiResult<JoinSet<ApiResult<()>>> {
     log::info!("Serving mac [{}]", bconf.mac);
 
     let tls_config = appstate.tls_config().await?;
-    let state_file = appstate.config().bifrost.state_file.clone();
 
-    tasks.spawn(server::http_server(
-        bconf.ipaddress,
-        bconf.http_port,
-        svc.clone(),
-    ));
-    tasks.spawn(server::https_server(
-        bconf.ipaddress,
-        bconf.https_port,
-        svc,
-        tls_config,
+    for addr in bconf.listen_addresses() {
+        if bconf.single_port {
+            let mut ports = vec![bconf.http_port, bconf.https_port];
+            ports.dedup();
+            for port in ports {
+                tasks.spawn(server::single_port_server(
+                    addr,
+                    port,
+                    svc.clone(),
+                    tls_config.clone(),
+                ));
+            }
+            continue;
+        }
+
+        tasks.spawn(server::http_server(addr, bconf.http_port, svc.clone()));
+        tasks.spawn(server::https_server(
+            addr,
+            bconf.https_port,
+            svc.clone(),
+            tls_config.clone(),
+        ));
+    }
+    tasks.spawn(server::config_writer(
+        appstate.res.clone(),
+        appstate.config(),
     ));
-    tasks.spawn(server::config_writer(appstate.res.clone(), state_file));
+
+    if let Some(path) = &appstate.config().bifrost.journal_file {
+        tasks.spawn(journal::writer(appstate.res.clone(), path.clone()));
+    }
 
     for (name, server) in &appstate.config().z2m.servers {
         let client = z2m::Client::new(
@@ -92,25 +75,210 @@ async fn build_tasks(appstate: AppState) -> ApiResult<JoinSet<ApiResult<()>>> {
         tasks.spawn(client.run_forever());
     }
 
+    if let Some(conf) = &appstate.config().virtual_devices {
+        let client = virt::VirtualClient::new(conf.clone(), appstate.res.clone());
+        tasks.spawn(client.run_forever());
+    }
+
+    /* always started, so lights removed from the config are cleaned up */
+    let lights = StaticLights::new(
+        appstate.config().static_lights.clone(),
+        appstate.res.clone(),
+    );
+    tasks.spawn(lights.run_forever());
+
+    let circadian = Circadian::new(appstate.config().circadian.clone(), appstate.res.clone());
+    tasks.spawn(circadian.run_forever());
+
+    let scheduler = Scheduler::new(
+        appstate.config().motion_sensors.clone(),
+        appstate.res.clone(),
+    );
+    tasks.spawn(scheduler.run_forever());
+
+    #[cfg(feature = "server-banner")]
+    tasks.spawn(banner::diagnostics(appstate.clone()));
+
+    if let Some(interface) = &bconf.interface {
+        tasks.spawn(netwatch::interface_watcher(
+            appstate.clone(),
+            interface.clone(),
+            mdns.ok(),
+        ));
+    }
+
     Ok(tasks)
 }
 
-async fn run() -> ApiResult<()> {
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Configuration file [default: config.yaml, or /etc/bifrost/config.yaml
+    /// if there is no config.yaml]
+    #[arg(short, long, env = "BIFROST_CONFIG")]
+    config: Option<Utf8PathBuf>,
+
+    /// State file [default: bifrost.state_file from the configuration file]
+    #[arg(short, long, env = "BIFROST_STATE")]
+    state: Option<Utf8PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the bridge (default)
+    Serve,
+
+    /// Check configuration and environment for common problems
+    Doctor,
+
+    /// Validate the configuration file only (exits non-zero on errors)
+    CheckConfig,
+
+    /// Inspect or repair the state file (while bifrost is not running)
+    State {
+        /// State file (default: the one set in the configuration file)
+        #[arg(short, long)]
+        file: Option<Utf8PathBuf>,
+
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+
+    /// Show the journal of resource changes
+    Journal {
+        /// Journal file (default: the one set in the configuration file)
+        #[arg(short, long)]
+        file: Option<Utf8PathBuf>,
+
+        /// Only show changes to this resource
+        #[arg(long)]
+        id: Option<Uuid>,
+
+        /// Only show changes from this source (api, z2m, scene-learn, virtual,
+        /// static, scheduler)
+        #[arg(long)]
+        source: Option<journal::Source>,
+
+        /// Only show changes caused by the api request with this trace id
+        #[arg(long)]
+        trace: Option<journal::TraceId>,
+
+        /// Print the resources as rebuilt from the journal, instead of the
+        /// changes
+        #[arg(long)]
+        replay: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum StateCommand {
+    /// List all resources
+    List,
+
+    /// Show a resource
+    Get { uuid: Uuid },
+
+    /// Delete a resource (a backup of the state file is kept)
+    Delete { uuid: Uuid },
+}
+
+fn state_command(
+    config_file: &Utf8PathBuf,
+    state_file: Option<Utf8PathBuf>,
+    command: &StateCommand,
+) -> ApiResult<()> {
+    let path = match state_file {
+        Some(file) => file,
+        None => config::parse(config_file)?.bifrost.state_file,
+    };
+
+    match command {
+        StateCommand::List => statefile::list(&path),
+        StateCommand::Get { uuid } => statefile::get(&path, uuid),
+        StateCommand::Delete { uuid } => statefile::delete(&path, uuid),
+    }
+}
+
+fn journal_command(
+    config_file: &Utf8PathBuf,
+    file: Option<Utf8PathBuf>,
+    filter: &journal::Filter,
+    replay: bool,
+) -> ApiResult<()> {
+    let path = match file {
+        Some(file) => file,
+        None => config::parse(config_file)?
+            .bifrost
+            .journal_file
+            .ok_or_else(|| ApiError::JournalNotConfigured(config_file.clone()))?,
+    };
+
+    if replay {
+        journal::print_replay(&path, filter)
+    } else {
+        journal::inspect(&path, filter)
+    }
+}
+
+async fn serve(config_file: &Utf8PathBuf, state_file: Option<&Utf8Path>) -> ApiResult<()> {
     init_logging()?;
 
     #[cfg(feature = "server-banner")]
     banner::print()?;
 
-    let config = config::parse("config.yaml".into())?;
+    let mut config = config::load(config_file, state_file)?;
     log::debug!("Configuration loaded successfully");
 
+    if let Some(interface) = &config.bridge.interface {
+        match netwatch::interface_ipv4(interface) {
+            Some(ip) => {
+                log::info!("Using address {ip} of interface [{interface}]");
+                config.bridge.ipaddress = ip;
+            }
+            None => log::warn!(
+                "Interface [{interface}] has no ipv4 address, using {}",
+                config.bridge.ipaddress
+            ),
+        }
+    }
+
+    /* in high availability mode, wait until this instance is active, while
+     * keeping a copy of the state of the active instance */
+    let leader = match &config.ha {
+        Some(ha) => {
+            let mut lock = LeaderLock::new(ha);
+            let follower = Follower::new(ha, &config.bifrost.state_file);
+            select! {
+                res = lock.acquire() => res?,
+                res = follower.run() => return res,
+            };
+            follower.finish().await;
+            Some(lock)
+        }
+        None => None,
+    };
+
     let appstate = AppState::from_config(config)?;
+    let res = appstate.res.clone();
+    let conf = appstate.config();
 
     let mut tasks = build_tasks(appstate).await?;
 
+    if let (Some(lock), Some(ha)) = (leader, &conf.ha) {
+        if let Some(listen) = ha.listen {
+            let heartbeat = Duration::from_secs(ha.heartbeat_secs);
+            tasks.spawn(replication::serve(listen, res, lock.token(), heartbeat));
+        }
+        tasks.spawn(lock.hold());
+    }
+
     loop {
         match tasks.join_next().await {
             None => break Ok(()),
+            Some(Ok(Err(err @ ApiError::LeadershipLost(_)))) => break Err(err),
             Some(Ok(Ok(res))) => log::info!("Worker returned: {res:?}"),
             Some(Ok(Err(res))) => log::error!("Worked task failed: {res:?}"),
             Some(Err(err)) => log::error!("Error spawning from worker: {err:?}"),
@@ -120,8 +288,46 @@ async fn run() -> ApiResult<()> {
 
 #[tokio::main]
 async fn main() {
-    if let Err(err) = run().await {
-        log::error!("Bifrost error: {err}");
-        log::error!("Fatal error encountered, cannot continue.");
+    let args = Args::parse();
+    let config_file = args.config.unwrap_or_else(config::default_file);
+    let state_file = args.state.as_deref();
+
+    match args.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            if let Err(err) = serve(&config_file, state_file).await {
+                log::error!("Bifrost error: {err}");
+                log::error!("Fatal error encountered, cannot continue.");
+            }
+        }
+        Command::Doctor => {
+            if !doctor::run(&config_file, state_file).await {
+                std::process::exit(1);
+            }
+        }
+        Command::CheckConfig => {
+            if !doctor::check_config(&config_file, state_file) {
+                std::process::exit(1);
+            }
+        }
+        Command::State { file, command } => {
+            let file = file.or_else(|| args.state.clone());
+            if let Err(err) = state_command(&config_file, file, &command) {
+                eprintln!("Error: {err}");
+                std::process::exit(1);
+            }
+        }
+        Command::Journal {
+            file,
+            id,
+            source,
+            trace,
+            replay,
+        } => {
+            let filter = journal::Filter { id, source, trace };
+            if let Err(err) = journal_command(&config_file, file, &filter, replay) {
+                eprintln!("Error: {err}");
+                std::process::exit(1);
+            }
+        }
     }
 }
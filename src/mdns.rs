@@ -1,4 +1,4 @@
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 
 use mac_address::MacAddress;
 use mdns_sd::{ServiceDaemon, ServiceInfo};
@@ -7,7 +7,7 @@ use crate::error::ApiResult;
 use crate::hue;
 use crate::server::certificate;
 
-pub fn register_mdns(mac: MacAddress, ip: Ipv4Addr) -> ApiResult<ServiceDaemon> {
+pub fn register_mdns(mac: MacAddress, addrs: &[IpAddr]) -> ApiResult<ServiceDaemon> {
     /* Create a new mDNS daemon. */
     let mdns = ServiceDaemon::new()?;
     let service_type = "_hue._tcp.local.";
@@ -19,7 +19,12 @@ pub fn register_mdns(mac: MacAddress, ip: Ipv4Addr) -> ApiResult<ServiceDaemon>
     );
 
     let service_hostname = format!("{instance_name}.{service_type}");
-    let service_addr = ip.to_string();
+    /* one A or AAAA record per address */
+    let service_addr = addrs
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
     let service_port = 80;
 
     let properties = [
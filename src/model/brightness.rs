@@ -0,0 +1,177 @@
+/// Brightness, independent of the scale used to express it.
+///
+/// Different apis use different scales for brightness:
+///
+///  - Hue v2 api: percentage, `0.0..=100.0`
+///  - Hue v1 api: integer, `1..=254`
+///  - zigbee2mqtt: integer, `0..=254` (although 0 is not useful for lights)
+///
+/// All conversions between these scales must go through this type, so that
+/// rounding is consistent everywhere. Otherwise, a value written through one
+/// api might be reported back slightly differently by another.
+#[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct Brightness(f64);
+
+impl Brightness {
+    /// Largest value on the v1 and zigbee2mqtt scales
+    pub const MAX_RAW: f64 = 254.0;
+
+    /// Smallest value on the v1 and zigbee2mqtt scales, that is still "on"
+    pub const MIN_RAW: f64 = 1.0;
+
+    pub const MIN: Self = Self(0.0);
+    pub const MAX: Self = Self(1.0);
+
+    /// Brightness from a percentage (hue v2 api)
+    #[must_use]
+    pub fn from_percent(percent: f64) -> Self {
+        Self((percent / 100.0).clamp(0.0, 1.0))
+    }
+
+    /// Brightness from a zigbee2mqtt value
+    #[must_use]
+    pub fn from_z2m(value: f64) -> Self {
+        Self((value / Self::MAX_RAW).clamp(0.0, 1.0))
+    }
+
+    /// Brightness from a hue v1 api value
+    #[must_use]
+    pub fn from_v1(value: u32) -> Self {
+        Self::from_z2m(f64::from(value))
+    }
+
+    /// Brightness as a percentage (hue v2 api)
+    #[must_use]
+    pub fn percent(self) -> f64 {
+        self.0 * 100.0
+    }
+
+    /// Brightness as a zigbee2mqtt value
+    ///
+    /// The result is rounded to a whole number, and never less than
+    /// [`Self::MIN_RAW`], since a brightness of 0 is ambiguous for lights
+    /// that are on.
+    #[must_use]
+    pub fn z2m(self) -> f64 {
+        (self.0 * Self::MAX_RAW)
+            .round()
+            .clamp(Self::MIN_RAW, Self::MAX_RAW)
+    }
+
+    /// Brightness as a hue v1 api value
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn v1(self) -> u32 {
+        self.z2m() as u32
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::Brightness;
+
+    const RAW_STEP: f64 = 100.0 / Brightness::MAX_RAW;
+
+    #[test]
+    fn v1_roundtrip() {
+        for bri in 1..=254 {
+            assert_eq!(Brightness::from_v1(bri).v1(), bri);
+        }
+    }
+
+    #[test]
+    fn z2m_roundtrip() {
+        for raw in 1..=254 {
+            let raw = f64::from(raw);
+            assert_eq!(Brightness::from_z2m(raw).z2m(), raw);
+        }
+    }
+
+    #[test]
+    fn v1_z2m_agree() {
+        for bri in 1..=254 {
+            assert_eq!(
+                Brightness::from_v1(bri).z2m(),
+                f64::from(bri),
+                "v1 and z2m scales disagree at {bri}"
+            );
+        }
+    }
+
+    #[test]
+    fn raw_percent_raw_roundtrip() {
+        for raw in 1..=254 {
+            let raw = f64::from(raw);
+            let percent = Brightness::from_z2m(raw).percent();
+            assert_eq!(Brightness::from_percent(percent).z2m(), raw);
+        }
+    }
+
+    #[test]
+    fn percent_roundtrip_within_one_step() {
+        for tenths in 4..=1000 {
+            let percent = f64::from(tenths) / 10.0;
+            let back = Brightness::from_z2m(Brightness::from_percent(percent).z2m()).percent();
+            assert!(
+                (back - percent).abs() <= RAW_STEP / 2.0 + f64::EPSILON,
+                "{percent}% came back as {back}%"
+            );
+        }
+    }
+
+    #[test]
+    fn percent_to_raw_is_monotonic() {
+        let mut last = 0.0;
+        for tenths in 0..=1000 {
+            let raw = Brightness::from_percent(f64::from(tenths) / 10.0).z2m();
+            assert!(raw >= last, "{raw} < {last} at {tenths}");
+            last = raw;
+        }
+    }
+
+    #[test]
+    fn endpoints() {
+        assert_eq!(Brightness::from_percent(100.0).z2m(), 254.0);
+        assert_eq!(Brightness::from_percent(100.0).v1(), 254);
+        assert_eq!(Brightness::from_v1(254).percent(), 100.0);
+        assert_eq!(Brightness::from_z2m(254.0).percent(), 100.0);
+        assert_eq!(Brightness::from_z2m(0.0).percent(), 0.0);
+        assert_eq!(Brightness::MAX.percent(), 100.0);
+        assert_eq!(Brightness::MIN.percent(), 0.0);
+    }
+
+    #[test]
+    fn midpoint() {
+        assert_eq!(Brightness::from_percent(50.0).z2m(), 127.0);
+        assert_eq!(Brightness::from_v1(127).percent(), 50.0);
+    }
+
+    #[test]
+    fn zero_is_minimum_on() {
+        assert_eq!(Brightness::from_percent(0.0).z2m(), 1.0);
+        assert_eq!(Brightness::from_percent(0.0).v1(), 1);
+        assert_eq!(Brightness::from_z2m(0.0).z2m(), 1.0);
+        assert_eq!(Brightness::from_v1(0).v1(), 1);
+    }
+
+    #[test]
+    fn out_of_range_is_clamped() {
+        assert_eq!(Brightness::from_percent(-10.0), Brightness::MIN);
+        assert_eq!(Brightness::from_percent(150.0), Brightness::MAX);
+        assert_eq!(Brightness::from_z2m(-1.0), Brightness::MIN);
+        assert_eq!(Brightness::from_z2m(255.0), Brightness::MAX);
+        assert_eq!(Brightness::from_v1(1000), Brightness::MAX);
+        assert_eq!(Brightness::from_percent(f64::MAX).v1(), 254);
+    }
+
+    #[test]
+    fn rounding_is_nearest() {
+        /* 50.2% is 127.508 raw */
+        assert_eq!(Brightness::from_percent(50.2).z2m(), 128.0);
+        /* 50.1% is 127.254 raw */
+        assert_eq!(Brightness::from_percent(50.1).z2m(), 127.0);
+        /* legacy code truncated, turning 99.9% into 253 */
+        assert_eq!(Brightness::from_percent(99.9).v1(), 254);
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::hue::api::{ColorTemperatureUpdate, ColorUpdate, Light};
+use crate::model::types::XY;
+
+/* sums are kept in fixed point, so taking a light out of a total exactly
+ * undoes adding it, however often that happens */
+const SCALE: f64 = 1e12;
+
+#[allow(clippy::cast_possible_truncation)]
+fn fixed(value: f64) -> i128 {
+    (value * SCALE).round() as i128
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn float(value: i128) -> f64 {
+    value as f64 / SCALE
+}
+
+/// What a single light adds to the combined state of the groups it is in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LightShare {
+    on: bool,
+    brightness: Option<i128>,
+    /* u', v' and weight, when showing a color */
+    color: Option<(i128, i128, i128)>,
+    /* mirek and weight, when showing a color temperature */
+    mirek: Option<(i128, i128)>,
+}
+
+impl LightShare {
+    /// Lights that are on are weighted by their brightness (lights without
+    /// dimming count as fully bright)
+    #[must_use]
+    pub fn new(light: &Light) -> Self {
+        if !light.on.on {
+            return Self::default();
+        }
+
+        let brightness = light.dimming.map(|dim| dim.brightness);
+        let weight = brightness.unwrap_or(100.0);
+        let color = light
+            .as_color_opt()
+            .filter(|_| light.as_mirek_opt().is_none() && weight > 0.0)
+            .and_then(XY::to_uv)
+            .map(|(u, v)| (fixed(weight * u), fixed(weight * v), fixed(weight)));
+        let mirek = light
+            .as_mirek_opt()
+            .filter(|_| weight > 0.0)
+            .map(|mirek| (fixed(weight * f64::from(mirek)), fixed(weight)));
+
+        Self {
+            on: true,
+            brightness: brightness.map(fixed),
+            color,
+            mirek,
+        }
+    }
+}
+
+/// Combined state of the lights in a group, kept up to date one light at a
+/// time, so a change to one light never needs a look at all the others.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GroupTotals {
+    on: u32,
+    levels: u32,
+    brightness: i128,
+    color: (i128, i128, i128),
+    mirek: (i128, i128),
+}
+
+impl GroupTotals {
+    pub fn add(&mut self, share: &LightShare) {
+        self.on += u32::from(share.on);
+        if let Some(brightness) = share.brightness {
+            self.levels += 1;
+            self.brightness += brightness;
+        }
+        if let Some((u, v, weight)) = share.color {
+            self.color.0 += u;
+            self.color.1 += v;
+            self.color.2 += weight;
+        }
+        if let Some((mirek, weight)) = share.mirek {
+            self.mirek.0 += mirek;
+            self.mirek.1 += weight;
+        }
+    }
+
+    pub fn remove(&mut self, share: &LightShare) {
+        self.on -= u32::from(share.on);
+        if let Some(brightness) = share.brightness {
+            self.levels -= 1;
+            self.brightness -= brightness;
+        }
+        if let Some((u, v, weight)) = share.color {
+            self.color.0 -= u;
+            self.color.1 -= v;
+            self.color.2 -= weight;
+        }
+        if let Some((mirek, weight)) = share.mirek {
+            self.mirek.0 -= mirek;
+            self.mirek.1 -= weight;
+        }
+    }
+
+    /// Any light in the group is on
+    #[must_use]
+    pub const fn any_on(&self) -> bool {
+        self.on > 0
+    }
+
+    /// Average brightness of the lights that are on
+    #[must_use]
+    pub fn brightness(&self) -> Option<f64> {
+        (self.levels > 0).then(|| float(self.brightness) / f64::from(self.levels))
+    }
+
+    /// The combined color of the lights that are on: their average color
+    /// (weighted by brightness) if any of them shows a color, or otherwise
+    /// their average color temperature
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    #[must_use]
+    pub fn color(&self) -> Option<(Option<ColorUpdate>, Option<ColorTemperatureUpdate>)> {
+        let (u, v, weight) = self.color;
+        if weight > 0 {
+            let xy = XY::from_uv(u as f64 / weight as f64, v as f64 / weight as f64);
+            return Some((Some(ColorUpdate::new(xy)), None));
+        }
+
+        let (mirek, weight) = self.mirek;
+        if weight > 0 {
+            let mirek = (mirek as f64 / weight as f64).round() as u32;
+            return Some((None, Some(ColorTemperatureUpdate::new(mirek))));
+        }
+
+        None
+    }
+}
+
+/// Which grouped lights (of rooms, zones and the bridge home) each light
+/// belongs to, and the combined state of each of them
+#[derive(Clone, Debug, Default)]
+pub struct GroupIndex {
+    groups: HashMap<Uuid, Vec<Uuid>>,
+    shares: HashMap<Uuid, LightShare>,
+    totals: HashMap<Uuid, GroupTotals>,
+    home: Option<Uuid>,
+}
+
+impl GroupIndex {
+    /// Add a grouped light, with the lights it controls
+    pub fn add_group(
+        &mut self,
+        glight: Uuid,
+        lights: impl IntoIterator<Item = (Uuid, LightShare)>,
+    ) {
+        let totals = self.totals.entry(glight).or_default();
+        for (light, share) in lights {
+            totals.add(&share);
+            self.shares.insert(light, share);
+            self.groups.entry(light).or_default().push(glight);
+        }
+    }
+
+    /// Mark a grouped light as the one of the bridge home
+    pub fn set_home(&mut self, glight: Uuid) {
+        self.home = Some(glight);
+    }
+
+    #[must_use]
+    pub fn is_home(&self, glight: &Uuid) -> bool {
+        self.home.as_ref() == Some(glight)
+    }
+
+    #[must_use]
+    pub fn totals(&self, glight: &Uuid) -> Option<&GroupTotals> {
+        self.totals.get(glight)
+    }
+
+    /// Replace the share of a light, and return the grouped lights affected
+    pub fn update_light(&mut self, light: Uuid, share: LightShare) -> Vec<Uuid> {
+        let old = self.shares.insert(light, share).unwrap_or_default();
+        let groups = self.groups.get(&light).cloned().unwrap_or_default();
+        for glight in &groups {
+            if let Some(totals) = self.totals.get_mut(glight) {
+                totals.remove(&old);
+                totals.add(&share);
+            }
+        }
+        groups
+    }
+}
@@ -1,2 +1,5 @@
+pub mod brightness;
+pub mod group_totals;
 pub mod state;
+pub mod sun;
 pub mod types;
@@ -1,18 +1,51 @@
-use std::{collections::BTreeMap, io::Read};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
 
+use camino::Utf8Path;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use serde::{Deserialize, Serialize};
 use serde_yml::Value;
 use uuid::Uuid;
 
 use crate::{
+    config::LightDefaultsConfig,
     error::{ApiError, ApiResult},
-    hue::api::{Resource, ResourceLink},
+    hue::api::{Resource, ResourceLink, SceneActionElement},
+    hue::legacy_api::{ApiResourceLink, Whitelist},
+    model::sun::Coordinates,
+    scheduler::SceneSchedule,
 };
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct AuxData {
     pub topic: Option<String>,
     pub index: Option<u32>,
+    /// Scene actions learned from observing a scene recall
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actions: Option<Vec<SceneActionElement>>,
+    /// Room membership changed since the actions were learned, so they
+    /// should be learned again on the next scene recall
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub relearn: bool,
+    /// Location set through the geolocation resource, which the hue api
+    /// never reports back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Coordinates>,
+    /// Name of the z2m server the resource was announced by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// Light defaults from the config that were pushed to the device already
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defaults: Option<LightDefaultsConfig>,
+    /// Name generated from the z2m friendly name, to tell whether the
+    /// resource was renamed through the api since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Scene appdata, stored separately for each application key
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub appdata: BTreeMap<String, String>,
 }
 
 impl AuxData {
@@ -36,6 +69,40 @@ impl AuxData {
             ..self
         }
     }
+
+    #[must_use]
+    pub fn with_actions(self, actions: Option<Vec<SceneActionElement>>) -> Self {
+        Self { actions, ..self }
+    }
+
+    #[must_use]
+    pub fn with_relearn(self, relearn: bool) -> Self {
+        Self { relearn, ..self }
+    }
+
+    #[must_use]
+    pub fn with_location(self, location: Option<Coordinates>) -> Self {
+        Self { location, ..self }
+    }
+
+    #[must_use]
+    pub fn with_server(self, server: &str) -> Self {
+        Self {
+            server: Some(server.to_string()),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_defaults(self, defaults: Option<LightDefaultsConfig>) -> Self {
+        Self { defaults, ..self }
+    }
+
+    #[must_use]
+    pub fn with_appdata(mut self, user: &str, appdata: String) -> Self {
+        self.appdata.insert(user.to_string(), appdata);
+        self
+    }
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -44,6 +111,9 @@ pub struct IdMap {
     reverse: BTreeMap<u32, Uuid>,
     #[serde(skip)]
     next_id: u32,
+    /// Next id to allocate, when ids must never be reused (persisted)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    next_stable_id: Option<u32>,
 }
 
 impl IdMap {
@@ -52,7 +122,30 @@ impl IdMap {
         Self::default()
     }
 
+    /// Enable or disable stable ids.
+    ///
+    /// With stable ids, an id is never handed out again after being freed,
+    /// even across restarts. This matches the behavior of a real bridge, where
+    /// clients can rely on ids not suddenly referring to another resource.
+    pub fn set_stable(&mut self, stable: bool) {
+        if !stable {
+            self.next_stable_id = None;
+        } else if self.next_stable_id.is_none() {
+            let next = self.reverse.keys().next_back().map_or(0, |id| id + 1);
+            self.next_stable_id = Some(next);
+        }
+    }
+
     fn find_next_id(&mut self) -> u32 {
+        if let Some(next) = &mut self.next_stable_id {
+            while self.reverse.contains_key(next) {
+                *next += 1;
+            }
+            let id = *next;
+            *next += 1;
+            return id;
+        }
+
         while self.reverse.contains_key(&self.next_id) {
             self.next_id += 1;
         }
@@ -99,12 +192,57 @@ pub enum StateVersion {
     V1 = 1,
 }
 
+/// On-disk format of the state file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StateFormat {
+    #[default]
+    Yaml,
+    Json,
+    JsonGz,
+}
+
+impl StateFormat {
+    const GZIP_MAGIC: &'static [u8] = &[0x1f, 0x8b];
+
+    /// Format to write, chosen by file extension (`.json`, `.json.gz`, and
+    /// yaml for anything else)
+    #[must_use]
+    pub fn from_path(path: &Utf8Path) -> Self {
+        match path.extension() {
+            Some("gz") => Self::JsonGz,
+            Some("json") => Self::Json,
+            _ => Self::Yaml,
+        }
+    }
+
+    /// Format of serialized state, regardless of file name
+    #[must_use]
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(Self::GZIP_MAGIC) {
+            Self::JsonGz
+        } else if data.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+            Self::Json
+        } else {
+            Self::Yaml
+        }
+    }
+}
+
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct State {
     version: StateVersion,
     aux: BTreeMap<Uuid, AuxData>,
     id_v1: IdMap,
     pub res: BTreeMap<Uuid, Resource>,
+    /// v1 resourcelinks, which have no v2 counterpart
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resourcelinks: BTreeMap<u32, ApiResourceLink>,
+    /// Scene recalls scheduled through the admin api
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scene_schedules: BTreeMap<Uuid, SceneSchedule>,
+    /// v1 users created by pairing, by username
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub users: BTreeMap<Uuid, Whitelist>,
 }
 
 impl State {
@@ -157,6 +295,9 @@ impl State {
             aux,
             id_v1,
             res,
+            resourcelinks: BTreeMap::new(),
+            scene_schedules: BTreeMap::new(),
+            users: BTreeMap::new(),
         })
     }
 
@@ -172,6 +313,67 @@ impl State {
         }
     }
 
+    /// Load state in any supported format (older versions only exist as yaml)
+    pub fn from_slice(data: &[u8]) -> ApiResult<Self> {
+        match StateFormat::detect(data) {
+            StateFormat::Yaml => Self::from_reader(data),
+            StateFormat::Json => Ok(serde_json::from_slice(data)?),
+            StateFormat::JsonGz => {
+                let mut json = vec![];
+                GzDecoder::new(data).read_to_end(&mut json)?;
+                Ok(serde_json::from_slice(&json)?)
+            }
+        }
+    }
+
+    pub fn to_vec(&self, format: StateFormat) -> ApiResult<Vec<u8>> {
+        match format {
+            StateFormat::Yaml => Ok(serde_yml::to_string(self)?.into_bytes()),
+            StateFormat::Json => Ok(serde_json::to_vec_pretty(self)?),
+            StateFormat::JsonGz => {
+                let mut enc = GzEncoder::new(vec![], Compression::fast());
+                enc.write_all(&serde_json::to_vec(self)?)?;
+                Ok(enc.finish()?)
+            }
+        }
+    }
+
+    /// Assemble (current version) state from its parts, as stored by
+    /// backends that do not keep the state as a single document
+    #[must_use]
+    pub const fn from_parts(
+        aux: BTreeMap<Uuid, AuxData>,
+        id_v1: IdMap,
+        res: BTreeMap<Uuid, Resource>,
+        resourcelinks: BTreeMap<u32, ApiResourceLink>,
+        scene_schedules: BTreeMap<Uuid, SceneSchedule>,
+        users: BTreeMap<Uuid, Whitelist>,
+    ) -> Self {
+        Self {
+            version: StateVersion::V1,
+            aux,
+            id_v1,
+            res,
+            resourcelinks,
+            scene_schedules,
+            users,
+        }
+    }
+
+    #[must_use]
+    pub const fn aux(&self) -> &BTreeMap<Uuid, AuxData> {
+        &self.aux
+    }
+
+    #[must_use]
+    pub const fn id_map(&self) -> &IdMap {
+        &self.id_v1
+    }
+
+    pub fn set_stable_id_v1(&mut self, stable: bool) {
+        self.id_v1.set_stable(stable);
+    }
+
     #[must_use]
     pub fn try_aux_get(&self, id: &Uuid) -> Option<&AuxData> {
         self.aux.get(id)
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A location on earth, in degrees
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.latitude) && (-180.0..=180.0).contains(&self.longitude)
+    }
+
+    /// Elevation of the sun above the horizon (in degrees) at the given time.
+    ///
+    /// This uses the low-precision formulas from the Astronomical Almanac,
+    /// which are accurate to about a degree. Refraction is ignored.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn sun_elevation(&self, time: DateTime<Utc>) -> f64 {
+        /* days since the J2000.0 epoch */
+        let n = (time.timestamp() as f64 - 946_728_000.0) / 86400.0;
+
+        /* mean longitude and mean anomaly of the sun */
+        let mean_long = 0.985_647_4f64.mul_add(n, 280.460).rem_euclid(360.0);
+        let anomaly = 0.985_600_3f64
+            .mul_add(n, 357.528)
+            .rem_euclid(360.0)
+            .to_radians();
+
+        /* ecliptic longitude, and obliquity of the ecliptic */
+        let ecl_long = 0.020f64
+            .mul_add(
+                (2.0 * anomaly).sin(),
+                1.915f64.mul_add(anomaly.sin(), mean_long),
+            )
+            .to_radians();
+        let obliquity = (-0.000_000_4f64).mul_add(n, 23.439).to_radians();
+
+        let right_ascension = (obliquity.cos() * ecl_long.sin()).atan2(ecl_long.cos());
+        let declination = (obliquity.sin() * ecl_long.sin()).asin();
+
+        /* local sidereal time, and hour angle of the sun */
+        let sidereal = 24.065_709_824_419_08f64
+            .mul_add(n, 18.697_374_558)
+            .rem_euclid(24.0);
+        let hour_angle = sidereal.mul_add(15.0, self.longitude).to_radians() - right_ascension;
+
+        let lat = self.latitude.to_radians();
+        lat.sin()
+            .mul_add(
+                declination.sin(),
+                lat.cos() * declination.cos() * hour_angle.cos(),
+            )
+            .asin()
+            .to_degrees()
+    }
+}
@@ -1,5 +1,30 @@
 use serde::{Deserialize, Serialize};
 
+/* linear sRGB (D65) to CIE XYZ, and back */
+const SRGB_TO_XYZ: [[f64; 3]; 3] = [
+    [0.4124, 0.3576, 0.1805],
+    [0.2126, 0.7152, 0.0722],
+    [0.0193, 0.1192, 0.9505],
+];
+
+const XYZ_TO_SRGB: [[f64; 3]; 3] = [
+    [3.2406, -1.5372, -0.4986],
+    [-0.9689, 1.8758, 0.0415],
+    [0.0557, -0.2040, 1.0570],
+];
+
+fn dot(row: [f64; 3], vec: [f64; 3]) -> f64 {
+    row.iter().zip(vec).map(|(a, b)| a * b).sum()
+}
+
+/* third degree polynomial, with coefficients from the highest power down */
+fn cubic(coef: [f64; 4], val: f64) -> f64 {
+    coef[0]
+        .mul_add(val, coef[1])
+        .mul_add(val, coef[2])
+        .mul_add(val, coef[3])
+}
+
 #[derive(Copy, Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct XY {
     pub x: f64,
@@ -16,6 +41,155 @@ impl XY {
     pub const fn new(x: f64, y: f64) -> Self {
         Self { x, y }
     }
+
+    /// Convert an sRGB color to CIE xy (ignoring brightness)
+    #[must_use]
+    pub fn from_rgb(red: u8, green: u8, blue: u8) -> Self {
+        fn linear(c: u8) -> f64 {
+            let c = f64::from(c) / 255.0;
+            if c > 0.04045 {
+                ((c + 0.055) / 1.055).powf(2.4)
+            } else {
+                c / 12.92
+            }
+        }
+
+        let rgb = [linear(red), linear(green), linear(blue)];
+        let [cx, cy, cz] = SRGB_TO_XYZ.map(|row| dot(row, rgb));
+
+        let sum = cx + cy + cz;
+        if sum == 0.0 {
+            return Self::D65_WHITE_POINT;
+        }
+
+        Self::new(cx / sum, cy / sum)
+    }
+
+    /// Convert CIE xy to an sRGB color, at full brightness
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn to_rgb(&self) -> [u8; 3] {
+        fn gamma(c: f64) -> f64 {
+            if c <= 0.003_130_8 {
+                12.92 * c
+            } else {
+                1.055f64.mul_add(c.powf(1.0 / 2.4), -0.055)
+            }
+        }
+
+        if self.y == 0.0 {
+            return [0, 0, 0];
+        }
+
+        let xyz = [self.x / self.y, 1.0, (1.0 - self.x - self.y) / self.y];
+        let rgb = XYZ_TO_SRGB.map(|row| dot(row, xyz).max(0.0));
+
+        let max = rgb.iter().copied().fold(0.0, f64::max);
+        if max == 0.0 {
+            return [0, 0, 0];
+        }
+
+        rgb.map(|c| (gamma(c / max).clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+
+    /// Color of a color temperature (the point on the Planckian locus),
+    /// using the approximation by Kim et al.
+    #[must_use]
+    pub fn from_mirek(mirek: u32) -> Self {
+        let kelvin = (1_000_000.0 / f64::from(mirek.max(1))).clamp(1667.0, 25000.0);
+        let k = 1000.0 / kelvin;
+
+        let x = if kelvin <= 4000.0 {
+            cubic([-0.266_123_9, -0.234_358_9, 0.877_695_6, 0.179_910], k)
+        } else {
+            cubic([-3.025_846_9, 2.107_037_9, 0.222_634_7, 0.240_390], k)
+        };
+
+        let y = if kelvin <= 2222.0 {
+            cubic(
+                [-1.106_381_4, -1.348_110_20, 2.185_558_32, -0.202_196_83],
+                x,
+            )
+        } else if kelvin <= 4000.0 {
+            cubic(
+                [-0.954_947_6, -1.374_185_93, 2.091_370_15, -0.167_488_67],
+                x,
+            )
+        } else {
+            cubic([3.081_758_0, -5.873_386_70, 3.751_129_97, -0.370_014_83], x)
+        };
+
+        Self::new(x, y)
+    }
+
+    /// Nearest color temperature of a color (which is only meaningful for
+    /// colors close to white)
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn to_mirek(&self) -> u32 {
+        /* approximation by McCamy */
+        let n = (self.x - 0.3320) / (self.y - 0.1858);
+        let kelvin = cubic([-449.0, 3525.0, -6823.3, 5520.33], n);
+        (1_000_000.0 / kelvin.clamp(1000.0, 25000.0)).round() as u32
+    }
+
+    /// Parse a color in `#rrggbb` notation
+    #[must_use]
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.strip_prefix('#')?;
+        if hex.len() != 6 {
+            return None;
+        }
+        let val = u32::from_str_radix(hex, 16).ok()?;
+        let [_, r, g, b] = val.to_be_bytes();
+        Some(Self::from_rgb(r, g, b))
+    }
+
+    /// Format as a color in `#rrggbb` notation
+    #[must_use]
+    pub fn to_hex(&self) -> String {
+        let [r, g, b] = self.to_rgb();
+        format!("#{r:02x}{g:02x}{b:02x}")
+    }
+
+    /// The color in CIE 1976 u'v' chromaticity coordinates, if it is a valid
+    /// color at all
+    #[must_use]
+    pub fn to_uv(self) -> Option<(f64, f64)> {
+        let denom = (-2.0f64).mul_add(self.x, 12.0f64.mul_add(self.y, 3.0));
+        (denom > 0.0).then(|| (4.0 * self.x / denom, 9.0 * self.y / denom))
+    }
+
+    /// The color at CIE 1976 u'v' chromaticity coordinates
+    #[must_use]
+    pub fn from_uv(u: f64, v: f64) -> Self {
+        let denom = 6.0f64.mul_add(u, (-16.0f64).mul_add(v, 12.0));
+        Self::new(9.0 * u / denom, 4.0 * v / denom)
+    }
+
+    /// Weighted average of colors.
+    ///
+    /// Averaging is done in the CIE 1976 u'v' chromaticity space, where equal
+    /// distances look about equally different (unlike xy, where the average
+    /// of two colors is skewed towards green).
+    #[must_use]
+    pub fn average(colors: impl IntoIterator<Item = (Self, f64)>) -> Option<Self> {
+        let (mut u, mut v, mut total) = (0.0, 0.0, 0.0);
+        for (xy, weight) in colors {
+            let Some((cu, cv)) = xy.to_uv().filter(|_| weight > 0.0) else {
+                continue;
+            };
+            u += weight * cu;
+            v += weight * cv;
+            total += weight;
+        }
+
+        if total == 0.0 {
+            return None;
+        }
+
+        Some(Self::from_uv(u / total, v / total))
+    }
 }
 
 impl From<[f64; 2]> for XY {
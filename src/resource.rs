@@ -1,40 +1,89 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::io::{Read, Write};
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
 use serde_json::json;
 use tokio::sync::broadcast::{Receiver, Sender};
-use tokio::sync::Notify;
+use tokio::sync::{mpsc, watch, Notify};
 use uuid::Uuid;
 
+use crate::circadian::CircadianRoom;
 use crate::error::{ApiError, ApiResult};
 use crate::hue::api::{
-    Bridge, BridgeHome, Device, DeviceArchetype, DeviceProductData, Metadata, RType, Resource,
-    ResourceLink, ResourceRecord, TimeZone, ZigbeeConnectivity, ZigbeeConnectivityStatus,
-    ZigbeeDeviceDiscovery,
+    Bridge, BridgeHome, Device, DeviceArchetype, DevicePower, DeviceProductData, DimmingUpdate,
+    Geolocation, GroupedLight, Light, Metadata, Motion, MotionSensitivityStatus, On, RType,
+    Resource, ResourceLink, ResourceRecord, Room, Scene, SceneActionElement, SceneMetadata,
+    SceneStatus, TimeZone, ZigbeeConnectivity, ZigbeeConnectivityStatus, ZigbeeConnectivityUpdate,
+    ZigbeeDeviceDiscovery, Zone,
 };
-use crate::hue::api::{GroupedLightUpdate, LightUpdate, SceneUpdate, Update};
+use crate::hue::api::{ButtonUpdate, GroupedLightUpdate, LightUpdate, SceneUpdate, Update};
+use crate::hue::behavior_scripts::BundledScript;
 use crate::hue::event::EventBlock;
+use crate::hue::legacy_api::{ApiResourceLink, ApiResourceType, Whitelist};
+use crate::journal::{JournalEntry, TraceId};
+use crate::model::group_totals::{GroupIndex, LightShare};
 use crate::model::state::{AuxData, State};
-use crate::z2m::request::ClientRequest;
+use crate::model::sun::Coordinates;
+use crate::scheduler::SceneSchedule;
+use crate::z2m::api::{Expose, TouchlinkDevice};
+use crate::z2m::request::{ClientRequest, RequestFailure};
+use crate::z2m::status::{ConnectionState, LogEntry, ServerStatus};
+use crate::z2m::update::DeviceUpdate;
 
 #[derive(Clone, Debug)]
 pub struct Resources {
     state: State,
+    /// Option schemas reported by z2m, by device (not persisted)
+    device_options: HashMap<Uuid, Vec<Expose>>,
+    /// Devices found by the latest touchlink scan, by z2m server (not persisted)
+    touchlink: BTreeMap<String, Vec<TouchlinkDevice>>,
+    /// Most recent failed z2m requests, oldest first (not persisted)
+    z2m_failures: VecDeque<RequestFailure>,
+    /// Most recent warnings and errors logged by z2m, oldest first (not persisted)
+    z2m_logs: VecDeque<LogEntry>,
+    /// Connection status, by z2m server (not persisted)
+    z2m_status: BTreeMap<String, ServerStatus>,
+    /// Circadian settings changed through the admin api, by room (not persisted)
+    circadian: BTreeMap<Uuid, CircadianRoom>,
+    /// Lights and rooms locked against api changes, until the given time (not persisted)
+    locks: BTreeMap<Uuid, DateTime<Utc>>,
+    /// Grouped lights of each light, and their combined state, built on
+    /// first use (not persisted)
+    groups: Option<GroupIndex>,
+    /// Where to send resource changes, when the journal is enabled
+    journal: Option<mpsc::UnboundedSender<JournalEntry>>,
     state_updates: Arc<Notify>,
+    /// Counter of changes to the persistent state (not persisted)
+    state_version: watch::Sender<u64>,
     pub hue_updates: Sender<EventBlock>,
-    pub z2m_updates: Sender<Arc<ClientRequest>>,
+    /// Requests for the backends, with the trace id of the api request (if
+    /// any) they were made for
+    pub z2m_updates: Sender<(Option<TraceId>, Arc<ClientRequest>)>,
 }
 
 impl Resources {
     const MAX_SCENE_ID: u32 = 100;
+    const MAX_Z2M_FAILURES: usize = 32;
+    const MAX_Z2M_LOGS: usize = 64;
+    const MAX_RESOURCELINKS: u32 = 64;
 
     #[allow(clippy::new_without_default)]
     #[must_use]
     pub fn new(state: State) -> Self {
         Self {
             state,
+            device_options: HashMap::new(),
+            touchlink: BTreeMap::new(),
+            z2m_failures: VecDeque::new(),
+            z2m_logs: VecDeque::new(),
+            z2m_status: BTreeMap::new(),
+            circadian: BTreeMap::new(),
+            locks: BTreeMap::new(),
+            groups: None,
+            journal: None,
             state_updates: Arc::new(Notify::new()),
+            state_version: watch::Sender::new(0),
             hue_updates: Sender::new(32),
             z2m_updates: Sender::new(32),
         }
@@ -42,6 +91,7 @@ impl Resources {
 
     pub fn read(&mut self, rdr: impl Read) -> ApiResult<()> {
         self.state = State::from_reader(rdr)?;
+        self.groups = None;
         Ok(())
     }
 
@@ -49,12 +99,203 @@ impl Resources {
         Ok(serde_yml::to_writer(wr, &self.state)?)
     }
 
-    pub fn serialize(&self) -> ApiResult<String> {
-        Ok(serde_yml::to_string(&self.state)?)
+    /// Copy of the persistent state, which can be serialized without holding
+    /// on to the resources
+    #[must_use]
+    pub fn snapshot(&self) -> State {
+        self.state.clone()
+    }
+
+    pub fn set_device_options(&mut self, device: &ResourceLink, options: Vec<Expose>) {
+        self.device_options.insert(device.rid, options);
+    }
+
+    #[must_use]
+    pub fn get_device_options(&self, device: &ResourceLink) -> Option<&[Expose]> {
+        self.device_options.get(&device.rid).map(Vec::as_slice)
+    }
+
+    pub fn set_touchlink_results(&mut self, server: &str, found: Vec<TouchlinkDevice>) {
+        self.touchlink.insert(server.to_string(), found);
+    }
+
+    #[must_use]
+    pub const fn get_touchlink_results(&self) -> &BTreeMap<String, Vec<TouchlinkDevice>> {
+        &self.touchlink
+    }
+
+    pub fn add_z2m_failure(&mut self, failure: RequestFailure) {
+        if self.z2m_failures.len() >= Self::MAX_Z2M_FAILURES {
+            self.z2m_failures.pop_front();
+        }
+        self.z2m_failures.push_back(failure);
+    }
+
+    #[must_use]
+    pub const fn get_z2m_failures(&self) -> &VecDeque<RequestFailure> {
+        &self.z2m_failures
+    }
+
+    pub fn add_z2m_log(&mut self, entry: LogEntry) {
+        if self.z2m_logs.len() >= Self::MAX_Z2M_LOGS {
+            self.z2m_logs.pop_front();
+        }
+        self.z2m_logs.push_back(entry);
+    }
+
+    #[must_use]
+    pub const fn get_z2m_logs(&self) -> &VecDeque<LogEntry> {
+        &self.z2m_logs
+    }
+
+    /// Connection status of a z2m server, created on first use
+    pub fn z2m_status_mut(&mut self, server: &str, url: &str) -> &mut ServerStatus {
+        self.z2m_status
+            .entry(server.to_string())
+            .or_insert_with(|| ServerStatus::new(url))
+    }
+
+    #[must_use]
+    pub const fn get_z2m_status(&self) -> &BTreeMap<String, ServerStatus> {
+        &self.z2m_status
+    }
+
+    pub fn set_circadian(&mut self, room: Uuid, settings: CircadianRoom) {
+        self.circadian.insert(room, settings);
+    }
+
+    #[must_use]
+    pub const fn get_circadian(&self) -> &BTreeMap<Uuid, CircadianRoom> {
+        &self.circadian
+    }
+
+    /// Lock a light or room against changes through the api, until `until`
+    pub fn set_lock(&mut self, id: Uuid, until: DateTime<Utc>) {
+        self.locks.insert(id, until);
+    }
+
+    /// Remove a lock, returning false if there was none
+    pub fn remove_lock(&mut self, id: &Uuid) -> bool {
+        self.locks.remove(id).is_some()
+    }
+
+    /// Locks that are still in effect at `now`
+    pub fn get_locks(&mut self, now: DateTime<Utc>) -> &BTreeMap<Uuid, DateTime<Utc>> {
+        self.locks.retain(|_, until| *until > now);
+        &self.locks
+    }
+
+    /* lights of a room, zone or bridge home (through its grouped light) */
+    fn group_lights(&self, owner: &ResourceLink) -> Vec<ResourceLink> {
+        match owner.rtype {
+            RType::Room => self
+                .get::<Room>(owner)
+                .map(|room| {
+                    room.children
+                        .iter()
+                        .filter_map(|rl| self.get::<Device>(rl).ok()?.light_service().copied())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            RType::Zone => self
+                .get::<Zone>(owner)
+                .map(|zone| self.get_zone_lights(zone))
+                .unwrap_or_default(),
+            RType::BridgeHome => self
+                .get_resources_by_type(RType::Light)
+                .into_iter()
+                .map(|rr| RType::Light.link_to(rr.id))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Check that an api request to `link` (a light, grouped light or scene)
+    /// does not change any locked light, or light in a locked room.
+    ///
+    /// Changes reported by z2m are not affected by locks.
+    pub fn check_unlocked(&self, link: &ResourceLink, now: DateTime<Utc>) -> ApiResult<()> {
+        let active = self.locks.iter().filter(|(_, until)| **until > now);
+
+        let mut locked = HashMap::new();
+        for (id, until) in active {
+            let room = RType::Room.link_to(*id);
+            if self.get::<Room>(&room).is_ok() {
+                for light in self.group_lights(&room) {
+                    locked.insert(light.rid, (*id, *until));
+                }
+            } else {
+                locked.insert(*id, (*id, *until));
+            }
+        }
+
+        if locked.is_empty() {
+            return Ok(());
+        }
+
+        for target in self.request_targets(link)? {
+            if let Some((id, until)) = locked.get(&target.rid) {
+                return Err(ApiError::Locked(*id, *until));
+            }
+        }
+
+        Ok(())
+    }
+
+    /* lights changed by an api request to a light, grouped light or scene */
+    fn request_targets(&self, link: &ResourceLink) -> ApiResult<Vec<ResourceLink>> {
+        Ok(match link.rtype {
+            RType::Light => vec![*link],
+            RType::GroupedLight => self.group_lights(&self.get::<GroupedLight>(link)?.owner),
+            RType::Scene => self
+                .get::<Scene>(link)?
+                .actions
+                .iter()
+                .map(|act| act.target)
+                .collect(),
+            _ => vec![],
+        })
+    }
+
+    /// Name of the z2m server a light was announced by, if it is unreachable
+    #[must_use]
+    pub fn unreachable_server(&self, light: &ResourceLink) -> Option<&str> {
+        let server = self.state.try_aux_get(&light.rid)?.server.as_deref()?;
+        self.z2m_status
+            .get(server)
+            .is_some_and(ServerStatus::is_unreachable)
+            .then_some(server)
+    }
+
+    /// Check that an api request to `link` (a light, grouped light or scene)
+    /// can be carried out, i.e. that the z2m server of its lights is
+    /// connected.
+    ///
+    /// Groups and scenes are only refused when none of their lights can be
+    /// reached, since the rest of the lights still follow the request.
+    pub fn check_reachable(&self, link: &ResourceLink) -> ApiResult<()> {
+        let targets = self.request_targets(link)?;
+
+        let mut offline = None;
+        for target in &targets {
+            match self.unreachable_server(target) {
+                Some(server) => offline = Some(server),
+                None => return Ok(()),
+            }
+        }
+
+        offline.map_or(Ok(()), |server| {
+            Err(ApiError::ServerOffline(server.to_string()))
+        })
+    }
+
+    pub fn set_stable_id_v1(&mut self, stable: bool) {
+        self.state.set_stable_id_v1(stable);
     }
 
     pub fn init(&mut self, bridge_id: &str) -> ApiResult<()> {
-        self.add_bridge(bridge_id.to_owned())
+        self.add_bridge(bridge_id.to_owned())?;
+        self.add_bridge_services(bridge_id)
     }
 
     pub fn aux_get(&self, link: &ResourceLink) -> ApiResult<&AuxData> {
@@ -72,14 +313,18 @@ impl Resources {
                     .with_brightness(light.dimming)
                     .with_on(light.on)
                     .with_color_temperature(light.as_mirek_opt())
-                    .with_color_xy(light.as_color_opt());
+                    .with_color_xy(light.as_color_opt())
+                    .with_gradient(light.as_gradient_opt());
 
                 Ok(Some(Update::Light(upd)))
             }
             Resource::GroupedLight(glight) => {
                 let upd = GroupedLightUpdate::new()
                     .with_on(glight.on)
-                    .with_brightness(glight.as_brightness_opt());
+                    .with_brightness(glight.as_brightness_opt())
+                    .with_color_temperature(glight.as_mirek_opt())
+                    .with_color_xy(glight.as_color_opt())
+                    .with_signaling(glight.as_signaling_opt().cloned());
 
                 Ok(Some(Update::GroupedLight(upd)))
             }
@@ -90,7 +335,28 @@ impl Resources {
 
                 Ok(Some(Update::Scene(upd)))
             }
-            Resource::Room(_) => Ok(None),
+            Resource::Button(button) => Ok(Some(Update::Button(ButtonUpdate {
+                button: button.button.clone(),
+            }))),
+            Resource::Motion(motion) => Ok(Some(Update::Motion(motion.into()))),
+            Resource::RelativeRotary(rotary) => Ok(Some(Update::RelativeRotary(rotary.into()))),
+            Resource::Contact(contact) => Ok(Some(Update::Contact(contact.into()))),
+            Resource::Tamper(tamper) => Ok(Some(Update::Tamper(tamper.into()))),
+            Resource::DeviceSoftwareUpdate(swu) => {
+                Ok(Some(Update::DeviceSoftwareUpdate(swu.into())))
+            }
+            Resource::Bridge(bridge) => Ok(Some(Update::Bridge(bridge.into()))),
+            Resource::Device(dev) => Ok(Some(Update::Device(dev.into()))),
+            Resource::DevicePower(power) => Ok(Some(Update::DevicePower(power.into()))),
+            Resource::ZigbeeConnectivity(zbc) => {
+                Ok(Some(Update::ZigbeeConnectivity(ZigbeeConnectivityUpdate {
+                    status: zbc.status,
+                })))
+            }
+            Resource::Room(_)
+            | Resource::Zone(_)
+            | Resource::Geolocation(_)
+            | Resource::BehaviorInstance(_) => Ok(None),
             obj => Err(ApiError::UpdateUnsupported(obj.rtype())),
         }
     }
@@ -103,15 +369,36 @@ impl Resources {
     where
         for<'a> &'a mut T: TryFrom<&'a mut Resource, Error = ApiError>,
     {
+        let journal = self.journal.is_some();
         let obj = self.state.get_mut(id)?;
+        let before = journal.then(|| serde_json::to_value(&*obj)).transpose()?;
         func(obj.try_into()?)?;
 
-        if let Some(delta) = Self::generate_update(obj)? {
+        let rtype = obj.rtype();
+        let after = before
+            .is_some()
+            .then(|| serde_json::to_value(&*obj))
+            .transpose()?;
+        let delta = Self::generate_update(obj)?;
+
+        if let (Some(before), Some(after)) = (before, after) {
+            if let Some(entry) = JournalEntry::update(*id, rtype, &before, &after) {
+                self.journal_record(entry);
+            }
+        }
+
+        if let Some(delta) = delta {
             let id_v1 = self.state.id_v1(id);
             self.hue_event(EventBlock::update(id, id_v1, delta)?);
         }
 
-        self.state_updates.notify_one();
+        self.state_changed();
+
+        if rtype == RType::Light {
+            self.refresh_groups(id)?;
+        } else if Self::affects_groups(rtype) {
+            self.groups = None;
+        }
 
         Ok(())
     }
@@ -126,6 +413,45 @@ impl Resources {
         })
     }
 
+    /// Change the sensitivity of a motion sensor. The new level is shown as
+    /// changing, until confirmed by z2m.
+    pub fn set_motion_sensitivity(&mut self, id: &Uuid, sensitivity: u32) -> ApiResult<()> {
+        let motion = self.get::<Motion>(&RType::Motion.link_to(*id))?;
+        let owner = motion.owner;
+
+        if !motion
+            .sensitivity
+            .is_some_and(|sens| sensitivity <= sens.sensitivity_max)
+        {
+            return Err(ApiError::MotionSensitivityUnsupported(sensitivity));
+        }
+
+        self.update::<Motion>(id, |motion| {
+            if let Some(sens) = &mut motion.sensitivity {
+                sens.sensitivity = sensitivity;
+                sens.status = MotionSensitivityStatus::Changing;
+            }
+        })?;
+
+        self.z2m_request(ClientRequest::motion_sensitivity(owner, sensitivity))
+    }
+
+    /// Rooms made from the z2m group `topic`, on any server
+    #[must_use]
+    pub fn find_rooms(&self, topic: &str) -> Vec<ResourceLink> {
+        self.state
+            .res
+            .iter()
+            .filter(|(_, obj)| matches!(obj, Resource::Room(_)))
+            .filter(|(id, _)| {
+                self.state
+                    .try_aux_get(id)
+                    .is_some_and(|aux| aux.topic.as_deref() == Some(topic))
+            })
+            .map(|(id, _)| RType::Room.link_to(*id))
+            .collect()
+    }
+
     #[must_use]
     pub fn get_scenes_for_room(&self, id: &Uuid) -> Vec<Uuid> {
         self.state
@@ -146,6 +472,274 @@ impl Resources {
             .collect()
     }
 
+    /// Location of the bridge, if configured through the geolocation resource
+    #[must_use]
+    pub fn get_location(&self) -> Option<Coordinates> {
+        self.get_resources_by_type(RType::Geolocation)
+            .iter()
+            .find_map(|rr| {
+                self.aux_get(&RType::Geolocation.link_to(rr.id))
+                    .ok()?
+                    .location
+            })
+    }
+
+    /// Lights in a zone, whether added to it as lights or as whole devices
+    #[must_use]
+    pub fn get_zone_lights(&self, zone: &Zone) -> Vec<ResourceLink> {
+        zone.children
+            .iter()
+            .filter_map(|rl| match rl.rtype {
+                RType::Light => Some(*rl),
+                RType::Device => self.get::<Device>(rl).ok()?.light_service().copied(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Grouped light of the bridge home ("group 0"), controlling all lights
+    #[must_use]
+    pub fn get_bridge_home_light(&self) -> Option<ResourceLink> {
+        self.state.res.values().find_map(|obj| match obj {
+            Resource::BridgeHome(home) => home.grouped_light_service().copied(),
+            _ => None,
+        })
+    }
+
+    /// Send an update to every light, using the group of each room, and
+    /// updating lights outside of any room one at a time
+    pub fn bridge_home_request(&self, upd: &DeviceUpdate) -> ApiResult<()> {
+        let mut roomed = HashSet::new();
+
+        for rr in self.get_resources_by_type(RType::Room) {
+            let room: Room = rr.obj.try_into()?;
+            for light in room
+                .children
+                .iter()
+                .filter_map(|rl| self.get::<Device>(rl).ok()?.light_service())
+            {
+                roomed.insert(light.rid);
+            }
+
+            if let Some(glight) = room.grouped_light_service() {
+                self.z2m_request(ClientRequest::group_update(*glight, upd.clone()))?;
+            }
+        }
+
+        for rr in self.get_resources_by_type(RType::Light) {
+            if !roomed.contains(&rr.id) {
+                let link = RType::Light.link_to(rr.id);
+                self.z2m_request(ClientRequest::light_update(link, upd.clone()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /* changes to these can move lights in or out of a group */
+    const fn affects_groups(rtype: RType) -> bool {
+        matches!(
+            rtype,
+            RType::Light | RType::Device | RType::Room | RType::Zone | RType::BridgeHome
+        )
+    }
+
+    fn build_group_index(&self) -> GroupIndex {
+        let mut index = GroupIndex::default();
+
+        for (id, obj) in &self.state.res {
+            let (owner, glight) = match obj {
+                Resource::Room(room) => (RType::Room.link_to(*id), room.grouped_light_service()),
+                Resource::Zone(zone) => (RType::Zone.link_to(*id), zone.grouped_light_service()),
+                Resource::BridgeHome(home) => {
+                    (RType::BridgeHome.link_to(*id), home.grouped_light_service())
+                }
+                _ => continue,
+            };
+            let Some(glight) = glight else {
+                continue;
+            };
+
+            let lights = self.group_lights(&owner).into_iter().filter_map(|link| {
+                let light = self.get::<Light>(&link).ok()?;
+                Some((link.rid, LightShare::new(light)))
+            });
+            index.add_group(glight.rid, lights);
+        }
+
+        if let Some(home) = self.get_bridge_home_light() {
+            index.set_home(home.rid);
+        }
+
+        index
+    }
+
+    fn group_index(&mut self) -> &mut GroupIndex {
+        if self.groups.is_none() {
+            self.groups = Some(self.build_group_index());
+        }
+        self.groups.get_or_insert_with(GroupIndex::default)
+    }
+
+    /* show the combined state of their lights on all groups containing a
+     * light, after it changed */
+    fn refresh_groups(&mut self, light: &Uuid) -> ApiResult<()> {
+        let share = LightShare::new(self.get::<Light>(&RType::Light.link_to(*light))?);
+        for glight in self.group_index().update_light(*light, share) {
+            self.refresh_group(&glight)?;
+        }
+        Ok(())
+    }
+
+    fn refresh_home_group(&mut self) -> ApiResult<()> {
+        self.get_bridge_home_light()
+            .map_or(Ok(()), |link| self.refresh_group(&link.rid))
+    }
+
+    /* every group shows the combined color of the lights that are on, and the
+     * bridge home grouped light also shows all lights combined: on if any
+     * light is on, at the average brightness of the lights that are */
+    fn refresh_group(&mut self, id: &Uuid) -> ApiResult<()> {
+        let index = self.group_index();
+        let Some(totals) = index.totals(id).copied() else {
+            return Ok(());
+        };
+        let home = index.is_home(id);
+
+        let link = RType::GroupedLight.link_to(*id);
+        let glight = self.get::<GroupedLight>(&link)?;
+
+        let mut on = glight.on;
+        let mut brightness = glight.as_brightness_opt();
+        if home {
+            on = Some(On::new(totals.any_on()));
+            brightness = totals.brightness().or(brightness);
+        }
+
+        let (color, color_temperature) = totals
+            .color()
+            .unwrap_or_else(|| (glight.color.clone(), glight.color_temperature.clone()));
+
+        if glight.on == on
+            && glight.as_brightness_opt() == brightness
+            && glight.color == color
+            && glight.color_temperature == color_temperature
+        {
+            return Ok(());
+        }
+
+        self.update::<GroupedLight>(id, |glight| {
+            glight.on = on;
+            if let Some(brightness) = brightness {
+                glight.dimming = Some(DimmingUpdate { brightness });
+            }
+            glight.color = color;
+            glight.color_temperature = color_temperature;
+        })
+    }
+
+    /// Add the grouped light of the bridge home, if missing (state files from
+    /// older versions lack it)
+    pub fn add_bridge_home_light(&mut self) -> ApiResult<()> {
+        for rr in self.get_resources_by_type(RType::BridgeHome) {
+            let home: BridgeHome = rr.obj.try_into()?;
+            if let Some(link_glight) = home.grouped_light_service() {
+                let glight = GroupedLight::new(RType::BridgeHome.link_to(rr.id));
+                self.add(link_glight, Resource::GroupedLight(glight))?;
+            }
+        }
+
+        self.refresh_home_group()
+    }
+
+    /// Overwrite the actions of a scene with the current state of the lights
+    /// in its room (or zone), and have z2m store it as well
+    pub fn scene_capture(&mut self, link: &ResourceLink) -> ApiResult<()> {
+        let scene = self.get::<Scene>(link)?;
+        let group = scene.group;
+        let name = scene.metadata.name.clone();
+        let index = self.aux_get(link)?.index;
+
+        let actions: Vec<SceneActionElement> = self
+            .group_lights(&group)
+            .into_iter()
+            .filter_map(|target| {
+                let light = self.get::<Light>(&target).ok()?;
+                Some(SceneActionElement {
+                    action: light.into(),
+                    target,
+                })
+            })
+            .collect();
+
+        /* keep a copy in aux data, so it survives the scene being rebuilt */
+        let aux = self
+            .aux_get(link)?
+            .clone()
+            .with_actions(Some(actions.clone()))
+            .with_relearn(false);
+        self.aux_set(link, aux);
+
+        self.update::<Scene>(&link.rid, |scene| scene.actions = actions)?;
+
+        /* zones have no z2m group to store scenes in */
+        if group.rtype == RType::Zone {
+            return Ok(());
+        }
+
+        let index = index.ok_or(ApiError::SceneIndexMissing(link.rid))?;
+        self.z2m_request(ClientRequest::scene_store(group, index, name))
+    }
+
+    /// Recall a scene.
+    ///
+    /// Scenes of rooms are recalled through z2m, which has them stored in
+    /// the group of the room. Zones have no such group, so each backend
+    /// sends the actions of zone scenes to its own lights (see
+    /// [`Self::zone_scene_updates`]). Either way, this is a single request,
+    /// however many lights the scene has.
+    pub fn scene_recall(&self, link: &ResourceLink) -> ApiResult<()> {
+        self.get::<Scene>(link)?;
+        self.z2m_request(ClientRequest::scene_recall(*link))
+    }
+
+    /// The light updates that recall a zone scene, or `None` for scenes of
+    /// rooms
+    pub fn zone_scene_updates(
+        &self,
+        link: &ResourceLink,
+    ) -> ApiResult<Option<Vec<(ResourceLink, DeviceUpdate)>>> {
+        let scene = self.get::<Scene>(link)?;
+        if scene.group.rtype != RType::Zone {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            scene
+                .actions
+                .iter()
+                .map(|elem| (elem.target, DeviceUpdate::from(&elem.action)))
+                .collect(),
+        ))
+    }
+
+    /// Mark scene as active (and all other scenes in the same room as inactive)
+    pub fn scene_set_active(&mut self, link: &ResourceLink) -> ApiResult<()> {
+        let room = self.get::<Scene>(link)?.group;
+
+        for rid in self.get_scenes_for_room(&room.rid) {
+            self.update(&rid, |scn: &mut Scene| {
+                if rid == link.rid {
+                    scn.status = Some(SceneStatus::Static);
+                } else {
+                    scn.status = Some(SceneStatus::Inactive);
+                }
+            })?;
+        }
+
+        Ok(())
+    }
+
     pub fn add(&mut self, link: &ResourceLink, obj: Resource) -> ApiResult<()> {
         assert!(
             link.rtype == obj.rtype(),
@@ -161,7 +755,12 @@ impl Resources {
 
         self.state.insert(link.rid, obj);
 
-        self.state_updates.notify_one();
+        self.state_changed();
+
+        if self.journal.is_some() {
+            let obj = serde_json::to_value(self.state.get(&link.rid)?)?;
+            self.journal_record(JournalEntry::add(link.rid, link.rtype, obj));
+        }
 
         let evt = EventBlock::add(serde_json::to_value(self.get_resource_by_id(&link.rid)?)?);
 
@@ -169,14 +768,53 @@ impl Resources {
 
         self.hue_event(evt);
 
+        if Self::affects_groups(link.rtype) {
+            self.groups = None;
+        }
+        if link.rtype == RType::Light {
+            self.refresh_home_group()?;
+        }
+
         Ok(())
     }
 
     pub fn delete(&mut self, link: &ResourceLink) -> ApiResult<()> {
         log::info!("Deleting {link:?}..");
+        if self.journal.is_some() {
+            let obj = serde_json::to_value(self.state.get(&link.rid)?)?;
+            self.journal_record(JournalEntry::delete(link.rid, link.rtype, obj));
+        }
+        /* resourcelinks must not point to resources that are gone */
+        if matches!(
+            link.rtype,
+            RType::Light | RType::GroupedLight | RType::Scene
+        ) {
+            if let Some(addr) = self
+                .state
+                .try_get(&link.rid)
+                .and_then(|obj| self.id_v1_scope(&link.rid, obj))
+            {
+                self.unlink_v1(&addr);
+            }
+        }
+
+        if link.rtype == RType::Scene {
+            self.state
+                .scene_schedules
+                .retain(|_, schedule| schedule.scene != link.rid);
+        }
+
+        if matches!(link.rtype, RType::Device | RType::Light) {
+            self.unlink_member(link)?;
+        }
+
         self.state.remove(&link.rid)?;
 
-        self.state_updates.notify_one();
+        if Self::affects_groups(link.rtype) {
+            self.groups = None;
+        }
+
+        self.state_changed();
 
         let evt = EventBlock::delete(link)?;
 
@@ -185,11 +823,57 @@ impl Resources {
         Ok(())
     }
 
+    /* take a device or light that is going away out of the rooms and zones
+     * it is in, and out of the scenes that have an action for it (including
+     * the actions kept in aux data) */
+    fn unlink_member(&mut self, link: &ResourceLink) -> ApiResult<()> {
+        let mut rooms = vec![];
+        let mut zones = vec![];
+        let mut scenes = vec![];
+        for (id, obj) in &self.state.res {
+            match obj {
+                Resource::Room(room) if room.children.contains(link) => rooms.push(*id),
+                Resource::Zone(zone) if zone.children.contains(link) => zones.push(*id),
+                Resource::Scene(_) => scenes.push(*id),
+                _ => {}
+            }
+        }
+
+        for id in rooms {
+            self.update::<Room>(&id, |room| room.children.retain(|child| child != link))?;
+        }
+        for id in zones {
+            self.update::<Zone>(&id, |zone| zone.children.retain(|child| child != link))?;
+        }
+
+        for id in scenes {
+            let scene = self.get::<Scene>(&RType::Scene.link_to(id))?;
+            if scene.actions.iter().any(|act| act.target == *link) {
+                self.update::<Scene>(&id, |scene| {
+                    scene.actions.retain(|act| act.target != *link);
+                })?;
+            }
+
+            let Some(mut aux) = self.state.try_aux_get(&id).cloned() else {
+                continue;
+            };
+            if let Some(actions) = &mut aux.actions {
+                if actions.iter().any(|act| act.target == *link) {
+                    actions.retain(|act| act.target != *link);
+                    self.aux_set(&RType::Scene.link_to(id), aux);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn add_bridge(&mut self, bridge_id: String) -> ApiResult<()> {
         let link_bridge = RType::Bridge.deterministic(&bridge_id);
         let link_bridge_home = RType::BridgeHome.deterministic(format!("{bridge_id}HOME"));
         let link_bridge_dev = RType::Device.deterministic(link_bridge.rid);
         let link_bridge_home_dev = RType::Device.deterministic(link_bridge_home.rid);
+        let link_bridge_home_glight = RType::GroupedLight.deterministic(link_bridge_home.rid);
         let link_zbdd = RType::ZigbeeDeviceDiscovery.deterministic(link_bridge.rid);
         let link_zbc = RType::ZigbeeConnectivity.deterministic(link_bridge.rid);
 
@@ -213,7 +897,7 @@ impl Resources {
 
         let bridge_home = BridgeHome {
             children: vec![link_bridge_dev],
-            services: vec![RType::GroupedLight.deterministic(link_bridge_home.rid)],
+            services: vec![link_bridge_home_glight],
         };
 
         let zbdd = ZigbeeDeviceDiscovery {
@@ -236,12 +920,144 @@ impl Resources {
         self.add(&link_bridge, Resource::Bridge(bridge))?;
         self.add(&link_bridge_home_dev, Resource::Device(bridge_home_dev))?;
         self.add(&link_bridge_home, Resource::BridgeHome(bridge_home))?;
+        self.add(
+            &link_bridge_home_glight,
+            Resource::GroupedLight(GroupedLight::new(link_bridge_home)),
+        )?;
         self.add(&link_zbdd, Resource::ZigbeeDeviceDiscovery(zbdd))?;
         self.add(&link_zbc, Resource::ZigbeeConnectivity(zbc))?;
 
         Ok(())
     }
 
+    /// Add bridge services that state files from older versions lack. This
+    /// runs on every start, after the state is loaded.
+    pub fn add_bridge_services(&mut self, bridge_id: &str) -> ApiResult<()> {
+        let link_bridge = RType::Bridge.deterministic(bridge_id);
+        let link_bridge_dev = RType::Device.deterministic(link_bridge.rid);
+        let link_geoloc = RType::Geolocation.deterministic(link_bridge.rid);
+        let link_power = RType::DevicePower.deterministic(link_bridge.rid);
+
+        self.add(
+            &link_geoloc,
+            Resource::Geolocation(Geolocation {
+                is_configured: false,
+            }),
+        )?;
+
+        /* the bridge runs on mains power */
+        if !self
+            .get::<Device>(&link_bridge_dev)?
+            .services
+            .contains(&link_power)
+        {
+            self.update::<Device>(&link_bridge_dev.rid, |dev| dev.services.push(link_power))?;
+        }
+        self.add(
+            &link_power,
+            Resource::DevicePower(DevicePower::mains(link_bridge_dev)),
+        )?;
+
+        Ok(())
+    }
+
+    /// Name the bridge, and set its time zone, from the config. This is only
+    /// done for a new state, since both can be changed through the api.
+    pub fn configure_bridge(&mut self, name: &str, time_zone: &str) -> ApiResult<()> {
+        let Some(link_bridge) = self.get_bridge_link() else {
+            return Ok(());
+        };
+        let owner = self.get::<Bridge>(&link_bridge)?.owner;
+
+        self.update::<Bridge>(&link_bridge.rid, |bridge| {
+            bridge.time_zone.time_zone = time_zone.to_string();
+        })?;
+        self.update::<Device>(&owner.rid, |dev| dev.metadata.name = name.to_string())
+    }
+
+    fn get_bridge_link(&self) -> Option<ResourceLink> {
+        self.state.res.iter().find_map(|(id, obj)| {
+            matches!(obj, Resource::Bridge(_)).then(|| RType::Bridge.link_to(*id))
+        })
+    }
+
+    /// The bridge resource, and the device it belongs to (which holds the
+    /// name of the bridge)
+    #[must_use]
+    pub fn get_bridge(&self) -> Option<(&Bridge, &Device)> {
+        let bridge = self.get::<Bridge>(&self.get_bridge_link()?).ok()?;
+        let dev = self.get::<Device>(&bridge.owner).ok()?;
+        Some((bridge, dev))
+    }
+
+    /* the zigbee connectivity service of the bridge device */
+    fn bridge_connectivity(&self) -> Option<ResourceLink> {
+        self.get_resources_by_type(RType::Bridge)
+            .into_iter()
+            .find_map(|rr| {
+                let bridge: Bridge = rr.obj.try_into().ok()?;
+                self.get::<Device>(&bridge.owner)
+                    .ok()?
+                    .zigbee_connectivity_service()
+                    .copied()
+            })
+    }
+
+    /// Show the bridge as connected to zigbee, as long as any z2m server is
+    /// connected (and has its coordinator online)
+    pub fn refresh_bridge_connectivity(&mut self) -> ApiResult<()> {
+        let Some(link_zbc) = self.bridge_connectivity() else {
+            return Ok(());
+        };
+
+        let online = self
+            .z2m_status
+            .values()
+            .any(|status| status.state == ConnectionState::Connected);
+        let status = if online {
+            ZigbeeConnectivityStatus::Connected
+        } else {
+            ZigbeeConnectivityStatus::ConnectivityIssue
+        };
+
+        if self.get::<ZigbeeConnectivity>(&link_zbc)?.status == status {
+            return Ok(());
+        }
+
+        self.update::<ZigbeeConnectivity>(&link_zbc.rid, |zbc| zbc.status = status)
+    }
+
+    /// Report the zigbee network of a z2m coordinator on the bridge device
+    pub fn set_bridge_network(&mut self, coord: &ZigbeeConnectivity) -> ApiResult<()> {
+        let Some(link_zbc) = self.bridge_connectivity() else {
+            return Ok(());
+        };
+
+        let zbc = self.get::<ZigbeeConnectivity>(&link_zbc)?;
+        if zbc.mac_address == coord.mac_address
+            && zbc.channel == coord.channel
+            && zbc.extended_pan_id == coord.extended_pan_id
+        {
+            return Ok(());
+        }
+
+        self.update::<ZigbeeConnectivity>(&link_zbc.rid, |zbc| {
+            zbc.mac_address.clone_from(&coord.mac_address);
+            zbc.channel.clone_from(&coord.channel);
+            zbc.extended_pan_id.clone_from(&coord.extended_pan_id);
+        })
+    }
+
+    /// Add the behavior scripts bundled with Bifrost, if not already present
+    pub fn add_behavior_scripts(&mut self) -> ApiResult<()> {
+        for script in BundledScript::ALL {
+            let link = RType::BehaviorScript.link_to(script.id());
+            self.add(&link, Resource::BehaviorScript(script.script()))?;
+        }
+
+        Ok(())
+    }
+
     pub fn get_next_scene_id(&self, room: &ResourceLink) -> ApiResult<u32> {
         let mut set: HashSet<u32> = HashSet::new();
 
@@ -317,22 +1133,33 @@ impl Resources {
                 .and_then(|light| self.state.id_v1(&light.rid))
                 .map(|id| format!("/lights/{id}")),
 
+            /* Entertainment services map to the light they render on */
+            Resource::Entertainment(ent) => self
+                .state
+                .id_v1(&ent.renderer_reference.rid)
+                .map(|id| format!("/lights/{id}")),
+
             /* BridgeHome maps to "group 0" that seems to be present in the v1 api */
             Resource::BridgeHome(_) => Some(String::from("/groups/0")),
 
             /* No id v1 */
             Resource::BehaviorInstance(_)
             | Resource::Button(_)
+            | Resource::Contact(_)
+            | Resource::DevicePower(_)
+            | Resource::DeviceSoftwareUpdate(_)
             | Resource::PublicImage(_)
             | Resource::Zone(_)
             | Resource::BehaviorScript(_)
             | Resource::Bridge(_)
-            | Resource::Entertainment(_)
             | Resource::GeofenceClient(_)
             | Resource::Geolocation(_)
             | Resource::Homekit(_)
             | Resource::Matter(_)
+            | Resource::Motion(_)
+            | Resource::RelativeRotary(_)
             | Resource::SmartScene(_)
+            | Resource::Tamper(_)
             | Resource::ZigbeeConnectivity(_)
             | Resource::ZigbeeDeviceDiscovery(_) => None,
         }
@@ -376,6 +1203,245 @@ impl Resources {
             .collect()
     }
 
+    /// Give a device or room the name `name`, generated from its z2m friendly
+    /// name `raw`, unless it was renamed through the api since the last name
+    /// was generated. This way, changed naming rules are applied to known
+    /// resources too.
+    pub fn apply_generated_name(
+        &mut self,
+        link: &ResourceLink,
+        raw: &str,
+        name: &str,
+    ) -> ApiResult<()> {
+        let mut aux = self
+            .state
+            .try_aux_get(&link.rid)
+            .cloned()
+            .unwrap_or_default();
+        let previous = aux.name.clone().unwrap_or_else(|| raw.to_string());
+
+        if previous != name {
+            match link.rtype {
+                RType::Device => {
+                    let dev = self.get::<Device>(link)?;
+                    let light = dev.light_service().copied();
+                    if dev.metadata.name == previous {
+                        self.update::<Device>(&link.rid, |dev| {
+                            dev.metadata.name = name.to_string();
+                        })?;
+                    }
+                    if let Some(light) = light {
+                        if self.get::<Light>(&light)?.metadata.name == previous {
+                            self.update::<Light>(&light.rid, |light| {
+                                light.metadata.name = name.to_string();
+                            })?;
+                        }
+                    }
+                }
+                RType::Room if self.get::<Room>(link)?.metadata.name == previous => {
+                    self.update::<Room>(&link.rid, |room| {
+                        room.metadata.name = name.to_string();
+                    })?;
+                }
+                _ => {}
+            }
+        }
+
+        aux.name = Some(name.to_string());
+        self.aux_set(link, aux);
+
+        Ok(())
+    }
+
+    /// Show a resource record the way the application `user` sees it: scenes
+    /// carry the appdata stored by that application, if any.
+    #[must_use]
+    pub fn user_view(&self, mut rec: ResourceRecord, user: Option<&str>) -> ResourceRecord {
+        if let (Resource::Scene(scene), Some(user)) = (&mut rec.obj, user) {
+            if let Some(appdata) = self
+                .state
+                .try_aux_get(&rec.id)
+                .and_then(|aux| aux.appdata.get(user))
+            {
+                scene.metadata.appdata = Some(appdata.clone());
+            }
+        }
+        rec
+    }
+
+    /// Store scene appdata for the application `user`, or as the shared
+    /// appdata when the request was not made by a known application.
+    pub fn scene_appdata_set(
+        &mut self,
+        link: &ResourceLink,
+        user: Option<&str>,
+        appdata: String,
+    ) -> ApiResult<()> {
+        let Some(user) = user else {
+            return self.update(&link.rid, |scn: &mut Scene| {
+                scn.metadata.appdata = Some(appdata);
+            });
+        };
+
+        self.get::<Scene>(link)?;
+        let aux = self
+            .state
+            .try_aux_get(&link.rid)
+            .cloned()
+            .unwrap_or_default()
+            .with_appdata(user, appdata);
+        self.aux_set(link, aux);
+
+        /* per-user appdata is private, so only announce that the metadata
+         * changed; clients re-read it with their own key */
+        let scene = self.get::<Scene>(link)?;
+        let upd = SceneUpdate {
+            metadata: Some(SceneMetadata {
+                appdata: None,
+                ..scene.metadata.clone()
+            }),
+            ..SceneUpdate::new()
+        };
+        let id_v1 = self.state.id_v1(&link.rid);
+        self.hue_event(EventBlock::update(&link.rid, id_v1, Update::Scene(upd))?);
+        self.state_changed();
+
+        Ok(())
+    }
+
+    #[must_use]
+    pub const fn get_resourcelinks(&self) -> &BTreeMap<u32, ApiResourceLink> {
+        &self.state.resourcelinks
+    }
+
+    pub fn get_resourcelink(&self, id: u32) -> ApiResult<&ApiResourceLink> {
+        self.state
+            .resourcelinks
+            .get(&id)
+            .ok_or(ApiError::V1NotFound(id))
+    }
+
+    /// Store a new resourcelink, and return its id
+    pub fn add_resourcelink(&mut self, link: ApiResourceLink) -> ApiResult<u32> {
+        let id = (1..=Self::MAX_RESOURCELINKS)
+            .find(|id| !self.state.resourcelinks.contains_key(id))
+            .ok_or(ApiError::V1Full(ApiResourceType::Resourcelinks))?;
+
+        self.state.resourcelinks.insert(id, link);
+        self.state_changed();
+
+        Ok(id)
+    }
+
+    pub fn update_resourcelink(
+        &mut self,
+        id: u32,
+        func: impl FnOnce(&mut ApiResourceLink),
+    ) -> ApiResult<()> {
+        let link = self
+            .state
+            .resourcelinks
+            .get_mut(&id)
+            .ok_or(ApiError::V1NotFound(id))?;
+        func(link);
+        self.state_changed();
+
+        Ok(())
+    }
+
+    /// Delete a resourcelink, along with the resourcelinks it refers to that
+    /// are marked for recycling, and no longer referred to by anything else.
+    ///
+    /// Returns the ids of all deleted resourcelinks.
+    pub fn delete_resourcelink(&mut self, id: u32) -> ApiResult<Vec<u32>> {
+        let link = self
+            .state
+            .resourcelinks
+            .remove(&id)
+            .ok_or(ApiError::V1NotFound(id))?;
+        self.unlink_v1(&format!("/resourcelinks/{id}"));
+        self.state_changed();
+
+        let mut deleted = vec![id];
+        for addr in &link.links {
+            let Some(child) = addr
+                .strip_prefix("/resourcelinks/")
+                .and_then(|child| child.parse().ok())
+            else {
+                continue;
+            };
+            let recycle = self
+                .state
+                .resourcelinks
+                .get(&child)
+                .is_some_and(|child| child.recycle);
+            let referenced = self
+                .state
+                .resourcelinks
+                .values()
+                .any(|other| other.links.contains(addr));
+            if recycle && !referenced {
+                deleted.extend(self.delete_resourcelink(child)?);
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// v1 users created by pairing, by username
+    #[must_use]
+    pub const fn get_users(&self) -> &BTreeMap<Uuid, Whitelist> {
+        &self.state.users
+    }
+
+    /// Add a v1 user, after pairing
+    pub fn add_user(&mut self, username: Uuid, name: &str) {
+        let now = Utc::now();
+        self.state.users.insert(
+            username,
+            Whitelist {
+                create_date: now,
+                last_use_date: now,
+                name: name.to_string(),
+            },
+        );
+        self.state_changed();
+    }
+
+    #[must_use]
+    pub const fn get_scene_schedules(&self) -> &BTreeMap<Uuid, SceneSchedule> {
+        &self.state.scene_schedules
+    }
+
+    /// Store a new scene schedule (for an existing scene), and return its id
+    pub fn add_scene_schedule(&mut self, schedule: SceneSchedule) -> ApiResult<Uuid> {
+        self.get::<Scene>(&RType::Scene.link_to(schedule.scene))?;
+
+        let id = Uuid::new_v4();
+        self.state.scene_schedules.insert(id, schedule);
+        self.state_changed();
+
+        Ok(id)
+    }
+
+    pub fn delete_scene_schedule(&mut self, id: &Uuid) -> ApiResult<SceneSchedule> {
+        let schedule = self
+            .state
+            .scene_schedules
+            .remove(id)
+            .ok_or(ApiError::NotFound(*id))?;
+        self.state_changed();
+
+        Ok(schedule)
+    }
+
+    /* remove the v1 address `addr` from all resourcelinks */
+    fn unlink_v1(&mut self, addr: &str) {
+        for link in self.state.resourcelinks.values_mut() {
+            link.links.retain(|other| other != addr);
+        }
+    }
+
     pub fn get_id_v1_index(&self, uuid: Uuid) -> ApiResult<u32> {
         self.state.id_v1(&uuid).ok_or(ApiError::NotFound(uuid))
     }
@@ -393,26 +1459,64 @@ impl Resources {
         self.state_updates.clone()
     }
 
+    /// Number of changes made to the persistent state since startup
+    #[must_use]
+    pub fn state_version(&self) -> u64 {
+        *self.state_version.borrow()
+    }
+
+    /// Follow changes to the persistent state, without taking the single
+    /// notification meant for the state writer
+    #[must_use]
+    pub fn state_watch(&self) -> watch::Receiver<u64> {
+        self.state_version.subscribe()
+    }
+
+    fn state_changed(&self) {
+        self.state_updates.notify_one();
+        self.state_version.send_modify(|version| *version += 1);
+    }
+
     #[must_use]
     pub fn hue_channel(&self) -> Receiver<EventBlock> {
         self.hue_updates.subscribe()
     }
 
     fn hue_event(&self, evt: EventBlock) {
+        /* events caused by an api request are logged, with its trace id */
+        if TraceId::current().is_some() {
+            log::debug!("Sending hue event {}", evt.id);
+        }
         if let Err(err) = self.hue_updates.send(evt) {
             log::trace!("Overflow on hue event pipe: {err}");
         }
     }
 
+    /// Enable the journal, returning the channel all resource changes are
+    /// sent to from now on
+    pub fn journal_channel(&mut self) -> mpsc::UnboundedReceiver<JournalEntry> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.journal = Some(tx);
+        rx
+    }
+
+    fn journal_record(&self, entry: JournalEntry) {
+        if let Some(journal) = &self.journal {
+            if journal.send(entry).is_err() {
+                log::warn!("Journal writer has stopped, change not recorded");
+            }
+        }
+    }
+
     #[must_use]
-    pub fn z2m_channel(&self) -> Receiver<Arc<ClientRequest>> {
+    pub fn z2m_channel(&self) -> Receiver<(Option<TraceId>, Arc<ClientRequest>)> {
         self.z2m_updates.subscribe()
     }
 
     pub fn z2m_request(&self, req: ClientRequest) -> ApiResult<()> {
         log::debug!("z2m request: {req:#?}");
 
-        self.z2m_updates.send(Arc::new(req))?;
+        self.z2m_updates.send((TraceId::current(), Arc::new(req)))?;
 
         Ok(())
     }
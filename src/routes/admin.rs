@@ -0,0 +1,415 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use axum::{
+    extract::{Path, Request, State},
+    http::header::AUTHORIZATION,
+    middleware::Next,
+    response::Response,
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::circadian::{self, CircadianRoom};
+use crate::error::{ApiError, ApiResult};
+use crate::hue::api::{Device, DeviceSoftwareUpdate, RType, Room, Scene, SoftwareUpdateState};
+use crate::hue::legacy_api::ApiResourceLink;
+use crate::logging;
+use crate::scheduler::SceneSchedule;
+use crate::server::appstate::AppState;
+use crate::server::eventclients::EventClientInfo;
+use crate::server::same_secret;
+use crate::z2m::api::{Expose, TouchlinkDevice};
+use crate::z2m::request::{ClientRequest, RequestFailure, TouchlinkAction};
+use crate::z2m::status::{LogEntry, ServerStatus};
+use crate::z2m::update::DeviceUpdate;
+
+#[derive(Debug, Deserialize)]
+struct TouchlinkTarget {
+    /// z2m server to send the request through (default: all servers)
+    server: Option<String>,
+    #[serde(flatten)]
+    device: TouchlinkDevice,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockRequest {
+    /// How long to lock the light or room for
+    minutes: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct LockReply {
+    until: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LogFilters {
+    /// Log filters in effect, in `RUST_LOG` syntax
+    filters: String,
+    /// Log filters bifrost was started with
+    #[serde(default, skip_deserializing)]
+    initial: String,
+}
+
+async fn get_device_options(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<Vec<Expose>>> {
+    let link = RType::Device.link_to(id);
+    let lock = state.res.lock().await;
+
+    lock.get::<Device>(&link)?;
+    let options = lock.get_device_options(&link).unwrap_or_default().to_vec();
+    drop(lock);
+
+    Ok(Json(options))
+}
+
+async fn put_device_options(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(put): Json<Value>,
+) -> ApiResult<Json<Value>> {
+    log::info!("PUT admin/device/{id}/options");
+    log::debug!("json data\n{}", serde_json::to_string_pretty(&put)?);
+
+    let link = RType::Device.link_to(id);
+    let lock = state.res.lock().await;
+
+    lock.get::<Device>(&link)?;
+    let known = lock.get_device_options(&link).unwrap_or_default();
+
+    let Value::Object(ref options) = put else {
+        return Err(ApiError::DeviceOptionsInvalid(put));
+    };
+
+    /* only pass on options that z2m reported for this device */
+    for key in options.keys() {
+        if !known.iter().any(|opt| opt.name() == Some(key)) {
+            return Err(ApiError::DeviceOptionUnknown(key.clone()));
+        }
+    }
+
+    lock.z2m_request(ClientRequest::device_options(link, put.clone()))?;
+    drop(lock);
+
+    Ok(Json(put))
+}
+
+async fn post_device_identify(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<Value>> {
+    log::info!("POST admin/device/{id}/identify");
+
+    let link = RType::Device.link_to(id);
+    let lock = state.res.lock().await;
+
+    let light = *lock
+        .get::<Device>(&link)?
+        .light_service()
+        .ok_or(ApiError::NotFound(id))?;
+
+    let upd = DeviceUpdate {
+        effect: Some("blink".to_string()),
+        ..DeviceUpdate::default()
+    };
+
+    lock.z2m_request(ClientRequest::light_update(light, upd))?;
+    drop(lock);
+
+    Ok(Json(json!({})))
+}
+
+async fn post_device_configure_reporting(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<Value>> {
+    log::info!("POST admin/device/{id}/configure_reporting");
+
+    let link = RType::Device.link_to(id);
+    let lock = state.res.lock().await;
+
+    lock.get::<Device>(&link)?;
+    lock.z2m_request(ClientRequest::configure_reporting(link))?;
+    drop(lock);
+
+    Ok(Json(json!({})))
+}
+
+async fn get_touchlink(
+    State(state): State<AppState>,
+) -> Json<BTreeMap<String, Vec<TouchlinkDevice>>> {
+    Json(state.res.lock().await.get_touchlink_results().clone())
+}
+
+async fn post_touchlink_scan(State(state): State<AppState>) -> ApiResult<Json<Value>> {
+    log::info!("POST admin/touchlink/scan");
+
+    let req = ClientRequest::touchlink(None, TouchlinkAction::Scan);
+    state.res.lock().await.z2m_request(req)?;
+
+    Ok(Json(json!({})))
+}
+
+async fn post_touchlink_identify(
+    State(state): State<AppState>,
+    Json(target): Json<TouchlinkTarget>,
+) -> ApiResult<Json<Value>> {
+    log::info!("POST admin/touchlink/identify: {target:?}");
+
+    let req = ClientRequest::touchlink(target.server, TouchlinkAction::Identify(target.device));
+    state.res.lock().await.z2m_request(req)?;
+
+    Ok(Json(json!({})))
+}
+
+async fn post_touchlink_factory_reset(
+    State(state): State<AppState>,
+    Json(target): Json<TouchlinkTarget>,
+) -> ApiResult<Json<Value>> {
+    log::warn!("POST admin/touchlink/factory_reset: {target:?}");
+
+    let req = ClientRequest::touchlink(target.server, TouchlinkAction::FactoryReset(target.device));
+    state.res.lock().await.z2m_request(req)?;
+
+    Ok(Json(json!({})))
+}
+
+async fn get_z2m_failures(State(state): State<AppState>) -> Json<VecDeque<RequestFailure>> {
+    Json(state.res.lock().await.get_z2m_failures().clone())
+}
+
+async fn get_z2m_logs(State(state): State<AppState>) -> Json<VecDeque<LogEntry>> {
+    Json(state.res.lock().await.get_z2m_logs().clone())
+}
+
+async fn get_z2m_servers(State(state): State<AppState>) -> Json<BTreeMap<String, ServerStatus>> {
+    Json(state.res.lock().await.get_z2m_status().clone())
+}
+
+async fn get_circadian(State(state): State<AppState>) -> Json<BTreeMap<Uuid, CircadianRoom>> {
+    let lock = state.res.lock().await;
+    Json(circadian::room_settings(&state.config().circadian, &lock))
+}
+
+async fn put_circadian(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(put): Json<CircadianRoom>,
+) -> ApiResult<Json<CircadianRoom>> {
+    log::info!("PUT admin/circadian/{id}: {put:?}");
+
+    let mut lock = state.res.lock().await;
+    lock.get::<Room>(&RType::Room.link_to(id))?;
+    lock.set_circadian(id, put);
+    drop(lock);
+
+    Ok(Json(put))
+}
+
+async fn post_scene_capture(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<Scene>> {
+    log::info!("POST admin/scene/{id}/capture");
+
+    let link = RType::Scene.link_to(id);
+    let mut lock = state.res.lock().await;
+    lock.scene_capture(&link)?;
+    let scene = lock.get::<Scene>(&link)?.clone();
+    drop(lock);
+
+    Ok(Json(scene))
+}
+
+/* devices with a firmware update available or in progress, by name */
+async fn get_ota(State(state): State<AppState>) -> Json<BTreeMap<String, DeviceSoftwareUpdate>> {
+    let lock = state.res.lock().await;
+
+    let updates = lock
+        .get_resources_by_type(RType::DeviceSoftwareUpdate)
+        .into_iter()
+        .filter_map(|rr| {
+            let swu = DeviceSoftwareUpdate::try_from(rr.obj).ok()?;
+            if swu.state == SoftwareUpdateState::NoUpdate {
+                return None;
+            }
+            let dev = lock.get::<Device>(&swu.owner).ok()?;
+            Some((dev.metadata.name.clone(), swu))
+        })
+        .collect();
+    drop(lock);
+
+    Json(updates)
+}
+
+async fn get_resourcelinks(State(state): State<AppState>) -> Json<BTreeMap<u32, ApiResourceLink>> {
+    Json(state.res.lock().await.get_resourcelinks().clone())
+}
+
+async fn get_locks(State(state): State<AppState>) -> Json<BTreeMap<Uuid, DateTime<Utc>>> {
+    Json(state.res.lock().await.get_locks(Utc::now()).clone())
+}
+
+async fn put_lock(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(put): Json<LockRequest>,
+) -> ApiResult<Json<LockReply>> {
+    log::info!("PUT admin/locks/{id}: {put:?}");
+
+    let mut lock = state.res.lock().await;
+    let rr = lock.get_resource_by_id(&id)?;
+    if !matches!(rr.obj.rtype(), RType::Light | RType::Room) {
+        return Err(ApiError::WrongType(RType::Light, rr.obj.rtype()));
+    }
+
+    let until = Utc::now() + Duration::minutes(i64::from(put.minutes));
+    lock.set_lock(id, until);
+    drop(lock);
+
+    Ok(Json(LockReply { until }))
+}
+
+async fn delete_lock(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<Value>> {
+    log::info!("DELETE admin/locks/{id}");
+
+    if !state.res.lock().await.remove_lock(&id) {
+        return Err(ApiError::NotFound(id));
+    }
+
+    Ok(Json(json!({})))
+}
+
+async fn get_schedules(State(state): State<AppState>) -> Json<BTreeMap<Uuid, SceneSchedule>> {
+    Json(state.res.lock().await.get_scene_schedules().clone())
+}
+
+async fn post_schedule(
+    State(state): State<AppState>,
+    Json(post): Json<SceneSchedule>,
+) -> ApiResult<Json<Value>> {
+    log::info!("POST admin/schedules: {post:?}");
+
+    let id = state.res.lock().await.add_scene_schedule(post)?;
+
+    Ok(Json(json!({"id": id})))
+}
+
+async fn delete_schedule(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<Value>> {
+    log::info!("DELETE admin/schedules/{id}");
+
+    state.res.lock().await.delete_scene_schedule(&id)?;
+
+    Ok(Json(json!({})))
+}
+
+async fn get_linkbutton(State(state): State<AppState>) -> Json<Value> {
+    Json(json!({"pressed": state.linkbutton_pressed()}))
+}
+
+async fn post_linkbutton(State(state): State<AppState>) -> Json<Value> {
+    state.press_linkbutton();
+    Json(json!({"pressed": state.linkbutton_pressed()}))
+}
+
+async fn get_eventstream_clients(State(state): State<AppState>) -> Json<Vec<EventClientInfo>> {
+    Json(state.event_clients().info())
+}
+
+fn current_log_filters() -> Json<LogFilters> {
+    let (filters, initial) = logging::log_filters().unwrap_or_default();
+    Json(LogFilters { filters, initial })
+}
+
+async fn get_log_filters() -> Json<LogFilters> {
+    current_log_filters()
+}
+
+async fn put_log_filters(Json(put): Json<LogFilters>) -> ApiResult<Json<LogFilters>> {
+    log::warn!("PUT admin/log/filters: {:?}", put.filters);
+
+    logging::set_log_filters(&put.filters)?;
+
+    Ok(current_log_filters())
+}
+
+async fn delete_log_filters() -> Json<LogFilters> {
+    log::warn!("DELETE admin/log/filters");
+
+    logging::reset_log_filters();
+
+    current_log_filters()
+}
+
+/// Middleware for the admin api: with `admin_token` set, only requests with
+/// that token (as `Authorization: Bearer <token>`) are let through
+pub async fn require_token(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> ApiResult<Response> {
+    if let Some(token) = &state.config().bifrost.admin_token {
+        let given = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|auth| auth.as_bytes().strip_prefix(b"Bearer "))
+            .unwrap_or_default();
+        if !same_secret(given, token.as_bytes()) {
+            return Err(ApiError::AdminTokenInvalid);
+        }
+    }
+
+    Ok(next.run(req).await)
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/device/:id/options",
+            get(get_device_options).put(put_device_options),
+        )
+        .route("/device/:id/identify", post(post_device_identify))
+        .route(
+            "/device/:id/configure_reporting",
+            post(post_device_configure_reporting),
+        )
+        .route("/touchlink", get(get_touchlink))
+        .route("/touchlink/scan", post(post_touchlink_scan))
+        .route("/touchlink/identify", post(post_touchlink_identify))
+        .route(
+            "/touchlink/factory_reset",
+            post(post_touchlink_factory_reset),
+        )
+        .route("/z2m/failures", get(get_z2m_failures))
+        .route("/z2m/logs", get(get_z2m_logs))
+        .route("/z2m/servers", get(get_z2m_servers))
+        .route("/circadian", get(get_circadian))
+        .route("/circadian/:id", put(put_circadian))
+        .route("/scene/:id/capture", post(post_scene_capture))
+        .route("/ota", get(get_ota))
+        .route("/resourcelinks", get(get_resourcelinks))
+        .route("/locks", get(get_locks))
+        .route("/locks/:id", put(put_lock).delete(delete_lock))
+        .route("/schedules", get(get_schedules).post(post_schedule))
+        .route("/schedules/:id", delete(delete_schedule))
+        .route("/linkbutton", get(get_linkbutton).post(post_linkbutton))
+        .route("/eventstream/clients", get(get_eventstream_clients))
+        .route(
+            "/log/filters",
+            get(get_log_filters)
+                .put(put_log_filters)
+                .delete(delete_log_filters),
+        )
+}
@@ -2,24 +2,31 @@ use std::collections::HashMap;
 
 use axum::{
     extract::{Path, State},
-    response::IntoResponse,
-    routing::{get, post, put},
-    Json, Router,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Extension, Json, Router,
 };
 
 use bytes::Bytes;
+use chrono::Utc;
 use log::{info, warn};
 use serde_json::{json, Value};
 use tokio::sync::MutexGuard;
 use uuid::Uuid;
 
-use crate::hue::api::{Device, GroupedLight, Light, RType, ResourceLink, Room, Scene, V1Reply};
+use crate::hue::api::{
+    Device, GroupedLight, Light, LightCapabilities, RType, ResourceLink, Room, Scene, V1Reply,
+    ZigbeeConnectivity, ZigbeeConnectivityStatus, Zone,
+};
 use crate::hue::legacy_api::{
-    ApiGroup, ApiLight, ApiLightStateUpdate, ApiResourceType, ApiScene, ApiUserConfig,
-    Capabilities, HueResult, NewUser, NewUserReply,
+    ApiConfigUpdate, ApiGroup, ApiGroupState, ApiLight, ApiLightStateUpdate, ApiResourceLinkUpdate,
+    ApiResourceType, ApiScene, ApiUserConfig, Capabilities, HueError, HueResult, NewResourceLink,
+    NewUser, NewUserReply,
 };
+use crate::model::brightness::Brightness;
 use crate::resource::Resources;
 use crate::server::appstate::AppState;
+use crate::server::proxy::ClientInfo;
 use crate::z2m::request::ClientRequest;
 use crate::z2m::update::DeviceUpdate;
 use crate::{
@@ -28,17 +35,74 @@ use crate::{
 };
 
 async fn get_api_config(State(state): State<AppState>) -> impl IntoResponse {
-    Json(state.api_short_config())
+    let lock = state.res.lock().await;
+    Json(state.api_short_config(&lock))
+}
+
+/* like a real bridge, answer config requests from unknown users (apps probe
+ * e.g. /api/0/config and /api/nouser/config) with the short config, and only
+ * give the full config to users created by pairing */
+async fn get_api_user_config(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Response {
+    let lock = state.res.lock().await;
+    /* serialized directly (not through a Value), to keep the field order */
+    match username.parse() {
+        Ok(username) if lock.get_users().contains_key(&username) => {
+            Json(state.api_config(username, &lock)).into_response()
+        }
+        _ => Json(state.api_short_config(&lock)).into_response(),
+    }
+}
+
+/* the hue app polls the portal (remote access) state, and tries to turn the
+ * portal services on when they are off. bifrost has no portal, so these
+ * only report what the fake_portal option says. */
+async fn get_api_user_portalstate(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.portal_state())
 }
 
-async fn post_api(bytes: Bytes) -> ApiResult<impl IntoResponse> {
+async fn put_api_user_config(
+    State(state): State<AppState>,
+    Json(upd): Json<ApiConfigUpdate>,
+) -> ApiResult<Json<Value>> {
+    let portalservices = upd
+        .portalservices
+        .map(|_| state.config().bifrost.fake_portal);
+    let reply = V1Reply::new("/config".to_string()).add_option("portalservices", portalservices)?;
+    Ok(Json(reply.json()))
+}
+
+async fn post_api(State(state): State<AppState>, bytes: Bytes) -> ApiResult<Response> {
     let json: NewUser = serde_json::from_slice(&bytes)?;
     info!("post: {json:?}");
+
+    if !state.pairing_allowed() {
+        warn!("Refusing to pair {json:?}: link button not pressed");
+        let error = HueResult::<Value>::Error(HueError::link_button_not_pressed());
+        return Ok(Json(vec![error]).into_response());
+    }
+
     let res = NewUserReply {
         clientkey: Uuid::new_v4(),
         username: Uuid::new_v4(),
     };
-    Ok(Json(vec![HueResult::Success(res)]))
+    state
+        .res
+        .lock()
+        .await
+        .add_user(res.username, &json.devicetype);
+    Ok(Json(vec![HueResult::Success(res)]).into_response())
+}
+
+/* lights without connectivity information (e.g. virtual lights) are always reachable */
+fn is_reachable(res: &MutexGuard<Resources>, dev: &Device) -> bool {
+    dev.zigbee_connectivity_service()
+        .and_then(|rl| res.get::<ZigbeeConnectivity>(rl).ok())
+        .map_or(true, |zbc| {
+            zbc.status == ZigbeeConnectivityStatus::Connected
+        })
 }
 
 fn get_lights(res: &MutexGuard<Resources>) -> ApiResult<HashMap<String, ApiLight>> {
@@ -49,40 +113,81 @@ fn get_lights(res: &MutexGuard<Resources>) -> ApiResult<HashMap<String, ApiLight
         let dev = res.get::<Device>(&light.owner)?;
         lights.insert(
             res.get_id_v1(rr.id)?,
-            ApiLight::from_dev_and_light(&rr.id, dev, &light),
+            ApiLight::from_dev_and_light(&rr.id, dev, &light)
+                .with_reachable(is_reachable(res, dev)),
         );
     }
 
     Ok(lights)
 }
 
+/* v1 member ids, state and combined capabilities of the lights in a group */
+fn group_lights(
+    res: &MutexGuard<Resources>,
+    members: &[ResourceLink],
+) -> (Vec<String>, ApiGroupState, LightCapabilities) {
+    let lights: Vec<String> = members
+        .iter()
+        .filter_map(|rl| res.get_id_v1(rl.rid).ok())
+        .collect();
+
+    let member_lights: Vec<&Light> = members
+        .iter()
+        .filter_map(|rl| res.get::<Light>(rl).ok())
+        .collect();
+
+    let state = ApiGroupState::from_lights(member_lights.iter().map(|light| light.on.on));
+    let caps: Vec<LightCapabilities> = member_lights
+        .iter()
+        .map(|light| light.capabilities())
+        .collect();
+
+    (lights, state, caps.iter().collect())
+}
+
 fn get_groups(res: &MutexGuard<Resources>) -> ApiResult<HashMap<String, ApiGroup>> {
-    let mut rooms = HashMap::new();
+    let mut groups = HashMap::new();
 
     for rr in res.get_resources_by_type(RType::Room) {
         let room: Room = rr.obj.try_into()?;
         let uuid = room
-            .services
-            .iter()
-            .find(|rl| rl.rtype == RType::GroupedLight)
+            .grouped_light_service()
             .ok_or(ApiError::NotFound(rr.id))?;
 
         let glight = res.get::<GroupedLight>(uuid)?.clone();
-        let lights: Vec<String> = room
+        let members: Vec<ResourceLink> = room
             .children
             .iter()
             .filter_map(|rl| res.get(rl).ok())
             .filter_map(Device::light_service)
-            .filter_map(|rl| res.get_id_v1(rl.rid).ok())
+            .copied()
             .collect();
 
-        rooms.insert(
+        let (lights, state, caps) = group_lights(res, &members);
+
+        groups.insert(
             res.get_id_v1(rr.id)?,
-            ApiGroup::from_lights_and_room(glight, lights, room),
+            ApiGroup::from_lights_and_room(glight, lights, room, state, &caps),
         );
     }
 
-    Ok(rooms)
+    for rr in res.get_resources_by_type(RType::Zone) {
+        let zone: Zone = rr.obj.try_into()?;
+        let uuid = zone
+            .grouped_light_service()
+            .ok_or(ApiError::NotFound(rr.id))?;
+
+        let glight = res.get::<GroupedLight>(uuid)?.clone();
+        let members = res.get_zone_lights(&zone);
+        let (lights, state, caps) = group_lights(res, &members);
+
+        groups.insert(
+            res.get_id_v1(rr.id)?,
+            ApiGroup::from_lights_and_zone(glight, lights, zone, state, &caps),
+        );
+    }
+
+    Ok(groups)
 }
 
 fn get_scenes(owner: &Uuid, res: &MutexGuard<Resources>) -> ApiResult<HashMap<String, ApiScene>> {
@@ -108,10 +213,14 @@ async fn get_api_user(
     let lock = state.res.lock().await;
 
     Ok(Json(ApiUserConfig {
-        config: state.api_config(username),
+        config: state.api_config(username, &lock),
         groups: get_groups(&lock)?,
         lights: get_lights(&lock)?,
-        resourcelinks: HashMap::new(),
+        resourcelinks: lock
+            .get_resourcelinks()
+            .iter()
+            .map(|(id, link)| (*id, link.clone()))
+            .collect(),
         rules: HashMap::new(),
         scenes: get_scenes(&username, &lock)?,
         schedules: HashMap::new(),
@@ -125,22 +234,36 @@ async fn get_api_user_resource(
 ) -> ApiResult<Json<Value>> {
     let lock = &state.res.lock().await;
     match resource {
-        ApiResourceType::Config => Ok(Json(json!(state.api_config(username)))),
+        ApiResourceType::Config => Ok(Json(json!(state.api_config(username, lock)))),
         ApiResourceType::Lights => Ok(Json(json!(get_lights(lock)?))),
         ApiResourceType::Groups => Ok(Json(json!(get_groups(lock)?))),
         ApiResourceType::Scenes => Ok(Json(json!(get_scenes(&username, lock)?))),
-        ApiResourceType::Resourcelinks
-        | ApiResourceType::Rules
-        | ApiResourceType::Schedules
-        | ApiResourceType::Sensors => Ok(Json(json!({}))),
+        ApiResourceType::Resourcelinks => Ok(Json(json!(lock.get_resourcelinks()))),
+        ApiResourceType::Rules | ApiResourceType::Schedules | ApiResourceType::Sensors => {
+            Ok(Json(json!({})))
+        }
         ApiResourceType::Capabilities => Ok(Json(json!(Capabilities::new()))),
     }
 }
 
 async fn post_api_user_resource(
-    Path((_username, resource)): Path<(Uuid, ApiResourceType)>,
+    State(state): State<AppState>,
+    Path((username, resource)): Path<(Uuid, ApiResourceType)>,
     Json(req): Json<Value>,
 ) -> ApiResult<Json<Value>> {
+    if resource == ApiResourceType::Resourcelinks {
+        let new: NewResourceLink = serde_json::from_value(req)?;
+        let id = state
+            .res
+            .lock()
+            .await
+            .add_resourcelink(new.with_owner(username))?;
+        log::info!("Created v1 resourcelink {id}");
+
+        let reply = HueResult::<Value>::Success(json!({"id": id.to_string()}));
+        return Ok(Json(json!([reply])));
+    }
+
     warn!("POST v1 user resource unsupported");
     warn!("Request: {req:?}");
     Err(ApiError::V1CreateUnsupported(resource))
@@ -169,7 +292,8 @@ async fn get_api_user_resource_id(
             let light = lock.get::<Light>(&link)?;
             let dev = lock.get::<Device>(&light.owner)?;
 
-            json!(ApiLight::from_dev_and_light(&uuid, dev, light))
+            json!(ApiLight::from_dev_and_light(&uuid, dev, light)
+                .with_reachable(is_reachable(&lock, dev)))
         }
         ApiResourceType::Scenes => {
             let lock = state.res.lock().await;
@@ -179,6 +303,9 @@ async fn get_api_user_resource_id(
 
             json!(ApiScene::from_scene(&lock, username, scene)?)
         }
+        ApiResourceType::Resourcelinks => {
+            json!(state.res.lock().await.get_resourcelink(id)?)
+        }
         ApiResourceType::Groups => {
             let lock = state.res.lock().await;
             let groups = get_groups(&lock)?;
@@ -194,11 +321,138 @@ async fn get_api_user_resource_id(
     Ok(Json(result))
 }
 
+async fn put_api_user_resource_id_root(
+    State(state): State<AppState>,
+    Path((_username, resource, id)): Path<(String, ApiResourceType, u32)>,
+    Json(req): Json<Value>,
+) -> ApiResult<Json<Value>> {
+    /* like a real bridge, other resources are changed through a sub-path
+     * (e.g. /lights/1/state), or not at all */
+    if resource != ApiResourceType::Resourcelinks {
+        let rtype = serde_json::to_value(resource)?;
+        let address = format!("/{}/{id}", rtype.as_str().unwrap_or_default());
+        let error = HueResult::<Value>::Error(HueError::method_not_available(&address, "PUT"));
+        return Ok(Json(json!([error])));
+    }
+
+    let upd: ApiResourceLinkUpdate = serde_json::from_value(req)?;
+    state.res.lock().await.update_resourcelink(id, |link| {
+        if let Some(name) = &upd.name {
+            link.name.clone_from(name);
+        }
+        if let Some(description) = &upd.description {
+            link.description.clone_from(description);
+        }
+        if let Some(classid) = upd.classid {
+            link.classid = classid;
+        }
+        if let Some(links) = &upd.links {
+            link.links.clone_from(links);
+        }
+    })?;
+
+    let reply = V1Reply::new(format!("/resourcelinks/{id}"))
+        .add_option("name", upd.name)?
+        .add_option("description", upd.description)?
+        .add_option("classid", upd.classid)?
+        .add_option("links", upd.links)?;
+
+    Ok(Json(reply.json()))
+}
+
+async fn delete_api_user_resource_id(
+    State(state): State<AppState>,
+    Path((_username, resource, id)): Path<(String, ApiResourceType, u32)>,
+) -> ApiResult<Json<Value>> {
+    if resource != ApiResourceType::Resourcelinks {
+        return Err(ApiError::V1DeleteUnsupported(resource));
+    }
+
+    let deleted = state.res.lock().await.delete_resourcelink(id)?;
+    log::info!("Deleted v1 resourcelinks {deleted:?}");
+
+    let reply = HueResult::<Value>::Success(json!(format!("/resourcelinks/{id} deleted")));
+    Ok(Json(json!([reply])))
+}
+
+/* like a real bridge, answer commands from clients that send them too fast
+ * with error 901, so they back off */
+fn rate_limited(
+    state: &AppState,
+    client: Option<&ClientInfo>,
+    address: &str,
+) -> Option<Json<Value>> {
+    let client = client?;
+    if state.v1_allow(client.addr) {
+        return None;
+    }
+
+    log::warn!(
+        "v1 client {} over rate limit, rejecting {address}",
+        client.addr
+    );
+    let error = HueResult::<Value>::Error(HueError::internal_error(address, 503));
+    Some(Json(json!([error])))
+}
+
+/* lights (or rooms) locked through the admin api, and lights on a z2m server
+ * that is not connected, reject changes with the error a real bridge uses for
+ * parameters that cannot be changed */
+fn refused(lock: &Resources, link: &ResourceLink, address: &str) -> ApiResult<Option<Json<Value>>> {
+    let res = lock
+        .check_unlocked(link, Utc::now())
+        .and_then(|()| lock.check_reachable(link));
+    match res {
+        Ok(()) => Ok(None),
+        Err(err @ (ApiError::Locked(..) | ApiError::ServerOffline(_))) => {
+            log::warn!("Rejecting {address}: {err}");
+            let error =
+                HueResult::<Value>::Error(HueError::not_modifiable(address, &err.to_string()));
+            Ok(Some(Json(json!([error]))))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/* Send a v1 light state update, fitted to what the light can do */
+fn put_light_state(res: &Resources, link: ResourceLink, id: u32, req: Value) -> ApiResult<Value> {
+    let caps = res.get::<Light>(&link)?.capabilities();
+    let upd: ApiLightStateUpdate = serde_json::from_value(req)?;
+    let (upd, unsupported) = upd.split_unsupported(&caps);
+    let (xy, ct) = caps.fit_color(upd.xy.map(Into::into), upd.ct)?;
+
+    let payload = DeviceUpdate::default()
+        .with_state(upd.on)
+        .with_brightness(upd.bri.map(|bri| Brightness::from_v1(bri).z2m()))
+        .with_color_xy(xy)
+        .with_color_temp(ct);
+
+    res.z2m_request(ClientRequest::light_update(link, payload))?;
+
+    let mut reply = V1Reply::for_light(id, "state")
+        .with_light_state_update(&upd)?
+        .json();
+
+    /* like a real bridge, report the parameters the light does not have */
+    if let Value::Array(results) = &mut reply {
+        for param in unsupported {
+            let address = format!("/lights/{id}/state/{param}");
+            let error = HueError::not_available(&address, param);
+            results.push(serde_json::to_value(HueResult::<Value>::Error(error))?);
+        }
+    }
+
+    Ok(reply)
+}
+
 async fn put_api_user_resource_id(
     State(state): State<AppState>,
+    client_info: Option<Extension<ClientInfo>>,
     Path((_username, resource, id, path)): Path<(String, ApiResourceType, u32, String)>,
     Json(req): Json<Value>,
 ) -> ApiResult<Json<Value>> {
+    let client = client_info.as_deref();
+
     match resource {
         ApiResourceType::Lights => {
             log::debug!("req: {}", serde_json::to_string_pretty(&req)?);
@@ -206,23 +460,21 @@ async fn put_api_user_resource_id(
                 return Err(ApiError::V1NotFound(id))?;
             }
 
+            if let Some(reply) = rate_limited(&state, client, &format!("/lights/{id}/{path}")) {
+                return Ok(reply);
+            }
+
             let lock = state.res.lock().await;
             let uuid = lock.from_id_v1(id)?;
             let link = ResourceLink::new(uuid, RType::Light);
-            let upd: ApiLightStateUpdate = serde_json::from_value(req)?;
-
-            let payload = DeviceUpdate::default()
-                .with_state(upd.on)
-                .with_brightness(upd.bri.map(f64::from))
-                .with_color_xy(upd.xy.map(Into::into))
-                .with_color_temp(upd.ct);
+            if let Some(reply) = refused(&lock, &link, &format!("/lights/{id}/{path}"))? {
+                return Ok(reply);
+            }
 
-            lock.z2m_request(ClientRequest::light_update(link, payload))?;
+            let reply = put_light_state(&lock, link, id, req)?;
             drop(lock);
 
-            let reply = V1Reply::for_light(id, &path).with_light_state_update(&upd)?;
-
-            Ok(Json(reply.json()))
+            Ok(Json(reply))
         }
         ApiResourceType::Groups => {
             log::debug!("req: {}", serde_json::to_string_pretty(&req)?);
@@ -230,11 +482,35 @@ async fn put_api_user_resource_id(
                 return Err(ApiError::V1NotFound(id))?;
             }
 
+            if let Some(reply) = rate_limited(&state, client, &format!("/groups/{id}/{path}")) {
+                return Ok(reply);
+            }
+
             let lock = state.res.lock().await;
-            let uuid = lock.from_id_v1(id)?;
-            let link = ResourceLink::new(uuid, RType::Room);
-            let room: &Room = lock.get(&link)?;
-            let glight = room.grouped_light_service().unwrap();
+
+            /* group 0 is the bridge home, containing all lights */
+            let home = id == 0;
+            let mut zone_lights = None;
+            let glight = if home {
+                lock.get_bridge_home_light()
+                    .ok_or(ApiError::V1NotFound(id))?
+            } else {
+                let uuid = lock.from_id_v1(id)?;
+                if let Ok(zone) = lock.get::<Zone>(&RType::Zone.link_to(uuid)) {
+                    /* zones without a z2m group are updated one light at a time */
+                    if !state.config().zone_zigbee_group(&zone.metadata.name) {
+                        zone_lights = Some(lock.get_zone_lights(zone));
+                    }
+                    *zone
+                        .grouped_light_service()
+                        .ok_or(ApiError::V1NotFound(id))?
+                } else {
+                    let room: &Room = lock.get(&RType::Room.link_to(uuid))?;
+                    *room
+                        .grouped_light_service()
+                        .ok_or(ApiError::V1NotFound(id))?
+                }
+            };
 
             let upd: ApiGroupActionUpdate = serde_json::from_value(req)?;
 
@@ -242,11 +518,23 @@ async fn put_api_user_resource_id(
                 ApiGroupActionUpdate::LightUpdate(upd) => {
                     let payload = DeviceUpdate::default()
                         .with_state(upd.on)
-                        .with_brightness(upd.bri.map(f64::from))
+                        .with_brightness(upd.bri.map(|bri| Brightness::from_v1(bri).z2m()))
                         .with_color_xy(upd.xy.map(Into::into))
                         .with_color_temp(upd.ct);
 
-                    lock.z2m_request(ClientRequest::group_update(*glight, payload))?;
+                    if let Some(reply) = refused(&lock, &glight, &format!("/groups/{id}/{path}"))? {
+                        return Ok(reply);
+                    }
+
+                    if home {
+                        lock.bridge_home_request(&payload)?;
+                    } else if let Some(lights) = zone_lights {
+                        for light in lights {
+                            lock.z2m_request(ClientRequest::light_update(light, payload.clone()))?;
+                        }
+                    } else {
+                        lock.z2m_request(ClientRequest::group_update(glight, payload))?;
+                    }
                     drop(lock);
 
                     V1Reply::for_group(id, &path).with_light_state_update(&upd)?
@@ -255,7 +543,10 @@ async fn put_api_user_resource_id(
                     let scene_id = upd.scene.parse()?;
                     let scene_uuid = lock.from_id_v1(scene_id)?;
                     let rlink = RType::Scene.link_to(scene_uuid);
-                    lock.z2m_request(ClientRequest::scene_recall(rlink))?;
+                    if let Some(reply) = refused(&lock, &rlink, &format!("/groups/{id}/{path}"))? {
+                        return Ok(reply);
+                    }
+                    lock.scene_recall(&rlink)?;
                     drop(lock);
 
                     V1Reply::for_group(id, &path).add("scene", upd.scene)?
@@ -274,14 +565,30 @@ async fn put_api_user_resource_id(
     }
 }
 
+/// Minimal v1 api, for setups with the full v1 api disabled.
+///
+/// Only contains the endpoints needed to discover the bridge and pair
+/// new clients with it.
+pub fn pairing_router() -> Router<AppState> {
+    Router::new()
+        .route("/", post(post_api))
+        .route("/config", get(get_api_config))
+        .route("/:user/config", get(get_api_user_config))
+}
+
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/", post(post_api))
         .route("/config", get(get_api_config))
         .route("/:user", get(get_api_user))
+        .route("/:user/config", get(get_api_user_config))
+        .route("/:user/config", put(put_api_user_config))
+        .route("/:user/config/portalstate", get(get_api_user_portalstate))
         .route("/:user/:rtype", get(get_api_user_resource))
         .route("/:user/:rtype", post(post_api_user_resource))
         .route("/:user/:rtype", put(put_api_user_resource))
         .route("/:user/:rtype/:id", get(get_api_user_resource_id))
+        .route("/:user/:rtype/:id", put(put_api_user_resource_id_root))
+        .route("/:user/:rtype/:id", delete(delete_api_user_resource_id))
         .route("/:user/:rtype/:id/:key", put(put_api_user_resource_id))
 }
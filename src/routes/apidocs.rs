@@ -0,0 +1,328 @@
+use axum::extract::State;
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use serde_json::{json, Map, Value};
+
+use crate::config::AppConfig;
+use crate::server::appstate::AppState;
+use crate::server::proxy::ClientInfo;
+
+/*
+ * OpenAPI document for the endpoints Bifrost implements.
+ *
+ * Bifrost only implements a subset of the hue api, so integrators need to know
+ * exactly which endpoints are there. The document is generated from the tables
+ * below (and the config, which decides if the v1 api is enabled). Endpoints
+ * that are not part of any hue api are marked with "x-bifrost-extension".
+ */
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Section {
+    /// Part of the v1 api that is available even with `enable_v1` off, as
+    /// long as `v1_pairing` is on
+    V1Pairing,
+    V1,
+    V2,
+    EventStream,
+    Licenses,
+    Admin,
+    ApiDocs,
+    /// Only present when the frontend of any z2m server is served
+    Z2mFrontend,
+}
+
+impl Section {
+    const fn tag(self) -> &'static str {
+        match self {
+            Self::V1Pairing | Self::V1 => "v1",
+            Self::V2 => "v2",
+            Self::EventStream => "eventstream",
+            Self::Licenses => "licenses",
+            Self::Admin => "admin",
+            Self::ApiDocs => "api-docs",
+            Self::Z2mFrontend => "z2m",
+        }
+    }
+
+    /* everything outside the hue apis is specific to bifrost */
+    const fn is_extension(self) -> bool {
+        matches!(self, Self::Admin | Self::ApiDocs | Self::Z2mFrontend)
+    }
+
+    fn is_enabled(self, config: &AppConfig) -> bool {
+        match self {
+            Self::V1 => config.bifrost.enable_v1,
+            Self::V1Pairing => config.bifrost.enable_v1 || config.bifrost.v1_pairing,
+            Self::Z2mFrontend => config
+                .z2m
+                .servers
+                .values()
+                .any(|server| server.frontend.is_some()),
+            _ => true,
+        }
+    }
+}
+
+const TAGS: &[(&str, &str)] = &[
+    ("v1", "Legacy hue api (partial)"),
+    ("v2", "Modern hue (clip v2) api (partial)"),
+    ("eventstream", "Server-sent events for v2 resource changes"),
+    (
+        "licenses",
+        "License information, as served by a real bridge",
+    ),
+    (
+        "admin",
+        "Bifrost admin api, not part of any hue api (with a bearer token, if admin_token is set)",
+    ),
+    ("api-docs", "This document"),
+    (
+        "z2m",
+        "Frontends of z2m servers, behind basic authentication (over https)",
+    ),
+];
+
+/* endpoints of each section, one per line: method, path and summary */
+
+const V1_PAIRING: &str = "
+POST   /api                Create a user (while the link button is pressed)
+GET    /api/config         Short bridge config
+GET    /api/{user}/config  Bridge config (the short one, for unknown users)
+";
+
+const V1: &str = "
+GET    /api/{user}                        Full state: lights, groups, scenes and config
+PUT    /api/{user}/config                 Portal services (acknowledged as set by fake_portal)
+GET    /api/{user}/config/portalstate     Cloud portal state (connected with fake_portal)
+GET    /api/{user}/{rtype}                Lights, groups, scenes, capabilities or resourcelinks
+POST   /api/{user}/{rtype}                Create a resourcelink
+PUT    /api/{user}/{rtype}                Not supported for any resource type
+GET    /api/{user}/{rtype}/{id}           A light, group, scene or resourcelink
+PUT    /api/{user}/{rtype}/{id}           Update a resourcelink
+DELETE /api/{user}/{rtype}/{id}           Delete a resourcelink
+PUT    /api/{user}/{rtype}/{id}/{key}     Light state, or group action
+";
+
+const V2: &str = "
+GET    /clip/v2/resource                        All resources
+GET    /clip/v2/resource/{rtype}                Resources of a type
+POST   /clip/v2/resource/{rtype}                Create a resource (e.g. a behavior instance)
+GET    /clip/v2/resource/{rtype}/{id}           A single resource
+PUT    /clip/v2/resource/{rtype}/{id}           Not supported, except for the types below
+DELETE /clip/v2/resource/{rtype}/{id}           Not supported, except for the types below
+GET    /clip/v2/resource/bridge/{id}            The bridge
+PUT    /clip/v2/resource/bridge/{id}            Time zone, and name (as an extension)
+GET    /clip/v2/resource/light                  All lights
+GET    /clip/v2/resource/light/{id}             A single light
+PUT    /clip/v2/resource/light/{id}             Change a light, or start a timed effect
+PUT    /clip/v2/resource/grouped_light/{id}     Change the lights of a room, zone or the home
+PUT    /clip/v2/resource/motion/{id}            Enable a motion sensor, or change its sensitivity
+PUT    /clip/v2/resource/contact/{id}           Enable a contact sensor
+PUT    /clip/v2/resource/geolocation/{id}       Location (used for circadian mode)
+GET    /clip/v2/resource/scene                  All scenes
+POST   /clip/v2/resource/scene                  Create a scene
+GET    /clip/v2/resource/scene/{id}             A single scene
+PUT    /clip/v2/resource/scene/{id}             Change or recall a scene
+DELETE /clip/v2/resource/scene/{id}             Delete a scene
+POST   /clip/v2/resource/zone                   Create a zone
+PUT    /clip/v2/resource/zone/{id}              Change a zone
+DELETE /clip/v2/resource/zone/{id}              Delete a zone
+";
+
+const EVENTSTREAM: &str = "
+GET    /eventstream/clip/v2    Server-sent events for all resource changes
+GET    /clip/v2/eventstream    Same as /eventstream/clip/v2
+";
+
+const LICENSES: &str = "
+GET    /licenses/packages.json         Licenses of javascript packages
+GET    /licenses/hardcoded.json        Licenses of hardcoded components
+GET    /licenses/rust-packages.json    Licenses of rust packages
+GET    /licenses/gpl-3.0.txt           Text of the GPL-3.0 license
+";
+
+const ADMIN: &str = "
+GET    /admin/device/{id}/options               z2m device options, by v2 device id
+PUT    /admin/device/{id}/options               Change z2m device options (e.g. transition)
+POST   /admin/device/{id}/identify              Blink the light of a device
+POST   /admin/device/{id}/configure_reporting   Make a light report its state changes to z2m
+GET    /admin/touchlink                         Results of the latest touchlink scan, by z2m server
+POST   /admin/touchlink/scan                    Start a touchlink scan on all z2m servers
+POST   /admin/touchlink/identify                Identify a scanned device
+POST   /admin/touchlink/factory_reset           Factory reset a scanned device
+GET    /admin/z2m/failures                      Recent z2m requests that failed, or were never answered
+GET    /admin/z2m/logs                          Recent warnings and errors logged by z2m
+GET    /admin/z2m/servers                       Connection state, message and reconciliation counters
+GET    /admin/circadian                         Circadian settings of all rooms, by v2 room id
+PUT    /admin/circadian/{id}                    Enable circadian mode for a room (until restart)
+POST   /admin/scene/{id}/capture                Store the current light states in a scene
+GET    /admin/ota                               Devices with a firmware update available or installing
+GET    /admin/resourcelinks                     All v1 resourcelinks
+GET    /admin/locks                             Locked lights and rooms, with the time each lock ends
+PUT    /admin/locks/{id}                        Lock a light or room for some minutes
+DELETE /admin/locks/{id}                        Unlock a light or room
+GET    /admin/schedules                         Scene schedules
+POST   /admin/schedules                         Add a scene schedule
+DELETE /admin/schedules/{id}                    Remove a scene schedule
+GET    /admin/linkbutton                        State of the emulated link button
+POST   /admin/linkbutton                        Press the emulated link button
+GET    /admin/eventstream/clients               Connected eventstream clients, with their event lag
+GET    /admin/log/filters                       Log filters in effect, and the ones set at startup
+PUT    /admin/log/filters                       Replace the log filters (until restart)
+DELETE /admin/log/filters                       Go back to the log filters set at startup
+";
+
+const API_DOCS: &str = "
+GET    /api-docs    This document
+";
+
+const Z2M_FRONTEND: &str = "
+GET    /z2m/{server}           Redirect to the frontend of a z2m server
+GET    /z2m/{server}/          Frontend of a z2m server
+GET    /z2m/{server}/{path}    Anything under the frontend (any method, and its websocket)
+";
+
+const SECTIONS: &[(Section, &str)] = &[
+    (Section::V1Pairing, V1_PAIRING),
+    (Section::V1, V1),
+    (Section::V2, V2),
+    (Section::EventStream, EVENTSTREAM),
+    (Section::Licenses, LICENSES),
+    (Section::Admin, ADMIN),
+    (Section::ApiDocs, API_DOCS),
+    (Section::Z2mFrontend, Z2M_FRONTEND),
+];
+
+struct Endpoint {
+    method: &'static str,
+    path: &'static str,
+    section: Section,
+    summary: &'static str,
+}
+
+impl Endpoint {
+    fn all() -> impl Iterator<Item = Self> {
+        SECTIONS.iter().flat_map(|(section, table)| {
+            table.lines().filter_map(|line| {
+                let (method, rest) = line.split_once(' ')?;
+                let (path, summary) = rest.trim_start().split_once(' ')?;
+                Some(Self {
+                    method,
+                    path,
+                    section: *section,
+                    summary: summary.trim_start(),
+                })
+            })
+        })
+    }
+
+    fn parameters(&self) -> Vec<Value> {
+        self.path
+            .split('/')
+            .filter_map(|seg| seg.strip_prefix('{')?.strip_suffix('}'))
+            .map(|name| {
+                /* v2 resources are always identified by uuid */
+                let schema = if name == "id" && self.section == Section::V2 {
+                    json!({"type": "string", "format": "uuid"})
+                } else {
+                    json!({"type": "string"})
+                };
+                json!({"name": name, "in": "path", "required": true, "schema": schema})
+            })
+            .collect()
+    }
+
+    fn operation(&self) -> Value {
+        let schema = if self.section == Section::V2 {
+            json!({"$ref": "#/components/schemas/V2Reply"})
+        } else {
+            json!({})
+        };
+
+        let mut op = json!({
+            "tags": [self.section.tag()],
+            "summary": self.summary,
+            "responses": {
+                "200": {
+                    "description": "Success",
+                    "content": {"application/json": {"schema": schema}},
+                },
+            },
+        });
+
+        let params = self.parameters();
+        if !params.is_empty() {
+            op["parameters"] = Value::Array(params);
+        }
+        if matches!(self.method, "PUT" | "POST") {
+            op["requestBody"] = json!({
+                "content": {"application/json": {"schema": {"type": "object"}}},
+            });
+        }
+        if self.section.is_extension() {
+            op["x-bifrost-extension"] = Value::Bool(true);
+        }
+
+        op
+    }
+}
+
+/// Api docs for the endpoints enabled by `config`, as an openapi document
+#[must_use]
+pub fn document(config: &AppConfig) -> Value {
+    let mut paths = Map::new();
+
+    for ep in Endpoint::all().filter(|ep| ep.section.is_enabled(config)) {
+        let path = paths
+            .entry(ep.path)
+            .or_insert_with(|| Value::Object(Map::new()));
+        path[ep.method.to_lowercase()] = ep.operation();
+    }
+
+    let tags: Vec<Value> = TAGS
+        .iter()
+        .map(|(name, description)| json!({"name": name, "description": description}))
+        .collect();
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Bifrost",
+            "description": "Hue bridge emulator. Only the endpoints listed here are implemented.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{"url": config.bifrost.base_path.as_deref().unwrap_or("/")}],
+        "tags": tags,
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "V2Reply": {
+                    "type": "object",
+                    "properties": {
+                        "data": {"type": "array", "items": {"type": "object"}},
+                        "errors": {"type": "array", "items": {"type": "string"}},
+                    },
+                },
+            },
+        },
+    })
+}
+
+/* behind a reverse proxy, tell clients where the api is, as they see it */
+async fn get_api_docs(
+    State(state): State<AppState>,
+    client_info: Option<Extension<ClientInfo>>,
+) -> Json<Value> {
+    let config = state.config();
+    let mut doc = document(&config);
+    if let Some(url) =
+        client_info.and_then(|Extension(info)| info.base_url(config.bifrost.base_path.as_deref()))
+    {
+        doc["servers"] = json!([{"url": url}]);
+    }
+    Json(doc)
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/", get(get_api_docs))
+}
@@ -0,0 +1,58 @@
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    routing::get,
+    Json, Router,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::hue::api::{Bridge, BridgeUpdate, RType, V2Reply};
+use crate::hue::is_valid_timezone;
+use crate::routes::clip::{generic, ApiV2Result};
+use crate::server::appstate::AppState;
+
+async fn get_bridge(
+    state: State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> ApiV2Result {
+    generic::get_resource_id(state, Path((RType::Bridge, id)), headers).await
+}
+
+async fn put_bridge(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(put): Json<Value>,
+) -> ApiV2Result {
+    log::info!("PUT bridge/{id}");
+    log::debug!("json data\n{}", serde_json::to_string_pretty(&put)?);
+
+    let rlink = RType::Bridge.link_to(id);
+    let upd: BridgeUpdate = serde_json::from_value(put)?;
+
+    /* checked against the time zone database on disk, so before taking the
+     * lock on the resources */
+    if let Some(tz) = &upd.time_zone {
+        if !is_valid_timezone(&tz.time_zone) {
+            return Err(ApiError::TimeZoneInvalid(tz.time_zone.clone()));
+        }
+    }
+
+    let mut lock = state.res.lock().await;
+
+    lock.get::<Bridge>(&rlink)?;
+
+    if let Some(tz) = upd.time_zone {
+        log::info!("Changing bridge time zone to {:?}", tz.time_zone);
+        lock.update::<Bridge>(&id, |bridge| bridge.time_zone = tz)?;
+    }
+    drop(lock);
+
+    V2Reply::ok(rlink)
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/:id", get(get_bridge).put(put_bridge))
+}
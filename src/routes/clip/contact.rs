@@ -0,0 +1,42 @@
+use axum::{
+    extract::{Path, State},
+    routing::put,
+    Json, Router,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::hue::api::{Contact, ContactUpdate, RType, V2Reply};
+use crate::routes::clip::ApiV2Result;
+use crate::server::appstate::AppState;
+
+async fn put_contact(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(put): Json<Value>,
+) -> ApiV2Result {
+    log::info!("PUT contact/{id}");
+    log::debug!("json data\n{}", serde_json::to_string_pretty(&put)?);
+
+    let rlink = RType::Contact.link_to(id);
+    let mut lock = state.res.lock().await;
+
+    lock.get::<Contact>(&rlink)?;
+
+    let upd: ContactUpdate = serde_json::from_value(put)?;
+
+    /* like for motion sensors, the enabled state is only known to bifrost */
+    lock.update::<Contact>(&id, |contact| {
+        if let Some(enabled) = upd.enabled {
+            contact.enabled = enabled;
+        }
+    })?;
+
+    drop(lock);
+
+    V2Reply::ok(rlink)
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/:id", put(put_contact))
+}
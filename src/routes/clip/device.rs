@@ -0,0 +1,54 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::hue::api::{Device, DeviceUpdate, RType, V2Reply};
+use crate::routes::clip::ApiV2Result;
+use crate::server::appstate::AppState;
+
+/* longest name a real bridge accepts */
+const MAX_NAME_LENGTH: usize = 32;
+
+/* The hue app renames devices (including the bridge itself) through their
+ * metadata. Routed from the generic resource routes, so devices keep all
+ * other methods from there. */
+pub(super) async fn put_device(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(put): Json<Value>,
+) -> ApiV2Result {
+    log::info!("PUT device/{id}");
+    log::debug!("json data\n{}", serde_json::to_string_pretty(&put)?);
+
+    let rlink = RType::Device.link_to(id);
+    let upd: DeviceUpdate = serde_json::from_value(put)?;
+    let metadata = upd.metadata.unwrap_or_default();
+
+    let name = metadata.name.map(|name| name.trim().to_string());
+    if let Some(name) = &name {
+        if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+            return Err(ApiError::NameInvalid(name.to_string()));
+        }
+    }
+
+    let mut lock = state.res.lock().await;
+
+    lock.get::<Device>(&rlink)?;
+
+    lock.update::<Device>(&id, |dev| {
+        if let Some(name) = name {
+            log::info!("Renaming device {id} to {name:?}");
+            dev.metadata.name = name;
+        }
+        if let Some(archetype) = metadata.archetype {
+            dev.metadata.archetype = archetype;
+        }
+    })?;
+    drop(lock);
+
+    V2Reply::ok(rlink)
+}
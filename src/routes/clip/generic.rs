@@ -1,5 +1,6 @@
 use axum::{
     extract::{Path, State},
+    http::HeaderMap,
     response::IntoResponse,
     routing::{delete, get, post, put},
     Json, Router,
@@ -9,15 +10,34 @@ use uuid::Uuid;
 
 use crate::error::ApiError;
 use crate::hue::api::{RType, Resource, ResourceLink, V2Reply};
-use crate::routes::clip::ApiV2Result;
+use crate::hue::behavior_scripts::BundledScript;
+use crate::routes::clip::{application_key, device, ApiV2Result};
 use crate::server::appstate::AppState;
 
-async fn get_root(State(state): State<AppState>) -> impl IntoResponse {
-    V2Reply::list(state.res.lock().await.get_resources())
+async fn get_root(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let user = application_key(&headers);
+    let lock = state.res.lock().await;
+    V2Reply::list(
+        lock.get_resources()
+            .into_iter()
+            .map(|rec| lock.user_view(rec, user))
+            .collect(),
+    )
 }
 
-async fn get_resource(State(state): State<AppState>, Path(rtype): Path<RType>) -> ApiV2Result {
-    V2Reply::list(state.res.lock().await.get_resources_by_type(rtype))
+pub(super) async fn get_resource(
+    State(state): State<AppState>,
+    Path(rtype): Path<RType>,
+    headers: HeaderMap,
+) -> ApiV2Result {
+    let user = application_key(&headers);
+    let lock = state.res.lock().await;
+    V2Reply::list(
+        lock.get_resources_by_type(rtype)
+            .into_iter()
+            .map(|rec| lock.user_view(rec, user))
+            .collect(),
+    )
 }
 
 async fn post_resource(
@@ -29,6 +49,18 @@ async fn post_resource(
 
     let obj = Resource::from_value(rtype, req)?;
 
+    /* apps may know scripts we do not bundle, so those are stored as is */
+    if let Resource::BehaviorInstance(bi) = &obj {
+        if let Some(script) = BundledScript::from_id(&bi.script_id) {
+            script.validate(&bi.configuration)?;
+        } else {
+            log::warn!(
+                "Accepting behavior instance for unknown script {}, without validation",
+                bi.script_id
+            );
+        }
+    }
+
     let mut lock = state.res.lock().await;
 
     let rlink = ResourceLink::new(Uuid::new_v4(), obj.rtype());
@@ -38,17 +70,25 @@ async fn post_resource(
     V2Reply::ok(rlink)
 }
 
-async fn get_resource_id(
+pub(super) async fn get_resource_id(
     State(state): State<AppState>,
     Path((rtype, id)): Path<(RType, Uuid)>,
+    headers: HeaderMap,
 ) -> ApiV2Result {
-    V2Reply::ok(state.res.lock().await.get_resource(rtype, &id)?)
+    let lock = state.res.lock().await;
+    let rec = lock.get_resource(rtype, &id)?;
+    V2Reply::ok(lock.user_view(rec, application_key(&headers)))
 }
 
 async fn put_resource_id(
+    state: State<AppState>,
     Path((rtype, id)): Path<(RType, Uuid)>,
     Json(put): Json<Value>,
 ) -> ApiV2Result {
+    if rtype == RType::Device {
+        return device::put_device(state, Path(id), Json(put)).await;
+    }
+
     log::info!("PUT {rtype:?}/{id}");
     log::debug!("json data\n{}", serde_json::to_string_pretty(&put)?);
 
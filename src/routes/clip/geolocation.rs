@@ -0,0 +1,46 @@
+use axum::{
+    extract::{Path, State},
+    routing::put,
+    Json, Router,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::hue::api::{Geolocation, RType, V2Reply};
+use crate::model::sun::Coordinates;
+use crate::routes::clip::ApiV2Result;
+use crate::server::appstate::AppState;
+
+async fn put_geolocation(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(put): Json<Value>,
+) -> ApiV2Result {
+    log::info!("PUT geolocation/{id}");
+
+    let rlink = RType::Geolocation.link_to(id);
+    let mut lock = state.res.lock().await;
+
+    lock.get::<Geolocation>(&rlink)?;
+
+    /* the location is only kept by bifrost, and never reported back */
+    let location: Coordinates = serde_json::from_value(put)?;
+    if !location.is_valid() {
+        return Err(ApiError::LocationInvalid(
+            location.latitude,
+            location.longitude,
+        ));
+    }
+
+    let aux = lock.aux_get(&rlink).cloned().unwrap_or_default();
+    lock.aux_set(&rlink, aux.with_location(Some(location)));
+    lock.update::<Geolocation>(&id, |geoloc| geoloc.is_configured = true)?;
+    drop(lock);
+
+    V2Reply::ok(rlink)
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/:id", put(put_geolocation))
+}
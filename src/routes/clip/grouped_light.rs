@@ -3,10 +3,12 @@ use axum::{
     routing::put,
     Json, Router,
 };
+use chrono::Utc;
 use serde_json::Value;
 use uuid::Uuid;
 
-use crate::hue::api::{GroupedLight, GroupedLightUpdate, RType, V2Reply};
+use crate::hue::api::{BridgeHome, GroupedLight, GroupedLightUpdate, RType, V2Reply, Zone};
+use crate::model::brightness::Brightness;
 use crate::routes::clip::ApiV2Result;
 use crate::server::appstate::AppState;
 use crate::z2m::request::ClientRequest;
@@ -22,7 +24,9 @@ async fn put_grouped_light(
 
     let rlink = RType::GroupedLight.link_to(id);
     let lock = state.res.lock().await;
-    lock.get::<GroupedLight>(&rlink)?;
+    let owner = lock.get::<GroupedLight>(&rlink)?.owner;
+    lock.check_unlocked(&rlink, Utc::now())?;
+    lock.check_reachable(&rlink)?;
 
     log::info!("PUT grouped_light/{id}: updating");
 
@@ -30,11 +34,29 @@ async fn put_grouped_light(
 
     let payload = DeviceUpdate::default()
         .with_state(upd.on.map(|on| on.on))
-        .with_brightness(upd.dimming.map(|dim| dim.brightness / 100.0 * 254.0))
+        .with_brightness(
+            upd.dimming
+                .map(|dim| Brightness::from_percent(dim.brightness).z2m()),
+        )
         .with_color_temp(upd.color_temperature.map(|ct| ct.mirek))
         .with_color_xy(upd.color.map(|col| col.xy));
 
-    lock.z2m_request(ClientRequest::group_update(rlink, payload))?;
+    /* the bridge home covers all lights, and has no z2m group of its own */
+    if lock.get::<BridgeHome>(&owner).is_ok() {
+        lock.bridge_home_request(&payload)?;
+        drop(lock);
+        return V2Reply::ok(rlink);
+    }
+
+    /* zones without a z2m group are updated one light at a time */
+    match lock.get::<Zone>(&owner) {
+        Ok(zone) if !state.config().zone_zigbee_group(&zone.metadata.name) => {
+            for light in lock.get_zone_lights(zone) {
+                lock.z2m_request(ClientRequest::light_update(light, payload.clone()))?;
+            }
+        }
+        _ => lock.z2m_request(ClientRequest::group_update(rlink, payload))?,
+    }
 
     drop(lock);
 
@@ -1,17 +1,44 @@
 use axum::{
     extract::{Path, State},
-    routing::put,
+    http::HeaderMap,
+    routing::get,
     Json, Router,
 };
+use chrono::Utc;
 use serde_json::Value;
 use uuid::Uuid;
 
-use crate::hue::api::{Light, LightUpdate, RType, V2Reply};
-use crate::routes::clip::ApiV2Result;
+use crate::error::{ApiError, ApiResult};
+use crate::hue::api::{
+    Light, LightCapabilities, LightEffect, LightUpdate, RType, ResourceLink, TimedEffect, V2Reply,
+};
+use crate::model::brightness::Brightness;
+use crate::model::types::XY;
+use crate::resource::Resources;
+use crate::routes::clip::{generic, ApiV2Result};
 use crate::server::appstate::AppState;
 use crate::z2m::request::ClientRequest;
 use crate::z2m::update::DeviceUpdate;
 
+/* color temperature at the start and end of a sunrise (and the end and
+ * start of a sunset), and the duration of timed effects (in milliseconds)
+ * when not given */
+const SUNRISE_START_MIREK: u32 = 500;
+const SUNRISE_END_MIREK: u32 = 250;
+const TIMED_EFFECT_DEFAULT_DURATION: u32 = 30 * 60 * 1000;
+
+async fn get_lights(state: State<AppState>, headers: HeaderMap) -> ApiV2Result {
+    generic::get_resource(state, Path(RType::Light), headers).await
+}
+
+async fn get_light(
+    state: State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> ApiV2Result {
+    generic::get_resource_id(state, Path((RType::Light, id)), headers).await
+}
+
 async fn put_light(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
@@ -21,25 +48,166 @@ async fn put_light(
     log::debug!("json data\n{}", serde_json::to_string_pretty(&put)?);
 
     let rlink = RType::Light.link_to(id);
-    let lock = state.res.lock().await;
+    let mut lock = state.res.lock().await;
 
-    let _ = lock.get::<Light>(&rlink)?;
+    let caps = lock.get::<Light>(&rlink)?.capabilities();
+    lock.check_unlocked(&rlink, Utc::now())?;
+    lock.check_reachable(&rlink)?;
 
     let upd: LightUpdate = serde_json::from_value(put)?;
 
+    let effect = upd.effects.map(|eff| eff.effect);
+    if let Some(effect) = effect {
+        if !caps.supports_effect(effect) {
+            return Err(ApiError::EffectUnsupported(effect));
+        }
+    }
+
+    let gradient: Option<Vec<XY>> = upd.gradient.map(|grad| {
+        grad.points
+            .into_iter()
+            .map(|point| point.color.xy)
+            .collect()
+    });
+    if let Some(points) = &gradient {
+        let Some(points_capable) = caps.gradient_points else {
+            return Err(ApiError::GradientUnsupported);
+        };
+        if points.len() > points_capable as usize {
+            return Err(ApiError::GradientTooLong(points.len(), points_capable));
+        }
+    }
+
+    if upd.dimming.is_some() && !caps.dimmable {
+        return Err(ApiError::DimmingUnsupported);
+    }
+
+    let (xy, mirek) = caps.fit_color(
+        upd.color.map(|col| col.xy),
+        upd.color_temperature.map(|ct| ct.mirek),
+    )?;
+
+    if let Some(te) = &upd.timed_effects {
+        te.validate()?;
+    }
+
+    let timed_effect = upd.timed_effects.as_ref().and_then(|te| te.effect);
+    if let Some(effect @ (TimedEffect::Sunrise | TimedEffect::Sunset)) = timed_effect {
+        let duration = upd
+            .timed_effects
+            .and_then(|te| te.duration)
+            .unwrap_or(TIMED_EFFECT_DEFAULT_DURATION);
+        start_timed_effect(&mut lock, rlink, &caps, effect, duration)?;
+        drop(lock);
+        return V2Reply::ok(rlink);
+    }
+
+    /* like on a real bridge, any other change ends a timed effect */
+    let ends_timed_effect = timed_effect.is_some()
+        || effect.is_some()
+        || upd.on.is_some()
+        || upd.dimming.is_some()
+        || xy.is_some()
+        || mirek.is_some();
+
     let payload = DeviceUpdate::default()
         .with_state(upd.on.map(|on| on.on))
-        .with_brightness(upd.dimming.map(|dim| dim.brightness / 100.0 * 254.0))
-        .with_color_temp(upd.color_temperature.map(|ct| ct.mirek))
-        .with_color_xy(upd.color.map(|col| col.xy));
+        .with_brightness(
+            upd.dimming
+                .map(|dim| Brightness::from_percent(dim.brightness).z2m()),
+        )
+        .with_color_temp(mirek)
+        .with_color_xy(xy)
+        .with_effect(effect)
+        .with_gradient(gradient.as_deref())
+        .with_transition(upd.dynamics.and_then(|dyn_| dyn_.transition()));
+
+    /* stopping an effect also stops a sunrise (or other transition) that
+     * might be in progress */
+    if timed_effect == Some(TimedEffect::NoEffect) || effect == Some(LightEffect::NoEffect) {
+        lock.z2m_request(ClientRequest::light_stop(rlink))?;
+    }
 
     lock.z2m_request(ClientRequest::light_update(rlink, payload))?;
 
+    /* z2m does not report effect state, so track it here */
+    if effect.is_some() {
+        lock.update::<Light>(&id, |light| {
+            *light += LightUpdate::new().with_effect(effect);
+        })?;
+    }
+
+    if ends_timed_effect {
+        lock.update::<Light>(&id, |light| {
+            *light += LightUpdate::new().with_timed_effect(Some(TimedEffect::NoEffect), None);
+        })?;
+    }
+
     drop(lock);
 
     V2Reply::ok(rlink)
 }
 
+/* z2m does not report timed effects either, so the running effect is
+ * tracked here, until the next change to the light */
+fn start_timed_effect(
+    res: &mut Resources,
+    rlink: ResourceLink,
+    caps: &LightCapabilities,
+    effect: TimedEffect,
+    duration: u32,
+) -> ApiResult<()> {
+    if !caps.dimmable {
+        return Err(ApiError::TimedEffectUnsupported(effect));
+    }
+
+    let payloads = if effect == TimedEffect::Sunset {
+        vec![sunset(caps, duration)]
+    } else {
+        sunrise(caps, duration).to_vec()
+    };
+
+    for payload in payloads {
+        res.z2m_request(ClientRequest::light_update(rlink, payload))?;
+    }
+
+    res.update::<Light>(&rlink.rid, |light| {
+        *light += LightUpdate::new().with_timed_effect(Some(effect), Some(duration));
+    })
+}
+
+fn clamped_mirek(caps: &LightCapabilities, mirek: u32) -> Option<u32> {
+    caps.color_temperature
+        .is_some()
+        .then(|| caps.clamp_mirek(mirek))
+}
+
+/* Sunrise is a long transition from a dim, warm light to full brightness */
+fn sunrise(caps: &LightCapabilities, duration: u32) -> [DeviceUpdate; 2] {
+    let start = DeviceUpdate::default()
+        .with_state(Some(true))
+        .with_brightness(Some(Brightness::MIN.z2m()))
+        .with_color_temp(clamped_mirek(caps, SUNRISE_START_MIREK))
+        .with_transition(Some(0.0));
+
+    let end = DeviceUpdate::default()
+        .with_brightness(Some(Brightness::MAX.z2m()))
+        .with_color_temp(clamped_mirek(caps, SUNRISE_END_MIREK))
+        .with_transition(Some(f64::from(duration) / 1000.0));
+
+    [start, end]
+}
+
+/* Sunset fades the light from its current state to a warm glow, and off */
+fn sunset(caps: &LightCapabilities, duration: u32) -> DeviceUpdate {
+    DeviceUpdate::default()
+        .with_state(Some(false))
+        .with_color_temp(clamped_mirek(caps, SUNRISE_START_MIREK))
+        .with_transition(Some(f64::from(duration) / 1000.0))
+}
+
 pub fn router() -> Router<AppState> {
-    Router::new().route("/:id", put(put_light))
+    Router::new()
+        .route("/", get(get_lights))
+        .route("/:id", get(get_light).put(put_light))
 }
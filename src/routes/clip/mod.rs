@@ -1,8 +1,15 @@
+pub mod bridge;
+pub mod contact;
+pub mod device;
 pub mod generic;
+pub mod geolocation;
 pub mod grouped_light;
 pub mod light;
+pub mod motion;
 pub mod scene;
+pub mod zone;
 
+use axum::http::HeaderMap;
 use axum::{Json, Router};
 use serde::Serialize;
 use serde_json::Value;
@@ -13,6 +20,15 @@ use crate::server::appstate::AppState;
 
 type ApiV2Result = ApiResult<Json<V2Reply<Value>>>;
 
+const HUE_APPLICATION_KEY: &str = "hue-application-key";
+
+/// The application key a request was made with, if any
+fn application_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(HUE_APPLICATION_KEY)
+        .and_then(|key| key.to_str().ok())
+}
+
 impl<T: Serialize> V2Reply<T> {
     #[allow(clippy::unnecessary_wraps)]
     fn ok(obj: T) -> ApiV2Result {
@@ -36,8 +52,13 @@ impl<T: Serialize> V2Reply<T> {
 
 pub fn router() -> Router<AppState> {
     Router::new()
+        .nest("/bridge", bridge::router())
         .nest("/scene", scene::router())
         .nest("/light", light::router())
+        .nest("/motion", motion::router())
+        .nest("/contact", contact::router())
         .nest("/grouped_light", grouped_light::router())
+        .nest("/geolocation", geolocation::router())
+        .nest("/zone", zone::router())
         .nest("/", generic::router())
 }
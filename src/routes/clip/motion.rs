@@ -0,0 +1,44 @@
+use axum::{
+    extract::{Path, State},
+    routing::put,
+    Json, Router,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::hue::api::{Motion, MotionUpdate, RType, V2Reply};
+use crate::routes::clip::ApiV2Result;
+use crate::server::appstate::AppState;
+
+async fn put_motion(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(put): Json<Value>,
+) -> ApiV2Result {
+    log::info!("PUT motion/{id}");
+    log::debug!("json data\n{}", serde_json::to_string_pretty(&put)?);
+
+    let rlink = RType::Motion.link_to(id);
+    let mut lock = state.res.lock().await;
+    lock.get::<Motion>(&rlink)?;
+
+    let upd: MotionUpdate = serde_json::from_value(put)?;
+
+    if let Some(sens) = upd.sensitivity {
+        lock.set_motion_sensitivity(&id, sens.sensitivity)?;
+    }
+
+    /* the enabled state is only known to bifrost, so it is kept (and
+     * persisted) here, while sensitivity is confirmed by z2m */
+    if let Some(enabled) = upd.enabled {
+        lock.update::<Motion>(&id, |motion| motion.enabled = enabled)?;
+    }
+
+    drop(lock);
+
+    V2Reply::ok(rlink)
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/:id", put(put_motion))
+}
@@ -1,28 +1,49 @@
 use axum::{
     extract::{Path, State},
+    http::HeaderMap,
     response::IntoResponse,
-    routing::{delete, post, put},
+    routing::{delete, get, post, put},
     Json, Router,
 };
+use chrono::Utc;
 use serde_json::Value;
 use uuid::Uuid;
 
 use crate::error::{ApiError, ApiResult};
-use crate::hue::api::{
-    RType, Resource, Scene, SceneStatus, SceneStatusUpdate, SceneUpdate, V2Reply,
-};
+use crate::hue::api::{RType, Resource, Scene, SceneStatusUpdate, SceneUpdate, V2Reply};
 use crate::model::state::AuxData;
-use crate::routes::clip::ApiV2Result;
+use crate::routes::clip::{application_key, generic, ApiV2Result};
 use crate::server::appstate::AppState;
 use crate::z2m::request::ClientRequest;
 
+async fn get_scenes(state: State<AppState>, headers: HeaderMap) -> ApiV2Result {
+    generic::get_resource(state, Path(RType::Scene), headers).await
+}
+
+async fn get_scene(
+    state: State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> ApiV2Result {
+    generic::get_resource_id(state, Path((RType::Scene, id)), headers).await
+}
+
 async fn post_scene(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(req): Json<Value>,
 ) -> ApiResult<impl IntoResponse> {
     log::info!("POST: scene {}", serde_json::to_string(&req)?);
 
-    let scene: Scene = serde_json::from_value(req)?;
+    let mut scene: Scene = serde_json::from_value(req)?;
+    let mut aux = AuxData::new();
+
+    /* appdata belongs to the application that created the scene */
+    if let Some(user) = application_key(&headers) {
+        if let Some(appdata) = scene.metadata.appdata.take() {
+            aux = aux.with_appdata(user, appdata);
+        }
+    }
 
     let mut lock = state.res.lock().await;
 
@@ -34,18 +55,26 @@ async fn post_scene(
 
     lock.aux_set(
         &link_scene,
-        AuxData::new()
-            .with_topic(&scene.metadata.name)
-            .with_index(sid),
+        aux.with_topic(&scene.metadata.name).with_index(sid),
     );
 
-    lock.z2m_request(ClientRequest::scene_store(
-        scene.group,
-        sid,
-        scene.metadata.name.clone(),
-    ))?;
+    /* zones have no z2m group to store scenes in, so zone scenes are only
+     * kept here, starting from the current light states if no actions
+     * were given */
+    let zone = scene.group.rtype == RType::Zone;
+    let capture = zone && scene.actions.is_empty();
+    if !zone {
+        lock.z2m_request(ClientRequest::scene_store(
+            scene.group,
+            sid,
+            scene.metadata.name.clone(),
+        ))?;
+    }
 
     lock.add(&link_scene, Resource::Scene(scene))?;
+    if capture {
+        lock.scene_capture(&link_scene)?;
+    }
     drop(lock);
 
     V2Reply::ok(link_scene)
@@ -54,6 +83,7 @@ async fn post_scene(
 async fn put_scene(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
+    headers: HeaderMap,
     Json(put): Json<Value>,
 ) -> ApiV2Result {
     log::info!("PUT scene/{id}");
@@ -67,10 +97,10 @@ async fn put_scene(
     let upd: SceneUpdate = serde_json::from_value(put)?;
 
     if let Some(md) = upd.metadata {
+        if let Some(appdata) = md.appdata {
+            lock.scene_appdata_set(&rlink, application_key(&headers), appdata)?;
+        }
         lock.update(&id, |scn: &mut Scene| {
-            if md.appdata.is_some() {
-                scn.metadata.appdata = md.appdata;
-            }
             if md.image.is_some() {
                 scn.metadata.image = md.image;
             }
@@ -78,22 +108,15 @@ async fn put_scene(
         })?;
     }
 
-    let scene = lock.get::<Scene>(&rlink)?;
+    lock.get::<Scene>(&rlink)?;
 
     if let Some(recall) = upd.recall {
         if recall.action == Some(SceneStatusUpdate::Active) {
-            let scenes = lock.get_scenes_for_room(&scene.group.rid);
-            for rid in scenes {
-                lock.update(&rid, |scn: &mut Scene| {
-                    if rid == id {
-                        scn.status = Some(SceneStatus::Static);
-                    } else {
-                        scn.status = Some(SceneStatus::Inactive);
-                    }
-                })?;
-            }
+            lock.check_unlocked(&rlink, Utc::now())?;
+            lock.check_reachable(&rlink)?;
+            lock.scene_set_active(&rlink)?;
 
-            lock.z2m_request(ClientRequest::scene_recall(rlink))?;
+            lock.scene_recall(&rlink)?;
             drop(lock);
         } else {
             log::error!("Scene recall type not supported: {recall:?}");
@@ -107,10 +130,17 @@ async fn delete_scene(State(state): State<AppState>, Path(id): Path<Uuid>) -> Ap
     log::info!("DELETE scene/{id}");
     let link = RType::Scene.link_to(id);
 
-    let lock = state.res.lock().await;
+    let mut lock = state.res.lock().await;
     let res = lock.get_resource(RType::Scene, &id)?;
 
     match res.obj {
+        /* zone scenes only exist here */
+        Resource::Scene(scene) if scene.group.rtype == RType::Zone => {
+            lock.delete(&link)?;
+            drop(lock);
+
+            V2Reply::ok(link)
+        }
         Resource::Scene(_) => {
             lock.z2m_request(ClientRequest::scene_remove(link))?;
 
@@ -124,7 +154,9 @@ async fn delete_scene(State(state): State<AppState>, Path(id): Path<Uuid>) -> Ap
 
 pub fn router() -> Router<AppState> {
     Router::new()
+        .route("/", get(get_scenes))
         .route("/", post(post_scene))
+        .route("/:id", get(get_scene))
         .route("/:id", put(put_scene))
         .route("/:id", delete(delete_scene))
 }
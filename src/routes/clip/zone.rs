@@ -0,0 +1,115 @@
+use axum::{
+    extract::{Path, State},
+    routing::{delete, post, put},
+    Json, Router,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::error::ApiResult;
+use crate::hue::api::{GroupedLight, RType, Resource, ResourceLink, V2Reply, Zone, ZoneUpdate};
+use crate::resource::Resources;
+use crate::routes::clip::ApiV2Result;
+use crate::server::appstate::AppState;
+use crate::z2m::request::ClientRequest;
+
+/* Ask the z2m servers to update the zigbee group of a zone, if configured
+ * for it. Otherwise, zone updates are sent to each light separately. */
+fn zone_group_sync(
+    state: &AppState,
+    lock: &Resources,
+    link: ResourceLink,
+    zone: Option<&Zone>,
+) -> ApiResult<()> {
+    let lights = match zone {
+        Some(zone) if state.config().zone_zigbee_group(&zone.metadata.name) => {
+            lock.get_zone_lights(zone)
+        }
+        _ => vec![],
+    };
+
+    lock.z2m_request(ClientRequest::zone_group(link, lights))
+}
+
+async fn post_zone(State(state): State<AppState>, Json(req): Json<Value>) -> ApiV2Result {
+    log::info!("POST: zone {}", serde_json::to_string(&req)?);
+
+    let mut zone: Zone = serde_json::from_value(req)?;
+
+    let link_zone = RType::Zone.link_to(Uuid::new_v4());
+    let link_glight = RType::GroupedLight.deterministic(link_zone.rid);
+
+    log::info!("New zone: {link_zone:?} ({})", zone.metadata.name);
+
+    zone.services = vec![link_glight];
+
+    let mut lock = state.res.lock().await;
+
+    zone_group_sync(&state, &lock, link_zone, Some(&zone))?;
+
+    lock.add(&link_zone, Resource::Zone(zone))?;
+    lock.add(
+        &link_glight,
+        Resource::GroupedLight(GroupedLight::new(link_zone)),
+    )?;
+    drop(lock);
+
+    V2Reply::ok(link_zone)
+}
+
+async fn put_zone(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(put): Json<Value>,
+) -> ApiV2Result {
+    log::info!("PUT zone/{id}");
+    log::debug!("json data\n{}", serde_json::to_string_pretty(&put)?);
+
+    let rlink = RType::Zone.link_to(id);
+    let mut lock = state.res.lock().await;
+
+    lock.get::<Zone>(&rlink)?;
+
+    let upd: ZoneUpdate = serde_json::from_value(put)?;
+
+    lock.update::<Zone>(&id, |zone| {
+        if let Some(metadata) = upd.metadata {
+            zone.metadata = metadata;
+        }
+        if let Some(children) = upd.children {
+            zone.children = children;
+        }
+    })?;
+
+    let zone = lock.get::<Zone>(&rlink)?;
+    zone_group_sync(&state, &lock, rlink, Some(zone))?;
+    drop(lock);
+
+    V2Reply::ok(rlink)
+}
+
+async fn delete_zone(State(state): State<AppState>, Path(id): Path<Uuid>) -> ApiV2Result {
+    log::info!("DELETE zone/{id}");
+
+    let rlink = RType::Zone.link_to(id);
+    let mut lock = state.res.lock().await;
+
+    let services = lock.get::<Zone>(&rlink)?.services.clone();
+
+    zone_group_sync(&state, &lock, rlink, None)?;
+
+    for service in &services {
+        lock.delete(service)?;
+    }
+    lock.delete(&rlink)?;
+    drop(lock);
+
+    V2Reply::ok(rlink)
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", post(post_zone))
+        .route("/:id", put(put_zone))
+        .route("/:id", delete(delete_zone))
+}
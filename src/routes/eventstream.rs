@@ -1,27 +1,61 @@
+use std::time::Duration;
+
 use axum::extract::State;
-use axum::response::sse::{Event, Sse};
+use axum::http::header::{CONTENT_TYPE, USER_AGENT};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::IntoResponse;
 use axum::routing::get;
+use axum::Extension;
 use axum::Router;
 use chrono::Utc;
-use futures::stream::Stream;
 use futures::StreamExt;
-use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::ReceiverStream;
 
 use crate::error::ApiResult;
 use crate::server::appstate::AppState;
+use crate::server::proxy::ClientInfo;
+
+/* Matches the real bridge, which some clients check for verbatim */
+const CONTENT_TYPE_SSE: &str = "text/event-stream; charset=utf-8";
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/* Like the real bridge, events within this window are sent together, as a
+ * single message with an array of event blocks */
+const BATCH_WINDOW: Duration = Duration::from_secs(1);
+
+/* Upper limit on the number of event blocks in a single message */
+const BATCH_MAX_EVENTS: usize = 64;
 
 pub async fn get_clip_v2(
     State(state): State<AppState>,
-) -> Sse<impl Stream<Item = ApiResult<Event>>> {
+    client_info: Option<Extension<ClientInfo>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
     let hello = tokio_stream::iter([Ok(Event::default().comment("hi"))]);
 
     let mut prev_ts = Utc::now().timestamp();
     let mut idx = 0;
 
     let channel = state.res.lock().await.hue_channel();
+    let user_agent = headers
+        .get(USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .map(ToString::to_string);
+    let address = client_info.map(|Extension(info)| info.addr);
+    let (client, events) = state
+        .event_clients()
+        .subscribe(channel, user_agent, address);
 
-    let stream = BroadcastStream::new(channel).map(move |e| {
-        let json = [e?];
+    let batches = tokio_stream::StreamExt::chunks_timeout(
+        ReceiverStream::new(events),
+        BATCH_MAX_EVENTS,
+        BATCH_WINDOW,
+    );
+
+    let stream = batches.map(move |json| -> ApiResult<Event> {
+        client.sent(json.len());
         log::trace!(
             "## EVENT ##: {}",
             serde_json::to_string(&json).unwrap_or_else(|_| "ERROR".to_string())
@@ -36,9 +70,17 @@ pub async fn get_clip_v2(
         Ok(Event::default().id(format!("{ts}:{idx}")).json_data(json)?)
     });
 
-    Sse::new(hello.chain(stream))
+    let sse = Sse::new(hello.chain(stream))
+        .keep_alive(KeepAlive::new().interval(KEEPALIVE_INTERVAL).text("hi"));
+
+    ([(CONTENT_TYPE, CONTENT_TYPE_SSE)], sse)
 }
 
 pub fn router() -> Router<AppState> {
     Router::new().route("/clip/v2", get(get_clip_v2))
 }
+
+/// Alias for clients that look for the event stream next to the clip v2 api
+pub fn clip_alias_router() -> Router<AppState> {
+    Router::new().route("/", get(get_clip_v2))
+}
@@ -1,3 +1,4 @@
+use axum::middleware;
 use axum::response::{IntoResponse, Response};
 use axum::{Json, Router};
 use hyper::StatusCode;
@@ -7,7 +8,9 @@ use crate::error::ApiError;
 use crate::hue::api::V2Reply;
 use crate::server::appstate::AppState;
 
+pub mod admin;
 pub mod api;
+pub mod apidocs;
 pub mod clip;
 pub mod eventstream;
 pub mod licenses;
@@ -23,10 +26,31 @@ impl IntoResponse for ApiError {
 
         let status = match self {
             Self::NotFound(_) | Self::V1NotFound(_) => StatusCode::NOT_FOUND,
-            Self::Full(_) => StatusCode::INSUFFICIENT_STORAGE,
+            Self::Full(_) | Self::V1Full(_) => StatusCode::INSUFFICIENT_STORAGE,
             Self::WrongType(_, _) => StatusCode::NOT_ACCEPTABLE,
             Self::DeleteDenied(_) => StatusCode::FORBIDDEN,
-            Self::V1CreateUnsupported(_) => StatusCode::NOT_IMPLEMENTED,
+            Self::AdminTokenInvalid => StatusCode::UNAUTHORIZED,
+            Self::Locked(..) => StatusCode::LOCKED,
+            Self::ServerOffline(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::V1CreateUnsupported(_) | Self::V1DeleteUnsupported(_) => {
+                StatusCode::NOT_IMPLEMENTED
+            }
+            Self::EffectUnsupported(_)
+            | Self::TimedEffectUnsupported(_)
+            | Self::TimedEffectDuration(_)
+            | Self::GradientUnsupported
+            | Self::DimmingUnsupported
+            | Self::ColorUnsupported
+            | Self::ColorTemperatureUnsupported
+            | Self::MotionSensitivityUnsupported(_)
+            | Self::BehaviorConfigInvalid(_)
+            | Self::LocationInvalid(..)
+            | Self::TimeZoneInvalid(_)
+            | Self::NameInvalid(_)
+            | Self::GradientTooLong(..)
+            | Self::DeviceOptionUnknown(_)
+            | Self::DeviceOptionsInvalid(_)
+            | Self::LogFilterInvalid(_) => StatusCode::BAD_REQUEST,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -35,10 +59,31 @@ impl IntoResponse for ApiError {
 }
 
 pub fn router(appstate: AppState) -> Router<()> {
-    Router::new()
-        .nest("/api", api::router())
+    let conf = appstate.config();
+
+    let mut router = Router::new();
+
+    if conf.bifrost.enable_v1 {
+        router = router.nest("/api", api::router());
+    } else if conf.bifrost.v1_pairing {
+        log::info!("Hue v1 api disabled (except pairing)");
+        router = router.nest("/api", api::pairing_router());
+    } else {
+        log::info!("Hue v1 api disabled");
+    }
+
+    router
         .nest("/licenses", licenses::router())
+        .nest(
+            "/admin",
+            admin::router().route_layer(middleware::from_fn_with_state(
+                appstate.clone(),
+                admin::require_token,
+            )),
+        )
+        .nest("/api-docs", apidocs::router())
         .nest("/clip/v2/resource", clip::router())
         .nest("/eventstream", eventstream::router())
+        .nest("/clip/v2/eventstream", eventstream::clip_alias_router())
         .with_state(appstate)
 }
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{Local, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::config::{MotionSensorConfig, SensitivityPeriod};
+use crate::error::ApiResult;
+use crate::hue::api::{Motion, RType};
+use crate::journal::Source;
+use crate::resource::Resources;
+
+/* seconds between checks of the schedules */
+const INTERVAL_SECS: u64 = 30;
+
+/// The period of `schedule` in effect at `time`: the latest one to have
+/// started today, or the last one of the day, before the first has started
+#[must_use]
+pub fn active_period(
+    schedule: &[SensitivityPeriod],
+    time: NaiveTime,
+) -> Option<&SensitivityPeriod> {
+    schedule
+        .iter()
+        .filter(|period| period.at <= time)
+        .max_by_key(|period| period.at)
+        .or_else(|| schedule.iter().max_by_key(|period| period.at))
+}
+
+/// A scene recall at a local time of day, as a simpler alternative to v1
+/// schedules (managed through the admin api, and kept in the state file)
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SceneSchedule {
+    pub scene: Uuid,
+    pub at: NaiveTime,
+    /// Recall the scene every day, instead of only once
+    #[serde(default)]
+    pub daily: bool,
+}
+
+/// Whether `at` has passed after `last`, up to and including `now` (which
+/// can be on the next day)
+#[must_use]
+pub fn time_passed(at: NaiveTime, last: NaiveTime, now: NaiveTime) -> bool {
+    if last <= now {
+        last < at && at <= now
+    } else {
+        last < at || at <= now
+    }
+}
+
+/// Applies time of day settings, from the `motion_sensors` section of the
+/// config, and the scene schedules kept in the state.
+///
+/// Settings are applied when a scheduled period starts (or at startup), so
+/// changes made in between (e.g. from the hue app) last until the next one.
+pub struct Scheduler {
+    motion_sensors: HashMap<String, MotionSensorConfig>,
+    state: Arc<Mutex<Resources>>,
+    /* start of the period last applied, by motion resource */
+    applied: HashMap<Uuid, NaiveTime>,
+    /* time of the previous tick, to find the scene schedules due since */
+    last_tick: Option<NaiveTime>,
+}
+
+impl Scheduler {
+    #[must_use]
+    pub fn new(
+        motion_sensors: HashMap<String, MotionSensorConfig>,
+        state: Arc<Mutex<Resources>>,
+    ) -> Self {
+        Self {
+            motion_sensors,
+            state,
+            applied: HashMap::new(),
+            last_tick: None,
+        }
+    }
+
+    /* motion resources of the sensors with the given z2m name (which, unlike
+     * the device name, does not change when renamed from the hue app) */
+    fn motion_sensors(res: &Resources, name: &str) -> Vec<Uuid> {
+        res.get_resources_by_type(RType::Motion)
+            .into_iter()
+            .filter(|rr| {
+                res.aux_get(&RType::Motion.link_to(rr.id))
+                    .is_ok_and(|aux| aux.topic.as_deref() == Some(name))
+            })
+            .map(|rr| rr.id)
+            .collect()
+    }
+
+    /// Apply any schedule periods that have started, as of `time` (local)
+    pub async fn tick(&mut self, time: NaiveTime) -> ApiResult<()> {
+        let state = self.state.clone();
+        let mut res = state.lock().await;
+
+        for (name, conf) in &self.motion_sensors {
+            let Some(period) = active_period(&conf.sensitivity_schedule, time) else {
+                continue;
+            };
+
+            for id in Self::motion_sensors(&res, name) {
+                if self.applied.get(&id) == Some(&period.at) {
+                    continue;
+                }
+
+                let current = res
+                    .get::<Motion>(&RType::Motion.link_to(id))?
+                    .sensitivity
+                    .map(|sens| sens.sensitivity);
+
+                if current != Some(period.sensitivity) {
+                    log::info!(
+                        "Scheduler: setting sensitivity of [{name}] to {} (from {})",
+                        period.sensitivity,
+                        period.at
+                    );
+                    let result = Source::Scheduler
+                        .sync_scope(|| res.set_motion_sensitivity(&id, period.sensitivity));
+                    if let Err(err) = result {
+                        log::warn!("Scheduler: cannot set sensitivity of [{name}]: {err}");
+                    }
+                }
+
+                self.applied.insert(id, period.at);
+            }
+        }
+        /* schedules due while not running (e.g. before startup) are skipped */
+        if let Some(last) = self.last_tick.replace(time) {
+            Self::recall_scenes(&mut res, last, time);
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    fn recall_scenes(res: &mut Resources, last: NaiveTime, now: NaiveTime) {
+        let due: Vec<(Uuid, SceneSchedule)> = res
+            .get_scene_schedules()
+            .iter()
+            .filter(|(_, schedule)| time_passed(schedule.at, last, now))
+            .map(|(id, schedule)| (*id, *schedule))
+            .collect();
+
+        for (id, schedule) in due {
+            let link = RType::Scene.link_to(schedule.scene);
+            log::info!("Scheduler: recalling scene {link:?} (at {})", schedule.at);
+
+            let result = Source::Scheduler.sync_scope(|| {
+                res.check_unlocked(&link, Utc::now())?;
+                res.scene_set_active(&link)?;
+                res.scene_recall(&link)
+            });
+            if let Err(err) = result {
+                log::warn!("Scheduler: cannot recall scene {link:?}: {err}");
+            }
+
+            if !schedule.daily {
+                let _ = res.delete_scene_schedule(&id);
+            }
+        }
+    }
+
+    pub async fn run_forever(mut self) -> ApiResult<()> {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(INTERVAL_SECS));
+
+        loop {
+            ticker.tick().await;
+            self.tick(Local::now().time()).await?;
+        }
+    }
+}
@@ -1,23 +1,32 @@
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::sync::Arc;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, RwLock};
 
 use axum_server::tls_rustls::RustlsConfig;
 use camino::Utf8Path;
 use chrono::Utc;
 use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
 use uuid::Uuid;
 
 use crate::config::AppConfig;
 use crate::error::{ApiError, ApiResult};
-use crate::hue::legacy_api::{ApiConfig, ApiShortConfig, Whitelist};
-use crate::model::state::{State, StateVersion};
+use crate::hue::legacy_api::{ApiConfig, ApiShortConfig, ConnectionState, PortalState, Whitelist};
+use crate::hue::HUE_BRIDGE_V2_DATASTORE_VERSION;
+use crate::model::state::State;
 use crate::resource::Resources;
+use crate::server::eventclients::EventClients;
+use crate::server::linkbutton::LinkButton;
+use crate::server::ratelimit::RateLimiter;
 use crate::server::{self, certificate};
+use crate::storage;
 
 #[derive(Clone)]
 pub struct AppState {
     conf: Arc<AppConfig>,
+    ipaddress: Arc<RwLock<Ipv4Addr>>,
+    events: Arc<EventClients>,
+    v1_limiter: Option<Arc<std::sync::Mutex<RateLimiter>>>,
+    linkbutton: Arc<std::sync::Mutex<LinkButton>>,
     pub res: Arc<Mutex<Resources>>,
 }
 
@@ -34,34 +43,43 @@ impl AppState {
         }
 
         let mut res;
+        let bridge_id = server::certificate::hue_bridge_id(config.bridge.mac);
 
-        if let Ok(fd) = File::open(&config.bifrost.state_file) {
-            log::debug!("Existing state file found, loading..");
-            let yaml = serde_yml::from_reader(fd)?;
-            let state = match State::version(&yaml)? {
-                StateVersion::V0 => {
-                    log::info!("Detected state file version 0. Upgrading to new version..");
-                    let backup_path = &config.bifrost.state_file.with_extension("v0.bak");
-                    fs::rename(&config.bifrost.state_file, backup_path)?;
-                    log::info!("  ..saved old state file as {backup_path}");
-                    State::from_v0(yaml)?
-                }
-                StateVersion::V1 => {
-                    log::info!("Detected state file version 1. Loading..");
-                    State::from_v1(yaml)?
-                }
-            };
+        if let Some(state) = storage::load(&config.bifrost.state_file)? {
             res = Resources::new(state);
         } else {
             log::debug!("No state file found, initializing..");
             res = Resources::new(State::new());
-            res.init(&server::certificate::hue_bridge_id(config.bridge.mac))?;
+            res.init(&bridge_id)?;
+            res.configure_bridge(&config.bridge.name, &config.bridge.timezone)?;
         }
 
+        res.add_bridge_services(&bridge_id)?;
+        res.add_behavior_scripts()?;
+        res.add_bridge_home_light()?;
+        res.set_stable_id_v1(config.bifrost.stable_v1_ids);
+
+        let ipaddress = Arc::new(RwLock::new(config.bridge.ipaddress));
+        let v1_limiter = config
+            .bifrost
+            .v1_rate_limit
+            .map(|rate| Arc::new(std::sync::Mutex::new(RateLimiter::new(rate))));
+        let linkbutton = LinkButton::new(
+            config.bifrost.linkbutton_file.clone(),
+            Duration::from_secs(config.bifrost.linkbutton_startup_secs),
+            Instant::now(),
+        );
         let conf = Arc::new(config);
         let res = Arc::new(Mutex::new(res));
 
-        Ok(Self { conf, res })
+        Ok(Self {
+            conf,
+            ipaddress,
+            events: Arc::new(EventClients::new()),
+            v1_limiter,
+            linkbutton: Arc::new(std::sync::Mutex::new(linkbutton)),
+            res,
+        })
     }
 
     pub async fn tls_config(&self) -> ApiResult<RustlsConfig> {
@@ -78,33 +96,129 @@ impl AppState {
         self.conf.clone()
     }
 
+    /// Current ipv4 address of the bridge (which can change, when following
+    /// a network interface)
+    #[must_use]
+    pub fn ipaddress(&self) -> Ipv4Addr {
+        *self
+            .ipaddress
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    pub fn set_ipaddress(&self, ip: Ipv4Addr) {
+        *self
+            .ipaddress
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = ip;
+    }
+
+    /// Connected eventstream clients
+    #[must_use]
+    pub fn event_clients(&self) -> Arc<EventClients> {
+        self.events.clone()
+    }
+
+    /// Take a v1 command from the budget of `client`, returning false if the
+    /// client is over the configured rate limit
+    #[must_use]
+    pub fn v1_allow(&self, client: IpAddr) -> bool {
+        self.v1_limiter.as_ref().map_or(true, |limiter| {
+            limiter
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .allow(client, Instant::now())
+        })
+    }
+
+    pub fn press_linkbutton(&self) {
+        log::info!("Link button pressed");
+        self.linkbutton
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .press(Instant::now());
+    }
+
     #[must_use]
-    pub fn api_short_config(&self) -> ApiShortConfig {
+    pub fn linkbutton_pressed(&self) -> bool {
+        self.linkbutton
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .is_pressed(Instant::now())
+    }
+
+    /// New v1 apps can pair, unless the link button is required and not
+    /// pressed
+    #[must_use]
+    pub fn pairing_allowed(&self) -> bool {
+        !self.conf.bifrost.require_linkbutton || self.linkbutton_pressed()
+    }
+
+    /// The v1 short config. The name is that of the bridge device, so it
+    /// follows renames from the v2 api, and the datastore version goes up
+    /// with every change to the state, so apps know to refresh it.
+    #[must_use]
+    pub fn api_short_config(&self, res: &Resources) -> ApiShortConfig {
         let mac = self.conf.bridge.mac;
         ApiShortConfig {
-            bridgeid: certificate::hue_bridge_id(mac),
+            name: res.get_bridge().map_or_else(
+                || self.conf.bridge.name.clone(),
+                |(_, dev)| dev.metadata.name.clone(),
+            ),
+            /* same id as in the certificate, but in upper case, like a real bridge */
+            bridgeid: certificate::hue_bridge_id(mac).to_uppercase(),
+            datastoreversion: (HUE_BRIDGE_V2_DATASTORE_VERSION + res.state_version()).to_string(),
             mac,
             ..Default::default()
         }
     }
 
     #[must_use]
-    pub fn api_config(&self, username: Uuid) -> ApiConfig {
-        ApiConfig {
-            short_config: self.api_short_config(),
-            ipaddress: self.conf.bridge.ipaddress,
+    pub fn api_config(&self, username: Uuid, res: &Resources) -> ApiConfig {
+        let mut config = ApiConfig {
+            short_config: self.api_short_config(res),
+            ipaddress: self.ipaddress(),
             netmask: self.conf.bridge.netmask,
             gateway: self.conf.bridge.gateway,
-            timezone: self.conf.bridge.timezone.clone(),
-            whitelist: HashMap::from([(
-                username,
-                Whitelist {
-                    create_date: Utc::now(),
-                    last_use_date: Utc::now(),
-                    name: "User#foo".to_string(),
-                },
-            )]),
+            timezone: res.get_bridge().map_or_else(
+                || self.conf.bridge.timezone.clone(),
+                |(bridge, _)| bridge.time_zone.time_zone.clone(),
+            ),
+            linkbutton: self.linkbutton_pressed(),
+            whitelist: res
+                .get_users()
+                .iter()
+                .map(|(id, user)| (*id, user.clone()))
+                .collect(),
             ..ApiConfig::default()
+        };
+
+        /* apps paired before users were kept still see themselves */
+        config
+            .whitelist
+            .entry(username)
+            .or_insert_with(|| Whitelist {
+                create_date: Utc::now(),
+                last_use_date: Utc::now(),
+                name: "User#foo".to_string(),
+            });
+
+        if self.conf.bifrost.fake_portal {
+            config.portalconnection = ConnectionState::Connected;
+            config.portalservices = true;
+        }
+        config.portalstate = self.portal_state();
+
+        config
+    }
+
+    /// State of the hue cloud portal, as reported to v1 clients
+    #[must_use]
+    pub fn portal_state(&self) -> PortalState {
+        if self.conf.bifrost.fake_portal {
+            PortalState::connected()
+        } else {
+            PortalState::default()
         }
     }
 }
@@ -1,8 +1,16 @@
+use std::fs::File;
 use std::io::{IsTerminal, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
 
 use termcolor::{self, Color, ColorSpec, StandardStream, WriteColor};
+use tokio::time::sleep;
 
 use crate::error::ApiResult;
+use crate::hue::api::RType;
+use crate::resource::Resources;
+use crate::server::appstate::AppState;
+use crate::server::certificate;
 
 struct Rainbow {
     stderr: StandardStream,
@@ -83,3 +91,113 @@ pub fn print() -> ApiResult<()> {
 
     Ok(())
 }
+
+/* Give z2m servers time to announce devices and groups before reporting */
+const DIAGNOSTICS_DELAY: Duration = Duration::from_secs(10);
+
+fn cert_status(appstate: &AppState) -> String {
+    let conf = appstate.config();
+    let certfile = &conf.bifrost.cert_file;
+    let id = certificate::hue_bridge_id(conf.bridge.mac);
+
+    match File::open(certfile).map(certificate::extract_common_name) {
+        Ok(Ok(Some(cn))) if cn == id => format!("ok [{certfile}]"),
+        Ok(Ok(Some(cn))) => format!("MISMATCH: common name is {cn}, expected {id}"),
+        Ok(Ok(None) | Err(_)) => format!("INVALID [{certfile}]"),
+        Err(err) => format!("MISSING [{certfile}]: {err}"),
+    }
+}
+
+/* Split configured room names into (matched, unmatched) */
+fn match_rooms<'a>(
+    res: &Resources,
+    names: impl Iterator<Item = &'a String>,
+) -> (usize, Vec<&'a str>) {
+    let mut matched = 0;
+    let mut unmatched = vec![];
+
+    for name in names {
+        if res.find_rooms(name).is_empty() {
+            unmatched.push(name.as_str());
+        } else {
+            matched += 1;
+        }
+    }
+
+    unmatched.sort_unstable();
+    (matched, unmatched)
+}
+
+fn room_status((matched, unmatched): (usize, Vec<&str>)) -> String {
+    if unmatched.is_empty() {
+        format!("{matched} matched")
+    } else {
+        format!(
+            "{matched} matched, {} UNMATCHED: {}",
+            unmatched.len(),
+            unmatched.join(", ")
+        )
+    }
+}
+
+fn listen_status(addrs: &[IpAddr], port: u16) -> String {
+    addrs
+        .iter()
+        .map(|addr| SocketAddr::new(*addr, port).to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Print a short table of diagnostics, to help spot common misconfigurations.
+pub async fn diagnostics(appstate: AppState) -> ApiResult<()> {
+    sleep(DIAGNOSTICS_DELAY).await;
+
+    let conf = appstate.config();
+    let lock = appstate.res.lock().await;
+
+    let count = |rtype| lock.get_resources_by_type(rtype).len();
+    let rows = [
+        ("Devices", count(RType::Device).to_string()),
+        ("Lights", count(RType::Light).to_string()),
+        ("Rooms", count(RType::Room).to_string()),
+        ("Scenes", count(RType::Scene).to_string()),
+        (
+            "Room config",
+            room_status(match_rooms(
+                &lock,
+                conf.rooms
+                    .keys()
+                    .chain(conf.z2m.servers.values().flat_map(|srv| srv.rooms.keys())),
+            )),
+        ),
+        (
+            "Switch config",
+            room_status(match_rooms(
+                &lock,
+                conf.switches.values().map(|sw| &sw.room),
+            )),
+        ),
+        ("Certificate", cert_status(&appstate)),
+        (
+            "Listening (http)",
+            listen_status(&conf.bridge.listen_addresses(), conf.bridge.http_port),
+        ),
+        (
+            "Listening (https)",
+            listen_status(&conf.bridge.listen_addresses(), conf.bridge.https_port),
+        ),
+        ("z2m servers", conf.z2m.servers.len().to_string()),
+    ];
+    drop(lock);
+
+    eprintln!();
+    eprintln!("  Startup diagnostics");
+    eprintln!("  {}", "-".repeat(67));
+    for (key, value) in rows {
+        eprintln!("  {key:<18} {value}");
+    }
+    eprintln!("  {}", "-".repeat(67));
+    eprintln!();
+
+    Ok(())
+}
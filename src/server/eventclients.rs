@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::select;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::hue::event::EventBlock;
+
+/* Number of events buffered for each eventstream client. Clients that fall
+ * further behind than this are disconnected, so they cannot hold up events
+ * for anybody else. */
+const CLIENT_BUFFER: usize = 256;
+
+/// Statistics for a connected eventstream client
+#[derive(Debug, Clone, Serialize)]
+pub struct EventClientInfo {
+    pub id: u64,
+    pub user_agent: Option<String>,
+    /// Client address (as reported by a trusted proxy, if any)
+    pub address: Option<IpAddr>,
+    pub connected: DateTime<Utc>,
+    /// Events sent to the client
+    pub sent: u64,
+    /// Events waiting to be sent to the client
+    pub lag: u64,
+    /// Highest lag seen for this client
+    pub max_lag: u64,
+}
+
+#[derive(Debug)]
+pub struct EventClient {
+    id: u64,
+    user_agent: Option<String>,
+    address: Option<IpAddr>,
+    connected: DateTime<Utc>,
+    queued: AtomicU64,
+    sent: AtomicU64,
+    max_lag: AtomicU64,
+}
+
+impl EventClient {
+    fn lag(&self) -> u64 {
+        let queued = self.queued.load(Ordering::Relaxed);
+        queued.saturating_sub(self.sent.load(Ordering::Relaxed))
+    }
+
+    fn queue(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.max_lag.fetch_max(self.lag(), Ordering::Relaxed);
+    }
+
+    /// Record that `count` events have been sent to the client
+    pub fn sent(&self, count: usize) {
+        self.sent.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn info(&self) -> EventClientInfo {
+        EventClientInfo {
+            id: self.id,
+            user_agent: self.user_agent.clone(),
+            address: self.address,
+            connected: self.connected,
+            sent: self.sent.load(Ordering::Relaxed),
+            lag: self.lag(),
+            max_lag: self.max_lag.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Registry of connected eventstream clients.
+///
+/// Each client gets its own event buffer, fed from the shared hue event
+/// channel, so one slow client does not cause lag for the others.
+#[derive(Debug, Default)]
+pub struct EventClients {
+    next_id: AtomicU64,
+    clients: Mutex<BTreeMap<u64, Arc<EventClient>>>,
+}
+
+impl EventClients {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, Arc<EventClient>>> {
+        self.clients.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Register a new client, and start buffering events from `channel` for it.
+    ///
+    /// The returned receiver is closed when the client falls too far behind.
+    pub fn subscribe(
+        self: &Arc<Self>,
+        channel: broadcast::Receiver<EventBlock>,
+        user_agent: Option<String>,
+        address: Option<IpAddr>,
+    ) -> (Arc<EventClient>, mpsc::Receiver<EventBlock>) {
+        let client = Arc::new(EventClient {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            user_agent,
+            address,
+            connected: Utc::now(),
+            queued: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            max_lag: AtomicU64::new(0),
+        });
+
+        self.lock().insert(client.id, client.clone());
+
+        let (tx, rx) = mpsc::channel(CLIENT_BUFFER);
+        tokio::spawn(self.clone().forward(client.clone(), channel, tx));
+
+        (client, rx)
+    }
+
+    async fn forward(
+        self: Arc<Self>,
+        client: Arc<EventClient>,
+        mut channel: broadcast::Receiver<EventBlock>,
+        tx: mpsc::Sender<EventBlock>,
+    ) {
+        let id = client.id;
+
+        loop {
+            let evt = select! {
+                evt = channel.recv() => evt,
+                () = tx.closed() => break,
+            };
+
+            match evt {
+                Ok(evt) => match tx.try_send(evt) {
+                    Ok(()) => client.queue(),
+                    Err(TrySendError::Full(_)) => {
+                        log::warn!(
+                            "Eventstream client {id} is more than {CLIENT_BUFFER} events behind, disconnecting"
+                        );
+                        break;
+                    }
+                    Err(TrySendError::Closed(_)) => break,
+                },
+                Err(RecvError::Lagged(count)) => {
+                    log::warn!("Eventstream client {id} missed {count} events, disconnecting");
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+
+        log::debug!("Eventstream client {id} disconnected");
+        self.lock().remove(&id);
+    }
+
+    /// Statistics for all connected clients
+    #[must_use]
+    pub fn info(&self) -> Vec<EventClientInfo> {
+        self.lock().values().map(|client| client.info()).collect()
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::{to_bytes, Body};
+use axum::extract::{Path, Request, State};
+use axum::http::header::{AUTHORIZATION, CONTENT_LENGTH, HOST, LOCATION, WWW_AUTHENTICATE};
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::any;
+use axum::Router;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::BytesMut;
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::config::{AppConfig, Z2mFrontend, Z2mServer};
+use crate::server::proxy::ClientInfo;
+use crate::server::same_secret;
+use crate::server::singleport::Transport;
+
+/*
+ * Reverse proxy for the zigbee2mqtt frontends, under `/z2m/<server>/`, so
+ * only the port of bifrost has to be exposed.
+ *
+ * There is no http client among our dependencies, so requests are forwarded
+ * as plain http/1.0 (which means the response simply ends when the
+ * connection is closed). Websocket upgrades are forwarded as http/1.1, after
+ * which both connections are joined together.
+ */
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/* uploads to the frontend (e.g. firmware files) can be large */
+const MAX_REQUEST_BODY: usize = 64 * 1024 * 1024;
+
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Where a frontend is served from (plain http only)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Upstream {
+    /// Host (and port, if given) for the `Host` header
+    pub host: String,
+    /// Address to connect to
+    pub addr: String,
+    /// Path the frontend is served under, without trailing slash
+    pub path: String,
+}
+
+impl Upstream {
+    /// Parse an `http://` (or `ws://`) url
+    #[must_use]
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .or_else(|| url.strip_prefix("ws://"))?;
+        let rest = rest.split(['?', '#']).next().unwrap_or_default();
+        let (host, path) = rest.find('/').map_or((rest, ""), |idx| rest.split_at(idx));
+        if host.is_empty() {
+            return None;
+        }
+
+        let addr = if host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+        {
+            host.to_string()
+        } else {
+            format!("{host}:80")
+        };
+
+        Some(Self {
+            host: host.to_string(),
+            addr,
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// The configured frontend url, or else the one z2m serves its websocket
+    /// api from
+    #[must_use]
+    pub fn for_server(server: &Z2mServer, frontend: &Z2mFrontend) -> Option<Self> {
+        if let Some(url) = &frontend.url {
+            return Self::parse(url);
+        }
+
+        let mut upstream = Self::parse(&server.url)?;
+        if let Some(path) = upstream.path.strip_suffix("/api") {
+            upstream.path = path.to_string();
+        }
+        Some(upstream)
+    }
+}
+
+struct Frontend {
+    upstream: Upstream,
+    /// `Authorization` header required from clients
+    auth: HeaderValue,
+    /// Accept credentials over plain http
+    allow_insecure: bool,
+}
+
+type Frontends = Arc<HashMap<String, Frontend>>;
+
+impl Frontend {
+    fn new(upstream: Upstream, conf: &Z2mFrontend) -> Option<Self> {
+        let credentials = BASE64.encode(format!("{}:{}", conf.username, conf.password));
+        let auth = HeaderValue::from_str(&format!("Basic {credentials}")).ok()?;
+        Some(Self {
+            upstream,
+            auth,
+            allow_insecure: conf.allow_insecure,
+        })
+    }
+
+    fn is_authorized(&self, req: &Request) -> bool {
+        req.headers()
+            .get(AUTHORIZATION)
+            .is_some_and(|auth| same_secret(auth.as_bytes(), self.auth.as_bytes()))
+    }
+
+    fn request_head(&self, name: &str, req: &Request, path: &str, upgrade: bool) -> Vec<u8> {
+        let query = req
+            .uri()
+            .query()
+            .map(|q| format!("?{q}"))
+            .unwrap_or_default();
+        let version = if upgrade { "1.1" } else { "1.0" };
+
+        let mut head = vec![];
+        let _ = write!(
+            head,
+            "{} {}/{path}{query} HTTP/{version}\r\nHost: {}\r\nX-Forwarded-Prefix: /z2m/{name}\r\n",
+            req.method(),
+            self.upstream.path,
+            self.upstream.host,
+        );
+
+        for (key, value) in req.headers() {
+            let hop = HOP_BY_HOP.contains(&key.as_str());
+            /* websocket upgrades need the connection and upgrade headers */
+            let keep = !hop || (upgrade && matches!(key.as_str(), "connection" | "upgrade"));
+            if !keep || key == HOST || key == AUTHORIZATION || key == CONTENT_LENGTH {
+                continue;
+            }
+            head.extend_from_slice(key.as_str().as_bytes());
+            head.extend_from_slice(b": ");
+            head.extend_from_slice(value.as_bytes());
+            head.extend_from_slice(b"\r\n");
+        }
+
+        head
+    }
+
+    async fn forward(&self, name: &str, path: &str, mut req: Request) -> io::Result<Response> {
+        let upgrade = req.headers().contains_key("upgrade");
+        let on_upgrade = upgrade.then(|| hyper::upgrade::on(&mut req));
+
+        let mut head = self.request_head(name, &req, path, upgrade);
+        let body = to_bytes(req.into_body(), MAX_REQUEST_BODY)
+            .await
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err.to_string()))?;
+        if !body.is_empty() {
+            let _ = write!(head, "Content-Length: {}\r\n", body.len());
+        }
+        head.extend_from_slice(b"\r\n");
+        head.extend_from_slice(&body);
+
+        let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(&self.upstream.addr))
+            .await
+            .map_err(|_| io::Error::new(ErrorKind::TimedOut, "connect timed out"))??;
+        let mut stream = BufReader::new(stream);
+        stream.write_all(&head).await?;
+
+        let mut resp = timeout(RESPONSE_TIMEOUT, read_response_head(&mut stream))
+            .await
+            .map_err(|_| io::Error::new(ErrorKind::TimedOut, "no response"))??;
+
+        if let (StatusCode::SWITCHING_PROTOCOLS, Some(on_upgrade)) = (resp.status(), on_upgrade) {
+            let name = name.to_string();
+            tokio::spawn(async move {
+                let res = match on_upgrade.await {
+                    Ok(upgraded) => {
+                        let mut client = TokioIo::new(upgraded);
+                        tokio::io::copy_bidirectional(&mut client, &mut stream)
+                            .await
+                            .map(|_| ())
+                    }
+                    Err(err) => Err(io::Error::other(err)),
+                };
+                if let Err(err) = res {
+                    log::debug!("[{name}] z2m frontend connection closed: {err}");
+                }
+            });
+            return Ok(resp);
+        }
+
+        /* anything but a websocket upgrade drops the hop-by-hop headers */
+        for hop in HOP_BY_HOP {
+            resp.headers_mut().remove(*hop);
+        }
+
+        let body = futures::stream::try_unfold(stream, |mut stream| async move {
+            let mut buf = BytesMut::with_capacity(16 * 1024);
+            let len = stream.read_buf(&mut buf).await?;
+            Ok::<_, io::Error>((len > 0).then(|| (buf.freeze(), stream)))
+        });
+        *resp.body_mut() = Body::from_stream(body);
+
+        Ok(resp)
+    }
+}
+
+/* over https, or plain http to a trusted proxy that was reached over https */
+fn is_secure(req: &Request) -> bool {
+    let ext = req.extensions();
+    ext.get::<Transport>() == Some(&Transport::Tls)
+        || ext
+            .get::<ClientInfo>()
+            .is_some_and(|info| info.proto.as_deref() == Some("https"))
+}
+
+/* read the status line and headers of a response, leaving the body */
+async fn read_response_head(stream: &mut BufReader<TcpStream>) -> io::Result<Response> {
+    let invalid = || io::Error::new(ErrorKind::InvalidData, "invalid response from frontend");
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or_else(invalid)?;
+
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = status;
+
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(invalid());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (key, value) = line.split_once(':').ok_or_else(invalid)?;
+        let key = HeaderName::from_bytes(key.trim().as_bytes()).map_err(|_| invalid())?;
+        let value = HeaderValue::from_str(value.trim()).map_err(|_| invalid())?;
+        resp.headers_mut().append(key, value);
+    }
+
+    Ok(resp)
+}
+
+async fn frontend_request(
+    State(frontends): State<Frontends>,
+    Path(params): Path<HashMap<String, String>>,
+    req: Request,
+) -> Response {
+    let name = params.get("server").cloned().unwrap_or_default();
+    let Some(frontend) = frontends.get(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    /* basic auth sends the password as is, so never ask for it unencrypted */
+    if !frontend.allow_insecure && !is_secure(&req) {
+        return (
+            StatusCode::FORBIDDEN,
+            "The z2m frontend is only served over https",
+        )
+            .into_response();
+    }
+
+    if !frontend.is_authorized(&req) {
+        let challenge = format!("Basic realm=\"z2m {name}\"");
+        return (StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, challenge)]).into_response();
+    }
+
+    /* the frontend uses relative links, which need the trailing slash. The
+     * path is taken as sent (the first segment being the server name), so
+     * anything escaped in it stays that way. */
+    let Some((_, path)) = req.uri().path()[1..].split_once('/') else {
+        let location = format!("{}/", &req.uri().path()[1..]);
+        return (StatusCode::PERMANENT_REDIRECT, [(LOCATION, location)]).into_response();
+    };
+    let path = path.to_string();
+
+    match frontend.forward(&name, &path, req).await {
+        Ok(resp) => resp,
+        Err(err) => {
+            log::warn!("[{name}] Request to z2m frontend failed: {err}");
+            (
+                StatusCode::BAD_GATEWAY,
+                format!("z2m frontend unavailable: {err}"),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Router for the frontends of all z2m servers that have one configured, or
+/// `None` if there are none
+#[must_use]
+pub fn router(config: &AppConfig) -> Option<Router> {
+    let mut frontends = HashMap::new();
+
+    for (name, server) in &config.z2m.servers {
+        let Some(conf) = &server.frontend else {
+            continue;
+        };
+        let Some(frontend) =
+            Upstream::for_server(server, conf).and_then(|upstream| Frontend::new(upstream, conf))
+        else {
+            log::warn!("[{name}] Cannot serve z2m frontend (only plain http is supported)");
+            continue;
+        };
+        log::info!(
+            "[{name}] Serving z2m frontend from {} under /z2m/{name}/",
+            frontend.upstream.addr
+        );
+        if conf.allow_insecure {
+            log::warn!("[{name}] z2m frontend credentials are accepted over plain http");
+        }
+        frontends.insert(name.clone(), frontend);
+    }
+
+    if frontends.is_empty() {
+        return None;
+    }
+
+    Some(
+        Router::new()
+            .route("/:server", any(frontend_request))
+            .route("/:server/", any(frontend_request))
+            .route("/:server/*path", any(frontend_request))
+            .with_state(Arc::new(frontends)),
+    )
+}
@@ -0,0 +1,289 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+use uuid::Uuid;
+
+use crate::config::HaConfig;
+use crate::error::{ApiError, ApiResult};
+
+/// Contents of a lease file
+#[derive(Debug, Serialize, Deserialize)]
+struct LockRecord {
+    owner: Uuid,
+    name: String,
+    heartbeat: DateTime<Utc>,
+}
+
+/* the current lease, as found on disk */
+struct Lease {
+    token: u64,
+    record: Option<LockRecord>,
+    /* last time the lease was refreshed */
+    heartbeat: DateTime<Utc>,
+}
+
+impl Lease {
+    fn holder(self) -> String {
+        self.record
+            .map_or_else(|| "<unknown>".to_string(), |rec| rec.name)
+    }
+}
+
+/// Leader election between Bifrost instances, through leases on shared
+/// storage.
+///
+/// Each lease is a file named after the lock file, with a fencing token
+/// appended (`leader.lock.1`, `leader.lock.2`, ..), and the lease with the
+/// highest token is the current one. The active instance writes its id and a
+/// fresh timestamp to its lease every `heartbeat_secs`.
+///
+/// A standby instance waits until the current lease has not been refreshed
+/// for `timeout_secs`, and then takes over by creating the next lease with
+/// `O_CREAT|O_EXCL`, which only one instance can succeed at. The old leader
+/// sees the newer lease on its next heartbeat, and steps down.
+///
+/// When `peers` are configured, a standby also checks that none of them
+/// accepts tcp connections before taking over, so a leader is never replaced
+/// just because the shared storage shows a stale lease.
+pub struct LeaderLock {
+    path: Utf8PathBuf,
+    name: String,
+    owner: Uuid,
+    peers: Vec<String>,
+    heartbeat: Duration,
+    timeout: chrono::Duration,
+    token: u64,
+}
+
+impl LeaderLock {
+    #[must_use]
+    pub fn new(config: &HaConfig) -> Self {
+        Self {
+            path: config.lock_file.clone(),
+            name: config.name.clone(),
+            owner: Uuid::new_v4(),
+            peers: config.peers.clone(),
+            heartbeat: Duration::from_secs(config.heartbeat_secs),
+            timeout: chrono::Duration::seconds(i64::try_from(config.timeout_secs).unwrap_or(60)),
+            token: 0,
+        }
+    }
+
+    /// Fencing token of the lease held by this instance (0 before acquiring
+    /// leadership). Every new leader gets a higher token than the last.
+    #[must_use]
+    pub const fn token(&self) -> u64 {
+        self.token
+    }
+
+    fn lease_path(&self, token: u64) -> Utf8PathBuf {
+        lease_path(&self.path, token)
+    }
+
+    /* fencing tokens of all leases present */
+    fn tokens(&self) -> ApiResult<Vec<u64>> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_str().is_empty() => dir,
+            _ => Utf8Path::new("."),
+        };
+        let prefix = format!("{}.", self.path.file_name().unwrap_or_default());
+
+        let mut tokens = vec![];
+        for entry in dir.read_dir_utf8()? {
+            let entry = entry?;
+            if let Some(token) = entry
+                .file_name()
+                .strip_prefix(&prefix)
+                .and_then(|tail| tail.parse().ok())
+            {
+                tokens.push(token);
+            }
+        }
+        tokens.sort_unstable();
+        Ok(tokens)
+    }
+
+    fn current(&self) -> ApiResult<Option<Lease>> {
+        let Some(token) = self.tokens()?.last().copied() else {
+            return Ok(None);
+        };
+
+        let path = self.lease_path(token);
+        let record: Option<LockRecord> = fs::read_to_string(&path)
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok());
+
+        /* a lease that was only just created is still empty, so fall back to
+         * the modification time of the file */
+        let heartbeat = record.as_ref().map_or_else(
+            || {
+                fs::metadata(&path)
+                    .and_then(|md| md.modified())
+                    .map_or_else(|_| Utc::now(), DateTime::from)
+            },
+            |rec| rec.heartbeat,
+        );
+
+        Ok(Some(Lease {
+            token,
+            record,
+            heartbeat,
+        }))
+    }
+
+    fn record(&self) -> ApiResult<Vec<u8>> {
+        let record = LockRecord {
+            owner: self.owner,
+            name: self.name.clone(),
+            heartbeat: Utc::now(),
+        };
+        Ok(serde_json::to_vec(&record)?)
+    }
+
+    /* create the lease for `token`, failing if any other instance got to it
+     * first */
+    fn create(&self, token: u64) -> ApiResult<bool> {
+        let mut fd = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.lease_path(token))
+        {
+            Ok(fd) => fd,
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+
+        fd.write_all(&self.record()?)?;
+        fd.sync_all()?;
+        Ok(true)
+    }
+
+    /* refresh our own lease, which no other instance writes to */
+    fn refresh(&self) -> ApiResult<()> {
+        let path = self.lease_path(self.token);
+        let tmp = path.with_extension(format!("{}.tmp", self.owner));
+        let mut fd = File::create(&tmp)?;
+        fd.write_all(&self.record()?)?;
+        fd.sync_all()?;
+        Ok(fs::rename(&tmp, &path)?)
+    }
+
+    /* leases older than ours are of no use to anyone */
+    fn remove_old_leases(&self) -> ApiResult<()> {
+        for token in self.tokens()? {
+            if token < self.token {
+                fs::remove_file(self.lease_path(token))?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn peer_reachable(&self) -> Option<&str> {
+        for peer in &self.peers {
+            let Ok(addrs) = peer.to_socket_addrs() else {
+                continue;
+            };
+            for addr in addrs {
+                if let Ok(Ok(_)) = timeout(self.heartbeat, TcpStream::connect(addr)).await {
+                    return Some(peer);
+                }
+            }
+        }
+        None
+    }
+
+    /// Wait until this instance is the leader, and return its fencing token.
+    pub async fn acquire(&mut self) -> ApiResult<u64> {
+        let mut waiting = false;
+
+        loop {
+            let next = match self.current()? {
+                None => Some(1),
+                Some(lease) if lease.heartbeat + self.timeout < Utc::now() => Some(lease.token + 1),
+                Some(lease) => {
+                    if !waiting {
+                        let name = lease.holder();
+                        log::info!("[ha] Instance [{name}] is active, waiting as standby..");
+                        waiting = true;
+                    }
+                    None
+                }
+            };
+
+            if let Some(token) = next {
+                if let Some(peer) = self.peer_reachable().await {
+                    log::warn!("[ha] Lease is stale, but peer [{peer}] is still reachable");
+                } else if self.create(token)? {
+                    self.token = token;
+                    log::info!(
+                        "[ha] Acquired leadership as [{}], with token {token}",
+                        self.name
+                    );
+                    if let Err(err) = self.remove_old_leases() {
+                        log::warn!("[ha] Cannot remove old leases: {err}");
+                    }
+                    return Ok(token);
+                }
+            }
+
+            sleep(self.heartbeat).await;
+        }
+    }
+
+    /// Keep the lease fresh, for as long as we are the leader.
+    ///
+    /// Returns [`ApiError::LeadershipLost`] if another instance has taken
+    /// over, or if the lease could not be refreshed for so long that a
+    /// standby may take over before the next attempt. Either way, this
+    /// instance must stop serving immediately.
+    pub async fn hold(self) -> ApiResult<()> {
+        let mut refreshed = Utc::now();
+
+        loop {
+            sleep(self.heartbeat).await;
+
+            match self.current() {
+                Ok(Some(lease)) if lease.token > self.token => {
+                    return Err(ApiError::LeadershipLost(lease.holder()));
+                }
+                Ok(Some(lease))
+                    if lease
+                        .record
+                        .as_ref()
+                        .is_some_and(|rec| rec.owner != self.owner) =>
+                {
+                    return Err(ApiError::LeadershipLost(lease.holder()));
+                }
+                _ => {}
+            }
+
+            match self.refresh() {
+                Ok(()) => refreshed = Utc::now(),
+                Err(err) => {
+                    log::warn!("[ha] Cannot refresh lease {}: {err}", self.path);
+
+                    /* step down before a standby can consider the lease stale */
+                    let heartbeat =
+                        chrono::Duration::from_std(self.heartbeat).unwrap_or(self.timeout);
+                    if Utc::now() + heartbeat >= refreshed + self.timeout {
+                        log::error!("[ha] Lease could not be refreshed in time, stepping down");
+                        return Err(ApiError::LeadershipLost("<unknown>".to_string()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Path of the lease file for `token`
+#[must_use]
+pub fn lease_path(lock_file: &Utf8Path, token: u64) -> Utf8PathBuf {
+    Utf8PathBuf::from(format!("{lock_file}.{token}"))
+}
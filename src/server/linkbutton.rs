@@ -0,0 +1,51 @@
+use std::time::SystemTime;
+
+use camino::Utf8PathBuf;
+use tokio::time::{Duration, Instant};
+
+/// Emulated link button of a hue bridge.
+///
+/// Like on a real bridge, new apps can be paired for a short while after
+/// the button is pressed. Since there is no physical button, it is pressed
+/// through the admin api, by touching a file, or for a while after startup.
+#[derive(Debug)]
+pub struct LinkButton {
+    pressed_until: Option<Instant>,
+    file: Option<Utf8PathBuf>,
+}
+
+impl LinkButton {
+    /// Time a press of the button lasts, same as on a real bridge
+    pub const PRESS_DURATION: Duration = Duration::from_secs(30);
+
+    #[must_use]
+    pub fn new(file: Option<Utf8PathBuf>, startup: Duration, now: Instant) -> Self {
+        Self {
+            pressed_until: (!startup.is_zero()).then(|| now + startup),
+            file,
+        }
+    }
+
+    pub fn press(&mut self, now: Instant) {
+        let until = now + Self::PRESS_DURATION;
+        self.pressed_until = Some(self.pressed_until.map_or(until, |old| old.max(until)));
+    }
+
+    #[must_use]
+    pub fn is_pressed(&self, now: Instant) -> bool {
+        self.pressed_until.is_some_and(|until| now < until) || self.file_touched()
+    }
+
+    /* the file counts as a press from the time it was last modified */
+    fn file_touched(&self) -> bool {
+        let Some(file) = &self.file else {
+            return false;
+        };
+
+        std::fs::metadata(file)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+            .is_some_and(|age| age < Self::PRESS_DURATION)
+    }
+}
@@ -1,35 +1,53 @@
 pub mod appstate;
 pub mod banner;
 pub mod certificate;
+pub mod eventclients;
+pub mod frontend;
+pub mod ha;
+pub mod linkbutton;
+pub mod netwatch;
+pub mod proxy;
+pub mod ratelimit;
+pub mod replication;
+pub mod singleport;
 
-use std::fs::File;
-use std::io::Write;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
 
 use axum::body::Body;
+use axum::extract::connect_info::IntoMakeServiceWithConnectInfo;
 use axum::extract::Request;
+use axum::http::{HeaderValue, Method};
+use axum::middleware::{self, Next};
 use axum::response::Response;
-use axum::routing::IntoMakeService;
-use axum::{Router, ServiceExt};
+use axum::Router;
+use axum_server::accept::DefaultAcceptor;
 use axum_server::service::MakeService;
-use axum_server::tls_rustls::RustlsConfig;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
 
-use camino::Utf8PathBuf;
 use hyper::body::Incoming;
 use tokio::select;
 use tokio::sync::Mutex;
-use tokio::time::sleep_until;
+use tokio::task::spawn_blocking;
+use tokio::time::{sleep_until, Instant};
 use tower::Layer;
-use tower_http::normalize_path::{NormalizePath, NormalizePathLayer};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tower_http::normalize_path::NormalizePathLayer;
 use tower_http::trace::TraceLayer;
 use tracing::{info_span, Span};
 
+use crate::config::AppConfig;
 use crate::error::ApiResult;
+use crate::journal::TraceId;
 use crate::resource::Resources;
 use crate::routes;
+use crate::storage::{self, StateStore};
 use appstate::AppState;
+use proxy::ClientInfo;
+use singleport::{SinglePortAcceptor, Transport, TransportAcceptor};
+
+const X_TRACE_ID: &str = "x-trace-id";
 
 fn trace_layer_on_response(response: &Response<Body>, latency: Duration, span: &Span) {
     span.record(
@@ -39,30 +57,131 @@ fn trace_layer_on_response(response: &Response<Body>, latency: Duration, span: &
     span.record("status", tracing::field::display(response.status()));
 }
 
-fn router(appstate: AppState) -> Router<()> {
-    routes::router(appstate).layer(
-        TraceLayer::new_for_http()
-            .make_span_with(|request: &Request| {
-                info_span!(
-                    "http",
-                    method = ?request.method(),
-                    uri = ?request.uri(),
-                    status = tracing::field::Empty,
-                    /* latency = tracing::field::Empty, */
-                )
+/* every api request gets a trace id, which is added to the logs and journal
+ * entries for everything the request causes, and returned to the client */
+async fn trace_request(request: Request, next: Next) -> Response {
+    let trace = TraceId::new();
+    let mut response = trace.scope(next.run(request)).await;
+
+    if let Ok(value) = HeaderValue::from_str(&trace.to_string()) {
+        response.headers_mut().insert(X_TRACE_ID, value);
+    }
+
+    response
+}
+
+fn cors_layer(config: &AppConfig) -> Option<CorsLayer> {
+    let origins = &config.bifrost.cors_origins;
+    if origins.is_empty() {
+        return None;
+    }
+
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        let list = origins
+            .iter()
+            .filter_map(|origin| match HeaderValue::from_str(origin) {
+                Ok(value) => Some(value),
+                Err(err) => {
+                    log::warn!("Ignoring invalid cors origin {origin:?}: {err}");
+                    None
+                }
             })
-            .on_response(trace_layer_on_response),
+            .collect::<Vec<_>>();
+        AllowOrigin::list(list)
+    };
+
+    log::info!("Enabling cors for origins: {origins:?}");
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::DELETE,
+                Method::OPTIONS,
+            ])
+            .allow_headers(Any),
     )
 }
 
+fn router(appstate: AppState) -> Router<()> {
+    let config = appstate.config();
+    let cors = cors_layer(&config);
+
+    let mut router = routes::router(appstate.clone());
+
+    /* hue apps always use the root paths, so when a base path is configured
+     * (for use behind a reverse proxy), the api is available in both places */
+    if let Some(base_path) = &config.bifrost.base_path {
+        log::info!("Also serving api under base path {base_path}");
+        router = Router::new().nest(base_path, router.clone()).merge(router);
+    }
+
+    if let Some(cors) = cors {
+        router = router.layer(cors);
+    }
+
+    router
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|request: &Request| {
+                    let client = request
+                        .extensions()
+                        .get::<ClientInfo>()
+                        .map_or_else(|| String::from("-"), |client| client.addr.to_string());
+                    info_span!(
+                        "http",
+                        client = %client,
+                        method = ?request.method(),
+                        uri = ?request.uri(),
+                        status = tracing::field::Empty,
+                        /* latency = tracing::field::Empty, */
+                    )
+                })
+                .on_response(trace_layer_on_response),
+        )
+        .layer(middleware::from_fn(trace_request))
+        .layer(middleware::from_fn_with_state(appstate, proxy::client_info))
+}
+
+/// Compare a secret (e.g. a password or token) sent by a client with the
+/// wanted one, in constant time, so it cannot be guessed from how long it
+/// takes to reject
+#[must_use]
+pub fn same_secret(given: &[u8], wanted: &[u8]) -> bool {
+    let diff = given
+        .iter()
+        .zip(wanted)
+        .fold(0, |acc, (a, b)| std::hint::black_box(acc | (a ^ b)));
+    given.len() == wanted.len() && diff == 0
+}
+
 #[must_use]
-pub fn build_service(appstate: AppState) -> IntoMakeService<NormalizePath<Router>> {
+pub fn build_service(appstate: AppState) -> IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
+    let frontends = frontend::router(&appstate.config()).map(|frontends| {
+        frontends.layer(middleware::from_fn_with_state(
+            appstate.clone(),
+            proxy::client_info,
+        ))
+    });
     let normalized = NormalizePathLayer::trim_trailing_slash().layer(router(appstate));
 
-    ServiceExt::<Request>::into_make_service(normalized)
+    /* the z2m frontends are routed before the path is normalized, since
+     * their relative links depend on the trailing slash */
+    let mut svc = Router::new();
+    if let Some(frontends) = frontends {
+        svc = svc.nest_service("/z2m", frontends);
+    }
+
+    svc.fallback_service(normalized)
+        .into_make_service_with_connect_info::<SocketAddr>()
 }
 
-pub async fn http_server<S>(listen_addr: Ipv4Addr, listen_port: u16, svc: S) -> ApiResult<()>
+pub async fn http_server<S>(listen_addr: IpAddr, listen_port: u16, svc: S) -> ApiResult<()>
 where
     S: Send + MakeService<SocketAddr, Request<Incoming>>,
     S::MakeFuture: Send,
@@ -70,13 +189,16 @@ where
     let addr = SocketAddr::from((listen_addr, listen_port));
     log::info!("http listening on {}", addr);
 
-    axum_server::bind(addr).serve(svc).await?;
+    axum_server::bind(addr)
+        .acceptor(TransportAcceptor::new(DefaultAcceptor, Transport::Plain))
+        .serve(svc)
+        .await?;
 
     Ok(())
 }
 
 pub async fn https_server<S>(
-    listen_addr: Ipv4Addr,
+    listen_addr: IpAddr,
     listen_port: u16,
     svc: S,
     config: RustlsConfig,
@@ -88,48 +210,83 @@ where
     let addr = SocketAddr::from((listen_addr, listen_port));
     log::info!("https listening on {}", addr);
 
-    axum_server::bind_rustls(addr, config).serve(svc).await?;
+    let tls = RustlsAcceptor::new(config);
+    axum_server::bind(addr)
+        .acceptor(TransportAcceptor::new(tls, Transport::Tls))
+        .serve(svc)
+        .await?;
 
     Ok(())
 }
 
-pub async fn config_writer(res: Arc<Mutex<Resources>>, filename: Utf8PathBuf) -> ApiResult<()> {
-    const STABILIZE_TIME: Duration = Duration::from_secs(1);
+/// Serve http and https on the same port, telling them apart by the first
+/// bytes of each connection
+pub async fn single_port_server<S>(
+    listen_addr: IpAddr,
+    listen_port: u16,
+    svc: S,
+    config: RustlsConfig,
+) -> ApiResult<()>
+where
+    S: Send + MakeService<SocketAddr, Request<Incoming>>,
+    S::MakeFuture: Send,
+{
+    let addr = SocketAddr::from((listen_addr, listen_port));
+    log::info!("http and https listening on {}", addr);
+
+    axum_server::bind(addr)
+        .acceptor(SinglePortAcceptor::new(config))
+        .serve(svc)
+        .await?;
+
+    Ok(())
+}
+
+/* Serializing a large state takes a while, so only hold the lock while taking
+ * a snapshot, and leave the actual work to a blocking thread */
+async fn save_state(
+    res: &Mutex<Resources>,
+    mut store: Box<dyn StateStore>,
+) -> ApiResult<Box<dyn StateStore>> {
+    let state = res.lock().await.snapshot();
+    spawn_blocking(move || {
+        if store.save(&state)? {
+            log::debug!("State changed, saved");
+        }
+        Ok(store)
+    })
+    .await?
+}
+
+pub async fn config_writer(res: Arc<Mutex<Resources>>, config: Arc<AppConfig>) -> ApiResult<()> {
+    let bconf = &config.bifrost;
+    let debounce = Duration::from_millis(bconf.save_debounce_ms);
+    let max_delay = Duration::from_millis(bconf.save_max_delay_ms);
 
     let rx = res.lock().await.state_channel();
-    let tmp = filename.with_extension("tmp");
 
-    let mut old_state = res.lock().await.serialize()?;
+    /* save right away, in case the state was migrated from another file */
+    let mut store = save_state(&res, storage::open(&bconf.state_file)?).await?;
 
     loop {
         /* Wait for change notification */
         rx.notified().await;
 
         /* Updates often happen in burst, and we don't want to write the state
-         * file over and over, so ignore repeated update notifications within
-         * STABILIZE_TIME */
-        let deadline = tokio::time::Instant::now() + STABILIZE_TIME;
+         * file over and over, so wait until no updates have arrived for the
+         * debounce time. Changes can keep coming for a long time (e.g. while a
+         * light is blinking), so save after max_delay regardless. */
+        let max_deadline = Instant::now() + max_delay;
         loop {
+            let deadline = (Instant::now() + debounce).min(max_deadline);
             select! {
                 () = rx.notified() => {},
                 () = sleep_until(deadline) => break,
             }
         }
 
-        /* Now that the state is likely stabilized, serialize the new state */
-        let new_state = res.lock().await.serialize()?;
-
-        /* If state is not actually changed, try again */
-        if old_state == new_state {
-            continue;
-        }
-
-        log::debug!("Config changed, saving..");
-
-        let mut fd = File::create(&tmp)?;
-        fd.write_all(new_state.as_bytes())?;
-        std::fs::rename(&tmp, &filename)?;
-
-        old_state = new_state;
+        /* Now that the state is likely stabilized, save it (which does nothing,
+         * if the state is not actually changed) */
+        store = save_state(&res, store).await?;
     }
 }
@@ -0,0 +1,63 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use mdns_sd::ServiceDaemon;
+use tokio::time::sleep;
+
+use crate::error::ApiResult;
+use crate::mdns;
+use crate::server::appstate::AppState;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Find the (first) ipv4 address of the named network interface
+#[must_use]
+pub fn interface_ipv4(interface: &str) -> Option<Ipv4Addr> {
+    let addrs = if_addrs::get_if_addrs()
+        .map_err(|err| log::error!("Cannot list network interfaces: {err}"))
+        .ok()?;
+
+    addrs
+        .into_iter()
+        .filter(|iface| iface.name == interface)
+        .find_map(|iface| match iface.addr {
+            if_addrs::IfAddr::V4(addr) => Some(addr.ip),
+            if_addrs::IfAddr::V6(_) => None,
+        })
+}
+
+/// Follow address changes on the configured network interface (e.g. from a
+/// new DHCP lease), and announce the new address over mDNS.
+///
+/// The http(s) listeners are bound to all addresses in this mode, so they
+/// keep working without a restart.
+pub async fn interface_watcher(
+    appstate: AppState,
+    interface: String,
+    mut mdns: Option<ServiceDaemon>,
+) -> ApiResult<()> {
+    loop {
+        sleep(POLL_INTERVAL).await;
+
+        let Some(ip) = interface_ipv4(&interface) else {
+            log::warn!("Interface [{interface}] has no ipv4 address, keeping the previous one");
+            continue;
+        };
+
+        let old = appstate.ipaddress();
+        if ip == old {
+            continue;
+        }
+
+        log::warn!("Address of interface [{interface}] changed from {old} to {ip}, re-announcing");
+        appstate.set_ipaddress(ip);
+
+        let mut addrs = appstate.config().bridge.addresses();
+        addrs[0] = ip.into();
+
+        if let Some(Err(err)) = mdns.as_ref().map(ServiceDaemon::shutdown) {
+            log::error!("Failed to stop mDNS service: {err}");
+        }
+        mdns = Some(mdns::register_mdns(appstate.config().bridge.mac, &addrs)?);
+    }
+}
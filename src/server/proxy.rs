@@ -0,0 +1,113 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header::HOST, HeaderMap};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::server::appstate::AppState;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+
+/* first value of a (possibly comma separated) header */
+fn first_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// The client that made a request, as seen through any trusted reverse
+/// proxies. Available as a request extension on every request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub addr: IpAddr,
+    /// Protocol used by the client, if reported by a trusted proxy
+    pub proto: Option<String>,
+    /// Host name used by the client, if behind a trusted proxy
+    pub host: Option<String>,
+    /// Path prefix stripped by a trusted proxy, if any
+    pub prefix: Option<String>,
+}
+
+impl ClientInfo {
+    /// Find the client for a request from `peer`.
+    ///
+    /// Forwarded headers are only honored when `peer` is a trusted proxy. The
+    /// client address is then the last one in `X-Forwarded-For` that is not
+    /// itself a trusted proxy, since everything before that could have been
+    /// made up by the client.
+    #[must_use]
+    pub fn resolve(peer: IpAddr, headers: &HeaderMap, trusted: &[IpAddr]) -> Self {
+        if !trusted.contains(&peer) {
+            return Self {
+                addr: peer,
+                proto: None,
+                host: None,
+                prefix: None,
+            };
+        }
+
+        let chain: Vec<IpAddr> = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|addr| addr.trim().parse().ok())
+            .collect();
+
+        let addr = chain
+            .iter()
+            .rev()
+            .find(|addr| !trusted.contains(addr))
+            .or_else(|| chain.first())
+            .copied()
+            .unwrap_or(peer);
+
+        let proto = first_value(headers, X_FORWARDED_PROTO).map(str::to_ascii_lowercase);
+        let host = first_value(headers, X_FORWARDED_HOST)
+            .or_else(|| first_value(headers, HOST.as_str()))
+            .map(ToString::to_string);
+        let prefix = first_value(headers, X_FORWARDED_PREFIX)
+            .map(|prefix| prefix.trim_end_matches('/').to_string());
+
+        Self {
+            addr,
+            proto,
+            host,
+            prefix,
+        }
+    }
+
+    /// Absolute url of the api root, as seen by the client, if a trusted
+    /// proxy reported enough to tell. The path is the prefix stripped by the
+    /// proxy, or else `base_path`.
+    #[must_use]
+    pub fn base_url(&self, base_path: Option<&str>) -> Option<String> {
+        let proto = self.proto.as_ref()?;
+        let host = self.host.as_ref()?;
+        let path = self.prefix.as_deref().or(base_path).unwrap_or_default();
+        Some(format!("{proto}://{host}{path}"))
+    }
+}
+
+/// Middleware that attaches [`ClientInfo`] to each request
+pub async fn client_info(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if let Some(ConnectInfo(peer)) = connect_info {
+        let trusted = &state.config().bifrost.trusted_proxies;
+        let info = ClientInfo::resolve(peer.ip(), request.headers(), trusted);
+        request.extensions_mut().insert(info);
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use tokio::time::{Duration, Instant};
+
+/* clients idle for this long are forgotten */
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// Per-client token bucket, emulating the command rate limit of a real hue
+/// bridge.
+///
+/// Each client may burst up to `rate` commands, and is then limited to
+/// `rate` commands per second.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    clients: HashMap<IpAddr, Bucket>,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate: f64::from(rate.max(1)),
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Take a command from the budget of `client`, returning false if the
+    /// client is over the limit
+    pub fn allow(&mut self, client: IpAddr, now: Instant) -> bool {
+        self.clients
+            .retain(|_, bucket| now.saturating_duration_since(bucket.last) < IDLE_TIMEOUT);
+
+        let bucket = self.clients.entry(client).or_insert(Bucket {
+            tokens: self.rate,
+            last: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = elapsed.mul_add(self.rate, bucket.tokens).min(self.rate);
+        bucket.last = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+}
@@ -0,0 +1,175 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::select;
+use tokio::sync::Mutex;
+use tokio::task::spawn_blocking;
+use tokio::time::{sleep, timeout};
+
+use crate::config::HaConfig;
+use crate::error::ApiResult;
+use crate::model::state::State;
+use crate::resource::Resources;
+use crate::storage;
+
+/// A message from the active instance to a standby, sent as a single line of
+/// json. The state is only included when it changed since the last message.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Replica {
+    /// Fencing token of the sender, so messages from an instance that has
+    /// lost its lease can be told apart
+    pub token: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<State>,
+}
+
+/// Send the state to standby instances, while this instance is active.
+///
+/// Every standby that connects gets the full state right away, and again
+/// every time it changes. In between, a heartbeat (without state) is sent
+/// every `heartbeat`, so standbys can tell the connection is still alive.
+pub async fn serve(
+    listen: SocketAddr,
+    res: Arc<Mutex<Resources>>,
+    token: u64,
+    heartbeat: Duration,
+) -> ApiResult<()> {
+    let listener = TcpListener::bind(listen).await?;
+    log::info!("[ha] Replicating state on {listen}");
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        log::info!("[ha] Standby connected from {addr}");
+
+        let res = res.clone();
+        tokio::spawn(async move {
+            if let Err(err) = send_replicas(stream, res, token, heartbeat).await {
+                log::info!("[ha] Standby {addr} disconnected: {err}");
+            }
+        });
+    }
+}
+
+async fn send_replicas(
+    mut stream: TcpStream,
+    res: Arc<Mutex<Resources>>,
+    token: u64,
+    heartbeat: Duration,
+) -> ApiResult<()> {
+    let mut changes = res.lock().await.state_watch();
+    let mut dirty = true;
+
+    loop {
+        let state = if dirty {
+            Some(res.lock().await.snapshot())
+        } else {
+            None
+        };
+
+        let mut line = serde_json::to_vec(&Replica { token, state })?;
+        line.push(b'\n');
+        stream.write_all(&line).await?;
+
+        dirty = select! {
+            res = changes.changed() => res.map(|()| true)?,
+            () = sleep(heartbeat) => false,
+        };
+    }
+}
+
+/// Keeps a copy of the state of the active instance, while on standby.
+///
+/// Replicated state is saved to the local state file, so this instance
+/// starts from it when it takes over.
+pub struct Follower {
+    peers: Vec<String>,
+    state_file: Utf8PathBuf,
+    heartbeat: Duration,
+    /* held for as long as a save is in progress */
+    saving: Arc<Mutex<()>>,
+}
+
+impl Follower {
+    #[must_use]
+    pub fn new(config: &HaConfig, state_file: &Utf8Path) -> Self {
+        Self {
+            peers: config.peers.clone(),
+            state_file: state_file.to_path_buf(),
+            heartbeat: Duration::from_secs(config.heartbeat_secs),
+            saving: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Follow the peers, one at a time, for as long as the future is polled.
+    pub async fn run(&self) -> ApiResult<()> {
+        if self.peers.is_empty() {
+            return futures::future::pending().await;
+        }
+
+        let mut highest = 0;
+
+        loop {
+            for peer in &self.peers {
+                match self.follow(peer, &mut highest).await {
+                    Ok(()) => log::info!("[ha] Peer [{peer}] closed the connection"),
+                    Err(err) => log::debug!("[ha] Cannot replicate from [{peer}]: {err}"),
+                }
+            }
+            sleep(self.heartbeat).await;
+        }
+    }
+
+    async fn follow(&self, peer: &str, highest: &mut u64) -> ApiResult<()> {
+        let stream = timeout(self.heartbeat, TcpStream::connect(peer)).await??;
+        let mut lines = BufReader::new(stream).lines();
+
+        log::info!("[ha] Replicating state from [{peer}]");
+
+        /* a live leader sends a heartbeat at least this often */
+        while let Some(line) = timeout(self.heartbeat * 3, lines.next_line()).await?? {
+            let replica: Replica = serde_json::from_str(&line)?;
+
+            if replica.token < *highest {
+                log::warn!(
+                    "[ha] Ignoring state from [{peer}], with token {} (current is {highest})",
+                    replica.token
+                );
+                return Ok(());
+            }
+            *highest = replica.token;
+
+            if let Some(state) = replica.state {
+                self.save(state).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn save(&self, state: State) -> ApiResult<()> {
+        /* the guard moves into the blocking task, so a save keeps the lock
+         * until it is done, even if this future is dropped */
+        let guard = self.saving.clone().lock_owned().await;
+        let path = self.state_file.clone();
+
+        spawn_blocking(move || {
+            let _guard = guard;
+            if storage::open(&path)?.save(&state)? {
+                log::debug!("[ha] Replicated state saved");
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Wait for any save in progress to finish, so the state file is
+    /// complete before it is loaded.
+    pub async fn finish(self) {
+        let _ = self.saving.lock().await;
+    }
+}
@@ -0,0 +1,159 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::middleware::AddExtension;
+use axum::Extension;
+use axum_server::accept::{Accept, DefaultAcceptor};
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tower::Layer;
+
+/* every tls connection starts with a handshake record */
+const TLS_HANDSHAKE: u8 = 0x16;
+
+/* clients that connect, but do not send anything, are dropped after this */
+const SNIFF_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a request reached bifrost. Every request gets this as an extension,
+/// from the acceptor of the server it came in on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Plain,
+    Tls,
+}
+
+/* the service for a connection, telling each request how it arrived */
+fn with_transport<S>(service: S, transport: Transport) -> AddExtension<S, Transport> {
+    Extension(transport).layer(service)
+}
+
+/// Acceptor that adds the [`Transport`] of its connections to their requests
+#[derive(Clone, Debug)]
+pub struct TransportAcceptor<A> {
+    inner: A,
+    transport: Transport,
+}
+
+impl<A> TransportAcceptor<A> {
+    #[must_use]
+    pub const fn new(inner: A, transport: Transport) -> Self {
+        Self { inner, transport }
+    }
+}
+
+impl<A, I, S> Accept<I, S> for TransportAcceptor<A>
+where
+    A: Accept<I, AddExtension<S, Transport>>,
+{
+    type Stream = A::Stream;
+    type Service = A::Service;
+    type Future = A::Future;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        self.inner
+            .accept(stream, with_transport(service, self.transport))
+    }
+}
+
+/// A connection accepted by [`SinglePortAcceptor`]: either plain, or tls
+#[derive(Debug)]
+pub enum MaybeTlsStream<T> {
+    Plain(TcpStream),
+    Tls(T),
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for MaybeTlsStream<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for MaybeTlsStream<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Acceptor for serving http and https on the same port.
+///
+/// The first byte sent by the client is peeked at (without consuming it):
+/// connections starting with a tls handshake are handed to rustls, anything
+/// else is served as plain http.
+#[derive(Clone, Debug)]
+pub struct SinglePortAcceptor {
+    tls: RustlsAcceptor,
+}
+
+impl SinglePortAcceptor {
+    #[must_use]
+    pub fn new(config: RustlsConfig) -> Self {
+        Self {
+            tls: RustlsAcceptor::new(config),
+        }
+    }
+}
+
+type TlsStream = <RustlsAcceptor as Accept<TcpStream, ()>>::Stream;
+
+impl<S: Send + 'static> Accept<TcpStream, S> for SinglePortAcceptor
+where
+    <RustlsAcceptor as Accept<TcpStream, AddExtension<S, Transport>>>::Future: Send,
+{
+    type Stream = MaybeTlsStream<TlsStream>;
+    type Service = AddExtension<S, Transport>;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        let tls = self.tls.clone();
+
+        Box::pin(async move {
+            let mut first = [0u8; 1];
+            let len = timeout(SNIFF_TIMEOUT, stream.peek(&mut first))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "No data from client"))??;
+
+            if len == 1 && first[0] == TLS_HANDSHAKE {
+                let service = with_transport(service, Transport::Tls);
+                let (stream, service) = tls.accept(stream, service).await?;
+                Ok((MaybeTlsStream::Tls(stream), service))
+            } else {
+                let service = with_transport(service, Transport::Plain);
+                let (stream, service) = DefaultAcceptor.accept(stream, service).await?;
+                Ok((MaybeTlsStream::Plain(stream), service))
+            }
+        })
+    }
+}
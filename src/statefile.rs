@@ -0,0 +1,121 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+
+use camino::Utf8Path;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::error::ApiResult;
+use crate::hue::api::Resource;
+use crate::model::state::State;
+use crate::storage;
+
+/* Offline inspection and repair of the state file, for when bifrost is not
+ * running (it would overwrite any changes on the next save). */
+
+fn load(path: &Utf8Path) -> ApiResult<State> {
+    storage::open(path)?.load()?.ok_or_else(|| {
+        io::Error::new(ErrorKind::NotFound, format!("No state found in {path}")).into()
+    })
+}
+
+fn save(path: &Utf8Path, state: &State) -> ApiResult<()> {
+    let backup = path.with_extension("bak");
+    fs::copy(path, &backup)?;
+    println!("Saved backup of previous state as {backup}");
+
+    storage::open(path)?.save(state)?;
+    Ok(())
+}
+
+fn resource_type(obj: &Resource) -> String {
+    serde_json::to_value(obj.rtype())
+        .ok()
+        .and_then(|val| val.as_str().map(ToString::to_string))
+        .unwrap_or_default()
+}
+
+fn resource_name(obj: &Resource) -> String {
+    let value = serde_json::to_value(obj).unwrap_or_default();
+    value["metadata"]["name"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/* Find resources that link to the given uuid (e.g. the scenes of a room) */
+fn referenced_by(state: &State, uuid: &Uuid) -> Vec<Uuid> {
+    fn links_to(value: &Value, uuid: &str) -> bool {
+        match value {
+            Value::Object(map) => {
+                map.get("rid").and_then(Value::as_str) == Some(uuid)
+                    || map.values().any(|val| links_to(val, uuid))
+            }
+            Value::Array(list) => list.iter().any(|val| links_to(val, uuid)),
+            _ => false,
+        }
+    }
+
+    let needle = uuid.to_string();
+    state
+        .res
+        .iter()
+        .filter(|(id, obj)| {
+            *id != uuid && serde_json::to_value(obj).is_ok_and(|val| links_to(&val, &needle))
+        })
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+/// Print a table of all resources in the state file
+pub fn list(path: &Utf8Path) -> ApiResult<()> {
+    let state = load(path)?;
+
+    for (id, obj) in &state.res {
+        println!("{id}  {:<24} {}", resource_type(obj), resource_name(obj));
+    }
+
+    Ok(())
+}
+
+/// Print a single resource (and its auxiliary data, if any) as yaml
+pub fn get(path: &Utf8Path, uuid: &Uuid) -> ApiResult<()> {
+    let state = load(path)?;
+
+    let obj = state.get(uuid)?;
+    print!("{}", serde_yml::to_string(obj)?);
+
+    if let Some(aux) = state.try_aux_get(uuid) {
+        println!("# auxiliary data");
+        print!("{}", serde_yml::to_string(aux)?);
+    }
+
+    Ok(())
+}
+
+/// Remove a resource from the state file.
+///
+/// Resources linking to it are left alone, but listed, so they can be
+/// removed as well, if needed.
+pub fn delete(path: &Utf8Path, uuid: &Uuid) -> ApiResult<()> {
+    let mut state = load(path)?;
+
+    let obj = state.get(uuid)?;
+    println!(
+        "Deleting {} {uuid} ({})",
+        resource_type(obj),
+        resource_name(obj)
+    );
+
+    for id in referenced_by(&state, uuid) {
+        let obj = state.get(&id)?;
+        println!(
+            "  note: still referenced by {} {id} ({})",
+            resource_type(obj),
+            resource_name(obj)
+        );
+    }
+
+    state.remove(uuid)?;
+    save(path, &state)
+}
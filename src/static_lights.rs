@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::{watch, Mutex};
+use tokio::task::spawn_blocking;
+use uuid::Uuid;
+
+use crate::config::StaticLightConfig;
+use crate::error::{ApiError, ApiResult};
+use crate::hue::api::{
+    ColorGamut, ColorTemperature, Device, DeviceArchetype, DeviceProductData, Dimming, GamutType,
+    Light, LightColor, LightTimedEffects, LightUpdate, Metadata, MirekSchema, RType, Resource,
+    ResourceLink,
+};
+use crate::journal::{Source, TraceId};
+use crate::model::types::XY;
+use crate::resource::Resources;
+use crate::z2m::request::ClientRequest;
+
+/// Lights defined in the config, for devices zigbee2mqtt does not know about
+/// (e.g. relays or infrared blasters controlled by scripts).
+///
+/// Static lights take every update as given, and pass their new state on to
+/// a webhook and/or a shell command. Each light has its own output task, so
+/// a slow webhook or command never holds up requests for other lights; if
+/// updates arrive faster than they can be passed on, only the latest state
+/// is sent.
+pub struct StaticLights {
+    config: HashMap<String, StaticLightConfig>,
+    state: Arc<Mutex<Resources>>,
+    lights: HashMap<Uuid, String>,
+}
+
+impl StaticLights {
+    /// Time allowed for the webhook to answer
+    const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Time allowed for the command to finish, before it is killed
+    const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[must_use]
+    pub fn new(config: HashMap<String, StaticLightConfig>, state: Arc<Mutex<Resources>>) -> Self {
+        Self {
+            config,
+            state,
+            lights: HashMap::new(),
+        }
+    }
+
+    fn light(link_device: ResourceLink, name: &str, conf: &StaticLightConfig) -> Light {
+        let mut light = Light::new(
+            link_device,
+            Metadata::new(DeviceArchetype::ClassicBulb, name),
+        );
+
+        light.on.on = false;
+        if conf.dimming {
+            light.dimming = Some(Dimming {
+                brightness: 100.0,
+                min_dim_level: None,
+            });
+            light.timed_effects = Some(LightTimedEffects::new());
+        }
+        if conf.color_temperature {
+            light.color_temperature = Some(ColorTemperature {
+                mirek: Some(366),
+                mirek_schema: MirekSchema::DEFAULT,
+                mirek_valid: true,
+            });
+        }
+        if conf.color {
+            light.color = Some(LightColor {
+                gamut: Some(ColorGamut::GAMUT_C),
+                gamut_type: GamutType::C,
+                xy: XY::D65_WHITE_POINT,
+            });
+        }
+
+        light
+    }
+
+    /// Add the configured lights, bring known static lights in line with
+    /// their config, and remove static lights that are no longer configured.
+    pub async fn add_resources(&mut self) -> ApiResult<()> {
+        let mut res = self.state.lock().await;
+
+        for (name, conf) in &self.config {
+            let link_device = RType::Device.deterministic(("static", name.as_str()));
+            let link_light = RType::Light.deterministic(("static", name.as_str()));
+
+            let dev = Device {
+                product_data: DeviceProductData::static_light(),
+                metadata: Metadata::new(DeviceArchetype::ClassicBulb, name),
+                services: vec![link_light],
+            };
+
+            let light = Self::light(link_device, name, conf);
+
+            if res.get::<Light>(&link_light).is_ok() {
+                res.update::<Light>(&link_light.rid, |obj| Self::set_capabilities(obj, &light))?;
+            }
+
+            res.add(&link_device, Resource::Device(dev))?;
+            res.add(&link_light, Resource::Light(light))?;
+
+            self.lights.insert(link_light.rid, name.clone());
+        }
+
+        let removed: Vec<ResourceLink> = res
+            .get_resources_by_type(RType::Device)
+            .into_iter()
+            .filter_map(|rec| match rec.obj {
+                Resource::Device(dev) if dev.product_data.model_id == "static" => {
+                    Some(RType::Device.link_to(rec.id))
+                }
+                _ => None,
+            })
+            .filter(|link| {
+                !self
+                    .config
+                    .keys()
+                    .any(|name| *link == RType::Device.deterministic(("static", name.as_str())))
+            })
+            .collect();
+
+        for link_device in removed {
+            let device = res.get::<Device>(&link_device)?;
+            log::info!(
+                "[static] Removing light {:?}, which is no longer configured",
+                device.metadata.name
+            );
+            for service in device.services.clone() {
+                if res.get_resource_by_id(&service.rid).is_ok() {
+                    res.delete(&service)?;
+                }
+            }
+            res.delete(&link_device)?;
+        }
+        drop(res);
+
+        if !self.lights.is_empty() {
+            log::info!("[static] Added {} static lights", self.lights.len());
+        }
+
+        Ok(())
+    }
+
+    /* give a known light the capabilities of `conf`, keeping the state of
+     * those it already had */
+    fn set_capabilities(light: &mut Light, conf: &Light) {
+        if light.dimming.is_some() != conf.dimming.is_some() {
+            light.dimming.clone_from(&conf.dimming);
+            light.timed_effects.clone_from(&conf.timed_effects);
+        }
+        if light.color_temperature.is_some() != conf.color_temperature.is_some() {
+            light.color_temperature.clone_from(&conf.color_temperature);
+        }
+        if light.color.is_some() != conf.color.is_some() {
+            light.color.clone_from(&conf.color);
+        }
+    }
+
+    /* the state passed on to webhooks, with null for unsupported fields */
+    fn light_state(name: &str, light: &Light) -> Value {
+        json!({
+            "name": name,
+            "on": light.on.on,
+            "brightness": light.dimming.as_ref().map(|dim| dim.brightness),
+            "mirek": light.as_mirek_opt(),
+            "xy": light.as_color_opt(),
+        })
+    }
+
+    /// Apply a request to the static lights, and pass their new state on.
+    pub async fn handle_request(&self, req: &ClientRequest) -> ApiResult<()> {
+        for (name, state) in self.apply_request(req).await? {
+            let conf = self.config.get(&name).cloned().unwrap_or_default();
+            Self::output(name, conf, state).await?;
+        }
+        Ok(())
+    }
+
+    /* update the lights affected by `req`, and return their new state */
+    async fn apply_request(&self, req: &ClientRequest) -> ApiResult<Vec<(String, Value)>> {
+        /* group updates reach static lights through the bridge home, which
+         * sends a light update to every light outside of a room */
+        let updates = match req {
+            ClientRequest::LightUpdate { device, upd } => vec![(*device, upd.clone())],
+            ClientRequest::SceneRecall { scene } => self
+                .state
+                .lock()
+                .await
+                .zone_scene_updates(scene)?
+                .unwrap_or_default(),
+            _ => vec![],
+        };
+
+        let mut res = self.state.lock().await;
+        let mut states = vec![];
+        for (device, upd) in updates {
+            let Some(name) = self.lights.get(&device.rid) else {
+                continue;
+            };
+            res.update::<Light>(&device.rid, |light| *light += LightUpdate::from(&upd))?;
+            states.push((
+                name.clone(),
+                Self::light_state(name, res.get::<Light>(&device)?),
+            ));
+        }
+        drop(res);
+
+        Ok(states)
+    }
+
+    /* pass the state of a light on to its webhook and command */
+    async fn output(name: String, conf: StaticLightConfig, state: Value) -> ApiResult<()> {
+        spawn_blocking(move || {
+            if let Some(url) = &conf.webhook {
+                post_webhook(url, &state)?;
+            }
+            if let Some(command) = &conf.command {
+                run_command(command, &state)?;
+            }
+            Ok(())
+        })
+        .await?
+        .map_err(|err: io::Error| ApiError::StaticLightFailed(name, err.to_string()))
+    }
+
+    /* pass on the latest state of a light, for as long as `rx` is open */
+    async fn output_worker(
+        name: String,
+        conf: StaticLightConfig,
+        mut rx: watch::Receiver<Option<(Option<TraceId>, Value)>>,
+    ) {
+        while rx.changed().await.is_ok() {
+            let Some((trace, state)) = rx.borrow_and_update().clone() else {
+                continue;
+            };
+            let output = Self::output(name.clone(), conf.clone(), state);
+            if let Err(err) = TraceId::scope_opt(trace, output).await {
+                log::error!("[static] {err}");
+            }
+        }
+    }
+
+    pub async fn run_forever(self) -> ApiResult<()> {
+        Source::Static.scope(self.run()).await
+    }
+
+    async fn run(mut self) -> ApiResult<()> {
+        let mut chan: Receiver<(Option<TraceId>, Arc<ClientRequest>)> =
+            self.state.lock().await.z2m_channel();
+
+        self.add_resources().await?;
+
+        if self.lights.is_empty() {
+            return Ok(());
+        }
+
+        let mut outputs = HashMap::new();
+        for (name, conf) in &self.config {
+            let (tx, rx) = watch::channel(None);
+            let worker = Self::output_worker(name.clone(), conf.clone(), rx);
+            tokio::spawn(Source::Static.scope(worker));
+            outputs.insert(name.clone(), tx);
+        }
+
+        loop {
+            let (trace, req) = match chan.recv().await {
+                Ok(pkt) => pkt,
+                Err(RecvError::Lagged(count)) => {
+                    /* static lights have no state to re-sync from, so the
+                     * lost requests are gone */
+                    log::warn!("[static] Dropped {count} requests");
+                    continue;
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            };
+
+            match TraceId::scope_opt(trace, self.apply_request(&req)).await {
+                Ok(states) => {
+                    for (name, state) in states {
+                        if let Some(tx) = outputs.get(&name) {
+                            tx.send_replace(Some((trace, state)));
+                        }
+                    }
+                }
+                Err(err) => log::error!("[static] {err}"),
+            }
+        }
+    }
+}
+
+/* a minimal http/1.1 post, since static lights are the only http client */
+fn post_webhook(url: &str, state: &Value) -> io::Result<()> {
+    let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("unsupported url {url:?}"));
+
+    let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+    let (host, path) = rest.find('/').map_or((rest, "/"), |idx| rest.split_at(idx));
+    let addr = if host
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        host.to_string()
+    } else {
+        format!("{host}:80")
+    };
+
+    let sockaddr = addr.to_socket_addrs()?.next().ok_or_else(invalid)?;
+    let mut stream = TcpStream::connect_timeout(&sockaddr, StaticLights::WEBHOOK_TIMEOUT)?;
+    stream.set_read_timeout(Some(StaticLights::WEBHOOK_TIMEOUT))?;
+    stream.set_write_timeout(Some(StaticLights::WEBHOOK_TIMEOUT))?;
+
+    let body = state.to_string();
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\n\
+         Host: {host}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "webhook replied {:?}",
+            status.trim_end()
+        ))),
+    }
+}
+
+/* the state is passed in the environment, with unsupported fields left out,
+ * and the command is killed if it does not finish in time */
+fn run_command(command: &str, state: &Value) -> io::Result<()> {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+
+    cmd.env("BIFROST_LIGHT", state["name"].as_str().unwrap_or_default());
+    cmd.env("BIFROST_ON", state["on"].to_string());
+    for (var, value) in [
+        ("BIFROST_BRIGHTNESS", &state["brightness"]),
+        ("BIFROST_MIREK", &state["mirek"]),
+        ("BIFROST_X", &state["xy"]["x"]),
+        ("BIFROST_Y", &state["xy"]["y"]),
+    ] {
+        if !value.is_null() {
+            cmd.env(var, value.to_string());
+        }
+    }
+
+    let mut child = cmd.spawn()?;
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > StaticLights::COMMAND_TIMEOUT {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "command did not finish in {:?}",
+                    StaticLights::COMMAND_TIMEOUT
+                ),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    if !status.success() {
+        return Err(io::Error::other(format!("command exited with {status}")));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use std::fs::{self, File};
+use std::io::Write;
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::error::ApiResult;
+use crate::model::state::{State, StateFormat, StateVersion};
+use crate::storage::StateStore;
+
+/// State stored as a single yaml or json document, which is rewritten in full
+/// on every save.
+pub struct FileStore {
+    path: Utf8PathBuf,
+    format: StateFormat,
+    /* contents of the file, as last read or written */
+    last: Option<Vec<u8>>,
+}
+
+impl FileStore {
+    #[must_use]
+    pub fn new(path: &Utf8Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            format: StateFormat::from_path(path),
+            last: None,
+        }
+    }
+}
+
+impl StateStore for FileStore {
+    fn load(&mut self) -> ApiResult<Option<State>> {
+        let Ok(data) = fs::read(&self.path) else {
+            return Ok(None);
+        };
+
+        log::debug!("Existing state file found, loading..");
+        let state = match StateFormat::detect(&data) {
+            StateFormat::Yaml => {
+                let yaml = serde_yml::from_slice(&data)?;
+                match State::version(&yaml)? {
+                    StateVersion::V0 => {
+                        log::info!("Detected state file version 0. Upgrading to new version..");
+                        let backup_path = self.path.with_extension("v0.bak");
+                        fs::rename(&self.path, &backup_path)?;
+                        log::info!("  ..saved old state file as {backup_path}");
+                        State::from_v0(yaml)?
+                    }
+                    StateVersion::V1 => {
+                        log::info!("Detected state file version 1. Loading..");
+                        State::from_v1(yaml)?
+                    }
+                }
+            }
+            format => {
+                log::info!("Detected {format:?} state file. Loading..");
+                State::from_slice(&data)?
+            }
+        };
+
+        Ok(Some(state))
+    }
+
+    fn save(&mut self, state: &State) -> ApiResult<bool> {
+        let data = state.to_vec(self.format)?;
+
+        if self.last.is_none() {
+            self.last = fs::read(&self.path).ok();
+        }
+
+        if self.last.as_ref() == Some(&data) {
+            return Ok(false);
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        let mut fd = File::create(&tmp)?;
+        fd.write_all(&data)?;
+        fs::rename(&tmp, &self.path)?;
+
+        self.last = Some(data);
+        Ok(true)
+    }
+}
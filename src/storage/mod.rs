@@ -0,0 +1,64 @@
+pub mod file;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use camino::Utf8Path;
+
+use crate::error::ApiResult;
+use crate::model::state::State;
+
+/// Persistent storage for the bridge state.
+///
+/// Backends are free to store the state in any way they like, as long as
+/// [`StateStore::load`] returns what was last saved. Both methods block, so
+/// call them from a blocking thread, when in async context.
+pub trait StateStore: Send {
+    /// Load the stored state, or `None` if nothing has been stored yet
+    fn load(&mut self) -> ApiResult<Option<State>>;
+
+    /// Save the state, returning `false` if nothing had changed since the
+    /// last save (in which case nothing was written)
+    fn save(&mut self, state: &State) -> ApiResult<bool>;
+}
+
+/// Files ending in `.db` or `.sqlite` are sqlite databases
+#[must_use]
+pub fn is_sqlite(path: &Utf8Path) -> bool {
+    matches!(path.extension(), Some("db" | "sqlite"))
+}
+
+/// Open the state store for `path`.
+///
+/// Files ending in `.db` or `.sqlite` use the sqlite backend (when built with
+/// the `sqlite` feature), and everything else is a single yaml or json file.
+pub fn open(path: &Utf8Path) -> ApiResult<Box<dyn StateStore>> {
+    match path.extension() {
+        #[cfg(feature = "sqlite")]
+        Some("db" | "sqlite") => Ok(Box::new(sqlite::SqliteStore::open(path)?)),
+
+        #[cfg(not(feature = "sqlite"))]
+        Some("db" | "sqlite") => Err(crate::error::ApiError::StorageUnsupported(
+            path.to_path_buf(),
+        )),
+
+        _ => Ok(Box::new(file::FileStore::new(path))),
+    }
+}
+
+/// Load the state from `path`.
+///
+/// When using any other store than the default yaml file, and it is still
+/// empty, the state is imported from `state.yaml` next to it, if present.
+pub fn load(path: &Utf8Path) -> ApiResult<Option<State>> {
+    if let Some(state) = open(path)?.load()? {
+        return Ok(Some(state));
+    }
+
+    let yaml = path.with_file_name("state.yaml");
+    if path == yaml || !yaml.is_file() {
+        return Ok(None);
+    }
+
+    log::info!("State file [{path}] not found, migrating from [{yaml}]..");
+    file::FileStore::new(&yaml).load()
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use camino::Utf8Path;
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, Row};
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+
+use crate::error::ApiResult;
+use crate::journal::JournalEntry;
+use crate::model::state::{IdMap, State, StateVersion};
+use crate::storage::StateStore;
+
+/* Each resource and aux entry is a row of its own, so saving only touches the
+ * rows that have actually changed, and every save is a single transaction.
+ * The journal of resource changes is kept in the same database, one row per
+ * change, when it is configured to be. */
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS resources (id TEXT PRIMARY KEY, value TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS aux (id TEXT PRIMARY KEY, value TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS journal (seq INTEGER PRIMARY KEY, value TEXT NOT NULL);
+";
+
+/* the state and the journal are written through separate connections */
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Table {
+    Meta,
+    Resources,
+    Aux,
+}
+
+impl Table {
+    const ALL: [Self; 3] = [Self::Meta, Self::Resources, Self::Aux];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Meta => "meta",
+            Self::Resources => "resources",
+            Self::Aux => "aux",
+        }
+    }
+
+    const fn key(self) -> &'static str {
+        match self {
+            Self::Meta => "key",
+            Self::Resources | Self::Aux => "id",
+        }
+    }
+}
+
+type Rows = BTreeMap<(Table, String), String>;
+
+/// State stored in a sqlite database, with one row per resource.
+pub struct SqliteStore {
+    conn: Connection,
+    /* serialized rows, as last read or written */
+    rows: Option<Rows>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Utf8Path) -> ApiResult<Self> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self { conn, rows: None })
+    }
+
+    /// Append a change to the journal
+    pub fn append_journal(&self, entry: &JournalEntry) -> ApiResult<()> {
+        self.conn
+            .prepare_cached("INSERT INTO journal (value) VALUES (?1)")?
+            .execute([serde_json::to_string(entry)?])?;
+        Ok(())
+    }
+
+    /// Read all journal entries, oldest first
+    pub fn read_journal(&self) -> ApiResult<Vec<JournalEntry>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT value FROM journal ORDER BY seq")?;
+        let mut res = stmt.query([])?;
+
+        let mut entries = vec![];
+        while let Some(row) = res.next()? {
+            let value: String = row.get(0)?;
+            entries.push(serde_json::from_str(&value)?);
+        }
+
+        Ok(entries)
+    }
+
+    fn read_rows(&self) -> ApiResult<Rows> {
+        let mut rows = Rows::new();
+
+        for table in Table::ALL {
+            let sql = format!("SELECT {}, value FROM {}", table.key(), table.name());
+            let mut stmt = self.conn.prepare(&sql)?;
+            let mut res = stmt.query([])?;
+            while let Some(row) = res.next()? {
+                rows.insert((table, row.get(0)?), row.get(1)?);
+            }
+        }
+
+        Ok(rows)
+    }
+
+    fn state_rows(state: &State) -> ApiResult<Rows> {
+        let mut rows = Rows::new();
+
+        let meta = [
+            ("version", serde_json::to_string(&StateVersion::V1)?),
+            ("id_v1", serde_json::to_string(state.id_map())?),
+            (
+                "resourcelinks",
+                serde_json::to_string(&state.resourcelinks)?,
+            ),
+            (
+                "scene_schedules",
+                serde_json::to_string(&state.scene_schedules)?,
+            ),
+            ("users", serde_json::to_string(&state.users)?),
+        ];
+        for (key, value) in meta {
+            rows.insert((Table::Meta, key.to_string()), value);
+        }
+
+        for (id, obj) in &state.res {
+            rows.insert(
+                (Table::Resources, id.to_string()),
+                serde_json::to_string(obj)?,
+            );
+        }
+
+        for (id, aux) in state.aux() {
+            rows.insert((Table::Aux, id.to_string()), serde_json::to_string(aux)?);
+        }
+
+        Ok(rows)
+    }
+
+    fn parse_uuid(row: &Row) -> rusqlite::Result<Uuid> {
+        let id: String = row.get(0)?;
+        Uuid::parse_str(&id)
+            .map_err(|err| rusqlite::Error::FromSqlConversionFailure(0, Type::Text, Box::new(err)))
+    }
+
+    fn read_table<T: DeserializeOwned>(&self, table: Table) -> ApiResult<BTreeMap<Uuid, T>> {
+        let sql = format!("SELECT id, value FROM {}", table.name());
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut res = stmt.query([])?;
+
+        let mut items = BTreeMap::new();
+        while let Some(row) = res.next()? {
+            let value: String = row.get(1)?;
+            items.insert(Self::parse_uuid(row)?, serde_json::from_str(&value)?);
+        }
+
+        Ok(items)
+    }
+
+    fn read_meta(&self, key: &str) -> ApiResult<Option<String>> {
+        let mut stmt = self.conn.prepare("SELECT value FROM meta WHERE key = ?1")?;
+        let mut res = stmt.query([key])?;
+        Ok(res.next()?.map(|row| row.get(0)).transpose()?)
+    }
+}
+
+impl StateStore for SqliteStore {
+    fn load(&mut self) -> ApiResult<Option<State>> {
+        let Some(version) = self.read_meta("version")? else {
+            return Ok(None);
+        };
+
+        let version: StateVersion = serde_json::from_str(&version)?;
+        log::info!("Detected sqlite state database version {version:?}. Loading..");
+
+        let id_v1 = match self.read_meta("id_v1")? {
+            Some(id_v1) => serde_json::from_str(&id_v1)?,
+            None => IdMap::new(),
+        };
+
+        let resourcelinks = match self.read_meta("resourcelinks")? {
+            Some(links) => serde_json::from_str(&links)?,
+            None => BTreeMap::new(),
+        };
+
+        let scene_schedules = match self.read_meta("scene_schedules")? {
+            Some(schedules) => serde_json::from_str(&schedules)?,
+            None => BTreeMap::new(),
+        };
+
+        let users = match self.read_meta("users")? {
+            Some(users) => serde_json::from_str(&users)?,
+            None => BTreeMap::new(),
+        };
+
+        Ok(Some(State::from_parts(
+            self.read_table(Table::Aux)?,
+            id_v1,
+            self.read_table(Table::Resources)?,
+            resourcelinks,
+            scene_schedules,
+            users,
+        )))
+    }
+
+    fn save(&mut self, state: &State) -> ApiResult<bool> {
+        let new = Self::state_rows(state)?;
+        let old = match self.rows.take() {
+            Some(rows) => rows,
+            None => self.read_rows()?,
+        };
+
+        let tx = self.conn.transaction()?;
+        let mut changed = false;
+
+        for (row @ (table, key), value) in &new {
+            if old.get(row) != Some(value) {
+                let sql = format!(
+                    "INSERT OR REPLACE INTO {} ({}, value) VALUES (?1, ?2)",
+                    table.name(),
+                    table.key()
+                );
+                tx.prepare_cached(&sql)?.execute(params![key, value])?;
+                changed = true;
+            }
+        }
+
+        for row @ (table, key) in old.keys() {
+            if !new.contains_key(row) {
+                let sql = format!("DELETE FROM {} WHERE {} = ?1", table.name(), table.key());
+                tx.prepare_cached(&sql)?.execute([key])?;
+                changed = true;
+            }
+        }
+
+        tx.commit()?;
+        self.rows = Some(new);
+
+        Ok(changed)
+    }
+}
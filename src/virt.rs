@@ -0,0 +1,334 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+use uuid::Uuid;
+
+use crate::config::VirtualConfig;
+use crate::error::ApiResult;
+use crate::hue::api::{
+    ColorGamut, ColorTemperature, ColorTemperatureUpdate, ColorUpdate, Device, DeviceArchetype,
+    DeviceProductData, Dimming, DimmingUpdate, GamutType, GroupedLight, Light, LightColor,
+    LightTimedEffects, LightUpdate, Metadata, MirekSchema, On, RType, Resource, ResourceLink, Room,
+    RoomArchetype, RoomMetadata, Scene, SceneAction, SceneActionElement,
+};
+use crate::journal::{Source, TraceId};
+use crate::model::types::XY;
+use crate::resource::Resources;
+use crate::z2m::request::ClientRequest;
+
+/// Simulated lights and rooms, for testing without zigbee2mqtt.
+///
+/// Virtual lights accept updates like real ones, and report their new state
+/// back after the configured latency.
+pub struct VirtualClient {
+    config: VirtualConfig,
+    state: Arc<Mutex<Resources>>,
+    lights: HashSet<Uuid>,
+    rooms: HashMap<Uuid, Vec<ResourceLink>>,
+}
+
+impl VirtualClient {
+    #[must_use]
+    pub fn new(config: VirtualConfig, state: Arc<Mutex<Resources>>) -> Self {
+        Self {
+            config,
+            state,
+            lights: HashSet::new(),
+            rooms: HashMap::new(),
+        }
+    }
+
+    fn light(link_device: ResourceLink, name: &str) -> Light {
+        let mut light = Light::new(link_device, Metadata::new(DeviceArchetype::SpotBulb, name));
+
+        light.on.on = false;
+        light.dimming = Some(Dimming {
+            brightness: 100.0,
+            min_dim_level: Some(0.2),
+        });
+        light.timed_effects = Some(LightTimedEffects::new());
+        light.color_temperature = Some(ColorTemperature {
+            mirek: Some(366),
+            mirek_schema: MirekSchema::DEFAULT,
+            mirek_valid: true,
+        });
+        light.color = Some(LightColor {
+            gamut: Some(ColorGamut::GAMUT_C),
+            gamut_type: GamutType::C,
+            xy: XY::D65_WHITE_POINT,
+        });
+
+        light
+    }
+
+    async fn add_resources(&mut self) -> ApiResult<()> {
+        let mut res = self.state.lock().await;
+
+        for r in 1..=self.config.rooms {
+            let link_room = RType::Room.deterministic(("virtual", r));
+            let link_grouped = RType::GroupedLight.deterministic(("virtual", r));
+
+            let mut children = vec![];
+            let mut lights = vec![];
+
+            for l in 1..=self.config.lights {
+                let name = format!("Virtual light {r}.{l}");
+                let link_device = RType::Device.deterministic(("virtual", r, l));
+                let link_light = RType::Light.deterministic(("virtual", r, l));
+
+                let dev = Device {
+                    product_data: DeviceProductData::virtual_light(),
+                    metadata: Metadata::new(DeviceArchetype::SpotBulb, &name),
+                    services: vec![link_light],
+                };
+
+                res.add(&link_device, Resource::Device(dev))?;
+                res.add(
+                    &link_light,
+                    Resource::Light(Self::light(link_device, &name)),
+                )?;
+
+                children.push(link_device);
+                lights.push(link_light);
+                self.lights.insert(link_light.rid);
+            }
+
+            let room = Room {
+                children,
+                metadata: RoomMetadata::new(RoomArchetype::Other, &format!("Virtual room {r}")),
+                services: vec![link_grouped],
+            };
+
+            res.add(&link_room, Resource::Room(room))?;
+            res.add(
+                &link_grouped,
+                Resource::GroupedLight(GroupedLight::new(link_room)),
+            )?;
+
+            self.rooms.insert(link_grouped.rid, lights.clone());
+            self.rooms.insert(link_room.rid, lights);
+        }
+        drop(res);
+
+        log::info!(
+            "[virtual] Added {} rooms with {} lights each",
+            self.config.rooms,
+            self.config.lights
+        );
+
+        Ok(())
+    }
+
+    fn action_update(action: &SceneAction) -> LightUpdate {
+        LightUpdate::new()
+            .with_on(action.on)
+            .with_brightness(action.dimming.as_ref().map(|dim| dim.brightness))
+            .with_color_temperature(action.color_temperature.as_ref().map(|ct| ct.mirek))
+            .with_color_xy(action.color.as_ref().map(|col| col.xy))
+    }
+
+    fn update_grouped_light(
+        res: &mut Resources,
+        link_grouped: &ResourceLink,
+        lights: &[ResourceLink],
+    ) -> ApiResult<()> {
+        let states: Vec<&Light> = lights
+            .iter()
+            .filter_map(|rl| res.get::<Light>(rl).ok())
+            .collect();
+
+        let any_on = states.iter().any(|light| light.on.on);
+        let brightness = states
+            .iter()
+            .filter(|light| light.on.on)
+            .filter_map(|light| light.dimming.as_ref().map(|dim| dim.brightness))
+            .reduce(f64::max);
+
+        /* color follows the first light that is on (which has no color
+         * temperature, when in color mode) */
+        let first_on = states.iter().find(|light| light.on.on);
+        let mirek = first_on.and_then(|light| light.as_mirek_opt());
+        let xy = first_on
+            .filter(|_| mirek.is_none())
+            .and_then(|light| light.as_color_opt());
+
+        res.update::<GroupedLight>(&link_grouped.rid, |glight| {
+            glight.on = Some(On::new(any_on));
+            glight.dimming = brightness.map(DimmingUpdate::new);
+            glight.color_temperature = mirek.map(ColorTemperatureUpdate::new);
+            glight.color = xy.map(ColorUpdate::new);
+        })
+    }
+
+    fn scene_store(
+        res: &mut Resources,
+        room: &ResourceLink,
+        id: u32,
+        lights: &[ResourceLink],
+    ) -> ApiResult<()> {
+        let Some(scene) = res.get_scenes_for_room(&room.rid).into_iter().find(|rid| {
+            res.aux_get(&RType::Scene.link_to(*rid))
+                .is_ok_and(|aux| aux.index == Some(id))
+        }) else {
+            log::warn!("[virtual] Cannot store unknown scene {id} in {room:?}");
+            return Ok(());
+        };
+
+        let actions: Vec<SceneActionElement> = lights
+            .iter()
+            .filter_map(|rl| {
+                let light = res.get::<Light>(rl).ok()?;
+                Some(SceneActionElement {
+                    target: *rl,
+                    action: SceneAction {
+                        color: None,
+                        color_temperature: light.as_mirek_opt().map(ColorTemperatureUpdate::new),
+                        dimming: light.as_dimming_opt(),
+                        on: Some(light.on),
+                    },
+                })
+            })
+            .collect();
+
+        let link_scene = RType::Scene.link_to(scene);
+        let aux = res.aux_get(&link_scene)?.clone();
+        res.aux_set(&link_scene, aux.with_actions(Some(actions.clone())));
+
+        res.update::<Scene>(&scene, |scn| scn.actions = actions)
+    }
+
+    /* update the grouped lights of the rooms `light` is in */
+    fn refresh_rooms(&self, res: &mut Resources, light: &ResourceLink) -> ApiResult<()> {
+        for (rid, lights) in &self.rooms {
+            if lights.contains(light) {
+                let link = RType::GroupedLight.link_to(*rid);
+                if res.get::<GroupedLight>(&link).is_ok() {
+                    Self::update_grouped_light(res, &link, lights)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_request(&self, req: &ClientRequest) -> ApiResult<()> {
+        let mut res = self.state.lock().await;
+
+        match req {
+            ClientRequest::LightUpdate { device, upd } => {
+                if !self.lights.contains(&device.rid) {
+                    return Ok(());
+                }
+                res.update::<Light>(&device.rid, |light| *light += LightUpdate::from(upd))?;
+                self.refresh_rooms(&mut res, device)?;
+            }
+
+            ClientRequest::GroupUpdate { device, upd } => {
+                let Some(lights) = self.rooms.get(&device.rid) else {
+                    return Ok(());
+                };
+                for light in lights {
+                    res.update::<Light>(&light.rid, |light| *light += LightUpdate::from(upd))?;
+                }
+                Self::update_grouped_light(&mut res, device, lights)?;
+            }
+
+            ClientRequest::SceneStore { room, id, .. } => {
+                let Some(lights) = self.rooms.get(&room.rid) else {
+                    return Ok(());
+                };
+                Self::scene_store(&mut res, room, *id, lights)?;
+            }
+
+            ClientRequest::SceneRecall { scene } => {
+                /* zone scenes can span rooms, so the lights are updated one
+                 * by one */
+                if let Some(updates) = res.zone_scene_updates(scene)? {
+                    for (light, upd) in &updates {
+                        if self.lights.contains(&light.rid) {
+                            res.update::<Light>(&light.rid, |obj| {
+                                *obj += LightUpdate::from(upd);
+                            })?;
+                            self.refresh_rooms(&mut res, light)?;
+                        }
+                    }
+                    return Ok(());
+                }
+
+                let scn = res.get::<Scene>(scene)?;
+                let group = scn.group;
+                let actions = scn.actions.clone();
+                let Some(lights) = self.rooms.get(&group.rid) else {
+                    return Ok(());
+                };
+
+                for act in &actions {
+                    if self.lights.contains(&act.target.rid) {
+                        res.update::<Light>(&act.target.rid, |light| {
+                            *light += Self::action_update(&act.action);
+                        })?;
+                    }
+                }
+
+                let glight = res.get::<Room>(&group)?.grouped_light_service().copied();
+                if let Some(glight) = glight {
+                    Self::update_grouped_light(&mut res, &glight, lights)?;
+                }
+                res.scene_set_active(scene)?;
+            }
+
+            /* scenes are managed entirely by the api for virtual rooms,
+             * and virtual lights have no device options, sensors, zigbee
+             * groups, transitions or touchlink */
+            ClientRequest::SceneAdd { .. }
+            | ClientRequest::SceneRemove { .. }
+            | ClientRequest::DeviceOptions { .. }
+            | ClientRequest::ConfigureReporting { .. }
+            | ClientRequest::MotionSensitivity { .. }
+            | ClientRequest::ZoneGroup { .. }
+            | ClientRequest::LightStop { .. }
+            | ClientRequest::Touchlink { .. } => {}
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    pub async fn run_forever(self) -> ApiResult<()> {
+        Source::Virtual.scope(self.run()).await
+    }
+
+    async fn run(mut self) -> ApiResult<()> {
+        let mut chan: Receiver<(Option<TraceId>, Arc<ClientRequest>)> =
+            self.state.lock().await.z2m_channel();
+
+        self.add_resources().await?;
+
+        let latency = Duration::from_millis(self.config.latency_ms);
+
+        loop {
+            let (trace, req) = match chan.recv().await {
+                Ok(pkt) => pkt,
+                Err(RecvError::Lagged(count)) => {
+                    /* virtual lights only change through requests, so the
+                     * resources still show the state before the lost ones */
+                    log::warn!("[virtual] Dropped {count} requests");
+                    continue;
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            };
+
+            if !latency.is_zero() {
+                sleep(latency).await;
+            }
+
+            if let Err(err) = TraceId::scope_opt(trace, self.handle_request(&req)).await {
+                log::error!("[virtual] Failed to handle request {req:?}: {err}");
+            }
+        }
+    }
+}
@@ -1,6 +1,11 @@
 #![allow(clippy::struct_excessive_bools)]
 
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    num::ParseIntError,
+    str::FromStr,
+};
 
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
@@ -40,19 +45,65 @@ pub enum Message {
 
     #[serde(rename = "bridge/extensions")]
     BridgeExtensions(Value),
+
+    #[serde(rename = "bridge/response/touchlink/scan")]
+    TouchlinkScan(BridgeResponse<TouchlinkScan>),
+
+    #[serde(rename = "bridge/response/touchlink/identify")]
+    TouchlinkIdentify(BridgeResponse<Value>),
+
+    #[serde(rename = "bridge/response/touchlink/factory_reset")]
+    TouchlinkFactoryReset(BridgeResponse<Value>),
+
+    #[serde(rename = "bridge/response/device/options")]
+    DeviceOptions(BridgeResponse<Value>),
+
+    #[serde(rename = "bridge/response/device/configure_reporting")]
+    DeviceConfigureReporting(BridgeResponse<Value>),
+
+    #[serde(rename = "bridge/response/group/add")]
+    GroupAdd(BridgeResponse<Value>),
+
+    #[serde(rename = "bridge/response/group/remove")]
+    GroupRemove(BridgeResponse<Value>),
+
+    #[serde(rename = "bridge/response/group/members/add")]
+    GroupMembersAdd(BridgeResponse<Value>),
+
+    #[serde(rename = "bridge/response/group/members/remove")]
+    GroupMembersRemove(BridgeResponse<Value>),
+
+    #[serde(rename = "bridge/response/health_check")]
+    HealthCheck(BridgeResponse<Value>),
 }
 
-#[derive(Serialize, Deserialize, Clone, Hash, Debug, Copy)]
+#[derive(Serialize, Deserialize, Clone, Hash, Debug, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum Availability {
     Online,
     Offline,
 }
 
-#[derive(Serialize, Deserialize, Clone, Hash)]
+#[derive(Serialize, Deserialize, Clone, Hash, PartialEq, Eq)]
 #[serde(transparent)]
 pub struct IeeeAddress(#[serde(deserialize_with = "ieee_address")] u64);
 
+impl IeeeAddress {
+    /// Format address as colon-separated hex bytes (like a mac address)
+    #[must_use]
+    pub fn to_mac_string(&self) -> String {
+        self.0.to_be_bytes().map(|b| format!("{b:02x}")).join(":")
+    }
+}
+
+impl FromStr for IeeeAddress {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s.trim_start_matches("0x"), 16).map(Self)
+    }
+}
+
 impl Debug for IeeeAddress {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "IeeeAddress({:016x})", self.0)
@@ -73,6 +124,7 @@ where
 #[serde(rename_all = "lowercase")]
 pub enum BridgeOnlineState {
     Online,
+    Offline,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -81,6 +133,26 @@ pub struct BridgeState {
     pub state: BridgeOnlineState,
 }
 
+/// Payload of `<device>/availability` messages
+///
+/// Depending on the `legacy_availability_payload` setting, z2m sends either
+/// a json object, or a bare string.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(untagged)]
+pub enum AvailabilityUpdate {
+    Current { state: Availability },
+    Legacy(Availability),
+}
+
+impl AvailabilityUpdate {
+    #[must_use]
+    pub fn is_online(self) -> bool {
+        match self {
+            Self::Current { state } | Self::Legacy(state) => state == Availability::Online,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct BridgeEvent {
@@ -91,6 +163,122 @@ pub struct BridgeEvent {
     pub event_type: String,
 }
 
+impl BridgeEvent {
+    /// The device event this is, if any
+    #[must_use]
+    pub fn device_event(&self) -> Option<DeviceEvent> {
+        let event = match self.event_type.as_str() {
+            "device_joined" => {
+                DeviceEvent::DeviceJoined(Deserialize::deserialize(&self.data).ok()?)
+            }
+            "device_interview" => {
+                DeviceEvent::DeviceInterview(Deserialize::deserialize(&self.data).ok()?)
+            }
+            "device_leave" => DeviceEvent::DeviceLeave(Deserialize::deserialize(&self.data).ok()?),
+            _ => return None,
+        };
+        Some(event)
+    }
+}
+
+/// Events about devices joining or leaving the network
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+    DeviceJoined(DeviceEventData),
+    DeviceInterview(DeviceInterview),
+    DeviceLeave(DeviceEventData),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceEventData {
+    pub friendly_name: String,
+    pub ieee_address: IeeeAddress,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InterviewStatus {
+    Started,
+    Successful,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceInterview {
+    pub friendly_name: String,
+    pub ieee_address: IeeeAddress,
+    pub status: InterviewStatus,
+    #[serde(default)]
+    pub supported: Option<bool>,
+    #[serde(default)]
+    pub definition: Option<Definition>,
+}
+
+impl DeviceInterview {
+    /// The interviewed device, as it will appear in the next device list, if
+    /// the interview was successful
+    ///
+    /// Only the fields reported in the interview event are filled in.
+    #[must_use]
+    pub fn device(&self) -> Option<Device> {
+        if self.status != InterviewStatus::Successful {
+            return None;
+        }
+
+        let definition = self.definition.clone()?;
+
+        Some(Device {
+            description: None,
+            date_code: None,
+            manufacturer: Some(definition.vendor.clone()),
+            definition: Some(definition),
+            disabled: false,
+            endpoints: HashMap::new(),
+            friendly_name: self.friendly_name.clone(),
+            ieee_address: self.ieee_address.clone(),
+            interview_completed: true,
+            interviewing: false,
+            model_id: None,
+            network_address: 0,
+            power_source: PowerSource::default(),
+            software_build_id: None,
+            supported: self.supported,
+            device_type: String::from("Unknown"),
+            __: HashMap::new(),
+        })
+    }
+}
+
+/// Reply to a `bridge/request/...` message
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BridgeResponse<T> {
+    pub data: Option<T>,
+    pub status: String,
+    pub error: Option<String>,
+    /// Echo of the transaction id from the request, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<String>,
+}
+
+impl<T> BridgeResponse<T> {
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// A device found by a touchlink scan
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TouchlinkDevice {
+    pub ieee_address: String,
+    pub channel: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TouchlinkScan {
+    pub found: Vec<TouchlinkDevice>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct BridgeLogging {
@@ -178,6 +366,73 @@ pub struct Config {
     pub serial: ConfigSerial,
 }
 
+impl Config {
+    /// Transition time (in seconds) z2m applies to commands for each topic,
+    /// when none is given in the command itself.
+    ///
+    /// Includes every device and group with its own `transition` option. For
+    /// all others, the global default from `device_options` applies.
+    #[must_use]
+    pub fn transitions(&self) -> HashMap<String, f64> {
+        let devices = self.devices.iter().filter_map(|(ieee, dev)| {
+            let transition = dev.get("transition")?.as_f64()?;
+            let topic = dev
+                .get("friendly_name")
+                .and_then(Value::as_str)
+                .unwrap_or(ieee);
+            Some((topic.to_string(), transition))
+        });
+
+        let groups = self.groups.values().filter_map(|grp| {
+            grp.transition
+                .map(|transition| (grp.friendly_name.clone(), transition))
+        });
+
+        devices.chain(groups).collect()
+    }
+
+    /// Availability tracking, as configured globally and for each device
+    #[must_use]
+    pub fn availability_config(&self) -> AvailabilityConfig {
+        let devices = self.devices.iter().filter_map(|(ieee, dev)| {
+            let enabled = AvailabilityConfig::flag(dev.get("availability")?)?;
+            Some((ieee.parse().ok()?, enabled))
+        });
+
+        AvailabilityConfig {
+            enabled: AvailabilityConfig::flag(&self.availability).unwrap_or_default(),
+            devices: devices.collect(),
+        }
+    }
+}
+
+/// Devices that z2m tracks availability for.
+///
+/// Devices without availability tracking never send `<device>/availability`
+/// messages, so there is no way to know whether they are reachable.
+#[derive(Debug, Clone, Default)]
+pub struct AvailabilityConfig {
+    enabled: bool,
+    devices: HashMap<IeeeAddress, bool>,
+}
+
+impl AvailabilityConfig {
+    /* either a bool, or an object with timeouts (which is enabled, unless it
+     * has `enabled: false`, like the global setting in z2m 2.x) */
+    fn flag(value: &Value) -> Option<bool> {
+        match value {
+            Value::Bool(enabled) => Some(*enabled),
+            Value::Object(obj) => Some(obj.get("enabled").and_then(Value::as_bool) != Some(false)),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn is_enabled(&self, ieee: &IeeeAddress) -> bool {
+        self.devices.get(ieee).copied().unwrap_or(self.enabled)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Version {
@@ -203,6 +458,21 @@ pub struct Coordinator {
     pub coordinator_type: String,
 }
 
+impl Coordinator {
+    /// Best-effort extraction of the adapter firmware version.
+    ///
+    /// The layout of `meta` varies wildly between adapter types, but most
+    /// of them report a `revision` field, either as a string or a number.
+    #[must_use]
+    pub fn firmware_revision(&self) -> Option<String> {
+        match self.meta.get("revision")? {
+            Value::String(rev) => Some(rev.clone()),
+            Value::Number(rev) => Some(rev.to_string()),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigAdvanced {
     pub adapter_concurrent: Option<Value>,
@@ -271,6 +541,8 @@ pub struct ConfigHomeassistant {
 #[serde(deny_unknown_fields)]
 pub struct ConfigDeviceOptions {
     pub legacy: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transition: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -279,6 +551,8 @@ pub struct GroupValue {
     #[serde(default)]
     pub devices: Vec<String>,
     pub friendly_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transition: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -336,6 +610,21 @@ impl Device {
         self.definition.as_ref().map_or(&[], |def| &def.exposes)
     }
 
+    /// Ids of the scenes stored on the device itself (on any endpoint)
+    #[must_use]
+    pub fn stored_scenes(&self) -> HashSet<u32> {
+        self.endpoints
+            .values()
+            .flat_map(|ep| &ep.scenes)
+            .map(|scn| scn.id)
+            .collect()
+    }
+
+    #[must_use]
+    pub fn options(&self) -> &[Expose] {
+        self.definition.as_ref().map_or(&[], |def| &def.options)
+    }
+
     #[must_use]
     pub fn expose_light(&self) -> Option<&ExposeLight> {
         self.exposes().iter().find_map(|exp| {
@@ -357,6 +646,91 @@ impl Device {
             }
         })
     }
+
+    /// Returns true if the behavior after a power outage can be configured
+    #[must_use]
+    pub fn expose_power_on_behavior(&self) -> bool {
+        self.exposes().iter().any(|exp| {
+            matches!(exp, Expose::Enum(ExposeEnum { name, .. }) if name == "power_on_behavior")
+        })
+    }
+
+    /// Returns true if the device can be switched on and off, without being
+    /// a light (like smart plugs and relays)
+    #[must_use]
+    pub fn expose_switch(&self) -> bool {
+        self.exposes()
+            .iter()
+            .any(|exp| matches!(exp, Expose::Switch(_)))
+    }
+
+    /// Returns true if the device reports occupancy (i.e., is a motion sensor)
+    #[must_use]
+    pub fn expose_occupancy(&self) -> bool {
+        self.exposes().iter().any(
+            |exp| matches!(exp, Expose::Binary(ExposeBinary { name, .. }) if name == "occupancy"),
+        )
+    }
+
+    /// Returns true if the device reports contact (i.e., is a door/window sensor)
+    #[must_use]
+    pub fn expose_contact(&self) -> bool {
+        self.exposes().iter().any(
+            |exp| matches!(exp, Expose::Binary(ExposeBinary { name, .. }) if name == "contact"),
+        )
+    }
+
+    /// Returns true if the device reports tampering (e.g., an opened battery
+    /// cover)
+    #[must_use]
+    pub fn expose_tamper(&self) -> bool {
+        self.exposes()
+            .iter()
+            .any(|exp| matches!(exp, Expose::Binary(ExposeBinary { name, .. }) if name == "tamper"))
+    }
+
+    /// Returns the maximum number of gradient points, if the device supports
+    /// gradients (like Hue gradient light strips)
+    #[must_use]
+    pub fn expose_gradient(&self) -> Option<u32> {
+        self.exposes().iter().find_map(|exp| match exp {
+            Expose::List(obj) if obj["name"] == "gradient" => Some(
+                obj["length_max"]
+                    .as_u64()
+                    .and_then(|len| u32::try_from(len).ok())
+                    .unwrap_or(5),
+            ),
+            _ => None,
+        })
+    }
+
+    #[must_use]
+    pub fn enum_values(&self, enum_name: &str) -> &[String] {
+        self.exposes()
+            .iter()
+            .find_map(|exp| match exp {
+                Expose::Enum(ExposeEnum { name, values, .. }) if name == enum_name => {
+                    Some(values.as_slice())
+                }
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn action_values(&self) -> &[String] {
+        self.enum_values("action")
+    }
+
+    #[must_use]
+    pub fn effect_values(&self) -> &[String] {
+        self.enum_values("effect")
+    }
+
+    #[must_use]
+    pub fn motion_sensitivity_values(&self) -> &[String] {
+        self.enum_values("motion_sensitivity")
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -516,7 +890,7 @@ pub struct Endpoint {
     pub bindings: Vec<Binding>,
     pub clusters: Clusters,
     pub configured_reportings: Vec<ConfiguredReporting>,
-    pub scenes: Vec<Value>,
+    pub scenes: Vec<Scene>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,3686 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::config::{AppConfig, LightDefaultsConfig, SceneIconRule, Z2mServer};
+use crate::hue;
+use crate::hue::api::{
+    BehaviorInstance, Button, ButtonData, ButtonMetadata, ButtonReport, ColorTemperature,
+    ColorTemperatureUpdate, ColorUpdate, Contact, ContactReport, Device, DeviceArchetype,
+    DeviceProductData, DeviceSoftwareUpdate, Dimming, DimmingUpdate, Entertainment, GroupedLight,
+    Light, LightColor, LightEffect, LightEffects, LightGradient, LightGradientPoint,
+    LightGradientUpdate, LightTimedEffects, LightUpdate, Metadata, Motion, MotionReport,
+    MotionSensitivity, MotionSensitivityStatus, RType, RelativeRotary, RelativeRotaryData,
+    Resource, ResourceLink, Room, RoomArchetype, RoomMetadata, RotaryAction, RotaryDirection,
+    RotaryEvent, RotaryReport, RotaryRotation, Scene, SceneAction, SceneActionElement,
+    SceneMetadata, SceneStatus, SoftwareUpdateState, Tamper, TamperReport, TamperSource,
+    ZigbeeConnectivity, ZigbeeConnectivityStatus,
+};
+
+use crate::error::{ApiError, ApiResult};
+use crate::hue::scene_icons;
+use crate::hue::scene_presets::ScenePreset;
+use crate::journal::{Source, TraceId};
+use crate::model::brightness::Brightness;
+use crate::model::state::AuxData;
+use crate::model::types::XY;
+use crate::resource::Resources;
+use crate::z2m::api;
+use crate::z2m::api::{
+    AvailabilityConfig, AvailabilityUpdate, BridgeOnlineState, BridgeResponse, DeviceEvent,
+    DeviceEventData, ExposeLight, IeeeAddress, Message, RawMessage,
+};
+use crate::z2m::request::{ClientRequest, RequestFailure, TouchlinkAction, Z2mRequest};
+use crate::z2m::status::LogEntry;
+use crate::z2m::update::{
+    parse_button_action, parse_rotary_action, DeviceColor, DeviceUpdate, OtaState,
+};
+
+#[derive(Debug)]
+struct LearnScene {
+    pub expire: DateTime<Utc>,
+    pub missing: HashSet<Uuid>,
+    pub known: HashMap<Uuid, SceneAction>,
+}
+
+/* A command sent to z2m, which has not been confirmed yet.
+ *
+ * z2m publishes the new device state after every successful command. If
+ * that does not happen in time (or z2m reports an error), the command is
+ * considered failed, and the device state is requested again, so clients
+ * get corrective events for anything they assumed had changed.
+ *
+ * Commands with a transition are kept until the transition is over, since
+ * states reported along the way might be intermediate values. The device
+ * state is then requested again, to end up with the final state. */
+#[derive(Debug)]
+struct PendingCommand {
+    pub expire: DateTime<Utc>,
+    /* time when the transition (if any) is expected to be complete */
+    pub settle: DateTime<Utc>,
+    /* z2m has published a state since the command was sent */
+    pub confirmed: bool,
+    /* light that had its effect set optimistically */
+    pub effect: Option<Uuid>,
+    /* api request the command was sent for, which the resulting state
+     * changes are attributed to */
+    pub trace: Option<TraceId>,
+}
+
+/* Time allowed for z2m to confirm a command */
+const COMMAND_TIMEOUT: Duration = Duration::seconds(5);
+
+/* Time during which a state published by z2m is compared to recent commands */
+const ECHO_WINDOW: Duration = Duration::seconds(2);
+
+/* Time allowed for z2m to answer a reconciliation request */
+const RECONCILE_TIMEOUT: Duration = Duration::seconds(30);
+
+/* What a device can do as a member of a group, in increasing order */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Capability {
+    Unsupported,
+    OnOff,
+    Light,
+}
+
+impl Capability {
+    fn of(dev: &api::Device) -> Self {
+        if dev.expose_light().is_some() {
+            Self::Light
+        } else if dev.expose_switch() {
+            Self::OnOff
+        } else {
+            Self::Unsupported
+        }
+    }
+}
+
+/* A bridge request, waiting for z2m to publish a response with the same
+ * transaction id */
+#[derive(Debug)]
+struct PendingTransaction {
+    pub expire: DateTime<Utc>,
+    pub topic: String,
+}
+
+/* Time allowed for z2m to answer a bridge request (touchlink scans are slow) */
+const TRANSACTION_TIMEOUT: Duration = Duration::seconds(60);
+
+/* rotary actions in the same direction, less than this far apart, are
+ * reported as a continued ("repeat") rotation */
+const ROTARY_REPEAT_WINDOW: Duration = Duration::milliseconds(1000);
+
+/* duration reported for the first event of a rotation */
+const ROTARY_DEFAULT_DURATION: u32 = 400;
+
+/* requests made while z2m is not ready for them (disconnected, offline, or
+ * still announcing) are queued, up to a limit, and for a limited time.
+ *
+ * Api requests for lights on a server known to be disconnected are refused
+ * before they get here (see Resources::check_reachable), so while the
+ * connection is lost, this only holds internal requests (schedules, scripts,
+ * circadian lighting). Api requests are queued during the initial connection,
+ * and while z2m itself reports being offline or is re-announcing. */
+const MAX_QUEUED_REQUESTS: usize = 64;
+const QUEUED_REQUEST_MAX_AGE: Duration = Duration::minutes(2);
+
+/* z2m groups maintained for zones are named by this prefix and the zone id,
+ * and are never presented as rooms */
+const ZONE_GROUP_PREFIX: &str = "bifrost_zone_";
+
+/* Synchronization state of a z2m connection.
+ *
+ * When zigbee2mqtt restarts, it reports itself offline, then online, and
+ * re-announces all devices and groups. Any topic mappings we hold by then
+ * might be stale, so they are rebuilt from the announcements, after which
+ * fresh state is requested for everything we know about. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BridgeSync {
+    /* Connected, and waiting for the initial announcements */
+    Connected,
+    /* Bridge is online, and topic maps are up to date */
+    Online,
+    /* Bridge reported itself offline, requests are queued until it returns */
+    Offline,
+    /* Bridge is back online, waiting for devices and groups to be re-announced */
+    Resyncing {
+        devices: bool,
+        groups: bool,
+        previous: HashSet<String>,
+    },
+    /* Announcements are complete, device state must be requested */
+    Refresh,
+}
+
+/* attributes that make up the state of a light, by cluster */
+const REPORTING_ATTRIBUTES: &[(&str, &[&str])] = &[
+    ("genOnOff", &["onOff"]),
+    ("genLevelCtrl", &["currentLevel"]),
+    (
+        "lightingColorCtrl",
+        &["colorTemperature", "currentX", "currentY"],
+    ),
+];
+
+/* attribute that can be configured for reporting, on a device endpoint */
+#[derive(Clone, Debug)]
+struct Reporting {
+    endpoint: String,
+    cluster: &'static str,
+    attribute: &'static str,
+}
+
+impl Reporting {
+    /* each cluster is configured on the first endpoint that has it */
+    fn of(dev: &api::Device) -> Vec<Self> {
+        let mut endpoints: Vec<_> = dev.endpoints.iter().collect();
+        endpoints.sort_by_key(|(id, _)| id.parse::<u32>().unwrap_or(u32::MAX));
+
+        REPORTING_ATTRIBUTES
+            .iter()
+            .filter_map(|(cluster, attributes)| {
+                let (endpoint, _) = endpoints
+                    .iter()
+                    .find(|(_, ep)| ep.clusters.input.iter().any(|cl| cl == cluster))?;
+                Some(attributes.iter().map(|attribute| Self {
+                    endpoint: (*endpoint).clone(),
+                    cluster,
+                    attribute,
+                }))
+            })
+            .flatten()
+            .collect()
+    }
+}
+
+/// Mapping between zigbee2mqtt and hue resources, for a single z2m server.
+///
+/// This handles everything except the connection itself: incoming messages
+/// are fed to [`Mapper::handle_message`], api requests to
+/// [`Mapper::handle_request`], and everything that needs to be sent to z2m
+/// is collected, to be picked up with [`Mapper::take_outgoing`].
+pub struct Mapper {
+    name: String,
+    server: Z2mServer,
+    config: Arc<AppConfig>,
+    state: Arc<Mutex<Resources>>,
+    map: HashMap<String, Uuid>,
+    rmap: HashMap<Uuid, String>,
+    learn: HashMap<Uuid, LearnScene>,
+    ignore: HashSet<String>,
+    cycle: HashMap<Uuid, usize>,
+    /* motion sensitivity levels supported by each motion sensor device */
+    motion_levels: HashMap<Uuid, Vec<String>>,
+    /* attributes each device can be asked to report */
+    reporting: HashMap<Uuid, Vec<Reporting>>,
+    /* devices from the config, that reporting was configured for already */
+    reporting_done: HashSet<Uuid>,
+    /* time and direction of the last rotary action, per device */
+    rotary_last: HashMap<Uuid, (DateTime<Utc>, RotaryDirection)>,
+    /* lights in the z2m group of each zone, as announced by z2m */
+    zone_groups: HashMap<Uuid, HashSet<Uuid>>,
+    /* capabilities of all known devices, and grouped lights that can only
+     * be switched on and off */
+    capabilities: HashMap<Uuid, Capability>,
+    onoff_groups: HashSet<Uuid>,
+    /* default transition time (in seconds) configured in z2m, per topic */
+    transitions: HashMap<String, f64>,
+    default_transition: f64,
+    /* availability tracking configured in z2m, and the connectivity
+     * resource of each device */
+    availability: AvailabilityConfig,
+    connectivity: HashMap<IeeeAddress, ResourceLink>,
+    /* connectivity status of each device before the connection to z2m was
+     * lost, to restore when it is back */
+    detached: HashMap<ResourceLink, ZigbeeConnectivityStatus>,
+    /* ids of the scenes stored on each light, as reported by z2m */
+    stored_scenes: HashMap<Uuid, HashSet<u32>>,
+    sync: BridgeSync,
+    pending: HashMap<String, PendingCommand>,
+    /* payloads recently sent to each topic, with the time they were sent */
+    echoes: HashMap<String, VecDeque<(DateTime<Utc>, Value)>>,
+    transactions: HashMap<String, PendingTransaction>,
+    next_transaction: u64,
+    /* lights asked for their state by the current reconciliation sweep,
+     * with the time to give up on an answer */
+    reconcile: HashMap<String, DateTime<Utc>>,
+    next_reconcile: Option<DateTime<Utc>>,
+    /* api requests waiting for z2m to be ready, with the time they were made,
+     * and their trace id */
+    queue: VecDeque<(DateTime<Utc>, Option<TraceId>, ClientRequest)>,
+    outbox: Vec<RawMessage>,
+}
+
+impl Mapper {
+    #[must_use]
+    pub fn new(
+        name: String,
+        server: Z2mServer,
+        config: Arc<AppConfig>,
+        state: Arc<Mutex<Resources>>,
+    ) -> Self {
+        let map = HashMap::new();
+        let rmap = HashMap::new();
+        let learn = HashMap::new();
+        let ignore = HashSet::new();
+        let cycle = HashMap::new();
+        let motion_levels = HashMap::new();
+        let reporting = HashMap::new();
+        let reporting_done = HashSet::new();
+        let rotary_last = HashMap::new();
+        let zone_groups = HashMap::new();
+        let capabilities = HashMap::new();
+        let onoff_groups = HashSet::new();
+        let transitions = HashMap::new();
+        let availability = AvailabilityConfig::default();
+        let connectivity = HashMap::new();
+        let stored_scenes = HashMap::new();
+        let sync = BridgeSync::Connected;
+        let pending = HashMap::new();
+        let transactions = HashMap::new();
+        let outbox = vec![];
+        Self {
+            name,
+            server,
+            config,
+            state,
+            map,
+            rmap,
+            learn,
+            ignore,
+            cycle,
+            motion_levels,
+            reporting,
+            reporting_done,
+            rotary_last,
+            zone_groups,
+            capabilities,
+            onoff_groups,
+            transitions,
+            default_transition: 0.0,
+            availability,
+            connectivity,
+            stored_scenes,
+            detached: HashMap::new(),
+            sync,
+            pending,
+            echoes: HashMap::new(),
+            transactions,
+            next_transaction: 0,
+            reconcile: HashMap::new(),
+            next_reconcile: None,
+            queue: VecDeque::new(),
+            outbox,
+        }
+    }
+
+    /// Messages to send to z2m, in order
+    pub fn take_outgoing(&mut self) -> Vec<RawMessage> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    fn send(&mut self, msg: RawMessage) {
+        self.outbox.push(msg);
+    }
+
+    /// Start over, for a new connection (z2m announces everything again)
+    pub fn reset(&mut self) {
+        self.sync_reset();
+        self.sync = BridgeSync::Connected;
+        self.echoes.clear();
+        self.outbox.clear();
+        self.reconcile.clear();
+        self.next_reconcile = None;
+    }
+
+    /// The connection to z2m was lost, so mark all its devices unreachable
+    pub async fn disconnected(&mut self) -> ApiResult<()> {
+        let mut res = self.state.lock().await;
+
+        for link_zbc in self.connectivity.values() {
+            let status = res.get::<ZigbeeConnectivity>(link_zbc)?.status;
+            if status == ZigbeeConnectivityStatus::ConnectivityIssue {
+                continue;
+            }
+            self.detached.entry(*link_zbc).or_insert(status);
+            res.update::<ZigbeeConnectivity>(&link_zbc.rid, |zbc| {
+                zbc.status = ZigbeeConnectivityStatus::ConnectivityIssue;
+            })?;
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    /// The connection to z2m is back, so restore the status of all devices
+    /// from before it was lost (until z2m reports otherwise)
+    pub async fn connected(&mut self) -> ApiResult<()> {
+        let mut res = self.state.lock().await;
+
+        for (link_zbc, status) in self.detached.drain() {
+            let Ok(zbc) = res.get::<ZigbeeConnectivity>(&link_zbc) else {
+                continue;
+            };
+            if zbc.status != status {
+                res.update::<ZigbeeConnectivity>(&link_zbc.rid, |zbc| zbc.status = status)?;
+            }
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    /// z2m reports its bridge offline (e.g. mqtt is down)
+    #[must_use]
+    pub fn is_offline(&self) -> bool {
+        self.sync == BridgeSync::Offline
+    }
+
+    /// Requests can be sent: z2m is online, and has announced everything
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        self.sync == BridgeSync::Online
+    }
+
+    /// Number of requests waiting for z2m to be ready
+    #[must_use]
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Handle an api request, or queue it until z2m is ready for it
+    pub async fn submit(&mut self, req: &ClientRequest) -> ApiResult<()> {
+        if self.is_ready() && self.queue.is_empty() {
+            return self.handle_request(req).await;
+        }
+
+        /* requests go to every server, so only queue our own, which would
+         * otherwise be pushed out by requests for other servers */
+        let state = self.state.clone();
+        if !self.is_own_request(&*state.lock().await, req) {
+            return Ok(());
+        }
+
+        self.enqueue(req);
+        self.drain_queue().await
+    }
+
+    /* Whether `req` would be sent to this server by handle_request. Before
+     * any devices are known, everything might be. */
+    fn is_own_request(&self, res: &Resources, req: &ClientRequest) -> bool {
+        if self.rmap.is_empty() {
+            return true;
+        }
+
+        match req {
+            ClientRequest::Touchlink { server, .. } => {
+                server.as_ref().map_or(true, |name| name == &self.name)
+            }
+            ClientRequest::MotionSensitivity { device, .. }
+            | ClientRequest::LightStop { device }
+            | ClientRequest::ConfigureReporting { device } => self.rmap.contains_key(&device.rid),
+            ClientRequest::ZoneGroup { zone, lights } => {
+                self.zone_groups.contains_key(&zone.rid)
+                    || lights
+                        .iter()
+                        .any(|light| self.rmap.contains_key(&light.rid))
+            }
+            ClientRequest::SceneRecall { scene } => match res.zone_scene_updates(scene) {
+                Ok(Some(updates)) => updates
+                    .iter()
+                    .any(|(light, _)| self.rmap.contains_key(&light.rid)),
+                _ => matches!(self.resolve_request(res, req), Ok(Some(_))),
+            },
+            _ => matches!(self.resolve_request(res, req), Ok(Some(_))),
+        }
+    }
+
+    fn enqueue(&mut self, req: &ClientRequest) {
+        let now = Utc::now();
+
+        /* only the latest request is combined with, to keep the order */
+        if let Some((time, trace, last)) = self.queue.back_mut() {
+            if let Some(combined) = last.coalesce(req) {
+                *time = now;
+                *trace = TraceId::current();
+                *last = combined;
+                return;
+            }
+        }
+
+        if self.queue.len() >= MAX_QUEUED_REQUESTS {
+            log::warn!(
+                server:% = self.name;
+                "[{}] Request queue full, dropping oldest request",
+                self.name
+            );
+            self.queue.pop_front();
+        }
+
+        log::debug!(
+            server:% = self.name;
+            "[{}] Not ready, queueing request: {req:?}",
+            self.name
+        );
+        self.queue.push_back((now, TraceId::current(), req.clone()));
+    }
+
+    async fn drain_queue(&mut self) -> ApiResult<()> {
+        if !self.is_ready() || self.queue.is_empty() {
+            return Ok(());
+        }
+
+        log::info!(
+            server:% = self.name;
+            "[{}] Sending {} queued requests",
+            self.name,
+            self.queue.len()
+        );
+
+        let now = Utc::now();
+        while let Some((time, trace, req)) = self.queue.pop_front() {
+            if now - time > QUEUED_REQUEST_MAX_AGE {
+                log::info!(
+                    server:% = self.name;
+                    "[{}] Dropping stale request: {req:?}",
+                    self.name
+                );
+                continue;
+            }
+            TraceId::scope_opt(trace, self.handle_request(&req)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Request fresh state for all lights and groups, after requests were lost
+    pub async fn resync(&mut self) -> ApiResult<()> {
+        Source::Z2m.scope(self.sync_refresh()).await
+    }
+
+    /// Periodic housekeeping, to detect failed commands and requests
+    pub async fn tick(&mut self) -> ApiResult<()> {
+        Source::Z2m
+            .scope(async {
+                self.pending_check().await?;
+                self.reconcile_check().await;
+                Ok(())
+            })
+            .await
+    }
+
+    /* the first sweep is one interval after connecting, since z2m has just
+     * announced the state of everything by then */
+    async fn reconcile_check(&mut self) {
+        let now = Utc::now();
+        self.reconcile.retain(|_, expire| *expire > now);
+
+        let secs = std::time::Duration::from_secs(self.server.reconcile_secs);
+        let Some(interval) = Duration::from_std(secs).ok().filter(|dur| !dur.is_zero()) else {
+            return;
+        };
+        if self.sync != BridgeSync::Online {
+            return;
+        }
+
+        match self.next_reconcile {
+            Some(next) if next <= now => {
+                self.next_reconcile = Some(now + interval);
+                self.reconcile().await;
+            }
+            Some(_) => {}
+            None => self.next_reconcile = Some(now + interval),
+        }
+    }
+
+    /// Ask z2m for the actual state of every light (except those with a
+    /// command in progress). Lights that turn out to have diverged from
+    /// their state in bifrost are corrected, and counted in the server
+    /// status.
+    pub async fn reconcile(&mut self) {
+        let mut lock = self.state.lock().await;
+        let topics: Vec<String> = self
+            .map
+            .iter()
+            .filter(|(topic, _)| !self.pending.contains_key(*topic))
+            .filter(|(_, uuid)| lock.get::<Light>(&RType::Light.link_to(**uuid)).is_ok())
+            .map(|(topic, _)| topic.clone())
+            .collect();
+
+        let status = lock.z2m_status_mut(&self.name, &self.server.url);
+        status.reconcile.sweeps += 1;
+        status.reconcile.last_sweep = Some(Utc::now());
+        drop(lock);
+
+        log::debug!(
+            server:% = self.name;
+            "[{}] Reconciling the state of {} lights",
+            self.name,
+            topics.len()
+        );
+
+        let expire = Utc::now() + RECONCILE_TIMEOUT;
+        for topic in topics {
+            self.refresh_topic(&topic);
+            self.reconcile.insert(topic, expire);
+        }
+    }
+
+    /* compare the state of a light before and after the answer to a
+     * reconciliation request was applied */
+    async fn reconcile_done(&self, topic: &str, before: &Light, uuid: &Uuid) {
+        let mut lock = self.state.lock().await;
+        let diverged = lock
+            .get::<Light>(&RType::Light.link_to(*uuid))
+            .map(|after| light_divergence(before, after))
+            .unwrap_or_default();
+
+        let stats = &mut lock.z2m_status_mut(&self.name, &self.server.url).reconcile;
+        stats.checked += 1;
+        if diverged.is_empty() {
+            return;
+        }
+        stats.diverged += 1;
+        *stats.devices.entry(topic.to_string()).or_default() += 1;
+        drop(lock);
+
+        log::warn!(
+            server:% = self.name, topic = topic;
+            "[{}] State of [{topic}] had diverged from z2m ({}), corrected",
+            self.name,
+            diverged.join(", ")
+        );
+    }
+
+    fn sync_reset(&mut self) {
+        self.map.clear();
+        self.rmap.clear();
+        self.learn.clear();
+        self.ignore.clear();
+        self.cycle.clear();
+    }
+
+    fn sync_bridge_state(&mut self, state: &BridgeOnlineState) {
+        match (&self.sync, state) {
+            (BridgeSync::Offline, BridgeOnlineState::Online) => {
+                log::info!(
+                    server:% = self.name;
+                    "[{}] Bridge is back online, resynchronizing state",
+                    self.name
+                );
+                let previous = self.map.keys().cloned().collect();
+                self.sync_reset();
+                self.sync = BridgeSync::Resyncing {
+                    devices: false,
+                    groups: false,
+                    previous,
+                };
+            }
+            (BridgeSync::Connected, BridgeOnlineState::Online) => {
+                log::debug!(server:% = self.name; "[{}] Bridge is online", self.name);
+            }
+            (BridgeSync::Offline, BridgeOnlineState::Offline) | (_, BridgeOnlineState::Online) => {}
+            (_, BridgeOnlineState::Offline) => {
+                log::warn!(
+                    server:% = self.name;
+                    "[{}] Bridge went offline, queueing requests until it returns",
+                    self.name
+                );
+                self.learn.clear();
+                self.sync = BridgeSync::Offline;
+            }
+        }
+    }
+
+    fn sync_announced(&mut self, is_devices: bool) {
+        match &mut self.sync {
+            BridgeSync::Connected => {
+                if !is_devices {
+                    self.sync = BridgeSync::Online;
+                }
+            }
+            BridgeSync::Resyncing {
+                devices,
+                groups,
+                previous,
+            } => {
+                if is_devices {
+                    *devices = true;
+                } else {
+                    *groups = true;
+                }
+                if !(*devices && *groups) {
+                    return;
+                }
+
+                for topic in previous.iter() {
+                    if !self.map.contains_key(topic) && !self.ignore.contains(topic) {
+                        log::warn!(
+                            server:% = self.name;
+                            "[{}] Topic [{topic}] was not re-announced after bridge restart",
+                            self.name
+                        );
+                    }
+                }
+                self.sync = BridgeSync::Refresh;
+            }
+            BridgeSync::Online | BridgeSync::Offline | BridgeSync::Refresh => {}
+        }
+    }
+
+    async fn sync_refresh(&mut self) -> ApiResult<()> {
+        let topics: Vec<String> = {
+            let lock = self.state.lock().await;
+            self.map
+                .iter()
+                .filter(|(_, uuid)| {
+                    matches!(
+                        lock.get_resource_by_id(uuid).map(|res| res.obj),
+                        Ok(Resource::Light(_) | Resource::GroupedLight(_))
+                    )
+                })
+                .map(|(topic, _)| topic.clone())
+                .collect()
+        };
+
+        log::info!(
+            server:% = self.name;
+            "[{}] Requesting fresh state for {} lights and groups",
+            self.name,
+            topics.len()
+        );
+
+        for topic in topics {
+            self.refresh_topic(&topic);
+        }
+
+        if self.sync == BridgeSync::Refresh {
+            self.sync = BridgeSync::Online;
+        }
+        Ok(())
+    }
+
+    fn refresh_topic(&mut self, topic: &str) {
+        self.send(RawMessage {
+            payload: json!({"state": ""}),
+            topic: format!("{topic}/get"),
+        });
+    }
+
+    /* Time for a command to take full effect, which is the transition given
+     * in the command, or (if none) the default transition z2m applies */
+    fn settle_time(&self, topic: &str, transition: Option<f64>) -> Duration {
+        let secs = transition
+            .or_else(|| self.transitions.get(topic).copied())
+            .unwrap_or(self.default_transition);
+
+        std::time::Duration::try_from_secs_f64(secs)
+            .ok()
+            .and_then(|dur| Duration::from_std(dur).ok())
+            .unwrap_or_else(Duration::zero)
+    }
+
+    fn pending_add(&mut self, topic: &str, req: &ClientRequest) {
+        let (effect, transition) = match req {
+            ClientRequest::LightUpdate { device, upd } => {
+                (upd.effect.is_some().then_some(device.rid), upd.transition)
+            }
+            ClientRequest::GroupUpdate { upd, .. } => (None, upd.transition),
+            ClientRequest::MotionSensitivity { .. } => (None, Some(0.0)),
+            _ => return,
+        };
+
+        let settle = Utc::now() + self.settle_time(topic, transition);
+        self.pending.insert(
+            topic.to_string(),
+            PendingCommand {
+                expire: settle + COMMAND_TIMEOUT,
+                settle,
+                confirmed: false,
+                effect,
+                trace: TraceId::current(),
+            },
+        );
+    }
+
+    /* z2m published the state for this topic, which confirms the pending
+     * command, if any. During a transition, the command is kept, to re-sync
+     * the state once the transition is over. */
+    fn pending_confirm(&mut self, topic: &str) {
+        match self.pending.get_mut(topic) {
+            Some(cmd) if cmd.settle > Utc::now() => cmd.confirmed = true,
+            Some(_) => {
+                self.pending.remove(topic);
+            }
+            None => {}
+        }
+    }
+
+    /* Remember a payload sent to this topic, to recognize its echo later */
+    fn echo_track(&mut self, topic: &str, payload: &Value) {
+        let now = Utc::now();
+        let sent = self.echoes.entry(topic.to_string()).or_default();
+        sent.retain(|(time, _)| *time + ECHO_WINDOW > now);
+        sent.push_back((now, payload.clone()));
+    }
+
+    /* z2m echoes every command with the resulting state. When commands are
+     * sent in quick succession, the echo of an older command can arrive after
+     * a newer one was applied, and would revert it. Fields matching an older
+     * command (but not the newest one setting that field) are dropped. */
+    fn echo_suppress(&mut self, topic: &str, payload: &mut Value) {
+        let now = Utc::now();
+        if let Some(sent) = self.echoes.get_mut(topic) {
+            sent.retain(|(time, _)| *time + ECHO_WINDOW > now);
+            if sent.is_empty() {
+                self.echoes.remove(topic);
+            }
+        }
+
+        let (Some(sent), Some(obj)) = (self.echoes.get(topic), payload.as_object_mut()) else {
+            return;
+        };
+
+        let stale: Vec<String> = obj
+            .iter()
+            .filter(|(key, value)| {
+                let mut values = sent
+                    .iter()
+                    .rev()
+                    .filter_map(|(_, cmd)| cmd.get(key.as_str()));
+                values
+                    .next()
+                    .is_some_and(|newest| !echo_matches(newest, value))
+                    && values.any(|older| echo_matches(older, value))
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in stale {
+            log::debug!(
+                server:% = self.name, topic:% = topic;
+                "[{}] Ignoring stale echo of {key} on [{topic}]",
+                self.name
+            );
+            obj.remove(&key);
+        }
+    }
+
+    /* Register a bridge request, and return the transaction id to send with it */
+    fn transaction_new(&mut self, topic: &str) -> String {
+        self.next_transaction += 1;
+        let id = format!("bifrost-{}", self.next_transaction);
+        self.transactions.insert(
+            id.clone(),
+            PendingTransaction {
+                expire: Utc::now() + TRANSACTION_TIMEOUT,
+                topic: topic.to_string(),
+            },
+        );
+        id
+    }
+
+    async fn transaction_done(&mut self, topic: &str, resp: &BridgeResponse<Value>) {
+        /* responses to requests from other z2m clients are not our concern */
+        let Some(txn) = resp
+            .transaction
+            .as_ref()
+            .and_then(|id| self.transactions.remove(id))
+        else {
+            return;
+        };
+
+        if resp.is_ok() {
+            log::debug!(
+                server:% = self.name;
+                "[{}] Request on [{}] completed",
+                self.name,
+                txn.topic
+            );
+        } else {
+            let error = resp.error.as_deref().unwrap_or("unknown error");
+            self.report_failure(&txn.topic, &format!("{topic}: {error}"))
+                .await;
+        }
+    }
+
+    async fn report_failure(&self, topic: &str, error: &str) {
+        log::error!(
+            server:% = self.name, topic = topic;
+            "[{}] Request on [{topic}] failed: {error}",
+            self.name
+        );
+        self.state.lock().await.add_z2m_failure(RequestFailure {
+            time: Utc::now(),
+            server: self.name.clone(),
+            topic: topic.to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    /* warnings and errors are kept for the admin api, and optionally
+     * forwarded to our own log. failed publishes are reported as request
+     * failures instead, so they are not logged twice */
+    async fn handle_bridge_logging(&mut self, obj: &api::BridgeLogging) {
+        let level = match obj.level.as_str() {
+            "error" => log::Level::Error,
+            "warning" | "warn" => log::Level::Warn,
+            _ => return,
+        };
+
+        self.state.lock().await.add_z2m_log(LogEntry {
+            time: Utc::now(),
+            server: self.name.clone(),
+            level: obj.level.clone(),
+            message: obj.message.clone(),
+            topic: obj.topic.clone(),
+        });
+
+        if level == log::Level::Error {
+            if let Some(topic) = parse_publish_failure(&obj.message) {
+                self.report_failure(&format!("{topic}/set"), &obj.message)
+                    .await;
+                self.pending_fail(topic);
+                return;
+            }
+        }
+
+        if self.server.forward_logging {
+            log::log!(
+                level, server:% = self.name;
+                "[{}] z2m: {}",
+                self.name,
+                obj.message
+            );
+        }
+    }
+
+    /* z2m reported a failure for this topic, so handle it on the next check */
+    fn pending_fail(&mut self, topic: &str) {
+        if let Some(cmd) = self.pending.get_mut(topic) {
+            cmd.expire = Utc::now();
+        }
+    }
+
+    async fn pending_check(&mut self) -> ApiResult<()> {
+        let now = Utc::now();
+
+        let expired: Vec<String> = self
+            .transactions
+            .iter()
+            .filter(|(_, txn)| txn.expire <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            if let Some(txn) = self.transactions.remove(&id) {
+                self.report_failure(&txn.topic, "no response from zigbee2mqtt")
+                    .await;
+            }
+        }
+
+        /* topic maps might be incomplete until the bridge is in sync */
+        if self.sync != BridgeSync::Online || self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let settled: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, cmd)| cmd.confirmed && cmd.settle <= now)
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        for topic in settled {
+            self.pending.remove(&topic);
+
+            log::debug!(
+                server:% = self.name, topic:% = topic;
+                "[{}] Transition on [{topic}] complete, re-syncing state",
+                self.name
+            );
+
+            if self.map.contains_key(&topic) {
+                self.refresh_topic(&topic);
+            }
+        }
+
+        let expired: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, cmd)| !cmd.confirmed && cmd.expire <= now)
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        for topic in expired {
+            let Some(cmd) = self.pending.remove(&topic) else {
+                continue;
+            };
+
+            log::warn!(
+                server:% = self.name, topic:% = topic;
+                "[{}] Command for [{topic}] was not confirmed, re-syncing state",
+                self.name
+            );
+
+            /* z2m does not report effects, so undo the optimistic update */
+            if let Some(light) = cmd.effect {
+                let upd = LightUpdate::new().with_effect(Some(LightEffect::NoEffect));
+                self.state
+                    .lock()
+                    .await
+                    .update::<Light>(&light, |light| *light += upd)?;
+            }
+
+            if self.map.contains_key(&topic) {
+                self.refresh_topic(&topic);
+            }
+        }
+
+        Ok(())
+    }
+
+    /* Devices that z2m does not track availability for never report whether
+     * they are reachable, so assume they are. All others keep their last known
+     * status until z2m reports it (and new devices start out as reachable) */
+    fn initial_connectivity(
+        &self,
+        res: &Resources,
+        ieee: &IeeeAddress,
+        link_zbc: &ResourceLink,
+    ) -> ZigbeeConnectivityStatus {
+        if !self.availability.is_enabled(ieee) {
+            return ZigbeeConnectivityStatus::Connected;
+        }
+
+        res.get::<ZigbeeConnectivity>(link_zbc)
+            .map_or(ZigbeeConnectivityStatus::Connected, |zbc| zbc.status)
+    }
+
+    /* Apply (possibly changed) availability config to all known devices */
+    async fn seed_connectivity(&self) -> ApiResult<()> {
+        let mut res = self.state.lock().await;
+
+        for (ieee, link_zbc) in &self.connectivity {
+            let status = self.initial_connectivity(&res, ieee, link_zbc);
+            if res.get::<ZigbeeConnectivity>(link_zbc)?.status != status {
+                res.update::<ZigbeeConnectivity>(&link_zbc.rid, |zbc| zbc.status = status)?;
+            }
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub async fn add_light(&mut self, dev: &api::Device, expose: &ExposeLight) -> ApiResult<()> {
+        let name = &dev.friendly_name;
+
+        let link_device = RType::Device.deterministic(&dev.ieee_address);
+        let link_light = RType::Light.deterministic(&dev.ieee_address);
+        let link_zbc = RType::ZigbeeConnectivity.deterministic(&dev.ieee_address);
+        let link_ent = RType::Entertainment.deterministic(&dev.ieee_address);
+
+        let product_data = DeviceProductData::guess_from_device(dev);
+        let display_name = self.config.names.apply(name);
+        let metadata = Metadata::new(DeviceArchetype::SpotBulb, &display_name);
+        let effects = LightEffects::from_z2m_values(dev.effect_values());
+        let gradient = dev.expose_gradient().map(LightGradient::new);
+        let options = dev.options().to_vec();
+        let mac_address = dev.ieee_address.to_mac_string();
+        let ieee = dev.ieee_address.clone();
+        let power_on = dev.expose_power_on_behavior();
+        let stored_scenes = dev.stored_scenes();
+
+        let mut dev = hue::api::Device {
+            product_data,
+            metadata: metadata.clone(),
+            services: vec![link_light, link_zbc],
+        };
+
+        self.map.insert(name.to_string(), link_light.rid);
+        self.rmap.insert(link_light.rid, name.to_string());
+        self.rmap.insert(link_device.rid, name.to_string());
+        self.connectivity.insert(ieee.clone(), link_zbc);
+        self.stored_scenes.insert(link_light.rid, stored_scenes);
+
+        let mut res = self.state.lock().await;
+        let mut light = Light::new(link_device, metadata);
+
+        light.dimming = expose
+            .feature("brightness")
+            .and_then(Dimming::extract_from_expose);
+
+        light.color_temperature = expose
+            .feature("color_temp")
+            .and_then(ColorTemperature::extract_from_expose);
+
+        light.color = expose
+            .feature("color_xy")
+            .and_then(LightColor::extract_from_expose);
+
+        light.effects = effects;
+        light.gradient = gradient;
+        light.timed_effects = light.dimming.is_some().then(LightTimedEffects::new);
+
+        log::trace!("Detected capabilities: {:?}", light.capabilities());
+
+        /* only lights that can show colors take part in entertainment */
+        let ent = light
+            .capabilities()
+            .streamable()
+            .then(|| Entertainment::new(link_device, link_light, &light));
+        if ent.is_some() {
+            dev.services.push(link_ent);
+        }
+
+        let zbc = ZigbeeConnectivity {
+            owner: link_device,
+            mac_address,
+            status: self.initial_connectivity(&res, &ieee, &link_zbc),
+            channel: Some(json!({
+                "status": "set",
+                "value": "channel_25",
+            })),
+            extended_pan_id: String::from("0123456789abcdef"),
+        };
+
+        /* light defaults are only pushed once, unless they change */
+        let applied = res
+            .aux_get(&link_light)
+            .ok()
+            .and_then(|aux| aux.defaults.clone());
+        let defaults = &self.config.light_defaults;
+        let missing = defaults.not_in(applied.as_ref());
+        let recorded = if defaults.is_empty() {
+            applied
+        } else {
+            Some(defaults.clone())
+        };
+
+        res.aux_set(
+            &link_light,
+            AuxData::new()
+                .with_topic(name)
+                .with_server(&self.name)
+                .with_defaults(recorded),
+        );
+        let services = dev.services.clone();
+        let announced = light.clone();
+        res.add(&link_device, Resource::Device(dev))?;
+        res.add(&link_light, Resource::Light(light))?;
+        res.apply_generated_name(&link_device, name, &display_name)?;
+        Self::add_software_update(&mut res, &link_device)?;
+        res.add(&link_zbc, Resource::ZigbeeConnectivity(zbc))?;
+        if let Some(ent) = ent {
+            res.add(&link_ent, Resource::Entertainment(ent))?;
+        }
+        Self::refresh_stored(
+            &mut res,
+            &link_device,
+            &services,
+            Some((&link_light, &announced)),
+        )?;
+        res.set_device_options(&link_device, options);
+        drop(res);
+
+        if !missing.is_empty() {
+            self.light_defaults_send(name, &missing, power_on);
+        }
+
+        Ok(())
+    }
+
+    /* Push the configured light defaults to a new light. Transition and
+     * color sync are device options, but the power on behavior is a setting
+     * on the device itself (if it has one) */
+    fn light_defaults_send(&mut self, topic: &str, defaults: &LightDefaultsConfig, power_on: bool) {
+        log::info!(
+            server:% = self.name;
+            "[{}] Applying light defaults to [{topic}]: {defaults:?}",
+            self.name
+        );
+
+        let mut options = serde_json::Map::new();
+        if let Some(transition) = defaults.transition {
+            options.insert("transition".into(), json!(transition));
+        }
+        if let Some(color_sync) = defaults.color_sync {
+            options.insert("color_sync".into(), json!(color_sync));
+        }
+        if !options.is_empty() {
+            self.bridge_request(
+                "bridge/request/device/options",
+                json!({"id": topic, "options": options}),
+            );
+        }
+
+        if let Some(pob) = defaults.power_on_behavior.filter(|_| power_on) {
+            self.send(RawMessage {
+                topic: format!("{topic}/set"),
+                payload: json!({"power_on_behavior": pob}),
+            });
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub async fn add_switch(&mut self, dev: &api::Device) -> ApiResult<()> {
+        let name = &dev.friendly_name;
+        let display_name = self.config.names.apply(name);
+
+        let link_device = RType::Device.deterministic(&dev.ieee_address);
+        let link_zbc = RType::ZigbeeConnectivity.deterministic(&dev.ieee_address);
+
+        /* find the set of (hue-style) buttons this switch can report */
+        let mut control_ids: Vec<u32> = dev
+            .action_values()
+            .iter()
+            .filter_map(|action| parse_button_action(action))
+            .map(|(control_id, _)| control_id)
+            .collect();
+        control_ids.sort_unstable();
+        control_ids.dedup();
+
+        let link_buttons: Vec<(u32, ResourceLink)> = control_ids
+            .iter()
+            .map(|id| (*id, RType::Button.deterministic((&dev.ieee_address, id))))
+            .collect();
+
+        /* rotary controllers (dials, knobs) get a relative_rotary service */
+        let link_rotary = dev
+            .action_values()
+            .iter()
+            .any(|action| parse_rotary_action(action).is_some())
+            .then(|| RType::RelativeRotary.deterministic(&dev.ieee_address));
+
+        let mut services: Vec<ResourceLink> = link_buttons.iter().map(|(_, rl)| *rl).collect();
+        services.extend(link_rotary);
+        services.push(link_zbc);
+
+        let mac_address = dev.ieee_address.to_mac_string();
+        let ieee = dev.ieee_address.clone();
+        let options = dev.options().to_vec();
+
+        let dev = hue::api::Device {
+            product_data: DeviceProductData::guess_from_device(dev),
+            metadata: Metadata::new(DeviceArchetype::UnknownArchetype, &display_name),
+            services: services.clone(),
+        };
+
+        self.map.insert(name.to_string(), link_device.rid);
+        self.rmap.insert(link_device.rid, name.to_string());
+        self.connectivity.insert(ieee.clone(), link_zbc);
+
+        let mut res = self.state.lock().await;
+
+        let zbc = ZigbeeConnectivity {
+            owner: link_device,
+            mac_address,
+            status: self.initial_connectivity(&res, &ieee, &link_zbc),
+            channel: Some(json!({
+                "status": "set",
+                "value": "channel_25",
+            })),
+            extended_pan_id: String::from("0123456789abcdef"),
+        };
+
+        res.add(&link_device, Resource::Device(dev))?;
+        res.apply_generated_name(&link_device, name, &display_name)?;
+        Self::add_software_update(&mut res, &link_device)?;
+        for (control_id, link_button) in link_buttons {
+            let button = Button {
+                owner: link_device,
+                metadata: ButtonMetadata { control_id },
+                button: ButtonData {
+                    button_report: None,
+                    repeat_interval: Some(800),
+                    event_values: Some(json!([
+                        "initial_press",
+                        "repeat",
+                        "short_release",
+                        "long_release"
+                    ])),
+                },
+            };
+            res.add(&link_button, Resource::Button(button))?;
+        }
+        if let Some(link_rotary) = link_rotary {
+            if res.get::<RelativeRotary>(&link_rotary).is_err() {
+                let rotary = RelativeRotary::new(link_device);
+                res.add(&link_rotary, Resource::RelativeRotary(rotary))?;
+            }
+        }
+        res.add(&link_zbc, Resource::ZigbeeConnectivity(zbc))?;
+        Self::refresh_stored(&mut res, &link_device, &services, None)?;
+        res.set_device_options(&link_device, options);
+
+        /* link switch to room, if configured */
+        if let Some(switch_conf) = self.config.switches.get(name) {
+            let link_room = self.room_link(&res, &switch_conf.room);
+            let link_behavior = RType::BehaviorInstance.deterministic(link_device.rid);
+            let behavior = BehaviorInstance::switch_scene_cycle(name, link_device, link_room);
+
+            log::info!(
+                server:% = self.name;
+                "[{}] Linking switch {name} to room {} ({link_room:?})",
+                self.name,
+                switch_conf.room
+            );
+
+            if res.get::<BehaviorInstance>(&link_behavior).is_ok() {
+                res.update::<BehaviorInstance>(&link_behavior.rid, |obj| {
+                    obj.configuration = behavior.configuration;
+                })?;
+            } else {
+                res.add(&link_behavior, Resource::BehaviorInstance(behavior))?;
+            }
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    pub async fn add_motion_sensor(&mut self, dev: &api::Device) -> ApiResult<()> {
+        let name = &dev.friendly_name;
+        let display_name = self.config.names.apply(name);
+
+        let link_device = RType::Device.deterministic(&dev.ieee_address);
+        let link_motion = RType::Motion.deterministic(&dev.ieee_address);
+        let link_tamper = dev
+            .expose_tamper()
+            .then(|| RType::Tamper.deterministic(&dev.ieee_address));
+        let link_zbc = RType::ZigbeeConnectivity.deterministic(&dev.ieee_address);
+
+        let levels = dev.motion_sensitivity_values().to_vec();
+        let sensitivity = motion_sensitivity(&levels, None);
+        let mac_address = dev.ieee_address.to_mac_string();
+        let ieee = dev.ieee_address.clone();
+        let options = dev.options().to_vec();
+
+        let services: Vec<ResourceLink> = [Some(link_motion), link_tamper, Some(link_zbc)]
+            .into_iter()
+            .flatten()
+            .collect();
+
+        let dev = hue::api::Device {
+            product_data: DeviceProductData::guess_from_device(dev),
+            metadata: Metadata::new(DeviceArchetype::UnknownArchetype, &display_name),
+            services: services.clone(),
+        };
+
+        self.map.insert(name.to_string(), link_device.rid);
+        self.rmap.insert(link_device.rid, name.to_string());
+        self.motion_levels.insert(link_device.rid, levels);
+        self.connectivity.insert(ieee.clone(), link_zbc);
+
+        let mut res = self.state.lock().await;
+
+        let zbc = ZigbeeConnectivity {
+            owner: link_device,
+            mac_address,
+            status: self.initial_connectivity(&res, &ieee, &link_zbc),
+            channel: Some(json!({
+                "status": "set",
+                "value": "channel_25",
+            })),
+            extended_pan_id: String::from("0123456789abcdef"),
+        };
+        res.add(&link_device, Resource::Device(dev))?;
+        res.apply_generated_name(&link_device, name, &display_name)?;
+        Self::add_software_update(&mut res, &link_device)?;
+        /* the scheduler finds sensors by topic, since names can change */
+        res.aux_set(
+            &link_motion,
+            AuxData::new().with_topic(name).with_server(&self.name),
+        );
+        if res.get::<Motion>(&link_motion).is_err() {
+            res.add(
+                &link_motion,
+                Resource::Motion(Motion::new(link_device, sensitivity)),
+            )?;
+        }
+        if let Some(link_tamper) = link_tamper {
+            Self::add_tamper(&mut res, &link_device, &link_tamper)?;
+        }
+        res.add(&link_zbc, Resource::ZigbeeConnectivity(zbc))?;
+        Self::refresh_stored(&mut res, &link_device, &services, None)?;
+        res.set_device_options(&link_device, options);
+        drop(res);
+
+        Ok(())
+    }
+
+    /*
+     * Bring a device (and its light) up to date, when it is announced again.
+     *
+     * Adding a resource that is already known does nothing, so devices and
+     * lights stored by an earlier version would otherwise never get what was
+     * added since: services that did not exist yet are linked into the
+     * device, and capabilities the light did not have yet are filled in.
+     * Everything else is state, which is kept as stored.
+     */
+    fn refresh_stored(
+        res: &mut Resources,
+        link_device: &ResourceLink,
+        services: &[ResourceLink],
+        light: Option<(&ResourceLink, &Light)>,
+    ) -> ApiResult<()> {
+        let stored = &res.get::<Device>(link_device)?.services;
+        if services.iter().any(|svc| !stored.contains(svc)) {
+            res.update::<Device>(&link_device.rid, |dev| {
+                for svc in services {
+                    if !dev.services.contains(svc) {
+                        dev.services.push(*svc);
+                    }
+                }
+            })?;
+        }
+
+        let Some((link_light, announced)) = light else {
+            return Ok(());
+        };
+        let stored = res.get::<Light>(link_light)?;
+        let effects = announced
+            .effects
+            .clone()
+            .filter(|_| stored.effects.is_none());
+        let gradient = announced
+            .gradient
+            .clone()
+            .filter(|_| stored.gradient.is_none());
+        let timed_effects = announced
+            .timed_effects
+            .clone()
+            .filter(|_| stored.timed_effects.is_none());
+        if effects.is_none() && gradient.is_none() && timed_effects.is_none() {
+            return Ok(());
+        }
+
+        res.update::<Light>(&link_light.rid, |light| {
+            light.effects = light.effects.take().or(effects);
+            light.gradient = light.gradient.take().or(gradient);
+            light.timed_effects = light.timed_effects.take().or(timed_effects);
+        })
+    }
+
+    /* add the software update service of a device, which may be known from
+     * before updates were reported */
+    fn add_software_update(
+        res: &mut Resources,
+        link_device: &ResourceLink,
+    ) -> ApiResult<ResourceLink> {
+        let link_swu = RType::DeviceSoftwareUpdate.deterministic(link_device.rid);
+        if res.get::<DeviceSoftwareUpdate>(&link_swu).is_err() {
+            let swu = DeviceSoftwareUpdate::new(*link_device);
+            res.add(&link_swu, Resource::DeviceSoftwareUpdate(swu))?;
+        }
+        Self::refresh_stored(res, link_device, &[link_swu], None)?;
+        Ok(link_swu)
+    }
+
+    /* add the tamper service of a sensor, unless already known */
+    fn add_tamper(
+        res: &mut Resources,
+        link_device: &ResourceLink,
+        link_tamper: &ResourceLink,
+    ) -> ApiResult<()> {
+        if res.get::<Tamper>(link_tamper).is_err() {
+            res.add(link_tamper, Resource::Tamper(Tamper::new(*link_device)))?;
+        }
+        Ok(())
+    }
+
+    pub async fn add_contact_sensor(&mut self, dev: &api::Device) -> ApiResult<()> {
+        let name = &dev.friendly_name;
+        let display_name = self.config.names.apply(name);
+
+        let link_device = RType::Device.deterministic(&dev.ieee_address);
+        let link_contact = RType::Contact.deterministic(&dev.ieee_address);
+        let link_tamper = dev
+            .expose_tamper()
+            .then(|| RType::Tamper.deterministic(&dev.ieee_address));
+        let link_zbc = RType::ZigbeeConnectivity.deterministic(&dev.ieee_address);
+
+        let mac_address = dev.ieee_address.to_mac_string();
+        let ieee = dev.ieee_address.clone();
+        let options = dev.options().to_vec();
+
+        let services: Vec<ResourceLink> = [Some(link_contact), link_tamper, Some(link_zbc)]
+            .into_iter()
+            .flatten()
+            .collect();
+
+        let dev = hue::api::Device {
+            product_data: DeviceProductData::guess_from_device(dev),
+            metadata: Metadata::new(DeviceArchetype::UnknownArchetype, &display_name),
+            services: services.clone(),
+        };
+
+        self.map.insert(name.to_string(), link_device.rid);
+        self.rmap.insert(link_device.rid, name.to_string());
+        self.connectivity.insert(ieee.clone(), link_zbc);
+
+        let mut res = self.state.lock().await;
+
+        let zbc = ZigbeeConnectivity {
+            owner: link_device,
+            mac_address,
+            status: self.initial_connectivity(&res, &ieee, &link_zbc),
+            channel: Some(json!({
+                "status": "set",
+                "value": "channel_25",
+            })),
+            extended_pan_id: String::from("0123456789abcdef"),
+        };
+        res.add(&link_device, Resource::Device(dev))?;
+        res.apply_generated_name(&link_device, name, &display_name)?;
+        Self::add_software_update(&mut res, &link_device)?;
+        if res.get::<Contact>(&link_contact).is_err() {
+            res.add(&link_contact, Resource::Contact(Contact::new(link_device)))?;
+        }
+        if let Some(link_tamper) = link_tamper {
+            Self::add_tamper(&mut res, &link_device, &link_tamper)?;
+        }
+        res.add(&link_zbc, Resource::ZigbeeConnectivity(zbc))?;
+        Self::refresh_stored(&mut res, &link_device, &services, None)?;
+        res.set_device_options(&link_device, options);
+        drop(res);
+
+        Ok(())
+    }
+
+    pub async fn add_coordinator(&mut self, info: &api::BridgeInfo) -> ApiResult<()> {
+        let coord = &info.coordinator;
+
+        let link_device = RType::Device.deterministic(&coord.ieee_address);
+        let link_zbc = RType::ZigbeeConnectivity.deterministic(&coord.ieee_address);
+
+        let name = format!("Zigbee2MQTT coordinator ({})", self.name);
+
+        let dev = hue::api::Device {
+            product_data: DeviceProductData::zigbee2mqtt_coordinator(coord),
+            metadata: Metadata::new(DeviceArchetype::BridgeV2, &name),
+            services: vec![link_zbc],
+        };
+
+        let zbc = ZigbeeConnectivity {
+            owner: link_device,
+            mac_address: coord.ieee_address.to_mac_string(),
+            status: ZigbeeConnectivityStatus::Connected,
+            channel: Some(json!({
+                "status": "set",
+                "value": format!("channel_{}", info.network.channel),
+            })),
+            extended_pan_id: info
+                .network
+                .extended_pan_id
+                .as_str()
+                .map_or_else(|| info.network.extended_pan_id.to_string(), str::to_string)
+                .trim_start_matches("0x")
+                .to_string(),
+        };
+
+        let mut res = self.state.lock().await;
+        if res.get::<hue::api::Device>(&link_device).is_ok() {
+            /* firmware might have been upgraded since last time, so refresh product data */
+            res.update::<hue::api::Device>(&link_device.rid, |obj| {
+                obj.product_data = dev.product_data;
+            })?;
+        } else {
+            res.add(&link_device, Resource::Device(dev))?;
+        }
+        res.set_bridge_network(&zbc)?;
+        res.add(&link_zbc, Resource::ZigbeeConnectivity(zbc))?;
+        drop(res);
+
+        Ok(())
+    }
+
+    /* Rooms are keyed by server and group name, so identically named groups
+     * on different servers are different rooms. Rooms from before that was
+     * the case are keyed by group name alone, and keep their id, for
+     * whichever server announces them first. */
+    fn room_link(&self, res: &Resources, topic: &str) -> ResourceLink {
+        let legacy = RType::Room.deterministic(topic);
+        let adoptable = res.get::<Room>(&legacy).is_ok()
+            && res
+                .aux_get(&legacy)
+                .ok()
+                .and_then(|aux| aux.server.as_deref())
+                .map_or(true, |server| server == self.name);
+
+        if adoptable {
+            legacy
+        } else {
+            RType::Room.deterministic((self.name.as_str(), topic))
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub async fn add_group(&mut self, grp: &crate::z2m::api::Group) -> ApiResult<()> {
+        let room_name;
+
+        if let Some(ref prefix) = self.server.group_prefix {
+            if let Some(name) = grp.friendly_name.strip_prefix(prefix) {
+                room_name = name;
+            } else {
+                log::debug!(
+                    server:% = self.name;
+                    "[{}] Ignoring room outside our prefix: {}",
+                    self.name,
+                    grp.friendly_name
+                );
+                return Ok(());
+            }
+        } else {
+            room_name = &grp.friendly_name;
+        }
+
+        if let Some(id) = room_name.strip_prefix(ZONE_GROUP_PREFIX) {
+            self.add_zone_group(grp, id);
+            return Ok(());
+        }
+
+        let children: Vec<ResourceLink> = grp
+            .members
+            .iter()
+            .map(|f| RType::Device.deterministic(&f.ieee_address))
+            .collect();
+
+        let topic = grp.friendly_name.to_string();
+
+        /* members not announced (yet) are assumed to be lights */
+        let capability = children
+            .iter()
+            .map(|dev| {
+                self.capabilities
+                    .get(&dev.rid)
+                    .copied()
+                    .unwrap_or(Capability::Light)
+            })
+            .max()
+            .unwrap_or(Capability::Light);
+
+        if capability == Capability::Unsupported {
+            log::info!(
+                server:% = self.name, topic:% = topic;
+                "[{}] Skipping group [{topic}]: none of its {} members can be switched on or off",
+                self.name,
+                children.len()
+            );
+            return Ok(());
+        }
+
+        let mut res = self.state.lock().await;
+
+        let link_room = self.room_link(&res, &topic);
+        let link_glight = RType::GroupedLight.deterministic((link_room.rid, grp.id));
+
+        if capability == Capability::OnOff {
+            self.onoff_groups.insert(link_glight.rid);
+        } else {
+            self.onoff_groups.remove(&link_glight.rid);
+        }
+
+        /* if room membership changed, learned scene actions must be checked
+         * against the lights that are in the room now */
+        let room_lights: Option<HashSet<Uuid>> = res
+            .get::<Room>(&link_room)
+            .ok()
+            .filter(|room| room.children != children)
+            .map(|_| {
+                children
+                    .iter()
+                    .filter_map(|rl| res.get::<Device>(rl).ok())
+                    .filter_map(Device::light_service)
+                    .map(|rl| rl.rid)
+                    .collect()
+            });
+
+        let mut scenes_new = HashSet::new();
+
+        for scn in &grp.scenes {
+            let link_scene = RType::Scene.deterministic((link_room.rid, scn.id));
+
+            /* restore previously learned actions, if any */
+            let aux = res.aux_get(&link_scene).cloned().unwrap_or_default();
+            let mut learned = aux.actions.clone();
+            let mut relearn = aux.relearn;
+
+            if let (Some(actions), Some(lights)) = (&mut learned, &room_lights) {
+                if prune_scene_actions(actions, lights) {
+                    log::info!(
+                        server:% = self.name;
+                        "[{}] Room membership of {link_scene:?} ({}) changed, will re-learn on recall",
+                        self.name,
+                        scn.name
+                    );
+                    relearn = true;
+                }
+            }
+
+            let scene = Scene {
+                actions: learned.clone().unwrap_or_default(),
+                auto_dynamic: false,
+                group: link_room,
+                metadata: SceneMetadata {
+                    /* keep appdata set by clients across announcements */
+                    appdata: res
+                        .get::<Scene>(&link_scene)
+                        .ok()
+                        .and_then(|old| old.metadata.appdata.clone()),
+                    image: guess_scene_icon(&scn.name, &self.config.scene_icons),
+                    name: scn.name.to_string(),
+                },
+                palette: json!({
+                    "color": [],
+                    "dimming": [],
+                    "color_temperature": [],
+                    "effects": [],
+                }),
+                speed: 0.5,
+                status: Some(SceneStatus::Inactive),
+            };
+
+            res.aux_set(
+                &link_scene,
+                aux.with_topic(&topic)
+                    .with_index(scn.id)
+                    .with_actions(learned)
+                    .with_relearn(relearn),
+            );
+
+            scenes_new.insert(link_scene.rid);
+            res.add(&link_scene, Resource::Scene(scene))?;
+        }
+
+        let room_is_new = res.get::<Room>(&link_room).is_err();
+
+        if let Ok(room) = res.get::<Room>(&link_room) {
+            log::info!(
+                server:% = self.name;
+                "[{}] {link_room:?} ({}) known, updating..",
+                self.name,
+                room.metadata.name
+            );
+
+            let scenes_old: HashSet<Uuid> =
+                HashSet::from_iter(res.get_scenes_for_room(&link_room.rid));
+
+            log::trace!(server:% = self.name; "[{}] old scenes: {scenes_old:?}", self.name);
+            log::trace!(server:% = self.name; "[{}] new scenes: {scenes_new:?}", self.name);
+            let gone = scenes_old.difference(&scenes_new);
+            log::trace!(server:% = self.name; "[{}]   deleted: {gone:?}", self.name);
+            for uuid in gone {
+                log::debug!(
+                    server:% = self.name;
+                    "[{}] Deleting orphaned {uuid:?} in {link_room:?}",
+                    self.name
+                );
+                let _ = res.delete(&RType::Scene.link_to(*uuid));
+            }
+        } else {
+            log::debug!(
+                server:% = self.name;
+                "[{}] {link_room:?} ({}) is new, adding..",
+                self.name,
+                room_name
+            );
+        }
+
+        let archetype = guess_room_archetype(room_name).unwrap_or(RoomArchetype::Home);
+        let mut metadata = RoomMetadata::new(archetype, &self.config.names.apply(room_name));
+        /* settings for this server are applied last, to take precedence */
+        let room_confs = [self.config.rooms.get(&topic), self.server.rooms.get(&topic)];
+        for room_conf in room_confs.into_iter().flatten() {
+            if let Some(name) = &room_conf.name {
+                metadata.name = name.to_string();
+            }
+            if let Some(icon) = &room_conf.icon {
+                metadata.archetype = *icon;
+            }
+        }
+
+        let display_name = metadata.name.clone();
+        let room = Room {
+            children,
+            metadata,
+            services: vec![link_glight],
+        };
+
+        self.map.insert(topic.clone(), link_glight.rid);
+        self.rmap.insert(link_glight.rid, topic.clone());
+        self.rmap.insert(link_room.rid, topic.clone());
+
+        if room_is_new {
+            res.add(&link_room, Resource::Room(room))?;
+        } else {
+            /* keep the metadata, which might have been changed through the api */
+            res.update::<Room>(&link_room.rid, |obj| {
+                obj.children = room.children;
+                obj.services = room.services;
+            })?;
+        }
+        let aux = res.aux_get(&link_room).cloned().unwrap_or_default();
+        res.aux_set(&link_room, aux.with_topic(&topic).with_server(&self.name));
+        res.apply_generated_name(&link_room, room_name, &display_name)?;
+
+        let glight = GroupedLight::new(link_room);
+
+        res.add(&link_glight, Resource::GroupedLight(glight))?;
+
+        if room_is_new && self.config.default_scenes.enabled {
+            self.add_default_scenes(&mut res, grp, link_room)?;
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    /* the scenes of rooms whose group is no longer announced (e.g. because
+     * it was removed in the z2m frontend) are gone as well */
+    async fn remove_vanished_scenes(&self, groups: &[api::Group]) {
+        let mut res = self.state.lock().await;
+
+        let announced: HashSet<Uuid> = groups
+            .iter()
+            .map(|grp| self.room_link(&res, &grp.friendly_name).rid)
+            .collect();
+        let gone: Vec<Uuid> = self
+            .rmap
+            .keys()
+            .filter(|rid| !announced.contains(rid))
+            .filter(|rid| res.get::<Room>(&RType::Room.link_to(**rid)).is_ok())
+            .flat_map(|rid| res.get_scenes_for_room(rid))
+            .collect();
+
+        for uuid in gone {
+            log::debug!(
+                server:% = self.name;
+                "[{}] Deleting {uuid:?}, since its group is gone",
+                self.name
+            );
+            let _ = res.delete(&RType::Scene.link_to(uuid));
+        }
+    }
+
+    fn add_zone_group(&mut self, grp: &api::Group, id: &str) {
+        let Ok(zone) = Uuid::parse_str(id) else {
+            log::warn!(
+                server:% = self.name;
+                "[{}] Ignoring zone group with invalid id: {}",
+                self.name,
+                grp.friendly_name
+            );
+            return;
+        };
+
+        let link_zone = RType::Zone.link_to(zone);
+        let link_glight = RType::GroupedLight.deterministic(link_zone.rid);
+        let topic = grp.friendly_name.clone();
+
+        let lights = grp
+            .members
+            .iter()
+            .map(|f| RType::Light.deterministic(&f.ieee_address).rid)
+            .collect();
+
+        log::debug!(
+            server:% = self.name;
+            "[{}] Found z2m group [{topic}] for {link_zone:?}",
+            self.name
+        );
+
+        self.map.insert(topic.clone(), link_glight.rid);
+        self.rmap.insert(link_zone.rid, topic);
+        self.zone_groups.insert(zone, lights);
+    }
+
+    fn add_default_scenes(
+        &self,
+        res: &mut Resources,
+        grp: &api::Group,
+        link_room: ResourceLink,
+    ) -> ApiResult<()> {
+        let conf = &self.config.default_scenes;
+        let topic = &grp.friendly_name;
+
+        let lights: Vec<ResourceLink> = grp
+            .members
+            .iter()
+            .map(|f| RType::Device.deterministic(&f.ieee_address))
+            .filter_map(|rl| res.get::<Device>(&rl).ok())
+            .filter_map(Device::light_service)
+            .copied()
+            .collect();
+
+        for preset in ScenePreset::ALL {
+            let name = conf.name(preset);
+
+            /* Do not duplicate scenes already present in zigbee2mqtt */
+            if grp
+                .scenes
+                .iter()
+                .any(|scn| scn.name.eq_ignore_ascii_case(name))
+            {
+                continue;
+            }
+
+            let sid = res.get_next_scene_id(&link_room)?;
+            let link_scene = RType::Scene.deterministic((link_room.rid, sid));
+
+            log::info!(
+                server:% = self.name;
+                "[{}] Creating default scene {link_scene:?} ({name}) in {link_room:?}",
+                self.name
+            );
+
+            let action = preset.action();
+
+            let scene = Scene {
+                actions: lights
+                    .iter()
+                    .map(|target| SceneActionElement {
+                        action: action.clone(),
+                        target: *target,
+                    })
+                    .collect(),
+                auto_dynamic: false,
+                group: link_room,
+                metadata: SceneMetadata {
+                    appdata: None,
+                    image: Some(RType::PublicImage.link_to(preset.icon())),
+                    name: name.to_string(),
+                },
+                palette: json!({
+                    "color": [],
+                    "dimming": [],
+                    "color_temperature": [],
+                    "effects": [],
+                }),
+                speed: 0.5,
+                status: Some(SceneStatus::Inactive),
+            };
+
+            let upd = DeviceUpdate::new()
+                .with_state(Some(true))
+                .with_brightness(Some(Brightness::from_percent(preset.brightness()).z2m()))
+                .with_color_temp(preset.mirek())
+                .with_color_xy(preset.color_xy());
+
+            res.aux_set(
+                &link_scene,
+                AuxData::new().with_topic(topic).with_index(sid),
+            );
+            res.add(&link_scene, Resource::Scene(scene))?;
+            res.z2m_request(ClientRequest::scene_add(
+                link_room,
+                sid,
+                name.to_string(),
+                upd,
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn handle_update(&mut self, rid: &Uuid, payload: &Value) -> ApiResult<()> {
+        let upd = DeviceUpdate::deserialize(payload)?;
+
+        let obj = self.state.lock().await.get_resource_by_id(rid)?.obj;
+        match obj {
+            Resource::Light(_) => {
+                if let Err(e) = self.handle_update_light(rid, &upd).await {
+                    log::error!(
+                        server:% = self.name, uuid:% = rid;
+                        "[{}] FAIL: {e:?} in {upd:?}",
+                        self.name
+                    );
+                }
+                if let Err(e) = self.handle_update_ota(rid, &upd).await {
+                    log::error!(
+                        server:% = self.name, uuid:% = rid;
+                        "[{}] FAIL: {e:?} in {upd:?}",
+                        self.name
+                    );
+                }
+            }
+            Resource::GroupedLight(_) => {
+                if let Err(e) = self.handle_update_grouped_light(rid, &upd).await {
+                    log::error!(
+                        server:% = self.name, uuid:% = rid;
+                        "[{}] FAIL: {e:?} in {upd:?}",
+                        self.name
+                    );
+                }
+            }
+            Resource::Device(_) => {
+                if let Err(e) = self.handle_update_switch(rid, &upd).await {
+                    log::error!(
+                        server:% = self.name, uuid:% = rid;
+                        "[{}] FAIL: {e:?} in {upd:?}",
+                        self.name
+                    );
+                }
+                if let Err(e) = self.handle_update_motion(rid, &upd).await {
+                    log::error!(
+                        server:% = self.name, uuid:% = rid;
+                        "[{}] FAIL: {e:?} in {upd:?}",
+                        self.name
+                    );
+                }
+                if let Err(e) = self.handle_update_contact(rid, &upd).await {
+                    log::error!(
+                        server:% = self.name, uuid:% = rid;
+                        "[{}] FAIL: {e:?} in {upd:?}",
+                        self.name
+                    );
+                }
+                if let Err(e) = self.handle_update_ota(rid, &upd).await {
+                    log::error!(
+                        server:% = self.name, uuid:% = rid;
+                        "[{}] FAIL: {e:?} in {upd:?}",
+                        self.name
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    async fn handle_update_light(&mut self, uuid: &Uuid, devupd: &DeviceUpdate) -> ApiResult<()> {
+        let upd = LightUpdate::new()
+            .with_on(devupd.state.map(Into::into))
+            .with_brightness(devupd.brightness.map(|b| Brightness::from_z2m(b).percent()))
+            .with_color_temperature(devupd.color_temp)
+            .with_color_xy(devupd.color.and_then(|col| col.xy))
+            .with_gradient(devupd.gradient_xy().map(|points| LightGradientUpdate {
+                points: points.into_iter().map(LightGradientPoint::new).collect(),
+            }));
+
+        let mut res = self.state.lock().await;
+        res.update::<Light>(uuid, move |light| *light += upd)?;
+
+        if self.learn.is_empty() {
+            return Ok(());
+        }
+
+        let light = res.get::<Light>(&RType::Light.link_to(*uuid))?;
+
+        let dimming = light.as_dimming_opt();
+        let on = light.on;
+        drop(res);
+
+        /* learn bookkeeping does not need the resource lock */
+        for learn in self.learn.values_mut() {
+            if learn.missing.remove(uuid) {
+                let mut color_temperature = None;
+                let mut color = None;
+                if let Some(DeviceColor { xy: Some(xy), .. }) = devupd.color {
+                    color = Some(ColorUpdate { xy });
+                } else if let Some(mirek) = devupd.color_temp {
+                    color_temperature = Some(ColorTemperatureUpdate { mirek });
+                }
+
+                learn.known.insert(
+                    *uuid,
+                    SceneAction {
+                        color,
+                        color_temperature,
+                        dimming: dimming.clone(),
+                        on: Some(on),
+                    },
+                );
+            }
+            log::info!(server:% = self.name; "[{}] Learn: {learn:?}", self.name);
+        }
+
+        let done: Vec<Uuid> = self
+            .learn
+            .iter()
+            .filter(|(_, learn)| learn.missing.is_empty())
+            .map(|(uuid, _)| *uuid)
+            .collect();
+
+        if done.is_empty() {
+            return Ok(());
+        }
+
+        let mut res = self.state.lock().await;
+        for uuid in &done {
+            let lscene = self.learn.remove(uuid).unwrap();
+            log::info!(server:% = self.name; "[{}] Learned all lights {uuid}", self.name);
+            let actions: Vec<SceneActionElement> = lscene
+                .known
+                .into_iter()
+                .map(|(uuid, action)| SceneActionElement {
+                    action,
+                    target: RType::Light.link_to(uuid),
+                })
+                .collect();
+
+            /* keep a copy in aux data, so it survives the scene being rebuilt */
+            let link_scene = RType::Scene.link_to(*uuid);
+            let aux = res
+                .aux_get(&link_scene)
+                .cloned()
+                .unwrap_or_default()
+                .with_actions(Some(actions.clone()))
+                .with_relearn(false);
+            res.aux_set(&link_scene, aux);
+
+            Source::SceneLearn.sync_scope(|| {
+                res.update(uuid, |scene: &mut Scene| {
+                    scene.actions = actions;
+                })
+            })?;
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    async fn handle_update_grouped_light(&self, uuid: &Uuid, upd: &DeviceUpdate) -> ApiResult<()> {
+        let onoff = self.onoff_groups.contains(uuid);
+
+        let mut res = self.state.lock().await;
+        res.update::<GroupedLight>(uuid, |glight| {
+            if let Some(state) = &upd.state {
+                glight.on = Some((*state).into());
+            }
+
+            /* groups of plugs and relays have no dimming or color */
+            if onoff {
+                return;
+            }
+
+            if let Some(b) = upd.brightness {
+                glight.dimming = Some(DimmingUpdate {
+                    brightness: Brightness::from_z2m(b).percent(),
+                });
+            }
+
+            /* like lights, a group is in either color or color temperature mode */
+            if let Some(xy) = upd.color.and_then(|col| col.xy) {
+                glight.color = Some(ColorUpdate::new(xy));
+                glight.color_temperature = None;
+            } else if let Some(mirek) = upd.color_temp {
+                glight.color_temperature = Some(ColorTemperatureUpdate::new(mirek));
+                glight.color = None;
+            }
+        })
+    }
+
+    async fn handle_update_rotary(
+        &mut self,
+        uuid: &Uuid,
+        direction: RotaryDirection,
+        steps: u32,
+    ) -> ApiResult<()> {
+        let now = Utc::now();
+
+        let (action, duration) = match self.rotary_last.insert(*uuid, (now, direction)) {
+            Some((last, dir)) if dir == direction && now - last < ROTARY_REPEAT_WINDOW => {
+                let elapsed = (now - last).num_milliseconds();
+                (RotaryAction::Repeat, u32::try_from(elapsed).unwrap_or(0))
+            }
+            _ => (RotaryAction::Start, ROTARY_DEFAULT_DURATION),
+        };
+
+        let rotation = RotaryRotation {
+            direction,
+            steps,
+            duration,
+        };
+
+        let mut res = self.state.lock().await;
+
+        let dev = res.get::<Device>(&RType::Device.link_to(*uuid))?;
+        let Some(link_rotary) = dev.relative_rotary_service().copied() else {
+            return Ok(());
+        };
+
+        res.update::<RelativeRotary>(&link_rotary.rid, |rotary| {
+            rotary.relative_rotary = RelativeRotaryData {
+                last_event: Some(RotaryEvent { action, rotation }),
+                rotary_report: Some(RotaryReport::new(action, rotation)),
+            };
+        })
+    }
+
+    #[allow(clippy::too_many_lines)]
+    async fn handle_update_switch(&mut self, uuid: &Uuid, upd: &DeviceUpdate) -> ApiResult<()> {
+        let Some(action) = &upd.action else {
+            return Ok(());
+        };
+
+        if let Some((direction, steps)) = parse_rotary_action(action) {
+            return self.handle_update_rotary(uuid, direction, steps).await;
+        }
+
+        let Some((control_id, event)) = parse_button_action(action) else {
+            log::debug!(
+                server:% = self.name;
+                "[{}] Ignoring unknown switch action {action:?}",
+                self.name
+            );
+            return Ok(());
+        };
+
+        let mut res = self.state.lock().await;
+
+        let dev = res.get::<Device>(&RType::Device.link_to(*uuid))?;
+        let button = dev
+            .services
+            .iter()
+            .filter(|rl| rl.rtype == RType::Button)
+            .find(|rl| {
+                res.get::<Button>(rl)
+                    .is_ok_and(|btn| btn.metadata.control_id == control_id)
+            })
+            .copied();
+
+        if let Some(button) = button {
+            res.update::<Button>(&button.rid, |btn| {
+                btn.button.button_report = Some(ButtonReport {
+                    updated: Utc::now(),
+                    event: event.to_string(),
+                });
+            })?;
+        }
+
+        /* find enabled behavior linking this switch to a room */
+        let behavior = res
+            .get_resources_by_type(RType::BehaviorInstance)
+            .into_iter()
+            .find_map(|rr| {
+                let Resource::BehaviorInstance(bi) = rr.obj else {
+                    return None;
+                };
+                let conf = bi.switch_configuration().filter(|_| bi.enabled)?;
+                if conf.device.rid != *uuid {
+                    return None;
+                }
+                Some((rr.id, *conf.room()?))
+            });
+
+        let Some((behavior_id, link_room)) = behavior else {
+            return Ok(());
+        };
+
+        let Some(link_glight) = res
+            .get::<Room>(&link_room)?
+            .grouped_light_service()
+            .copied()
+        else {
+            return Ok(());
+        };
+
+        match (control_id, event) {
+            (1, "short_release") => {
+                let is_on = res
+                    .get::<GroupedLight>(&link_glight)?
+                    .on
+                    .is_some_and(|on| on.on);
+
+                let mut scenes = res.get_scenes_for_room(&link_room.rid);
+                scenes.sort_by_key(|id| {
+                    res.aux_get(&RType::Scene.link_to(*id))
+                        .ok()
+                        .and_then(|aux| aux.index)
+                });
+
+                if scenes.is_empty() {
+                    let upd = DeviceUpdate::new().with_state(Some(true));
+                    res.z2m_request(ClientRequest::group_update(link_glight, upd))?;
+                } else {
+                    /* start from the first scene when off, otherwise cycle to the next */
+                    let pos = match self.cycle.get(&behavior_id) {
+                        Some(pos) if is_on => (pos + 1) % scenes.len(),
+                        _ => 0,
+                    };
+                    self.cycle.insert(behavior_id, pos);
+
+                    let link_scene = RType::Scene.link_to(scenes[pos]);
+                    log::info!(
+                        server:% = self.name;
+                        "[{}] Switch recalls scene {link_scene:?}",
+                        self.name
+                    );
+                    res.scene_set_active(&link_scene)?;
+                    res.scene_recall(&link_scene)?;
+                }
+            }
+            (2 | 3, "initial_press" | "repeat") => {
+                let step = if control_id == 2 { 30.0 } else { -30.0 };
+                let upd = DeviceUpdate::new().with_brightness_step(Some(step));
+                res.z2m_request(ClientRequest::group_update(link_glight, upd))?;
+            }
+            (4, "short_release") => {
+                self.cycle.remove(&behavior_id);
+                let upd = DeviceUpdate::new().with_state(Some(false));
+                res.z2m_request(ClientRequest::group_update(link_glight, upd))?;
+            }
+            _ => {}
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    async fn handle_update_motion(&self, uuid: &Uuid, upd: &DeviceUpdate) -> ApiResult<()> {
+        if upd.occupancy.is_none() && upd.motion_sensitivity.is_none() {
+            return Ok(());
+        }
+
+        let levels = self.motion_levels.get(uuid).map_or(&[][..], Vec::as_slice);
+        let sensitivity = upd
+            .motion_sensitivity
+            .as_deref()
+            .and_then(|level| motion_sensitivity(levels, Some(level)));
+
+        let mut res = self.state.lock().await;
+
+        let dev = res.get::<Device>(&RType::Device.link_to(*uuid))?;
+        let Some(link_motion) = dev.motion_service().copied() else {
+            return Ok(());
+        };
+
+        /* a disabled sensor stays connected, but does not report motion */
+        let enabled = res.get::<Motion>(&link_motion)?.enabled;
+        if !enabled && sensitivity.is_none() {
+            return Ok(());
+        }
+
+        res.update::<Motion>(&link_motion.rid, |motion| {
+            if let (true, Some(occupancy)) = (enabled, upd.occupancy) {
+                motion.motion.motion = occupancy;
+                motion.motion.motion_valid = true;
+                motion.motion.motion_report = Some(MotionReport {
+                    changed: Utc::now(),
+                    motion: occupancy,
+                });
+            }
+
+            if sensitivity.is_some() {
+                motion.sensitivity = sensitivity;
+            }
+        })
+    }
+
+    async fn handle_update_contact(&self, uuid: &Uuid, upd: &DeviceUpdate) -> ApiResult<()> {
+        if upd.contact.is_none() && upd.tamper.is_none() {
+            return Ok(());
+        }
+
+        let mut res = self.state.lock().await;
+
+        let dev = res.get::<Device>(&RType::Device.link_to(*uuid))?;
+        let link_contact = dev.contact_service().copied();
+        let link_tamper = dev.tamper_service().copied();
+
+        if let (Some(link_contact), Some(contact)) = (link_contact, upd.contact) {
+            /* a disabled sensor stays connected, but does not report contact */
+            if res.get::<Contact>(&link_contact)?.enabled {
+                res.update::<Contact>(&link_contact.rid, |obj| {
+                    obj.contact_report = Some(ContactReport::new(contact.into()));
+                })?;
+            }
+        }
+
+        if let (Some(link_tamper), Some(tamper)) = (link_tamper, upd.tamper) {
+            /* z2m does not tell where the tampering happened, so it is
+             * reported as the battery door, like on hue sensors */
+            res.update::<Tamper>(&link_tamper.rid, |obj| {
+                obj.tamper_reports =
+                    vec![TamperReport::new(TamperSource::BatteryDoor, tamper.into())];
+            })?;
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    async fn handle_update_ota(&self, uuid: &Uuid, upd: &DeviceUpdate) -> ApiResult<()> {
+        let Some(ota) = &upd.update else {
+            return Ok(());
+        };
+        let Some(state) = ota.state.and_then(OtaState::software_update_state) else {
+            return Ok(());
+        };
+
+        let installing = state == SoftwareUpdateState::Installing;
+        let progress = ota.progress.filter(|_| installing);
+        let remaining = ota.remaining.filter(|_| installing);
+
+        let mut res = self.state.lock().await;
+
+        /* lights report the update status of their device */
+        let link_device = match res.get_resource_by_id(uuid)?.obj {
+            Resource::Light(light) => light.owner,
+            _ => RType::Device.link_to(*uuid),
+        };
+
+        let link_swu = Self::add_software_update(&mut res, &link_device)?;
+
+        let swu = res.get::<DeviceSoftwareUpdate>(&link_swu)?;
+        if (swu.state, swu.progress, swu.remaining) == (state, progress, remaining) {
+            return Ok(());
+        }
+
+        if swu.state != state {
+            let name = &res.get::<Device>(&link_device)?.metadata.name;
+            log::info!(
+                server:% = self.name, uuid:% = link_device.rid;
+                "[{}] Software update of [{name}]: {state:?}",
+                self.name
+            );
+        }
+
+        res.update::<DeviceSoftwareUpdate>(&link_swu.rid, |swu| {
+            swu.state = state;
+            swu.progress = progress;
+            swu.remaining = remaining;
+        })
+    }
+
+    async fn add_device(&mut self, dev: &api::Device) -> ApiResult<()> {
+        let link_device = RType::Device.deterministic(&dev.ieee_address);
+        self.capabilities
+            .insert(link_device.rid, Capability::of(dev));
+        self.reporting.insert(link_device.rid, Reporting::of(dev));
+
+        if let Some(exp) = dev.expose_light() {
+            log::info!(
+                server:% = self.name, topic:% = dev.friendly_name;
+                "[{}] Adding light {:?}: [{}] ({})",
+                self.name,
+                dev.ieee_address,
+                dev.friendly_name,
+                dev.model_id.as_deref().unwrap_or("<unknown model>")
+            );
+            self.add_light(dev, exp).await?;
+        } else if dev.expose_action() {
+            log::info!(
+                server:% = self.name, topic:% = dev.friendly_name;
+                "[{}] Adding switch {:?}: [{}] ({})",
+                self.name,
+                dev.ieee_address,
+                dev.friendly_name,
+                dev.model_id.as_deref().unwrap_or("<unknown model>")
+            );
+            self.add_switch(dev).await?;
+        } else if dev.expose_occupancy() {
+            log::info!(
+                server:% = self.name, topic:% = dev.friendly_name;
+                "[{}] Adding motion sensor {:?}: [{}] ({})",
+                self.name,
+                dev.ieee_address,
+                dev.friendly_name,
+                dev.model_id.as_deref().unwrap_or("<unknown model>")
+            );
+            self.add_motion_sensor(dev).await?;
+        } else if dev.expose_contact() {
+            log::info!(
+                server:% = self.name, topic:% = dev.friendly_name;
+                "[{}] Adding contact sensor {:?}: [{}] ({})",
+                self.name,
+                dev.ieee_address,
+                dev.friendly_name,
+                dev.model_id.as_deref().unwrap_or("<unknown model>")
+            );
+            self.add_contact_sensor(dev).await?;
+        } else {
+            log::debug!(
+                server:% = self.name;
+                "[{}] Ignoring unsupported device {}",
+                self.name,
+                dev.friendly_name
+            );
+            self.ignore.insert(dev.friendly_name.to_string());
+        }
+
+        if self.config.reporting.devices.contains(&dev.friendly_name)
+            && self.reporting_done.insert(link_device.rid)
+        {
+            self.configure_reporting_send(&link_device);
+        }
+
+        Ok(())
+    }
+
+    /* devices joining or leaving while running, so they show up (or go away)
+     * without waiting for the next device list */
+    async fn handle_device_event(&mut self, evt: &api::BridgeEvent) -> ApiResult<()> {
+        match evt.device_event() {
+            Some(DeviceEvent::DeviceJoined(dev)) => {
+                log::info!(
+                    server:% = self.name, topic:% = dev.friendly_name;
+                    "[{}] Device {:?} joined: [{}]",
+                    self.name,
+                    dev.ieee_address,
+                    dev.friendly_name
+                );
+            }
+            Some(DeviceEvent::DeviceInterview(interview)) => {
+                if let Some(dev) = interview.device() {
+                    self.ignore.remove(&dev.friendly_name);
+                    self.add_device(&dev).await?;
+                    self.update_product_data(&dev).await?;
+                } else {
+                    log::debug!(
+                        server:% = self.name, topic:% = interview.friendly_name;
+                        "[{}] Interview of [{}]: {:?}",
+                        self.name,
+                        interview.friendly_name,
+                        interview.status
+                    );
+                }
+            }
+            Some(DeviceEvent::DeviceLeave(dev)) => self.remove_device(&dev).await?,
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    /* a device listed before its interview finished only has placeholder
+     * product data, which the interview result can now fill in */
+    async fn update_product_data(&self, dev: &api::Device) -> ApiResult<()> {
+        let link_device = RType::Device.deterministic(&dev.ieee_address);
+
+        let mut res = self.state.lock().await;
+        let Ok(mut product_data) = res
+            .get::<hue::api::Device>(&link_device)
+            .map(|obj| obj.product_data.clone())
+        else {
+            return Ok(());
+        };
+
+        if product_data.fill_unknown(DeviceProductData::guess_from_device(dev)) {
+            res.update::<hue::api::Device>(&link_device.rid, |obj| {
+                obj.product_data = product_data;
+            })?;
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    async fn remove_device(&mut self, dev: &DeviceEventData) -> ApiResult<()> {
+        let link_device = RType::Device.deterministic(&dev.ieee_address);
+        let name = &dev.friendly_name;
+
+        log::info!(
+            server:% = self.name, topic:% = name;
+            "[{}] Device {:?} left: [{name}], removing",
+            self.name,
+            dev.ieee_address
+        );
+
+        self.capabilities.remove(&link_device.rid);
+        self.reporting.remove(&link_device.rid);
+        self.reporting_done.remove(&link_device.rid);
+        self.connectivity.remove(&dev.ieee_address);
+        self.ignore.remove(name);
+        self.map.remove(name);
+        self.rmap.retain(|_, topic| topic != name);
+
+        let mut res = self.state.lock().await;
+        let Ok(device) = res.get::<hue::api::Device>(&link_device) else {
+            return Ok(());
+        };
+
+        for service in device.services.clone() {
+            if res.get_resource_by_id(&service.rid).is_ok() {
+                res.delete(&service)?;
+            }
+        }
+
+        let link_swu = RType::DeviceSoftwareUpdate.deterministic(link_device.rid);
+        if res.get_resource_by_id(&link_swu.rid).is_ok() {
+            res.delete(&link_swu)?;
+        }
+        res.delete(&link_device)?;
+        drop(res);
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_lines)]
+    async fn handle_bridge_message(&mut self, msg: Message) -> ApiResult<()> {
+        #[allow(unused_variables)]
+        match msg {
+            Message::BridgeInfo(ref obj) => {
+                log::info!(
+                    server:% = self.name;
+                    "[{}] Found coordinator {:?} ({}, firmware {})",
+                    self.name,
+                    obj.coordinator.ieee_address,
+                    obj.coordinator.coordinator_type,
+                    obj.coordinator
+                        .firmware_revision()
+                        .as_deref()
+                        .unwrap_or("<unknown>")
+                );
+                self.transitions = obj.config.transitions();
+                self.default_transition = obj.config.device_options.transition.unwrap_or_default();
+                self.availability = obj.config.availability_config();
+                self.seed_connectivity().await?;
+                self.add_coordinator(obj).await?;
+            }
+            Message::BridgeLogging(ref obj) => self.handle_bridge_logging(obj).await,
+            Message::BridgeExtensions(ref obj) => { /* println!("{obj:#?}"); */ }
+            Message::BridgeEvent(ref obj) => self.handle_device_event(obj).await?,
+            Message::BridgeDefinitions(ref obj) => { /* println!("{obj:#?}"); */ }
+            Message::BridgeState(ref obj) => self.sync_bridge_state(&obj.state),
+
+            /* failures are reported by transaction_done */
+            Message::TouchlinkScan(ref obj) => {
+                let found = obj.data.as_ref().map(|scan| scan.found.clone());
+                if let (true, Some(found)) = (obj.is_ok(), found) {
+                    log::info!(
+                        server:% = self.name;
+                        "[{}] Touchlink scan found {} devices",
+                        self.name,
+                        found.len()
+                    );
+                    self.state
+                        .lock()
+                        .await
+                        .set_touchlink_results(&self.name, found);
+                }
+            }
+            Message::TouchlinkIdentify(ref obj) | Message::TouchlinkFactoryReset(ref obj) => {
+                if obj.is_ok() {
+                    log::info!(server:% = self.name; "[{}] Touchlink request completed", self.name);
+                }
+            }
+            Message::DeviceOptions(ref obj)
+            | Message::DeviceConfigureReporting(ref obj)
+            | Message::GroupAdd(ref obj)
+            | Message::GroupRemove(ref obj)
+            | Message::GroupMembersAdd(ref obj)
+            | Message::GroupMembersRemove(ref obj)
+            | Message::HealthCheck(ref obj) => {}
+
+            Message::BridgeDevices(ref obj) => {
+                for dev in obj {
+                    self.add_device(dev).await?;
+                }
+                self.sync_announced(true);
+            }
+
+            Message::BridgeGroups(ref obj) => {
+                /* println!("{obj:#?}"); */
+                self.zone_groups.clear();
+                for grp in obj {
+                    self.add_group(grp).await?;
+                }
+                self.remove_vanished_scenes(obj).await;
+                self.sync_announced(false);
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_availability(&self, topic: &str, payload: &Value) -> ApiResult<()> {
+        let Some(uuid) = self.map.get(topic) else {
+            return Ok(());
+        };
+
+        let online = AvailabilityUpdate::deserialize(payload)?.is_online();
+
+        let mut res = self.state.lock().await;
+
+        let link_device = match res.get_resource_by_id(uuid)?.obj {
+            Resource::Light(light) => light.owner,
+            Resource::Device(_) => RType::Device.link_to(*uuid),
+            _ => return Ok(()),
+        };
+
+        let Some(link_zbc) = res
+            .get::<hue::api::Device>(&link_device)?
+            .zigbee_connectivity_service()
+            .copied()
+        else {
+            return Ok(());
+        };
+
+        let status = if online {
+            ZigbeeConnectivityStatus::Connected
+        } else {
+            ZigbeeConnectivityStatus::ConnectivityIssue
+        };
+
+        if res.get::<ZigbeeConnectivity>(&link_zbc)?.status != status {
+            log::info!(
+                server:% = self.name, topic = topic;
+                "[{}] {topic} is now {status:?}",
+                self.name
+            );
+            res.update::<ZigbeeConnectivity>(&link_zbc.rid, |zbc| zbc.status = status)?;
+        }
+        drop(res);
+
+        Ok(())
+    }
+
+    async fn handle_device_message(&mut self, mut msg: RawMessage) -> ApiResult<()> {
+        if let Some(topic) = msg.topic.strip_suffix("/availability") {
+            if let Err(err) = self.handle_availability(topic, &msg.payload).await {
+                log::error!(
+                    server:% = self.name;
+                    "[{}] Cannot handle availability for {topic}: {err}",
+                    self.name
+                );
+            }
+            return Ok(());
+        }
+
+        if msg.topic.contains('/') {
+            return Ok(());
+        }
+
+        /* an answer to a reconciliation request only shows a divergence if
+         * no command was sent in the meantime */
+        let reconciling =
+            self.reconcile.remove(&msg.topic).is_some() && !self.pending.contains_key(&msg.topic);
+
+        /* the state confirming a command is the result of its api request */
+        let trace = self
+            .pending
+            .get(&msg.topic)
+            .filter(|cmd| !cmd.confirmed)
+            .and_then(|cmd| cmd.trace);
+        self.pending_confirm(&msg.topic);
+        self.echo_suppress(&msg.topic, &mut msg.payload);
+
+        let Some(ref val) = self.map.get(&msg.topic).copied() else {
+            if !self.ignore.contains(&msg.topic) {
+                log::warn!(
+                    server:% = self.name;
+                    "[{}] Notification on unknown topic {}",
+                    self.name,
+                    &msg.topic
+                );
+            }
+            return Ok(());
+        };
+
+        let before = if reconciling {
+            let lock = self.state.lock().await;
+            lock.get::<Light>(&RType::Light.link_to(*val)).ok().cloned()
+        } else {
+            None
+        };
+
+        let res = TraceId::scope_opt(trace, self.handle_update(val, &msg.payload)).await;
+        if let Err(ref err) = res {
+            log::error!(
+                "Cannot parse update: {err}\n{}",
+                serde_json::to_string_pretty(&msg.payload)?
+            );
+        } else if let Some(before) = before {
+            self.reconcile_done(&msg.topic, &before, val).await;
+        }
+
+        /* return Ok here, since we do not want to break the event loop */
+        Ok(())
+    }
+
+    /// Handle a message from z2m
+    pub async fn handle_message(&mut self, txt: &str) -> ApiResult<()> {
+        Source::Z2m
+            .scope(async {
+                self.read_message(txt).await?;
+
+                if self.sync == BridgeSync::Refresh {
+                    self.sync_refresh().await?;
+                }
+
+                Ok::<_, ApiError>(())
+            })
+            .await?;
+
+        self.drain_queue().await
+    }
+
+    async fn read_message(&mut self, txt: &str) -> ApiResult<()> {
+        let raw_msg: Result<RawMessage, _> = serde_json::from_str(txt);
+
+        let msg = raw_msg.map_err(|err| {
+            log::error!(
+                server:% = self.name;
+                "[{}] Invalid websocket message: {:#?} [{}..]",
+                self.name,
+                err,
+                &txt.chars().take(128).collect::<String>()
+            );
+            err
+        })?;
+
+        /* bridge messages are handled differently. everything else is a device message */
+        if !msg.topic.starts_with("bridge/") {
+            return self.handle_device_message(msg).await;
+        }
+
+        if msg.topic.starts_with("bridge/response/") {
+            if let Ok(resp) = BridgeResponse::deserialize(&msg.payload) {
+                self.transaction_done(&msg.topic, &resp).await;
+            }
+        }
+
+        match serde_json::from_str(txt) {
+            Ok(bridge_msg) => self.handle_bridge_message(bridge_msg).await,
+            Err(err) => {
+                match msg.topic.as_str() {
+                    topic @ ("bridge/devices" | "bridge/groups") => {
+                        log::error!(
+                            server:% = self.name;
+                            "[{}] Failed to parse critical z2m bridge message on [{}]:",
+                            self.name,
+                            topic
+                        );
+                        log::error!(
+                            server:% = self.name;
+                            "[{}] {}",
+                            self.name,
+                            serde_json::to_string(&msg.payload)?
+                        );
+                        Err(err)?
+                    }
+                    topic => {
+                        log::error!(
+                            server:% = self.name;
+                            "[{}] Failed to parse (non-critical) z2m bridge message on [{}]:",
+                            self.name,
+                            topic
+                        );
+                        log::error!("{}", serde_json::to_string(&msg.payload)?);
+
+                        /* Suppress this non-critical error, to avoid breaking the event loop */
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    fn learn_cleanup(&mut self) {
+        let now = Utc::now();
+        self.learn.retain(|uuid, lscene| {
+            let res = lscene.expire < now;
+            if !res {
+                log::warn!(
+                    server:% = self.name;
+                    "[{}] Failed to learn scene {uuid} before deadline",
+                    self.name
+                );
+            }
+            res
+        });
+    }
+
+    fn learn_scene_recall(&mut self, res: &Resources, lscene: &ResourceLink) -> ApiResult<()> {
+        log::info!(server:% = self.name; "[{}] Recall scene: {lscene:?}", self.name);
+        let scene: &Scene = res.get(lscene)?;
+        let relearn = res.aux_get(lscene).is_ok_and(|aux| aux.relearn);
+
+        if scene.actions.is_empty() || relearn {
+            let room: &Room = res.get(&scene.group)?;
+            let missing = self.scene_missing_lights(res, lscene)?;
+
+            /* lights without the scene stored will not change on recall, so
+             * there is no point in waiting for them */
+            let lights: Vec<Uuid> = room
+                .children
+                .iter()
+                .filter_map(|rl| res.get(rl).ok())
+                .filter_map(Device::light_service)
+                .filter(|rl| !missing.contains(rl))
+                .map(|rl| rl.rid)
+                .collect();
+
+            let learn = LearnScene {
+                expire: Utc::now() + Duration::seconds(5),
+                missing: HashSet::from_iter(lights),
+                known: HashMap::new(),
+            };
+
+            self.learn.insert(lscene.rid, learn);
+        }
+
+        Ok(())
+    }
+
+    /// Lights in the room of a scene, that z2m reports do not have the scene
+    /// stored on the device, and so will not change when it is recalled.
+    ///
+    /// Lights that z2m did not report any scenes for (for example, because
+    /// they belong to another z2m server) are assumed to have it.
+    pub fn scene_missing_lights(
+        &self,
+        res: &Resources,
+        lscene: &ResourceLink,
+    ) -> ApiResult<Vec<ResourceLink>> {
+        let scene: &Scene = res.get(lscene)?;
+        let Some(index) = res.aux_get(lscene)?.index else {
+            return Ok(vec![]);
+        };
+        let room: &Room = res.get(&scene.group)?;
+
+        let lights = room
+            .children
+            .iter()
+            .filter_map(|rl| res.get::<Device>(rl).ok())
+            .filter_map(Device::light_service)
+            .filter(|light| {
+                self.stored_scenes
+                    .get(&light.rid)
+                    .is_some_and(|ids| !ids.contains(&index))
+            })
+            .copied()
+            .collect();
+
+        Ok(lights)
+    }
+
+    /* Lights to turn off explicitly, when recalling a scene.
+     *
+     * z2m only touches the lights stored in its copy of the scene, and not
+     * all lights restore an "off" state from a scene, so (like a hue bridge)
+     * every light in the room that the scene has off, or does not include at
+     * all, is turned off after the recall. Nothing is done for scenes that
+     * are about to be learned, since their actions are not known yet. */
+    fn scene_off_lights(res: &Resources, lscene: &ResourceLink) -> ApiResult<Vec<ResourceLink>> {
+        let scene: &Scene = res.get(lscene)?;
+        let relearn = res.aux_get(lscene).is_ok_and(|aux| aux.relearn);
+
+        if scene.actions.is_empty() || relearn {
+            return Ok(vec![]);
+        }
+
+        let room: &Room = res.get(&scene.group)?;
+
+        let lights =
+            room.children
+                .iter()
+                .filter_map(|rl| res.get::<Device>(rl).ok())
+                .filter_map(Device::light_service)
+                .filter(|light| {
+                    !scene.actions.iter().any(|elem| {
+                        elem.target == **light && elem.action.on.map_or(true, |on| on.on)
+                    })
+                })
+                .copied()
+                .collect();
+
+        Ok(lights)
+    }
+
+    fn send_request(&mut self, topic: &str, payload: Z2mRequest) -> ApiResult<()> {
+        let Some(uuid) = self.map.get(topic) else {
+            log::trace!(
+                server:% = self.name;
+                "[{}] Topic [{topic}] unknown on this z2m connection",
+                self.name
+            );
+            return Ok(());
+        };
+
+        log::trace!(
+            server:% = self.name;
+            "[{}] Topic [{topic}] known as {uuid} on this z2m connection, queueing event..",
+            self.name
+        );
+        let api_req = match payload {
+            Z2mRequest::DeviceOptions(options) => {
+                let bridge_topic = "bridge/request/device/options";
+                let transaction = self.transaction_new(bridge_topic);
+                RawMessage {
+                    payload: json!({"id": topic, "options": options, "transaction": transaction}),
+                    topic: bridge_topic.to_string(),
+                }
+            }
+            payload => {
+                let is_update = matches!(payload, Z2mRequest::Update(_));
+                let payload = serde_json::to_value(payload)?;
+                if is_update {
+                    self.echo_track(topic, &payload);
+                }
+                RawMessage {
+                    payload,
+                    topic: format!("{topic}/set"),
+                }
+            }
+        };
+        self.send(api_req);
+        Ok(())
+    }
+
+    /* Turning a room on (and nothing else) restores each of its lights to
+     * the brightness and color in its light resource, like a real bridge
+     * does. The group is sent the state most of the lights share, and only
+     * lights with a different state get a command of their own. The
+     * requests are returned with their topics, the group first. If no light
+     * has any state to restore, nothing is returned, and the group is simply
+     * turned on. */
+    async fn room_on_restore(
+        &self,
+        glight: &ResourceLink,
+        upd: &DeviceUpdate,
+    ) -> ApiResult<Vec<(String, ClientRequest)>> {
+        if !upd.is_plain_on() {
+            return Ok(vec![]);
+        }
+
+        let res = self.state.lock().await;
+        let owner = res.get::<GroupedLight>(glight)?.owner;
+        let Some(group_topic) = self
+            .rmap
+            .get(&owner.rid)
+            .filter(|_| owner.rtype == RType::Room)
+        else {
+            return Ok(vec![]);
+        };
+
+        let lights: Vec<(ResourceLink, RestoreState)> = res
+            .get::<Room>(&owner)?
+            .children
+            .iter()
+            .filter_map(|rl| res.get::<Device>(rl).ok())
+            .filter_map(Device::light_service)
+            .filter_map(|light| {
+                let state = RestoreState::from_light(res.get::<Light>(light).ok()?);
+                Some((*light, state))
+            })
+            .collect();
+        drop(res);
+
+        /* the state that leaves the most lights as they should be */
+        let Some(common) = lights
+            .iter()
+            .map(|(_, state)| *state)
+            .filter(|state| !state.is_empty())
+            .max_by_key(|common| {
+                lights
+                    .iter()
+                    .filter(|(_, state)| common.covers(state))
+                    .count()
+            })
+        else {
+            return Ok(vec![]);
+        };
+
+        let group = ClientRequest::group_update(*glight, common.apply(upd.clone()));
+        let singles = lights
+            .into_iter()
+            .filter(|(_, state)| !common.covers(state))
+            .filter_map(|(light, state)| {
+                let Some(topic) = self.rmap.get(&light.rid).cloned() else {
+                    log::warn!(
+                        server:% = self.name;
+                        "[{}] {light:?} has no z2m topic, and will not be restored with its room",
+                        self.name
+                    );
+                    return None;
+                };
+                let restore = ClientRequest::light_update(light, state.apply(upd.clone()));
+                Some((topic, restore))
+            });
+
+        Ok(std::iter::once((group_topic.clone(), group))
+            .chain(singles)
+            .collect())
+    }
+
+    /* Resolve a client request to the z2m topic and payload to send.
+     *
+     * This only needs the resources for lookups, so the caller can release
+     * the lock before anything else happens. */
+    fn resolve_request<'a>(
+        &self,
+        res: &Resources,
+        req: &'a ClientRequest,
+    ) -> ApiResult<Option<(String, Z2mRequest<'a>)>> {
+        let (rid, z2mreq) = match req {
+            ClientRequest::LightUpdate { device, upd } => (device.rid, Z2mRequest::Update(upd)),
+
+            ClientRequest::DeviceOptions { device, options } => {
+                (device.rid, Z2mRequest::DeviceOptions(options))
+            }
+
+            /* sent directly by handle_request */
+            ClientRequest::Touchlink { .. }
+            | ClientRequest::ConfigureReporting { .. }
+            | ClientRequest::MotionSensitivity { .. }
+            | ClientRequest::LightStop { .. }
+            | ClientRequest::ZoneGroup { .. } => return Ok(None),
+
+            ClientRequest::GroupUpdate { device, upd } => {
+                let room = res.get::<GroupedLight>(device)?.owner.rid;
+                (room, Z2mRequest::Update(upd))
+            }
+
+            ClientRequest::SceneStore { room, id, name } => {
+                (room.rid, Z2mRequest::SceneStore { name, id: *id })
+            }
+
+            ClientRequest::SceneAdd {
+                room,
+                id,
+                name,
+                upd,
+            } => (room.rid, Z2mRequest::SceneAdd { name, id: *id, upd }),
+
+            ClientRequest::SceneRecall { scene } | ClientRequest::SceneRemove { scene } => {
+                let room = res.get::<Scene>(scene)?.group.rid;
+                let index = res
+                    .aux_get(scene)?
+                    .index
+                    .ok_or(ApiError::NotFound(scene.rid))?;
+                if matches!(req, ClientRequest::SceneRecall { .. }) {
+                    (room, Z2mRequest::SceneRecall(index))
+                } else {
+                    (room, Z2mRequest::SceneRemove(index))
+                }
+            }
+        };
+
+        Ok(self.rmap.get(&rid).map(|topic| (topic.clone(), z2mreq)))
+    }
+
+    fn touchlink_send(&mut self, action: &TouchlinkAction) {
+        let transaction = self.transaction_new(action.topic());
+        let payload = match action {
+            TouchlinkAction::Scan => json!({"transaction": transaction}),
+            TouchlinkAction::Identify(dev) | TouchlinkAction::FactoryReset(dev) => json!({
+                "ieee_address": dev.ieee_address,
+                "channel": dev.channel,
+                "transaction": transaction,
+            }),
+        };
+
+        log::info!(server:% = self.name; "[{}] Sending touchlink request {action:?}", self.name);
+        self.send(RawMessage {
+            topic: action.topic().to_string(),
+            payload,
+        });
+    }
+
+    fn motion_sensitivity_send(&mut self, device: &ResourceLink, sensitivity: u32) {
+        let (Some(topic), Some(levels)) = (
+            self.rmap.get(&device.rid).cloned(),
+            self.motion_levels.get(&device.rid),
+        ) else {
+            return;
+        };
+
+        let Some(level) = usize::try_from(sensitivity)
+            .ok()
+            .and_then(|idx| levels.get(idx))
+        else {
+            log::warn!(
+                server:% = self.name;
+                "[{}] Motion sensitivity {sensitivity} not supported by {topic}",
+                self.name
+            );
+            return;
+        };
+
+        let req = ClientRequest::motion_sensitivity(*device, sensitivity);
+        let payload = json!({"motion_sensitivity": level});
+        self.send(RawMessage {
+            topic: format!("{topic}/set"),
+            payload,
+        });
+        self.pending_add(&topic, &req);
+    }
+
+    /* Ask a device to report changes of its light state by itself, so the
+     * state cached by z2m stays accurate, even when the device is changed by
+     * other means (e.g. a physical switch, or a direct binding) */
+    fn configure_reporting_send(&mut self, device: &ResourceLink) {
+        let Some(topic) = self.rmap.get(&device.rid).cloned() else {
+            return;
+        };
+
+        let attributes = self.reporting.get(&device.rid).cloned().unwrap_or_default();
+        if attributes.is_empty() {
+            log::warn!(
+                server:% = self.name;
+                "[{}] Device [{topic}] has no attributes to report",
+                self.name
+            );
+        }
+
+        let conf = &self.config.reporting;
+        let (min, max) = (conf.min_interval, conf.max_interval);
+
+        log::info!(
+            server:% = self.name;
+            "[{}] Configuring reporting of {} attributes on [{topic}]",
+            self.name,
+            attributes.len()
+        );
+
+        for rep in &attributes {
+            self.bridge_request(
+                "bridge/request/device/configure_reporting",
+                json!({
+                    "id": topic,
+                    "endpoint": rep.endpoint,
+                    "cluster": rep.cluster,
+                    "attribute": rep.attribute,
+                    "minimum_report_interval": min,
+                    "maximum_report_interval": max,
+                    "reportable_change": 1,
+                }),
+            );
+        }
+    }
+
+    /* Halt the transition in progress on a light, if any. The light stops
+     * somewhere along the way, so its state is requested afterwards. */
+    async fn light_stop_send(&mut self, device: &ResourceLink) -> ApiResult<()> {
+        let Some(topic) = self.rmap.get(&device.rid).cloned() else {
+            return Ok(());
+        };
+
+        let moving = self
+            .pending
+            .get(&topic)
+            .is_some_and(|cmd| cmd.settle > Utc::now());
+        if !moving {
+            return Ok(());
+        }
+
+        let lock = self.state.lock().await;
+        let light = lock.get::<Light>(device)?;
+        let mut payload = json!({"brightness_move": "stop"});
+        if light.color_temperature.is_some() {
+            payload["color_temp_move"] = json!("stop");
+        }
+        drop(lock);
+
+        log::info!(server:% = self.name; "[{}] Stopping transition on [{topic}]", self.name);
+        self.send(RawMessage {
+            topic: format!("{topic}/set"),
+            payload,
+        });
+        self.pending.remove(&topic);
+        self.refresh_topic(&topic);
+
+        Ok(())
+    }
+
+    /* Send a bridge request, with a new transaction id */
+    /// Ask z2m for a health check, to find out if the connection still works
+    pub fn health_check(&mut self) {
+        self.bridge_request("bridge/request/health_check", json!({}));
+    }
+
+    fn bridge_request(&mut self, topic: &str, mut payload: Value) {
+        payload["transaction"] = json!(self.transaction_new(topic));
+        self.send(RawMessage {
+            topic: topic.to_string(),
+            payload,
+        });
+    }
+
+    /* Create, update or remove the z2m group of a zone, so it contains the
+     * lights of the zone that are on this z2m server. */
+    fn zone_group_sync(&mut self, zone: &ResourceLink, lights: &[ResourceLink]) {
+        let prefix = self.server.group_prefix.as_deref().unwrap_or_default();
+        let name = format!("{prefix}{ZONE_GROUP_PREFIX}{}", zone.rid);
+
+        let wanted: HashMap<Uuid, String> = lights
+            .iter()
+            .filter_map(|light| Some((light.rid, self.rmap.get(&light.rid)?.clone())))
+            .collect();
+
+        let current = self.zone_groups.get(&zone.rid).cloned();
+
+        if wanted.is_empty() {
+            if current.is_some() {
+                log::info!(server:% = self.name; "[{}] Removing z2m group [{name}]", self.name);
+                self.bridge_request("bridge/request/group/remove", json!({"id": name}));
+                self.zone_groups.remove(&zone.rid);
+                self.rmap.remove(&zone.rid);
+                self.map.remove(&name);
+            }
+            return;
+        }
+
+        let current = current.unwrap_or_else(|| {
+            log::info!(server:% = self.name; "[{}] Creating z2m group [{name}]", self.name);
+            self.bridge_request("bridge/request/group/add", json!({"friendly_name": name}));
+            HashSet::new()
+        });
+
+        for (light, device) in &wanted {
+            if !current.contains(light) {
+                self.bridge_request(
+                    "bridge/request/group/members/add",
+                    json!({"group": name, "device": device}),
+                );
+            }
+        }
+
+        for light in &current {
+            if let (false, Some(device)) = (wanted.contains_key(light), self.rmap.get(light)) {
+                let payload = json!({"group": name, "device": device});
+                self.bridge_request("bridge/request/group/members/remove", payload);
+            }
+        }
+    }
+
+    /// Handle a request from the api
+    pub async fn handle_request(&mut self, req: &ClientRequest) -> ApiResult<()> {
+        self.learn_cleanup();
+
+        if let ClientRequest::Touchlink { server, action } = req {
+            if server.as_ref().map_or(true, |name| name == &self.name) {
+                self.touchlink_send(action);
+            }
+            return Ok(());
+        }
+
+        if let ClientRequest::MotionSensitivity {
+            device,
+            sensitivity,
+        } = req
+        {
+            self.motion_sensitivity_send(device, *sensitivity);
+            return Ok(());
+        }
+
+        if let ClientRequest::LightStop { device } = req {
+            return self.light_stop_send(device).await;
+        }
+
+        /* the most common request, which needs nothing from the resources,
+         * so it is sent without waiting for the resource lock */
+        if let ClientRequest::LightUpdate { device, upd } = req {
+            if let Some(topic) = self.rmap.get(&device.rid).cloned() {
+                self.send_request(&topic, Z2mRequest::Update(upd))?;
+                self.pending_add(&topic, req);
+            }
+            return Ok(());
+        }
+
+        if let ClientRequest::ConfigureReporting { device } = req {
+            self.configure_reporting_send(device);
+            return Ok(());
+        }
+
+        if let ClientRequest::ZoneGroup { zone, lights } = req {
+            self.zone_group_sync(zone, lights);
+            return Ok(());
+        }
+
+        if let ClientRequest::SceneRecall { scene } = req {
+            let updates = self.state.lock().await.zone_scene_updates(scene)?;
+            if let Some(updates) = updates {
+                for (light, upd) in updates {
+                    let Some(topic) = self.rmap.get(&light.rid).cloned() else {
+                        continue;
+                    };
+                    self.send_request(&topic, Z2mRequest::Update(&upd))?;
+                    self.pending_add(&topic, &ClientRequest::light_update(light, upd));
+                }
+                return Ok(());
+            }
+        }
+
+        if let ClientRequest::GroupUpdate { device, upd } = req {
+            let restore = self.room_on_restore(device, upd).await?;
+            if !restore.is_empty() {
+                for (topic, req) in restore {
+                    if let ClientRequest::GroupUpdate { upd, .. }
+                    | ClientRequest::LightUpdate { upd, .. } = &req
+                    {
+                        self.send_request(&topic, Z2mRequest::Update(upd))?;
+                    }
+                    self.pending_add(&topic, &req);
+                }
+                return Ok(());
+            }
+        }
+
+        let state = self.state.clone();
+        let lock = state.lock().await;
+        let resolved = self.resolve_request(&lock, req)?;
+        let mut off_lights = vec![];
+        if let (Some(_), ClientRequest::SceneRecall { scene }) = (&resolved, req) {
+            off_lights = Self::scene_off_lights(&lock, scene)?;
+            for light in self.scene_missing_lights(&lock, scene)? {
+                log::warn!(
+                    server:% = self.name;
+                    "[{}] {light:?} ({}) does not have {scene:?} stored, and will not change on recall",
+                    self.name,
+                    self.rmap.get(&light.rid).map_or("?", String::as_str)
+                );
+            }
+            self.learn_scene_recall(&lock, scene)?;
+        }
+        drop(lock);
+
+        /* never hold the resource lock while sending */
+        if let Some((topic, z2mreq)) = resolved {
+            self.send_request(&topic, z2mreq)?;
+            self.pending_add(&topic, req);
+
+            /* z2m answers a scene removal only by announcing the groups
+             * again, so the scene is deleted right away. If z2m fails to
+             * remove it, that announcement brings it back. */
+            if let ClientRequest::SceneRemove { scene } = req {
+                self.state.lock().await.delete(scene)?;
+            }
+        }
+
+        for light in off_lights {
+            let Some(topic) = self.rmap.get(&light.rid).cloned() else {
+                continue;
+            };
+            let upd = DeviceUpdate::new().with_state(Some(false));
+            self.send_request(&topic, Z2mRequest::Update(&upd))?;
+            self.pending_add(&topic, &ClientRequest::light_update(light, upd));
+        }
+
+        Ok(())
+    }
+}
+
+/* The state a light is restored to when its room is turned on, as z2m
+ * values. Only the color mode the light was last in is kept. */
+#[derive(Clone, Copy, PartialEq)]
+struct RestoreState {
+    brightness: Option<f64>,
+    mirek: Option<u32>,
+    xy: Option<XY>,
+}
+
+impl RestoreState {
+    fn from_light(light: &Light) -> Self {
+        let mirek = light.as_mirek_opt();
+        Self {
+            brightness: light
+                .dimming
+                .as_ref()
+                .map(|dim| Brightness::from_percent(dim.brightness).z2m()),
+            mirek,
+            xy: light.as_color_opt().filter(|_| mirek.is_none()),
+        }
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.brightness.is_none() && self.mirek.is_none() && self.xy.is_none()
+    }
+
+    /* a group command with this state leaves a light in state `light` */
+    fn covers(&self, light: &Self) -> bool {
+        light
+            .brightness
+            .map_or(true, |b| self.brightness == Some(b))
+            && light.mirek.map_or(true, |m| self.mirek == Some(m))
+            && light
+                .xy
+                .map_or(true, |xy| self.mirek.is_none() && self.xy == Some(xy))
+    }
+
+    fn apply(&self, upd: DeviceUpdate) -> DeviceUpdate {
+        upd.with_brightness(self.brightness)
+            .with_color_temp(self.mirek)
+            .with_color_xy(self.xy)
+    }
+}
+
+/* The parts of a light state that differ between two versions of a light,
+ * ignoring differences small enough to come from rounding */
+fn light_divergence(before: &Light, after: &Light) -> Vec<&'static str> {
+    let mut diverged = vec![];
+
+    if before.on.on != after.on.on {
+        diverged.push("on");
+    }
+    if let (Some(a), Some(b)) = (&before.dimming, &after.dimming) {
+        if (a.brightness - b.brightness).abs() > 1.0 {
+            diverged.push("brightness");
+        }
+    }
+    if let (Some(a), Some(b)) = (&before.color_temperature, &after.color_temperature) {
+        if a.mirek.is_some() && b.mirek.is_some() && a.mirek != b.mirek {
+            diverged.push("color temperature");
+        }
+    }
+    if let (Some(a), Some(b)) = (&before.color, &after.color) {
+        if (a.xy.x - b.xy.x).abs() > 0.01 || (a.xy.y - b.xy.y).abs() > 0.01 {
+            diverged.push("color");
+        }
+    }
+
+    diverged
+}
+
+/* Built-in scene names (and common aliases), in lowercase.
+ *
+ * Ordered by priority, since partial matching picks the first hit. */
+const SCENE_ICON_NAMES: &[(&str, Uuid)] = &[
+    ("cool bright", scene_icons::COOL_BRIGHT),
+    ("night light", scene_icons::NIGHT_LIGHT),
+    ("nightlight", scene_icons::NIGHT_LIGHT),
+    ("concentrate", scene_icons::CONCENTRATE),
+    ("energize", scene_icons::ENERGIZE),
+    ("bright", scene_icons::BRIGHT),
+    ("relax", scene_icons::RELAX),
+    ("dimmed", scene_icons::DIMMED),
+    ("read", scene_icons::READ),
+    ("rest", scene_icons::REST),
+    /* Aliases */
+    ("night", scene_icons::NIGHT_LIGHT),
+    ("cool", scene_icons::COOL_BRIGHT),
+    ("dim", scene_icons::DIMMED),
+];
+
+/* Room names (in lowercase) for each archetype, in a few common languages.
+ *
+ * Ordered by priority, since partial matching picks the first hit. */
+const ROOM_ARCHETYPE_NAMES: &[(&str, RoomArchetype)] = &[
+    /* English */
+    ("living room", RoomArchetype::LivingRoom),
+    ("dining room", RoomArchetype::Dining),
+    ("kids bedroom", RoomArchetype::KidsBedroom),
+    ("kids room", RoomArchetype::KidsBedroom),
+    ("guest room", RoomArchetype::GuestRoom),
+    ("laundry room", RoomArchetype::LaundryRoom),
+    ("front door", RoomArchetype::FrontDoor),
+    ("top floor", RoomArchetype::TopFloor),
+    ("man cave", RoomArchetype::ManCave),
+    ("kitchen", RoomArchetype::Kitchen),
+    ("dining", RoomArchetype::Dining),
+    ("bedroom", RoomArchetype::Bedroom),
+    ("bathroom", RoomArchetype::Bathroom),
+    ("nursery", RoomArchetype::Nursery),
+    ("office", RoomArchetype::Office),
+    ("study", RoomArchetype::Office),
+    ("gym", RoomArchetype::Gym),
+    ("hallway", RoomArchetype::Hallway),
+    ("hall", RoomArchetype::Hallway),
+    ("corridor", RoomArchetype::Hallway),
+    ("toilet", RoomArchetype::Toilet),
+    ("wc", RoomArchetype::Toilet),
+    ("entrance", RoomArchetype::FrontDoor),
+    ("garage", RoomArchetype::Garage),
+    ("terrace", RoomArchetype::Terrace),
+    ("patio", RoomArchetype::Terrace),
+    ("garden", RoomArchetype::Garden),
+    ("driveway", RoomArchetype::Driveway),
+    ("carport", RoomArchetype::Carport),
+    ("downstairs", RoomArchetype::Downstairs),
+    ("upstairs", RoomArchetype::Upstairs),
+    ("attic", RoomArchetype::Attic),
+    ("staircase", RoomArchetype::Staircase),
+    ("stairs", RoomArchetype::Staircase),
+    ("lounge", RoomArchetype::Lounge),
+    ("computer", RoomArchetype::Computer),
+    ("studio", RoomArchetype::Studio),
+    ("music", RoomArchetype::Music),
+    ("tv", RoomArchetype::Tv),
+    ("closet", RoomArchetype::Closet),
+    ("storage", RoomArchetype::Storage),
+    ("laundry", RoomArchetype::LaundryRoom),
+    ("balcony", RoomArchetype::Balcony),
+    ("porch", RoomArchetype::Porch),
+    ("barbecue", RoomArchetype::Barbecue),
+    ("bbq", RoomArchetype::Barbecue),
+    ("pool", RoomArchetype::Pool),
+    ("living", RoomArchetype::LivingRoom),
+    /* Danish */
+    ("stue", RoomArchetype::LivingRoom),
+    ("køkken", RoomArchetype::Kitchen),
+    ("spisestue", RoomArchetype::Dining),
+    ("soveværelse", RoomArchetype::Bedroom),
+    ("børneværelse", RoomArchetype::KidsBedroom),
+    ("badeværelse", RoomArchetype::Bathroom),
+    ("kontor", RoomArchetype::Office),
+    ("gang", RoomArchetype::Hallway),
+    ("entré", RoomArchetype::FrontDoor),
+    ("terrasse", RoomArchetype::Terrace),
+    ("altan", RoomArchetype::Balcony),
+    ("loft", RoomArchetype::Attic),
+    /* German */
+    ("wohnzimmer", RoomArchetype::LivingRoom),
+    ("küche", RoomArchetype::Kitchen),
+    ("esszimmer", RoomArchetype::Dining),
+    ("schlafzimmer", RoomArchetype::Bedroom),
+    ("kinderzimmer", RoomArchetype::KidsBedroom),
+    ("gästezimmer", RoomArchetype::GuestRoom),
+    ("badezimmer", RoomArchetype::Bathroom),
+    ("bad", RoomArchetype::Bathroom),
+    ("büro", RoomArchetype::Office),
+    ("arbeitszimmer", RoomArchetype::Office),
+    ("flur", RoomArchetype::Hallway),
+    ("diele", RoomArchetype::Hallway),
+    ("garten", RoomArchetype::Garden),
+    ("balkon", RoomArchetype::Balcony),
+    ("dachboden", RoomArchetype::Attic),
+    ("treppenhaus", RoomArchetype::Staircase),
+    /* Dutch */
+    ("woonkamer", RoomArchetype::LivingRoom),
+    ("keuken", RoomArchetype::Kitchen),
+    ("eetkamer", RoomArchetype::Dining),
+    ("slaapkamer", RoomArchetype::Bedroom),
+    ("badkamer", RoomArchetype::Bathroom),
+    ("kantoor", RoomArchetype::Office),
+    ("tuin", RoomArchetype::Garden),
+    ("zolder", RoomArchetype::Attic),
+    /* French */
+    ("salle à manger", RoomArchetype::Dining),
+    ("salle de bain", RoomArchetype::Bathroom),
+    ("salon", RoomArchetype::LivingRoom),
+    ("cuisine", RoomArchetype::Kitchen),
+    ("chambre", RoomArchetype::Bedroom),
+    ("bureau", RoomArchetype::Office),
+    ("couloir", RoomArchetype::Hallway),
+    ("jardin", RoomArchetype::Garden),
+    ("grenier", RoomArchetype::Attic),
+];
+
+/// Map a z2m motion sensitivity level (like "medium") to its index in the
+/// list of levels supported by the sensor
+fn motion_sensitivity(values: &[String], level: Option<&str>) -> Option<MotionSensitivity> {
+    let sensitivity_max = u32::try_from(values.len().checked_sub(1)?).ok()?;
+    let sensitivity = match level {
+        Some(level) => values.iter().position(|val| val == level)?,
+        None => values.len() / 2,
+    };
+
+    Some(MotionSensitivity {
+        status: MotionSensitivityStatus::Set,
+        sensitivity: u32::try_from(sensitivity).ok()?,
+        sensitivity_max,
+    })
+}
+
+/// Remove scene actions for lights no longer in the room.
+///
+/// Returns true if the actions no longer match the lights in the room (some
+/// were removed, or some lights have no action), and should be learned again.
+/* z2m reports rounded values (e.g. brightness), so numbers only need to be close */
+fn echo_matches(sent: &Value, echo: &Value) -> bool {
+    match (sent.as_f64(), echo.as_f64()) {
+        (Some(sent), Some(echo)) => (sent - echo).abs() <= 0.5,
+        _ => sent == echo,
+    }
+}
+
+fn prune_scene_actions(actions: &mut Vec<SceneActionElement>, lights: &HashSet<Uuid>) -> bool {
+    let before = actions.len();
+    actions.retain(|act| lights.contains(&act.target.rid));
+
+    let targets: HashSet<Uuid> = actions.iter().map(|act| act.target.rid).collect();
+    actions.len() != before || !lights.is_subset(&targets)
+}
+
+fn guess_scene_icon(name: &str, rules: &[SceneIconRule]) -> Option<ResourceLink> {
+    /* User-defined rules take priority over built-in names */
+    let icon = rules
+        .iter()
+        .find(|rule| rule.pattern.is_match(name))
+        .map(|rule| rule.icon)
+        .or_else(|| guess_builtin_scene_icon(name))?;
+
+    Some(ResourceLink {
+        rid: icon,
+        rtype: RType::PublicImage,
+    })
+}
+
+fn guess_builtin_scene_icon(name: &str) -> Option<Uuid> {
+    find_keyword(name, SCENE_ICON_NAMES)
+}
+
+fn guess_room_archetype(name: &str) -> Option<RoomArchetype> {
+    find_keyword(name, ROOM_ARCHETYPE_NAMES)
+}
+
+/* Look up a name in a table of (lowercase) keywords */
+fn find_keyword<T: Copy>(name: &str, table: &[(&str, T)]) -> Option<T> {
+    /* Normalize to lowercase words separated by single spaces */
+    let words = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    /* Exact matches first.. */
+    if let Some((_, value)) = table.iter().find(|(key, _)| *key == words) {
+        return Some(*value);
+    }
+
+    /* ..then look for keywords as whole words inside the name */
+    let padded = format!(" {words} ");
+    table
+        .iter()
+        .find(|(key, _)| padded.contains(&format!(" {key} ")))
+        .map(|(_, value)| *value)
+}
+
+/* Find the device topic in a z2m publish failure, e.g.:
+ *
+ *   Publish 'set' 'state' to 'Kitchen ceiling' failed: 'Error: timeout'
+ */
+fn parse_publish_failure(message: &str) -> Option<&str> {
+    let rest = message.strip_prefix("Publish '")?;
+    let (_, rest) = rest.split_once("' to '")?;
+    let (topic, _) = rest.split_once("' failed")?;
+    Some(topic)
+}
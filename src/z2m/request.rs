@@ -1,6 +1,9 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::hue::api::ResourceLink;
+use crate::z2m::api::TouchlinkDevice;
 use crate::z2m::update::DeviceUpdate;
 
 #[derive(Clone, Debug, Deserialize)]
@@ -15,12 +18,24 @@ pub enum ClientRequest {
         upd: DeviceUpdate,
     },
 
+    /// Stop any transition in progress on a light (e.g. a sunrise effect)
+    LightStop {
+        device: ResourceLink,
+    },
+
     SceneStore {
         room: ResourceLink,
         id: u32,
         name: String,
     },
 
+    SceneAdd {
+        room: ResourceLink,
+        id: u32,
+        name: String,
+        upd: DeviceUpdate,
+    },
+
     SceneRecall {
         scene: ResourceLink,
     },
@@ -28,6 +43,56 @@ pub enum ClientRequest {
     SceneRemove {
         scene: ResourceLink,
     },
+
+    DeviceOptions {
+        device: ResourceLink,
+        options: Value,
+    },
+
+    /// Configure attribute reporting on a device, so it reports state changes
+    /// by itself
+    ConfigureReporting {
+        device: ResourceLink,
+    },
+
+    /// Set the sensitivity of a motion sensor, as an index into the levels
+    /// it supports
+    MotionSensitivity {
+        device: ResourceLink,
+        sensitivity: u32,
+    },
+
+    /// Keep the hidden z2m group of a zone in sync with the lights of the
+    /// zone. With no lights, the group is removed.
+    ZoneGroup {
+        zone: ResourceLink,
+        lights: Vec<ResourceLink>,
+    },
+
+    /// Touchlink request, for the named z2m server (or all of them)
+    Touchlink {
+        server: Option<String>,
+        action: TouchlinkAction,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TouchlinkAction {
+    Scan,
+    Identify(TouchlinkDevice),
+    FactoryReset(TouchlinkDevice),
+}
+
+impl TouchlinkAction {
+    #[must_use]
+    pub const fn topic(&self) -> &'static str {
+        match self {
+            Self::Scan => "bridge/request/touchlink/scan",
+            Self::Identify(_) => "bridge/request/touchlink/identify",
+            Self::FactoryReset(_) => "bridge/request/touchlink/factory_reset",
+        }
+    }
 }
 
 impl ClientRequest {
@@ -41,6 +106,11 @@ impl ClientRequest {
         Self::GroupUpdate { device, upd }
     }
 
+    #[must_use]
+    pub const fn light_stop(device: ResourceLink) -> Self {
+        Self::LightStop { device }
+    }
+
     #[must_use]
     pub const fn scene_remove(scene: ResourceLink) -> Self {
         Self::SceneRemove { scene }
@@ -51,10 +121,81 @@ impl ClientRequest {
         Self::SceneRecall { scene }
     }
 
+    #[must_use]
+    pub const fn scene_add(room: ResourceLink, id: u32, name: String, upd: DeviceUpdate) -> Self {
+        Self::SceneAdd {
+            room,
+            id,
+            name,
+            upd,
+        }
+    }
+
     #[must_use]
     pub const fn scene_store(room: ResourceLink, id: u32, name: String) -> Self {
         Self::SceneStore { room, id, name }
     }
+
+    #[must_use]
+    pub const fn zone_group(zone: ResourceLink, lights: Vec<ResourceLink>) -> Self {
+        Self::ZoneGroup { zone, lights }
+    }
+
+    #[must_use]
+    pub const fn touchlink(server: Option<String>, action: TouchlinkAction) -> Self {
+        Self::Touchlink { server, action }
+    }
+
+    #[must_use]
+    pub const fn motion_sensitivity(device: ResourceLink, sensitivity: u32) -> Self {
+        Self::MotionSensitivity {
+            device,
+            sensitivity,
+        }
+    }
+
+    #[must_use]
+    pub const fn device_options(device: ResourceLink, options: Value) -> Self {
+        Self::DeviceOptions { device, options }
+    }
+
+    #[must_use]
+    pub const fn configure_reporting(device: ResourceLink) -> Self {
+        Self::ConfigureReporting { device }
+    }
+
+    /// Combine this request with a `newer` one that follows it, if the
+    /// result has the same effect as sending both
+    #[must_use]
+    pub fn coalesce(&self, newer: &Self) -> Option<Self> {
+        match (self, newer) {
+            (
+                Self::LightUpdate { device, upd: old },
+                Self::LightUpdate {
+                    device: other,
+                    upd: new,
+                },
+            ) if device == other => Some(Self::light_update(*device, old.merged(new)?)),
+            (
+                Self::GroupUpdate { device, upd: old },
+                Self::GroupUpdate {
+                    device: other,
+                    upd: new,
+                },
+            ) if device == other => Some(Self::group_update(*device, old.merged(new)?)),
+            (Self::LightStop { device }, Self::LightStop { device: other })
+            | (
+                Self::MotionSensitivity { device, .. },
+                Self::MotionSensitivity { device: other, .. },
+            )
+            | (Self::ZoneGroup { zone: device, .. }, Self::ZoneGroup { zone: other, .. })
+                if device == other =>
+            {
+                Some(newer.clone())
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -66,10 +207,33 @@ pub enum Z2mRequest<'a> {
         id: u32,
     },
 
+    SceneAdd {
+        name: &'a str,
+        #[serde(rename = "ID")]
+        id: u32,
+        #[serde(flatten)]
+        upd: &'a DeviceUpdate,
+    },
+
     SceneRecall(u32),
 
     SceneRemove(u32),
 
+    /* sent as a bridge request, not to the device topic */
+    #[serde(skip)]
+    DeviceOptions(&'a Value),
+
     #[serde(untagged)]
     Update(&'a DeviceUpdate),
 }
+
+/// A request that zigbee2mqtt reported as failed (or never answered)
+#[derive(Clone, Debug, Serialize)]
+pub struct RequestFailure {
+    pub time: DateTime<Utc>,
+    /// Name of the z2m server the request was sent to
+    pub server: String,
+    /// Topic the request was sent to
+    pub topic: String,
+    pub error: String,
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    /// Trying to connect to the z2m websocket
+    Connecting,
+    /// Connected, and z2m reports its bridge online
+    Connected,
+    /// Connected, but z2m reports its bridge offline (e.g. mqtt is down)
+    Offline,
+    /// Connection lost or failed, waiting to retry
+    Disconnected,
+}
+
+/// Connection status of a z2m server, as shown in the admin api
+#[derive(Clone, Debug, Serialize)]
+pub struct ServerStatus {
+    pub url: String,
+    pub state: ConnectionState,
+    /// Time of the latest change of `state`
+    pub since: DateTime<Utc>,
+    /// Most recent connection error, if any
+    pub last_error: Option<String>,
+    pub last_error_time: Option<DateTime<Utc>>,
+    /// Successful connections made
+    pub connects: u64,
+    /// Messages received from z2m
+    pub received: u64,
+    /// Messages sent to z2m
+    pub sent: u64,
+    /// Api requests waiting to be sent, until z2m is ready
+    pub queued: usize,
+    /// Results of the periodic reconciliation with z2m
+    pub reconcile: ReconcileStats,
+}
+
+/// How often light states in bifrost were found to differ from the actual
+/// state reported by z2m
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ReconcileStats {
+    /// Sweeps started
+    pub sweeps: u64,
+    pub last_sweep: Option<DateTime<Utc>>,
+    /// Light states compared
+    pub checked: u64,
+    /// Light states that had diverged (and were corrected)
+    pub diverged: u64,
+    /// Divergences, by z2m topic
+    pub devices: BTreeMap<String, u64>,
+}
+
+impl ServerStatus {
+    #[must_use]
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            state: ConnectionState::Connecting,
+            since: Utc::now(),
+            last_error: None,
+            last_error_time: None,
+            connects: 0,
+            received: 0,
+            sent: 0,
+            queued: 0,
+            reconcile: ReconcileStats::default(),
+        }
+    }
+
+    pub fn set_state(&mut self, state: ConnectionState) {
+        if self.state != state {
+            self.state = state;
+            self.since = Utc::now();
+        }
+    }
+
+    /// The connection was lost (or never made), and is not back yet
+    #[must_use]
+    pub const fn is_unreachable(&self) -> bool {
+        match self.state {
+            ConnectionState::Disconnected => true,
+            ConnectionState::Connecting => self.last_error.is_some(),
+            ConnectionState::Connected | ConnectionState::Offline => false,
+        }
+    }
+
+    pub fn connected(&mut self) {
+        self.connects += 1;
+        self.set_state(ConnectionState::Connected);
+    }
+
+    pub fn failed(&mut self, error: String) {
+        self.last_error = Some(error);
+        self.last_error_time = Some(Utc::now());
+        self.set_state(ConnectionState::Disconnected);
+    }
+}
+
+/// Warning or error logged by a z2m server, as shown in the admin api
+#[derive(Clone, Debug, Serialize)]
+pub struct LogEntry {
+    pub time: DateTime<Utc>,
+    /// Name of the z2m server that logged the message
+    pub server: String,
+    pub level: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+}
@@ -3,7 +3,10 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::hue::api::On;
+use crate::hue::api::{
+    LightEffect, LightUpdate, On, RotaryDirection, SceneAction, SoftwareUpdateState,
+};
+use crate::model::brightness::Brightness;
 use crate::model::types::XY;
 
 #[allow(clippy::pub_underscore_fields)]
@@ -33,15 +36,31 @@ pub struct DeviceUpdate {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub power_on_behavior: Option<PowerOnBehavior>,
-    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    pub update: HashMap<String, Value>,
+    pub update: Option<OtaUpdate>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub update_available: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub battery: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub transition: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness_step: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effect: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gradient: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occupancy: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motion_sensitivity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tamper: Option<bool>,
 
     /* all other fields */
     #[serde(skip_serializing_if = "HashMap::is_empty")]
@@ -72,7 +91,88 @@ impl DeviceUpdate {
     #[must_use]
     pub fn with_brightness(self, brightness: Option<f64>) -> Self {
         Self {
-            brightness: brightness.map(|b| b.clamp(1.0, 254.0)),
+            brightness: brightness.map(|b| Brightness::from_z2m(b).z2m()),
+            ..self
+        }
+    }
+
+    /// This update, followed by `newer`: fields set in both take the value
+    /// from `newer`, and fields in `newer` replace the older fields they
+    /// conflict with (e.g. a color replaces a color temperature). Brightness
+    /// steps add up, or are applied to an older absolute brightness.
+    ///
+    /// Updates with different transitions are never merged, since they do
+    /// not have the same effect as one update (e.g. the start and end of a
+    /// sunrise).
+    #[must_use]
+    pub fn merged(&self, newer: &Self) -> Option<Self> {
+        /* fields of a newer update, and the older fields they replace */
+        const CONFLICTS: &[(&str, &[&str])] = &[
+            ("color", &["color_temp", "gradient"]),
+            ("color_temp", &["color", "gradient"]),
+            ("gradient", &["color", "color_temp"]),
+            ("brightness", &["brightness_step"]),
+        ];
+
+        if self.transition != newer.transition {
+            return None;
+        }
+
+        let Value::Object(mut res) = serde_json::to_value(self).ok()? else {
+            return None;
+        };
+        let Value::Object(fields) = serde_json::to_value(newer).ok()? else {
+            return None;
+        };
+        for (field, replaced) in CONFLICTS {
+            if fields.contains_key(*field) {
+                for key in *replaced {
+                    res.remove(*key);
+                }
+            }
+        }
+        res.extend(fields);
+
+        let mut res: Self = serde_json::from_value(Value::Object(res)).ok()?;
+        if let (None, Some(step)) = (newer.brightness, newer.brightness_step) {
+            if let Some(old) = self.brightness {
+                res.brightness = Some(Brightness::from_z2m(old + step).z2m());
+                res.brightness_step = None;
+            } else if let Some(old) = self.brightness_step {
+                res.brightness_step = Some(old + step);
+            }
+        }
+        Some(res)
+    }
+
+    /// The update only turns the light on (possibly with a transition), and
+    /// leaves everything else as it is
+    #[must_use]
+    pub fn is_plain_on(&self) -> bool {
+        let plain = Self::new()
+            .with_state(Some(true))
+            .with_transition(self.transition);
+        self.state == Some(DeviceState::On)
+            && serde_json::to_value(self).ok() == serde_json::to_value(plain).ok()
+    }
+
+    #[must_use]
+    pub fn with_brightness_step(self, step: Option<f64>) -> Self {
+        Self {
+            brightness_step: step,
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn with_transition(self, transition: Option<f64>) -> Self {
+        Self { transition, ..self }
+    }
+
+    #[must_use]
+    pub fn with_effect(self, effect: Option<LightEffect>) -> Self {
+        Self {
+            effect: effect.map(|eff| eff.z2m_name().to_string()),
             ..self
         }
     }
@@ -92,6 +192,84 @@ impl DeviceUpdate {
             ..self
         }
     }
+
+    #[must_use]
+    pub fn with_gradient(self, points: Option<&[XY]>) -> Self {
+        Self {
+            gradient: points.map(|points| points.iter().map(XY::to_hex).collect()),
+            ..self
+        }
+    }
+
+    /// Gradient colors reported by the device, converted from `#rrggbb` notation
+    #[must_use]
+    pub fn gradient_xy(&self) -> Option<Vec<XY>> {
+        self.gradient
+            .as_ref()
+            .map(|points| points.iter().filter_map(|hex| XY::from_hex(hex)).collect())
+    }
+}
+
+/// Map a zigbee2mqtt switch action (e.g. `on_press_release`) to a hue
+/// button control id and button event.
+///
+/// Button numbering follows the Hue dimmer switch (on, up, down, off/hue).
+#[must_use]
+pub fn parse_button_action(action: &str) -> Option<(u32, &'static str)> {
+    /* some (older) converters use dashes instead of underscores */
+    let action = action.replace('-', "_");
+    let (button, event) = action.split_once('_').unwrap_or((&action, ""));
+
+    let control_id = match button {
+        "on" => 1,
+        "up" => 2,
+        "down" => 3,
+        "off" | "hue" => 4,
+        _ => return None,
+    };
+
+    let event = match event {
+        "" | "press_release" => "short_release",
+        "press" => "initial_press",
+        "hold" => "repeat",
+        "hold_release" => "long_release",
+        _ => return None,
+    };
+
+    Some((control_id, event))
+}
+
+/// Map a zigbee2mqtt rotary action to a hue rotation direction, and amount
+/// of rotation (in hue rotary steps).
+///
+/// Supports the Hue Tap Dial (`dial_rotate_right_fast`), and IKEA Symfonisk
+/// controllers (`brightness_move_up` while turning, or `rotate_right` with
+/// older converters). The end of a rotation (`brightness_stop`) is ignored,
+/// since hue only reports rotation.
+#[must_use]
+pub fn parse_rotary_action(action: &str) -> Option<(RotaryDirection, u32)> {
+    let (direction, speed) = match action.split('_').collect::<Vec<_>>()[..] {
+        ["dial", "rotate", direction, speed] => (direction, speed),
+        ["brightness", "move", "up"] => ("right", "slow"),
+        ["brightness", "move", "down"] => ("left", "slow"),
+        ["rotate", direction] => (direction, "slow"),
+        _ => return None,
+    };
+
+    let direction = match direction {
+        "right" => RotaryDirection::ClockWise,
+        "left" => RotaryDirection::CounterClockWise,
+        _ => return None,
+    };
+
+    let steps = match speed {
+        "step" => 15,
+        "slow" => 30,
+        "fast" => 60,
+        _ => return None,
+    };
+
+    Some((direction, steps))
 }
 
 #[derive(Copy, Debug, Serialize, Deserialize, Clone)]
@@ -135,7 +313,7 @@ impl DeviceColor {
     }
 }
 
-#[derive(Copy, Debug, Serialize, Deserialize, Clone, Default)]
+#[derive(Copy, Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub enum PowerOnBehavior {
     #[default]
@@ -192,6 +370,50 @@ pub enum DeviceColorMode {
     Xy,
 }
 
+/// Firmware (OTA) update status, as reported by z2m
+#[allow(clippy::pub_underscore_fields)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OtaUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<OtaState>,
+    /// Progress of an update in progress (in percent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f64>,
+    /// Estimated seconds left of an update in progress
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<u64>,
+
+    /* all other fields (e.g. installed_version) */
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default, flatten)]
+    pub __: HashMap<String, Value>,
+}
+
+#[derive(Copy, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OtaState {
+    Idle,
+    Available,
+    Scheduled,
+    Updating,
+    #[serde(other)]
+    Unknown,
+}
+
+impl OtaState {
+    /// Matching hue software update state, if any
+    #[must_use]
+    pub const fn software_update_state(self) -> Option<SoftwareUpdateState> {
+        match self {
+            Self::Idle => Some(SoftwareUpdateState::NoUpdate),
+            Self::Available => Some(SoftwareUpdateState::ReadyToInstall),
+            Self::Scheduled => Some(SoftwareUpdateState::UpdatePending),
+            Self::Updating => Some(SoftwareUpdateState::Installing),
+            Self::Unknown => None,
+        }
+    }
+}
+
 #[derive(Copy, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum DeviceState {
@@ -201,6 +423,31 @@ pub enum DeviceState {
     Unlock,
 }
 
+impl From<&DeviceUpdate> for LightUpdate {
+    fn from(upd: &DeviceUpdate) -> Self {
+        Self::new()
+            .with_on(upd.state.map(Into::into))
+            .with_brightness(upd.brightness.map(|b| Brightness::from_z2m(b).percent()))
+            .with_color_temperature(upd.color_temp)
+            .with_color_xy(upd.color.and_then(|col| col.xy))
+    }
+}
+
+impl From<&SceneAction> for DeviceUpdate {
+    fn from(action: &SceneAction) -> Self {
+        Self::new()
+            .with_state(action.on.map(|on| on.on))
+            .with_brightness(
+                action
+                    .dimming
+                    .as_ref()
+                    .map(|dim| Brightness::from_percent(dim.brightness).z2m()),
+            )
+            .with_color_temp(action.color_temperature.as_ref().map(|ct| ct.mirek))
+            .with_color_xy(action.color.as_ref().map(|col| col.xy))
+    }
+}
+
 impl From<DeviceState> for On {
     fn from(value: DeviceState) -> Self {
         Self {
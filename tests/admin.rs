@@ -0,0 +1,62 @@
+/*
+ * Tests of access to the admin api.
+ */
+
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use tower::ServiceExt;
+
+use bifrost::routes;
+
+fn get(uri: &str, token: Option<&str>) -> Request<Body> {
+    let mut req = Request::get(uri);
+    if let Some(token) = token {
+        req = req.header("Authorization", format!("Bearer {token}"));
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn admin_api_is_open_without_token() {
+    let dir = common::TempDir::new("admin-open");
+    let router = routes::router(common::appstate(&dir, common::config()));
+
+    let resp = router
+        .oneshot(get("/admin/linkbutton", None))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn admin_token_is_required_when_set() {
+    let dir = common::TempDir::new("admin-token");
+    let mut config = common::config();
+    config.bifrost.admin_token = Some("s3cret".to_string());
+    let router = routes::router(common::appstate(&dir, config));
+
+    for token in [None, Some("wrong"), Some("s3cret2"), Some("")] {
+        let resp = router
+            .clone()
+            .oneshot(get("/admin/linkbutton", token))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{token:?}");
+    }
+
+    let resp = router
+        .clone()
+        .oneshot(get("/admin/linkbutton", Some("s3cret")))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    /* the hue apis are not affected */
+    let resp = router
+        .oneshot(get("/clip/v2/resource/bridge", None))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
@@ -0,0 +1,217 @@
+/*
+ * Tests of the OpenAPI document, against the routes actually served.
+ */
+
+mod common;
+
+use std::collections::BTreeSet;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use camino::{Utf8Path, Utf8PathBuf};
+use regex::Regex;
+use serde_json::Value;
+use tower::ServiceExt;
+
+use bifrost::routes::{self, apidocs};
+
+/* fill in the path parameters with values the routes accept */
+fn example_path(path: &str) -> String {
+    let v1 = path.starts_with("/api/");
+    path.split('/')
+        .map(|seg| match seg {
+            "{user}" => "apidocs",
+            "{rtype}" if v1 => "lights",
+            "{rtype}" => "device",
+            "{id}" if v1 => "1",
+            "{id}" => "00000000-0000-0000-0000-000000000000",
+            "{key}" => "state",
+            seg => seg,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[tokio::test]
+async fn documented_endpoints_are_served() {
+    let dir = common::TempDir::new("apidocs");
+    let appstate = common::appstate(&dir, common::config());
+    let router = routes::router(appstate.clone());
+
+    let req = Request::get("/api-docs").body(Body::empty()).unwrap();
+    let resp = router.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let doc: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(doc, apidocs::document(&appstate.config()));
+
+    let paths = doc["paths"].as_object().unwrap();
+    assert!(paths.contains_key("/api/{user}/{rtype}/{id}/{key}"));
+    assert_eq!(
+        paths["/admin/linkbutton"]["post"]["x-bifrost-extension"],
+        Value::Bool(true)
+    );
+    assert!(paths["/clip/v2/resource/light/{id}"]["put"]
+        .get("x-bifrost-extension")
+        .is_none());
+
+    /* a route that is not there gets a 405, or a 404 without a body (unlike
+     * a resource that is not there) */
+    for (path, ops) in paths {
+        for method in ops.as_object().unwrap().keys() {
+            let req = Request::builder()
+                .method(method.to_uppercase().as_str())
+                .uri(example_path(path))
+                .header("Content-Type", "application/json")
+                .body(Body::from("{}"))
+                .unwrap();
+            let resp = router.clone().oneshot(req).await.unwrap();
+            let status = resp.status();
+            assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {path}");
+            if status == StatusCode::NOT_FOUND {
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                assert!(!body.is_empty(), "{method} {path} is not routed");
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn disabled_v1_api_is_not_documented() {
+    let mut config = common::config();
+    config.bifrost.enable_v1 = false;
+
+    let doc = apidocs::document(&config);
+    let paths = doc["paths"].as_object().unwrap();
+    assert!(paths.contains_key("/api/config"));
+    assert!(!paths.contains_key("/api/{user}"));
+
+    config.bifrost.v1_pairing = false;
+    let doc = apidocs::document(&config);
+    let paths = doc["paths"].as_object().unwrap();
+    assert!(!paths.keys().any(|path| path.starts_with("/api/")));
+}
+
+/* path with every parameter (`:id`, `*path` or `{id}`) as `{}` */
+fn normalize(path: &str) -> String {
+    path.split('/')
+        .map(|seg| {
+            if seg.starts_with([':', '*', '{']) {
+                "{}"
+            } else {
+                seg
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn join(prefix: &str, path: &str) -> String {
+    if path == "/" && !prefix.is_empty() {
+        prefix.to_string()
+    } else {
+        format!("{}{path}", prefix.trim_end_matches('/'))
+    }
+}
+
+/*
+ * Routes (method and normalized path) set up by the function `func` in
+ * `file`, following `.nest()` into other modules. Axum cannot list the
+ * routes of a router, so they are read from the source.
+ */
+fn source_routes(
+    file: &Utf8Path,
+    func: &str,
+    prefix: &str,
+    routes: &mut BTreeSet<(String, String)>,
+) {
+    let src = std::fs::read_to_string(file).unwrap();
+    let start = src
+        .find(&format!("fn {func}("))
+        .unwrap_or_else(|| panic!("{func} not found in {file}"));
+    let body = &src[start + 1..];
+    let body = &body[..body
+        .find("\nfn ")
+        .or_else(|| body.find("\npub fn "))
+        .unwrap_or(body.len())];
+
+    /* submodules of mod.rs are next to it */
+    let dir = file.parent().unwrap();
+
+    let nest = Regex::new(r#"\.nest\(\s*"([^"]*)",\s*(\w+)::(\w+)\("#).unwrap();
+    for cap in nest.captures_iter(body) {
+        let module = dir.join(format!("{}.rs", &cap[2]));
+        let module = if module.exists() {
+            module
+        } else {
+            dir.join(&cap[2]).join("mod.rs")
+        };
+        source_routes(&module, &cap[3], &join(prefix, &cap[1]), routes);
+    }
+
+    let method = Regex::new(r"\b(get|post|put|delete|any)\(").unwrap();
+    for route in body.split(".route(").skip(1) {
+        let route = route.split(".nest(").next().unwrap();
+        let path = route.split('"').nth(1).unwrap();
+        for cap in method.captures_iter(route) {
+            routes.insert((cap[1].to_string(), normalize(&join(prefix, path))));
+        }
+    }
+}
+
+#[test]
+fn every_route_is_documented() {
+    let mut config = common::config();
+    config.z2m = serde_yml::from_str(
+        "server1:\n  url: ws://10.0.0.100:8080/api\n  frontend:\n    username: admin\n    password: secret",
+    )
+    .unwrap();
+
+    let doc = apidocs::document(&config);
+    let documented: BTreeSet<(String, String)> = doc["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .flat_map(|(path, ops)| {
+            ops.as_object()
+                .unwrap()
+                .keys()
+                .map(|method| (method.clone(), normalize(path)))
+        })
+        .collect();
+
+    let src = Utf8PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src");
+    let mut served = BTreeSet::new();
+    source_routes(&src.join("routes/mod.rs"), "router", "", &mut served);
+    source_routes(
+        &src.join("server/frontend.rs"),
+        "router",
+        "/z2m",
+        &mut served,
+    );
+    assert!(served.len() > 50, "{served:?}");
+
+    /* `any` routes only need one method documented */
+    let is_documented = |(method, path): &(String, String)| {
+        documented.contains(&(method.clone(), path.clone()))
+            || (method == "any" && documented.iter().any(|(_, doc)| doc == path))
+    };
+    let undocumented: Vec<_> = served
+        .iter()
+        .filter(|route| !is_documented(route))
+        .collect();
+    assert!(
+        undocumented.is_empty(),
+        "undocumented routes: {undocumented:?}"
+    );
+
+    let is_served = |(method, path): &(String, String)| {
+        served.contains(&(method.clone(), path.clone()))
+            || served.contains(&("any".to_string(), path.clone()))
+    };
+    let unserved: Vec<_> = documented.iter().filter(|ep| !is_served(ep)).collect();
+    assert!(
+        unserved.is_empty(),
+        "documented but not routed: {unserved:?}"
+    );
+}
@@ -0,0 +1,62 @@
+/*
+ * Tests of behavior instance validation against the bundled behavior scripts.
+ */
+
+use serde_json::{json, Value};
+
+use bifrost::error::ApiError;
+use bifrost::hue::behavior_scripts::BundledScript;
+
+fn wake_up() -> Value {
+    json!({
+        "end_brightness": 100,
+        "fade_in_duration": {"seconds": 1800},
+        "when": {
+            "recurrence_days": ["monday", "friday"],
+            "time_point": {"type": "time", "time": {"hour": 7, "minute": 30}},
+        },
+        "where": [{
+            "group": {"rid": "9c8b2bd2-5a69-4c0a-9b0e-1a2b3c4d5e6f", "rtype": "room"},
+        }],
+    })
+}
+
+fn errors(script: BundledScript, config: &Value) -> Vec<String> {
+    match script.validate(config) {
+        Ok(()) => vec![],
+        Err(ApiError::BehaviorConfigInvalid(errors)) => errors,
+        Err(err) => panic!("unexpected error: {err}"),
+    }
+}
+
+#[test]
+fn bundled_schemas_compile() {
+    for script in BundledScript::ALL {
+        assert_eq!(BundledScript::from_id(&script.id()), Some(script));
+        assert!(!matches!(
+            script.validate(&json!({})),
+            Err(ApiError::BehaviorSchemaInvalid(..))
+        ));
+    }
+}
+
+#[test]
+fn valid_configuration_is_accepted() {
+    assert!(errors(BundledScript::WakeUp, &wake_up()).is_empty());
+}
+
+#[test]
+fn invalid_configuration_reports_pointers() {
+    let mut config = wake_up();
+    config["end_brightness"] = json!(150);
+    config["when"]["time_point"]["time"]["hour"] = json!(24);
+    config.as_object_mut().unwrap().remove("fade_in_duration");
+
+    let errors = errors(BundledScript::WakeUp, &config);
+    assert_eq!(errors.len(), 3, "{errors:?}");
+    assert!(errors.iter().any(|e| e.starts_with("(root): ")));
+    assert!(errors.iter().any(|e| e.starts_with("/end_brightness: ")));
+    assert!(errors
+        .iter()
+        .any(|e| e.starts_with("/when/time_point/time/hour: ")));
+}
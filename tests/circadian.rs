@@ -0,0 +1,122 @@
+/*
+ * Tests of circadian mode: sun position, and adjustment of room lights.
+ */
+
+mod common;
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use tokio::sync::Mutex;
+
+use bifrost::circadian::{self, Circadian, CircadianRoom};
+use bifrost::config::CircadianConfig;
+use bifrost::hue::api::{Light, RType, ResourceLink};
+use bifrost::model::state::AuxData;
+use bifrost::model::sun::Coordinates;
+use bifrost::resource::Resources;
+use bifrost::z2m::request::ClientRequest;
+
+const COPENHAGEN: Coordinates = Coordinates {
+    latitude: 55.68,
+    longitude: 12.57,
+};
+
+fn time(hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 6, 21, hour, minute, 0).unwrap()
+}
+
+#[test]
+fn sun_elevation_follows_the_day() {
+    /* solar noon at midsummer is about 57.8 degrees up */
+    let noon = COPENHAGEN.sun_elevation(time(11, 10));
+    assert!((noon - 57.8).abs() < 1.0, "{noon}");
+
+    let midnight = COPENHAGEN.sun_elevation(time(23, 10));
+    assert!(midnight < -6.0, "{midnight}");
+}
+
+#[test]
+fn target_is_warm_at_night_and_cool_by_day() {
+    let conf = CircadianConfig::default();
+
+    assert_eq!(circadian::target(&conf, -20.0), (455, 30.0));
+    assert_eq!(circadian::target(&conf, 60.0), (200, 100.0));
+
+    let (mirek, brightness) = circadian::target(&conf, 12.0);
+    assert!(200 < mirek && mirek < 455);
+    assert!(30.0 < brightness && brightness < 100.0);
+}
+
+async fn kitchen() -> (Arc<Mutex<Resources>>, ResourceLink) {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+    for msg in common::bridge_announce() {
+        mapper.handle_message(&msg).await.unwrap();
+    }
+
+    let mut lock = res.lock().await;
+    lock.init("001788fffe000001").unwrap();
+
+    let geoloc = lock.get_resources_by_type(RType::Geolocation)[0].id;
+    lock.aux_set(
+        &RType::Geolocation.link_to(geoloc),
+        AuxData::new().with_location(Some(COPENHAGEN)),
+    );
+
+    let room = RType::Room.deterministic(("test", "Kitchen"));
+    let settings = CircadianRoom {
+        enabled: true,
+        brightness: false,
+    };
+    lock.set_circadian(room.rid, settings);
+
+    let light = RType::Light.link_to(lock.get_resources_by_type(RType::Light)[0].id);
+    lock.update::<Light>(&light.rid, |light| light.on.on = true)
+        .unwrap();
+    drop(lock);
+
+    (res, light)
+}
+
+fn set_mirek(res: &mut Resources, light: &ResourceLink, mirek: u32) {
+    res.update::<Light>(&light.rid, |light| {
+        light.color_temperature.as_mut().unwrap().mirek = Some(mirek);
+    })
+    .unwrap();
+}
+
+#[tokio::test]
+async fn lights_are_adjusted_until_changed_by_others() {
+    let (res, light) = kitchen().await;
+    let mut requests = res.lock().await.z2m_channel();
+    let mut circadian = Circadian::new(CircadianConfig::default(), res.clone());
+
+    /* at midnight, the light is set to the warmest color temperature */
+    circadian.tick(time(23, 10)).await.unwrap();
+    let (_, req) = requests.try_recv().unwrap();
+    let ClientRequest::LightUpdate { device, upd } = &*req else {
+        panic!("unexpected request: {req:?}");
+    };
+    assert_eq!(device, &light);
+    assert_eq!(upd.color_temp, Some(455));
+    assert_eq!(upd.brightness, None);
+
+    /* once there, it is left as is */
+    set_mirek(&mut *res.lock().await, &light, 455);
+    circadian.tick(time(23, 20)).await.unwrap();
+    assert!(requests.try_recv().is_err());
+
+    /* somebody else changes it, so it is left alone for a while.. */
+    set_mirek(&mut *res.lock().await, &light, 250);
+    circadian.tick(time(23, 30)).await.unwrap();
+    circadian.tick(time(23, 50)).await.unwrap();
+    assert!(requests.try_recv().is_err());
+
+    /* ..and adjusted again after that */
+    circadian
+        .tick(time(23, 30) + Duration::minutes(31))
+        .await
+        .unwrap();
+    assert!(requests.try_recv().is_ok());
+}
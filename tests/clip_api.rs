@@ -0,0 +1,419 @@
+/*
+ * Tests of the v2 (clip) api routes, without any z2m connection.
+ */
+
+mod common;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use bifrost::hue::api::{
+    Bridge, Device, DeviceArchetype, DevicePower, Dimming, Light, LightTimedEffects, Metadata,
+    RType, Resource, RoomArchetype, RoomMetadata, Scene, Zone,
+};
+use bifrost::hue::behavior_scripts::BundledScript;
+use bifrost::model::state::State;
+use bifrost::resource::Resources;
+use bifrost::routes;
+use bifrost::server::appstate::AppState;
+use bifrost::server::certificate;
+use bifrost::z2m::request::ClientRequest;
+use bifrost::z2m::update::DeviceState;
+
+async fn put(appstate: &AppState, uri: &str, body: &Value) -> (StatusCode, Value) {
+    let req = Request::put(uri)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    send(appstate, req).await
+}
+
+async fn send(appstate: &AppState, req: Request<Body>) -> (StatusCode, Value) {
+    let resp = routes::router(appstate.clone()).oneshot(req).await.unwrap();
+    let status = resp.status();
+    let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn behavior_instances_are_validated_against_bundled_scripts() {
+    let dir = common::TempDir::new("behavior-post");
+    let appstate = common::appstate(&dir, common::config());
+    let post = |body: Value| {
+        let req = Request::post("/clip/v2/resource/behavior_instance")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        send(&appstate, req)
+    };
+
+    /* bundled scripts check the configuration */
+    let script = BundledScript::WakeUp.id();
+    let (status, _) =
+        post(json!({"script_id": script, "configuration": {"end_brightness": 150}})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    /* scripts we do not know are accepted as they are */
+    let script = uuid::Uuid::new_v4();
+    let (status, reply) =
+        post(json!({"script_id": script, "configuration": {"any": "thing"}})).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let id = reply["data"][0]["rid"].as_str().unwrap();
+    let body = get(
+        &appstate,
+        &format!("/clip/v2/resource/behavior_instance/{id}"),
+    )
+    .await;
+    assert_eq!(body["data"][0]["script_id"], json!(script));
+}
+
+#[tokio::test]
+async fn bridge_time_zone_and_name_can_be_changed() {
+    let dir = common::TempDir::new("bridge-put");
+    let appstate = common::appstate(&dir, common::config());
+    let (id, owner) = {
+        let lock = appstate.res.lock().await;
+        let (bridge, dev) = lock.get_bridge().unwrap();
+        assert_eq!(dev.metadata.name, "test");
+        let id = lock.get_resources_by_type(RType::Bridge)[0].id;
+        (id, bridge.owner)
+    };
+
+    let uri = format!("/clip/v2/resource/bridge/{id}");
+    let (status, _) = put(&appstate, &uri, &json!({"time_zone": {"time_zone": "UTC"}})).await;
+    assert_eq!(status, StatusCode::OK);
+
+    /* the hue app renames the bridge through its device */
+    let dev_uri = format!("/clip/v2/resource/device/{}", owner.rid);
+    let (status, _) = put(
+        &appstate,
+        &dev_uri,
+        &json!({"metadata": {"name": "Attic bridge"}}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let lock = appstate.res.lock().await;
+    let bridge = lock.get::<Bridge>(&RType::Bridge.link_to(id)).unwrap();
+    assert_eq!(bridge.time_zone.time_zone, "UTC");
+    let dev = lock.get::<Device>(&bridge.owner).unwrap();
+    assert_eq!(dev.metadata.name, "Attic bridge");
+    assert!(matches!(dev.metadata.archetype, DeviceArchetype::BridgeV2));
+    drop(lock);
+
+    /* devices can still be read through the generic routes */
+    let body = get(&appstate, &dev_uri).await;
+    assert_eq!(body["data"][0]["metadata"]["name"], "Attic bridge");
+
+    /* ..and v1 clients see the same name and time zone */
+    let config = get(&appstate, "/api/config").await;
+    assert_eq!(config["name"], "Attic bridge");
+    let user = uuid::Uuid::new_v4();
+    appstate.res.lock().await.add_user(user, "test#app");
+    let config = get(&appstate, &format!("/api/{user}/config")).await;
+    assert_eq!(config["timezone"], "UTC");
+
+    /* invalid values change nothing */
+    let (status, _) = put(
+        &appstate,
+        &uri,
+        &json!({"time_zone": {"time_zone": "../etc/passwd"}}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = put(&appstate, &dev_uri, &json!({"metadata": {"name": " "}})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let lock = appstate.res.lock().await;
+    let bridge = lock.get::<Bridge>(&RType::Bridge.link_to(id)).unwrap();
+    assert_eq!(bridge.time_zone.time_zone, "UTC");
+    let dev = lock.get::<Device>(&bridge.owner).unwrap();
+    assert_eq!(dev.metadata.name, "Attic bridge");
+}
+
+#[tokio::test]
+async fn scene_appdata_is_kept_per_application() {
+    let dir = common::TempDir::new("scene-appdata");
+    let appstate = common::appstate(&dir, common::config());
+    let link = RType::Scene.deterministic("Evening");
+    let scene: Scene = serde_json::from_value(json!({
+        "actions": [],
+        "group": RType::Room.deterministic("Kitchen"),
+        "metadata": {"name": "Evening", "image": null, "appdata": "shared"},
+        "palette": {},
+        "speed": 0.5,
+        "status": null,
+    }))
+    .unwrap();
+    appstate
+        .res
+        .lock()
+        .await
+        .add(&link, Resource::Scene(scene))
+        .unwrap();
+
+    let uri = format!("/clip/v2/resource/scene/{}", link.rid);
+    for (key, appdata) in [("alpha", "alpha-data"), ("beta", "beta-data")] {
+        let req = Request::put(&uri)
+            .header("Content-Type", "application/json")
+            .header("hue-application-key", key)
+            .body(Body::from(
+                json!({"metadata": {"name": "Evening", "image": null, "appdata": appdata}})
+                    .to_string(),
+            ))
+            .unwrap();
+        assert_eq!(send(&appstate, req).await.0, StatusCode::OK);
+    }
+
+    for (key, appdata) in [
+        (Some("alpha"), "alpha-data"),
+        (Some("beta"), "beta-data"),
+        (Some("gamma"), "shared"),
+        (None, "shared"),
+    ] {
+        let mut req = Request::get(&uri);
+        if let Some(key) = key {
+            req = req.header("hue-application-key", key);
+        }
+        let (status, body) = send(&appstate, req.body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"][0]["metadata"]["appdata"], appdata, "{key:?}");
+    }
+}
+
+async fn get(appstate: &AppState, uri: &str) -> Value {
+    let req = Request::get(uri).body(Body::empty()).unwrap();
+    let (status, body) = send(appstate, req).await;
+    assert_eq!(status, StatusCode::OK, "{uri}");
+    body
+}
+
+#[tokio::test]
+async fn timed_effects_round_trip() {
+    let dir = common::TempDir::new("timed-effects");
+    let appstate = common::appstate(&dir, common::config());
+    let link = RType::Light.deterministic("Bedside");
+    let mut light = Light::new(
+        RType::Device.deterministic("Bedside"),
+        Metadata::new(DeviceArchetype::SpotBulb, "Bedside"),
+    );
+    light.dimming = Some(Dimming {
+        brightness: 100.0,
+        min_dim_level: None,
+    });
+    light.timed_effects = Some(LightTimedEffects::new());
+    appstate
+        .res
+        .lock()
+        .await
+        .add(&link, Resource::Light(light))
+        .unwrap();
+
+    let mut z2m = appstate.res.lock().await.z2m_channel();
+
+    let uri = format!("/clip/v2/resource/light/{}", link.rid);
+    let timed = |body: &Value| body["data"][0]["timed_effects"].clone();
+
+    let before = get(&appstate, &uri).await;
+    assert_eq!(timed(&before)["status"], json!("no_effect"));
+    assert_eq!(
+        timed(&before)["effect_values"],
+        json!(["no_effect", "sunrise", "sunset"])
+    );
+
+    let (status, _) = put(
+        &appstate,
+        &uri,
+        &json!({"timed_effects": {"effect": "sunset", "duration": 600_000}}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    /* a sunset fades the light out over the whole duration */
+    let ClientRequest::LightUpdate { upd, .. } = &*z2m.recv().await.unwrap().1 else {
+        panic!("expected a light update");
+    };
+    assert_eq!(upd.state, Some(DeviceState::Off));
+    assert_eq!(upd.transition, Some(600.0));
+
+    let running = get(&appstate, &uri).await;
+    assert_eq!(timed(&running)["status"], json!("sunset"));
+    assert_eq!(timed(&running)["duration"], json!(600_000));
+
+    /* durations beyond 6 hours are refused, and change nothing */
+    let (status, _) = put(
+        &appstate,
+        &uri,
+        &json!({"timed_effects": {"effect": "sunrise", "duration": 7 * 3_600_000}}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        timed(&get(&appstate, &uri).await)["status"],
+        json!("sunset")
+    );
+
+    /* any other change ends the effect */
+    let (status, _) = put(&appstate, &uri, &json!({"on": {"on": true}})).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let after = get(&appstate, &uri).await;
+    assert_eq!(timed(&after)["status"], json!("no_effect"));
+    assert!(timed(&after).get("duration").is_none());
+}
+
+#[tokio::test]
+async fn zone_scenes_are_recalled_light_by_light() {
+    let dir = common::TempDir::new("zone-scenes");
+    let appstate = common::appstate(&dir, common::config());
+    let mut z2m = appstate.res.lock().await.z2m_channel();
+
+    let lights = ["Desk", "Shelf"].map(|name| RType::Light.deterministic(name));
+    let link_zone = RType::Zone.deterministic("Reading corner");
+    {
+        let mut lock = appstate.res.lock().await;
+        for (link, brightness) in lights.iter().zip([40.0, 80.0]) {
+            let mut light = Light::new(
+                RType::Device.deterministic(link.rid),
+                Metadata::new(DeviceArchetype::SpotBulb, "light"),
+            );
+            light.dimming = Some(Dimming {
+                brightness,
+                min_dim_level: None,
+            });
+            lock.add(link, Resource::Light(light)).unwrap();
+        }
+        let zone = Zone {
+            metadata: RoomMetadata::new(RoomArchetype::Reading, "Reading corner"),
+            children: lights.to_vec(),
+            services: vec![],
+        };
+        lock.add(&link_zone, Resource::Zone(zone)).unwrap();
+    }
+
+    /* without actions, the scene starts from the current light states */
+    let req = Request::post("/clip/v2/resource/scene")
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            json!({
+                "actions": [],
+                "group": link_zone,
+                "metadata": {"name": "Reading"},
+                "palette": {},
+                "speed": 0.5,
+            })
+            .to_string(),
+        ))
+        .unwrap();
+    let (status, body) = send(&appstate, req).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let rid = body["data"][0]["rid"].as_str().unwrap().to_string();
+
+    let scene = get(&appstate, &format!("/clip/v2/resource/scene/{rid}")).await;
+    assert_eq!(scene["data"][0]["actions"].as_array().unwrap().len(), 2);
+
+    /* z2m is never asked to store the scene itself */
+    let uri = format!("/clip/v2/resource/scene/{rid}");
+    let (status, _) = put(&appstate, &uri, &json!({"recall": {"action": "active"}})).await;
+    assert_eq!(status, StatusCode::OK);
+
+    /* a single request, which each backend expands for its own lights */
+    let (_, req) = z2m.try_recv().unwrap();
+    let ClientRequest::SceneRecall { scene } = &*req else {
+        panic!("unexpected request {req:?}");
+    };
+    assert!(z2m.try_recv().is_err());
+
+    let mut recalled: Vec<_> = appstate
+        .res
+        .lock()
+        .await
+        .zone_scene_updates(scene)
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|(device, upd)| (device, upd.brightness))
+        .collect();
+    recalled.sort_by_key(|(device, _)| lights.iter().position(|light| light == device));
+    assert_eq!(recalled.len(), 2);
+    assert_eq!(recalled[0].0, lights[0]);
+    assert!(recalled[0].1.unwrap() < recalled[1].1.unwrap());
+
+    let scene = get(&appstate, &uri).await;
+    assert_eq!(scene["data"][0]["status"]["active"], json!("static"));
+
+    /* deleting needs no confirmation from z2m either */
+    let req = Request::delete(&uri).body(Body::empty()).unwrap();
+    assert_eq!(send(&appstate, req).await.0, StatusCode::OK);
+    assert!(appstate
+        .res
+        .lock()
+        .await
+        .get::<Scene>(&RType::Scene.link_to(rid.parse().unwrap()))
+        .is_err());
+}
+
+#[tokio::test]
+async fn offline_server_gives_service_unavailable() {
+    let dir = common::TempDir::new("offline-v2");
+    let appstate = common::appstate(&dir, common::config());
+    let mut mapper = common::mapper(appstate.res.clone());
+    for msg in common::bridge_announce() {
+        mapper.handle_message(&msg).await.unwrap();
+    }
+
+    let mut lock = appstate.res.lock().await;
+    let light = lock.get_resources_by_type(RType::Light)[0].id;
+    lock.z2m_status_mut("test", "ws://unused")
+        .failed("connection reset".into());
+    drop(lock);
+
+    let uri = format!("/clip/v2/resource/light/{light}");
+    let (status, body) = put(&appstate, &uri, &json!({"on": {"on": true}})).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["data"], json!([]));
+    assert!(body["errors"][0].as_str().unwrap().contains("test"));
+}
+
+#[tokio::test]
+async fn bridge_services_are_added_to_existing_state() {
+    let dir = common::TempDir::new("old-state");
+    let config = common::config();
+    let bridge_id = certificate::hue_bridge_id(config.bridge.mac);
+    let link_bridge = RType::Bridge.deterministic(&bridge_id);
+
+    /* a state file saved by a version that did not have these services yet */
+    let mut old = Resources::new(State::new());
+    old.add_bridge(bridge_id.clone()).unwrap();
+    old.write(std::fs::File::create(dir.join("state.yaml")).unwrap())
+        .unwrap();
+
+    let appstate = common::appstate(&dir, config);
+    let lock = appstate.res.lock().await;
+
+    let dev = lock
+        .get::<Device>(&RType::Device.deterministic(link_bridge.rid))
+        .unwrap();
+    let power = dev
+        .services
+        .iter()
+        .find(|rl| rl.rtype == RType::DevicePower)
+        .expect("bridge device has no power service");
+    assert!(lock.get::<DevicePower>(power).is_ok());
+
+    /* the location can be set, for circadian lighting */
+    let geoloc = RType::Geolocation.deterministic(link_bridge.rid);
+    drop(lock);
+    let (status, _) = put(
+        &appstate,
+        &format!("/clip/v2/resource/geolocation/{}", geoloc.rid),
+        &json!({"latitude": 55.7, "longitude": 12.6}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(appstate.res.lock().await.get_location().is_some());
+}
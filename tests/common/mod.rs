@@ -0,0 +1,278 @@
+/*
+ * Test harness with a mock zigbee2mqtt server.
+ *
+ * The mock speaks just enough of the z2m websocket protocol to drive a real
+ * z2m::Client: on connect, it announces canned bridge messages (by default,
+ * taken from the fuzzing corpus in fuzz/corpus/message), after which tests
+ * can push further messages (e.g. device updates), and observe everything
+ * the client sends back.
+ */
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{sleep, timeout};
+use tokio_tungstenite::tungstenite::Message;
+
+use bifrost::config::{AppConfig, Z2mServer};
+use bifrost::model::state::State;
+use bifrost::resource::Resources;
+use bifrost::server::appstate::AppState;
+use bifrost::z2m::mapper::Mapper;
+use bifrost::z2m::Client;
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const CONFIG: &str = r#"
+bridge:
+  name: test
+  mac: "00:11:22:33:44:55"
+  ipaddress: 127.0.0.1
+  http_port: 80
+  https_port: 443
+  netmask: 255.255.255.0
+  gateway: 127.0.0.1
+  timezone: UTC
+z2m: {}
+bifrost:
+  state_file: state.yaml
+  cert_file: cert.pem
+  enable_v1: true
+  v1_pairing: true
+  fake_portal: false
+  stable_v1_ids: false
+"#;
+
+#[must_use]
+pub fn config() -> AppConfig {
+    serde_yml::from_str(CONFIG).unwrap()
+}
+
+/// A temporary directory for a test, removed again when dropped
+pub struct TempDir(Utf8PathBuf);
+
+impl TempDir {
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        let dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("bifrost-test-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Utf8Path {
+        &self.0
+    }
+
+    #[must_use]
+    pub fn join(&self, path: &str) -> Utf8PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Application state for `config`, with its state and certificate files
+/// kept in `dir`
+#[must_use]
+pub fn appstate(dir: &TempDir, mut config: AppConfig) -> AppState {
+    config.bifrost.state_file = dir.join("state.yaml");
+    config.bifrost.cert_file = dir.join("cert.pem");
+    AppState::from_config(config).unwrap()
+}
+
+/// Read a message from the fuzzing corpus
+#[must_use]
+pub fn corpus(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz/corpus/message")
+        .join(format!("{name}.json"));
+
+    std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("cannot read {}: {err}", path.display()))
+}
+
+/// The messages z2m sends when a client connects
+#[must_use]
+pub fn bridge_announce() -> Vec<String> {
+    ["bridge_state_online", "bridge_devices", "bridge_groups"]
+        .map(corpus)
+        .to_vec()
+}
+
+pub struct MockZ2m {
+    pub url: String,
+    push: mpsc::UnboundedSender<Message>,
+    sent: mpsc::UnboundedReceiver<(Instant, Value)>,
+    pongs: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+impl MockZ2m {
+    /// Start a mock server, which sends `announce` to the first client
+    pub async fn start(announce: Vec<String>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let (push, mut pushed) = mpsc::unbounded_channel::<Message>();
+        let (sent_tx, sent) = mpsc::unbounded_channel();
+        let (pongs_tx, pongs) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+
+            for msg in announce {
+                ws.send(Message::Text(msg)).await.unwrap();
+            }
+
+            loop {
+                tokio::select! {
+                    msg = pushed.recv() => {
+                        let Some(msg) = msg else { break };
+                        let _ = ws.send(msg).await;
+                    }
+                    msg = ws.next() => {
+                        let Some(Ok(msg)) = msg else { break };
+                        match msg {
+                            Message::Text(txt) => {
+                                let _ = sent_tx.send((Instant::now(), serde_json::from_str(&txt).unwrap()));
+                            }
+                            Message::Pong(data) => {
+                                let _ = pongs_tx.send(data);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            url,
+            push,
+            sent,
+            pongs,
+        }
+    }
+
+    /// Start a mock server with the default bridge announcement
+    pub async fn with_bridge() -> Self {
+        Self::start(bridge_announce()).await
+    }
+
+    /// Send a message to the connected client
+    pub fn send(&self, msg: impl Into<String>) {
+        self.send_frame(Message::Text(msg.into()));
+    }
+
+    /// Send a raw websocket frame (e.g. ping or close) to the connected client
+    pub fn send_frame(&self, msg: Message) {
+        self.push.send(msg).unwrap();
+    }
+
+    /// Payload of the next pong sent by the client
+    pub async fn recv_pong(&mut self) -> Vec<u8> {
+        timeout(TIMEOUT, self.pongs.recv())
+            .await
+            .expect("no pong from z2m client")
+            .unwrap()
+    }
+
+    /// Next message sent by the client, with the time it arrived
+    pub async fn recv_timed(&mut self) -> (Instant, Value) {
+        timeout(TIMEOUT, self.sent.recv())
+            .await
+            .expect("no message from z2m client")
+            .unwrap()
+    }
+
+    /// Next message sent by the client
+    pub async fn recv(&mut self) -> Value {
+        self.recv_timed().await.1
+    }
+
+    /// Next message sent by the client, on the given topic
+    pub async fn recv_topic(&mut self, topic: &str) -> Value {
+        loop {
+            let msg = self.recv().await;
+            if msg["topic"] == topic {
+                return msg;
+            }
+        }
+    }
+
+    /// Configuration for a z2m client of this server
+    #[must_use]
+    pub fn server(&self) -> Z2mServer {
+        Z2mServer {
+            url: self.url.clone(),
+            group_prefix: None,
+            rooms: HashMap::new(),
+            watchdog_secs: Z2mServer::default_watchdog_secs(),
+            forward_logging: false,
+            reconcile_secs: 0,
+            frontend: None,
+        }
+    }
+
+    /// Connect a z2m client to this server, and run it in the background
+    pub fn spawn_client(&self, config: AppConfig, res: Arc<Mutex<Resources>>) {
+        let client = Client::new("test".into(), self.server(), Arc::new(config), res).unwrap();
+        tokio::spawn(client.run_forever());
+    }
+}
+
+/// A z2m mapper (without any connection), for testing the mapping layer
+#[must_use]
+pub fn mapper(res: Arc<Mutex<Resources>>) -> Mapper {
+    named_mapper("test", res)
+}
+
+/// A z2m mapper for the server called `name`
+#[must_use]
+pub fn named_mapper(name: &str, res: Arc<Mutex<Resources>>) -> Mapper {
+    let server = Z2mServer {
+        url: "ws://unused".into(),
+        group_prefix: None,
+        rooms: HashMap::new(),
+        watchdog_secs: Z2mServer::default_watchdog_secs(),
+        forward_logging: false,
+        reconcile_secs: 0,
+        frontend: None,
+    };
+    Mapper::new(name.into(), server, Arc::new(config()), res)
+}
+
+#[must_use]
+pub fn resources() -> Arc<Mutex<Resources>> {
+    Arc::new(Mutex::new(Resources::new(State::new())))
+}
+
+/// Poll the resources until `func` returns a value
+pub async fn wait_for<T>(res: &Mutex<Resources>, func: impl Fn(&Resources) -> Option<T>) -> T {
+    timeout(TIMEOUT, async {
+        loop {
+            if let Some(value) = func(&*res.lock().await) {
+                return value;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for resources")
+}
@@ -0,0 +1,192 @@
+/*
+ * Tests of configuration checks.
+ */
+
+mod common;
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+use bifrost::config::{self, unknown_keys};
+use bifrost::doctor::{self, Status};
+
+use common::TempDir;
+
+/* a valid configuration, with `bridge` settings, and `extra` lines added
+ * at the end (inside the z2m server section, if indented) */
+fn write_config(dir: &TempDir, bridge: &str, extra: &str) -> Utf8PathBuf {
+    let path = dir.join("config.yaml");
+    let yaml = format!(
+        "bifrost:\n  state_file: {}\n  cert_file: {}\n\
+         bridge:\n  name: Bifrost\n  timezone: Europe/Copenhagen\n{bridge}\n\
+         z2m:\n  server1:\n    url: ws://10.0.0.100:8080\n{extra}\n",
+        dir.join("state.yaml"),
+        dir.join("cert.pem"),
+    );
+    std::fs::write(&path, yaml).unwrap();
+    path
+}
+
+const BRIDGE: &str = "  mac: 00:11:22:33:44:55
+  ipaddress: 10.12.0.20
+  netmask: 255.255.255.0
+  gateway: 10.12.0.1";
+
+/* checks with the given status, whose message contains `text` */
+fn checks(config: &Utf8Path, status: Status, text: &str) -> Vec<String> {
+    doctor::config_checks(config, None)
+        .into_iter()
+        .filter(|(st, msg)| *st == status && msg.contains(text))
+        .map(|(_, msg)| msg)
+        .collect()
+}
+
+#[test]
+fn known_keys_are_accepted() {
+    let dir = TempDir::new("config-known");
+    let config = write_config(
+        &dir,
+        BRIDGE,
+        "rooms:\n  Living Room:\n    icon: living_room",
+    );
+
+    assert!(unknown_keys(&config).unwrap().is_empty());
+}
+
+#[test]
+fn misspelled_keys_are_reported() {
+    let dir = TempDir::new("config-misspelled");
+    let config = write_config(
+        &dir,
+        &format!("{BRIDGE}\n  enable_v2: true"),
+        "    urll: ws://typo\n\
+         scene_icon: []\n\
+         scene_icons:\n  - pattern: ^Relax\n    icon: a1f7da49-d181-4328-abea-68c9dc4b5416\n    name: x",
+    );
+
+    assert_eq!(
+        unknown_keys(&config).unwrap(),
+        [
+            "bridge.enable_v2",
+            "scene_icon",
+            "scene_icons.0.name",
+            "z2m.server1.urll",
+        ]
+    );
+}
+
+#[test]
+fn state_file_can_be_overridden() {
+    let example = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("config.example.yaml");
+
+    let config = config::load(&example, None).unwrap();
+    assert_eq!(config.bifrost.state_file, "state.yaml");
+
+    let state = Utf8Path::new("/var/lib/bifrost/state.json");
+    let config = config::load(&example, Some(state)).unwrap();
+    assert_eq!(config.bifrost.state_file, state);
+}
+
+#[test]
+fn valid_config_passes() {
+    let dir = TempDir::new("config-valid");
+    let config = write_config(&dir, BRIDGE, "");
+
+    assert!(checks(&config, Status::Fail, "").is_empty());
+    assert!(doctor::check_config(&config, None));
+}
+
+#[test]
+fn invalid_mac_fails() {
+    let dir = TempDir::new("config-mac");
+
+    for mac in [
+        "00:00:00:00:00:00",
+        "ff:ff:ff:ff:ff:ff",
+        "01:00:5e:00:00:01",
+    ] {
+        let bridge = BRIDGE.replace("00:11:22:33:44:55", mac);
+        let config = write_config(&dir, &bridge, "");
+        assert_eq!(
+            checks(&config, Status::Fail, "Mac address").len(),
+            1,
+            "{mac}"
+        );
+    }
+}
+
+#[test]
+fn overlapping_ports_fail() {
+    let dir = TempDir::new("config-ports");
+
+    let config = write_config(
+        &dir,
+        &format!("{BRIDGE}\n  http_port: 8080\n  https_port: 8080"),
+        "",
+    );
+    assert_eq!(checks(&config, Status::Fail, "both use port 8080").len(), 1);
+
+    /* unless both are meant to be served on one port */
+    let config = write_config(
+        &dir,
+        &format!("{BRIDGE}\n  http_port: 8080\n  https_port: 8080\n  single_port: true"),
+        "",
+    );
+    assert_eq!(checks(&config, Status::Ok, "single port mode").len(), 1);
+}
+
+#[test]
+fn gateway_outside_subnet_warns() {
+    let dir = TempDir::new("config-subnet");
+
+    let config = write_config(&dir, &BRIDGE.replace("10.12.0.1", "10.13.0.1"), "");
+    assert_eq!(checks(&config, Status::Warn, "not on the network").len(), 1);
+
+    let config = write_config(&dir, &BRIDGE.replace("255.255.255.0", "255.0.255.0"), "");
+    assert_eq!(
+        checks(&config, Status::Warn, "not a valid netmask").len(),
+        1
+    );
+
+    /* warnings do not fail the check */
+    assert!(doctor::check_config(&config, None));
+}
+
+#[test]
+fn unwritable_state_file_fails() {
+    let dir = TempDir::new("config-writable");
+    let config = write_config(&dir, BRIDGE, "");
+    assert_eq!(checks(&config, Status::Ok, "bifrost.state_file").len(), 1);
+
+    /* the state file would go in a directory that cannot exist */
+    std::fs::write(dir.join("file"), "").unwrap();
+    let state = dir.join("file/state.yaml");
+    let results = doctor::config_checks(&config, Some(&state));
+    assert!(results
+        .iter()
+        .any(|(st, msg)| *st == Status::Fail && msg.contains("bifrost.state_file")));
+    assert!(!doctor::check_config(&config, Some(&state)));
+}
+
+#[test]
+fn check_config_exit_code() {
+    let dir = TempDir::new("config-exit");
+    let bifrost = env!("CARGO_BIN_EXE_bifrost");
+    let run = |config: &Utf8Path| {
+        std::process::Command::new(bifrost)
+            .args(["--config", config.as_str(), "check-config"])
+            .env_remove("BIFROST_STATE")
+            .output()
+            .unwrap()
+            .status
+    };
+
+    let config = write_config(&dir, BRIDGE, "");
+    assert!(run(&config).success());
+
+    let config = write_config(
+        &dir,
+        &BRIDGE.replace("00:11:22:33:44:55", "00:00:00:00:00:00"),
+        "",
+    );
+    assert_eq!(run(&config).code(), Some(1));
+}
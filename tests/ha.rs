@@ -0,0 +1,190 @@
+/*
+ * Tests of leader election and state replication between instances in high
+ * availability mode.
+ */
+
+mod common;
+
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+use bifrost::config::HaConfig;
+use bifrost::error::ApiError;
+use bifrost::hue::api::{RType, Resource, Room, RoomArchetype, RoomMetadata};
+use bifrost::server::ha::{self, LeaderLock};
+use bifrost::server::replication::{self, Follower, Replica};
+use bifrost::storage;
+
+use common::TempDir;
+
+fn ha_config(dir: &TempDir, peers: &[String]) -> HaConfig {
+    serde_json::from_value(json!({
+        "lock_file": dir.join("leader.lock"),
+        "name": "test",
+        "peers": peers,
+        "heartbeat_secs": 1,
+        "timeout_secs": 1,
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn unwritable_lock_gives_up_leadership() {
+    let dir = TempDir::new("ha-unwritable");
+
+    let mut lock = LeaderLock::new(&ha_config(&dir, &[]));
+    lock.acquire().await.unwrap();
+
+    /* the shared storage goes away, so the lock can no longer be refreshed */
+    std::fs::remove_dir_all(dir.path()).unwrap();
+
+    let err = lock.hold().await.unwrap_err();
+    assert!(matches!(err, ApiError::LeadershipLost(_)), "{err}");
+}
+
+#[tokio::test]
+async fn only_one_instance_takes_over_a_stale_lease() {
+    let dir = TempDir::new("ha-takeover");
+    let config = ha_config(&dir, &[]);
+
+    /* the first leader stops refreshing its lease */
+    let mut old = LeaderLock::new(&config);
+    assert_eq!(old.acquire().await.unwrap(), 1);
+
+    let mut a = LeaderLock::new(&config);
+    let mut b = LeaderLock::new(&config);
+    let won = tokio::select! {
+        res = a.acquire() => res.unwrap(),
+        res = b.acquire() => res.unwrap(),
+    };
+    assert_eq!(won, 2);
+    assert_eq!(a.token() + b.token(), 2);
+
+    /* the loser keeps waiting, since the new lease is fresh */
+    let loser = if a.token() == 0 { &mut a } else { &mut b };
+    assert!(timeout(Duration::from_millis(500), loser.acquire())
+        .await
+        .is_err());
+
+    /* the old leader finds it has been replaced, and its lease is gone */
+    let err = old.hold().await.unwrap_err();
+    assert!(matches!(err, ApiError::LeadershipLost(_)), "{err}");
+    assert!(!ha::lease_path(&config.lock_file, 1).exists());
+}
+
+#[tokio::test]
+async fn reachable_peer_prevents_takeover() {
+    let dir = TempDir::new("ha-peer");
+    let peer = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = ha_config(&dir, &[peer.local_addr().unwrap().to_string()]);
+
+    std::fs::write(ha::lease_path(&config.lock_file, 1), "").unwrap();
+    std::thread::sleep(Duration::from_millis(1100));
+
+    /* the lease is stale, but the active instance still answers */
+    let mut lock = LeaderLock::new(&config);
+    assert!(timeout(Duration::from_millis(2500), lock.acquire())
+        .await
+        .is_err());
+
+    drop(peer);
+    assert_eq!(lock.acquire().await.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn standby_saves_replicated_state() {
+    let dir = TempDir::new("ha-replicate");
+    let state_file = dir.join("state.yaml");
+
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let res = common::resources();
+    let link = RType::Room.deterministic("replicated");
+    let room = Room {
+        children: vec![],
+        metadata: RoomMetadata::new(RoomArchetype::Office, "Office"),
+        services: vec![],
+    };
+    res.lock().await.add(&link, Resource::Room(room)).unwrap();
+
+    tokio::spawn(replication::serve(
+        addr,
+        res.clone(),
+        1,
+        Duration::from_secs(1),
+    ));
+
+    let follower = Follower::new(&ha_config(&dir, &[addr.to_string()]), &state_file);
+    let saved = |name: &'static str| {
+        let state_file = state_file.clone();
+        move || {
+            storage::load(&state_file)
+                .ok()
+                .flatten()
+                .and_then(|state| state.res.get(&link.rid).cloned())
+                .is_some_and(
+                    |res| matches!(res, Resource::Room(room) if room.metadata.name == name),
+                )
+        }
+    };
+
+    let wait = |check: Box<dyn Fn() -> bool>| async move {
+        timeout(common::TIMEOUT, async {
+            while !check() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .is_ok()
+    };
+
+    tokio::select! {
+        res = follower.run() => panic!("follower stopped: {res:?}"),
+        ok = async {
+            assert!(wait(Box::new(saved("Office"))).await);
+
+            /* changes are replicated as they happen */
+            res.lock()
+                .await
+                .update::<Room>(&link.rid, |room| room.metadata.name = "Study".into())
+                .unwrap();
+            wait(Box::new(saved("Study"))).await
+        } => assert!(ok),
+    }
+    follower.finish().await;
+}
+
+#[tokio::test]
+async fn standby_ignores_state_from_fenced_leader() {
+    let dir = TempDir::new("ha-fenced");
+    let state_file = dir.join("state.yaml");
+
+    /* a leader with token 2, followed by a stale leader with token 1 */
+    let mut peers = vec![];
+    for token in [2, 1] {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        peers.push(listener.local_addr().unwrap().to_string());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let state = (token == 1).then(bifrost::model::state::State::new);
+                let mut line = serde_json::to_vec(&Replica { token, state }).unwrap();
+                line.push(b'\n');
+                stream.write_all(&line).await.unwrap();
+            }
+        });
+    }
+
+    let follower = Follower::new(&ha_config(&dir, &peers), &state_file);
+    let _ = timeout(Duration::from_millis(1500), follower.run()).await;
+    follower.finish().await;
+
+    assert!(!state_file.exists());
+}
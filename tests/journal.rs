@@ -0,0 +1,138 @@
+/*
+ * Tests of the journal of resource changes.
+ */
+
+mod common;
+
+use std::net::SocketAddr;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use serde_json::json;
+use tower::{Service, ServiceExt};
+
+use bifrost::hue::api::{Light, On, RType};
+use bifrost::journal::{self, JournalEntry, Operation, Source, TraceId};
+use bifrost::server::{self};
+
+#[tokio::test]
+async fn updates_record_source_and_delta() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+    let mut rx = res.lock().await.journal_channel();
+
+    for msg in common::bridge_announce() {
+        mapper.handle_message(&msg).await.unwrap();
+    }
+    let lights = res.lock().await.get_resources_by_type(RType::Light);
+    let link = RType::Light.link_to(lights[0].id);
+    while rx.try_recv().is_ok() {}
+
+    /* a change from z2m */
+    let off = json!({"topic": "Kitchen ceiling", "payload": {"state": "OFF"}});
+    mapper.handle_message(&off.to_string()).await.unwrap();
+
+    let entry = rx.try_recv().unwrap();
+    assert_eq!(entry.source, Source::Z2m);
+    assert_eq!(entry.op, Operation::Update);
+    assert_eq!(entry.id, link.rid);
+    assert_eq!(entry.before, Some(json!({"on": {"on": true}})));
+    assert_eq!(entry.after, Some(json!({"on": {"on": false}})));
+
+    /* a change made directly (e.g. from an api handler) */
+    let mut lock = res.lock().await;
+    lock.update::<Light>(&link.rid, |light| light.on = On::new(true))
+        .unwrap();
+    let entry = rx.try_recv().unwrap();
+    assert_eq!(entry.source, Source::Api);
+
+    /* nothing changed, nothing recorded */
+    lock.update::<Light>(&link.rid, |light| light.on = On::new(true))
+        .unwrap();
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn api_request_is_traced_through_z2m() {
+    let dir = common::TempDir::new("trace");
+    let appstate = common::appstate(&dir, common::config());
+
+    let mut mapper = common::mapper(appstate.res.clone());
+    for msg in common::bridge_announce() {
+        mapper.handle_message(&msg).await.unwrap();
+    }
+    let lights = appstate
+        .res
+        .lock()
+        .await
+        .get_resources_by_type(RType::Light);
+    let link = RType::Light.link_to(lights[0].id);
+    let mut z2m = appstate.res.lock().await.z2m_channel();
+    let mut rx = appstate.res.lock().await.journal_channel();
+
+    /* the api request gets a trace id, returned to the client.. */
+    let svc = server::build_service(appstate.clone())
+        .call(SocketAddr::from(([127, 0, 0, 1], 40000)))
+        .await
+        .unwrap();
+    let req = Request::put(format!("/clip/v2/resource/light/{}", link.rid))
+        .header("Content-Type", "application/json")
+        .body(Body::from(json!({"on": {"on": false}}).to_string()))
+        .unwrap();
+    let resp = svc.oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let trace: TraceId = resp.headers()["x-trace-id"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    /* ..passed on with the z2m command.. */
+    let (req_trace, req) = z2m.try_recv().unwrap();
+    assert_eq!(req_trace, Some(trace));
+    TraceId::scope_opt(req_trace, mapper.submit(&req))
+        .await
+        .unwrap();
+
+    /* ..and recorded with the state change reported back by z2m */
+    let off = json!({"topic": "Kitchen ceiling", "payload": {"state": "OFF"}});
+    mapper.handle_message(&off.to_string()).await.unwrap();
+    let entry = rx.try_recv().unwrap();
+    assert_eq!(entry.source, Source::Z2m);
+    assert_eq!(entry.trace, Some(trace));
+    assert_eq!(entry.after, Some(json!({"on": {"on": false}})));
+    while let Ok(entry) = rx.try_recv() {
+        assert_eq!(entry.trace, Some(trace));
+    }
+
+    /* later changes from z2m have nothing to do with the request */
+    let on = json!({"topic": "Kitchen ceiling", "payload": {"state": "ON"}});
+    mapper.handle_message(&on.to_string()).await.unwrap();
+    let entry = rx.try_recv().unwrap();
+    assert_eq!(entry.id, link.rid);
+    assert_eq!(entry.trace, None);
+}
+
+#[test]
+fn replay_rebuilds_resources() {
+    let id = RType::Light.deterministic("replay").rid;
+    let entries = [
+        JournalEntry::add(id, RType::Light, json!({"on": {"on": true}, "name": "a"})),
+        JournalEntry::update(
+            id,
+            RType::Light,
+            &json!({"on": {"on": true}}),
+            &json!({"on": {"on": false}}),
+        )
+        .unwrap(),
+    ];
+
+    let res = journal::replay(&entries);
+    assert_eq!(res[&id], json!({"on": {"on": false}, "name": "a"}));
+
+    let deleted = [
+        entries[0].clone(),
+        JournalEntry::delete(id, RType::Light, json!({})),
+    ];
+    assert!(journal::replay(&deleted).is_empty());
+}
@@ -0,0 +1,619 @@
+/*
+ * Tests of the hue v1 api representation of resources.
+ */
+
+mod common;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use bifrost::error::ApiError;
+use bifrost::hue::api::{
+    ColorTemperatureUpdate, ColorUpdate, Device, DeviceArchetype, DeviceProductData, Dimming,
+    DimmingUpdate, GroupedLight, Light, LightCapabilities, LightEffect, LightEffects, Metadata,
+    MirekSchema, On, RType, Resource, ResourceLink, Room, RoomArchetype, RoomMetadata, Scene,
+    SceneAction, SceneActionElement, SceneMetadata, Zone,
+};
+use bifrost::hue::legacy_api::{
+    ApiLight, ApiLightStateUpdate, ApiResourceLink, ApiScene, NewResourceLink,
+};
+use bifrost::model::types::XY;
+use bifrost::resource::Resources;
+use bifrost::routes;
+
+fn add_light(res: &mut Resources, name: &str, func: impl FnOnce(&mut Light)) -> ResourceLink {
+    let link = RType::Light.deterministic(name);
+    let owner = RType::Device.deterministic(name);
+    let mut light = Light::new(owner, Metadata::new(DeviceArchetype::SpotBulb, name));
+    func(&mut light);
+    res.add(&link, Resource::Light(light)).unwrap();
+    link
+}
+
+/* a room with a color light, a dimmable white light, and an on/off plug,
+ * and a scene setting all of them to a color */
+fn mixed_room_scene(res: &mut Resources) -> (Value, [String; 3]) {
+    let color = add_light(res, "color", |light| {
+        light.dimming = Some(Dimming {
+            brightness: 100.0,
+            min_dim_level: None,
+        });
+        light.color_temperature = serde_json::from_value(json!({
+            "mirek": 300,
+            "mirek_valid": true,
+            "mirek_schema": {"mirek_minimum": 153, "mirek_maximum": 500},
+        }))
+        .unwrap();
+        light.color = serde_json::from_value(json!({
+            "xy": {"x": 0.3, "y": 0.3},
+            "gamut": {
+                "red": {"x": 0.68, "y": 0.31},
+                "green": {"x": 0.17, "y": 0.7},
+                "blue": {"x": 0.15, "y": 0.06},
+            },
+            "gamut_type": "C",
+        }))
+        .unwrap();
+    });
+    let white = add_light(res, "white", |light| {
+        light.dimming = Some(Dimming {
+            brightness: 100.0,
+            min_dim_level: None,
+        });
+    });
+    let plug = add_light(res, "plug", |_| {});
+
+    let link_room = RType::Room.deterministic("mixed");
+    let room = Room {
+        children: vec![],
+        metadata: RoomMetadata::new(RoomArchetype::LivingRoom, "mixed"),
+        services: vec![],
+    };
+    res.add(&link_room, Resource::Room(room)).unwrap();
+
+    let action = SceneAction {
+        color: Some(ColorUpdate::new(XY::new(0.5, 0.4))),
+        color_temperature: Some(ColorTemperatureUpdate::new(350)),
+        dimming: Some(DimmingUpdate::new(50.0)),
+        on: Some(On::new(true)),
+    };
+    let scene = Scene {
+        actions: [color, white, plug]
+            .into_iter()
+            .map(|target| SceneActionElement {
+                action: action.clone(),
+                target,
+            })
+            .collect(),
+        auto_dynamic: false,
+        group: link_room,
+        metadata: SceneMetadata {
+            appdata: None,
+            image: None,
+            name: "Evening".into(),
+        },
+        palette: Value::Null,
+        speed: 0.5,
+        status: None,
+    };
+
+    let api = ApiScene::from_scene(res, Uuid::nil(), &scene).unwrap();
+    let ids = [color, white, plug].map(|link| res.get_id_v1(link.rid).unwrap());
+    (serde_json::to_value(api).unwrap(), ids)
+}
+
+#[test]
+fn lightstates_follow_light_capabilities() {
+    let res = common::resources();
+    let mut lock = res.try_lock().unwrap();
+    let (scene, [color, white, plug]) = mixed_room_scene(&mut lock);
+    let states = &scene["lightstates"];
+
+    assert_eq!(states[&color]["bri"], json!(127));
+    assert!(states[&color]["xy"].is_array());
+    assert_eq!(states[&color]["ct"], json!(350));
+
+    assert!(states[&white]["bri"].is_number());
+    assert!(states[&white].get("xy").is_none());
+    assert!(states[&white].get("ct").is_none());
+
+    assert_eq!(states[&plug], json!({"on": true}));
+}
+
+#[test]
+fn light_colors_are_fitted_to_capabilities() {
+    let res = common::resources();
+    let mut lock = res.try_lock().unwrap();
+    mixed_room_scene(&mut lock);
+    add_light(&mut lock, "warm", |light| {
+        light.color_temperature = serde_json::from_value(json!({
+            "mirek": null,
+            "mirek_valid": true,
+            "mirek_schema": {"mirek_minimum": 200, "mirek_maximum": 454},
+        }))
+        .unwrap();
+    });
+    let caps = |name: &str| {
+        lock.get::<Light>(&RType::Light.deterministic(name))
+            .unwrap()
+            .capabilities()
+    };
+
+    /* a warm white color becomes the matching color temperature */
+    let warm_white = XY::from_mirek(370);
+    let (xy, mirek) = caps("warm").fit_color(Some(warm_white), None).unwrap();
+    assert!(xy.is_none());
+    assert!(mirek.unwrap().abs_diff(370) < 10, "{mirek:?}");
+
+    /* ..clamped to what the light can do */
+    let (_, mirek) = caps("warm")
+        .fit_color(Some(XY::D65_WHITE_POINT), None)
+        .unwrap();
+    assert_eq!(mirek, Some(200));
+
+    /* color lights keep their color */
+    let (xy, mirek) = caps("color").fit_color(Some(warm_white), None).unwrap();
+    assert_eq!((xy, mirek), (Some(warm_white), None));
+
+    assert!(matches!(
+        caps("white").fit_color(Some(warm_white), None),
+        Err(ApiError::ColorUnsupported)
+    ));
+    assert!(matches!(
+        caps("plug").fit_color(None, Some(370)),
+        Err(ApiError::ColorTemperatureUnsupported)
+    ));
+
+    /* in the v1 api, only the unsupported parameters are refused */
+    let upd: ApiLightStateUpdate =
+        serde_json::from_value(json!({"on": true, "bri": 100, "ct": 300})).unwrap();
+    let (upd, unsupported) = upd.split_unsupported(&caps("plug"));
+    assert_eq!(unsupported, ["bri", "ct"]);
+    assert_eq!(serde_json::to_value(upd).unwrap(), json!({"on": true}));
+}
+
+fn v1_light(res: &Resources, name: &str) -> Value {
+    let link = RType::Light.deterministic(name);
+    let light = res.get::<Light>(&link).unwrap();
+    let dev = Device {
+        product_data: DeviceProductData::hue_bridge_v2(),
+        metadata: light.metadata.clone(),
+        services: vec![link],
+    };
+    serde_json::to_value(ApiLight::from_dev_and_light(&link.rid, &dev, light)).unwrap()
+}
+
+#[test]
+fn v1_lights_follow_light_capabilities() {
+    let res = common::resources();
+    let mut lock = res.try_lock().unwrap();
+    mixed_room_scene(&mut lock);
+
+    let color = v1_light(&lock, "color");
+    assert_eq!(color["type"], json!("Extended color light"));
+    assert_eq!(
+        color["capabilities"]["control"]["colorgamuttype"],
+        json!("C")
+    );
+    assert_eq!(
+        color["capabilities"]["control"]["ct"],
+        json!({"min": 153, "max": 500})
+    );
+    assert_eq!(color["capabilities"]["streaming"]["renderer"], json!(true));
+
+    let white = v1_light(&lock, "white");
+    assert_eq!(white["type"], json!("Dimmable light"));
+    assert!(white["capabilities"]["control"].get("ct").is_none());
+
+    let plug = v1_light(&lock, "plug");
+    assert_eq!(plug["type"], json!("On/Off plug-in unit"));
+    assert!(plug["capabilities"]["control"].get("colorgamut").is_none());
+    assert_eq!(plug["capabilities"]["streaming"]["renderer"], json!(false));
+}
+
+#[test]
+fn group_capabilities_combine_members() {
+    let res = common::resources();
+    let mut lock = res.try_lock().unwrap();
+    mixed_room_scene(&mut lock);
+
+    add_light(&mut lock, "warm", |light| {
+        light.color_temperature = serde_json::from_value(json!({
+            "mirek": null,
+            "mirek_valid": true,
+            "mirek_schema": {"mirek_minimum": 200, "mirek_maximum": 454},
+        }))
+        .unwrap();
+        light.effects = LightEffects::from_z2m_values(&["candle".to_string()]);
+    });
+
+    let members: Vec<LightCapabilities> = ["white", "plug", "warm"]
+        .into_iter()
+        .map(|name| {
+            let link = RType::Light.deterministic(name);
+            lock.get::<Light>(&link).unwrap().capabilities()
+        })
+        .collect();
+    let caps: LightCapabilities = members.iter().collect();
+
+    assert!(caps.dimmable);
+    assert!(caps.color.is_none());
+    assert_eq!(
+        caps.color_temperature,
+        Some(MirekSchema {
+            mirek_minimum: 200,
+            mirek_maximum: 454,
+        })
+    );
+    assert!(caps.supports_effect(LightEffect::Candle));
+    assert_eq!(caps.clamp_mirek(500), 454);
+}
+
+async fn get_body(router: &axum::Router, uri: &str) -> String {
+    let req = Request::get(uri).body(Body::empty()).unwrap();
+    let resp = router.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK, "{uri}");
+
+    let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+async fn get_json(router: &axum::Router, uri: &str) -> Value {
+    serde_json::from_str(&get_body(router, uri).await).unwrap()
+}
+
+#[tokio::test]
+async fn groups_report_class_and_type() {
+    let dir = common::TempDir::new("groups");
+    let state = common::appstate(&dir, common::config());
+
+    let (room_id, zone_id, light_id) = {
+        let mut lock = state.res.lock().await;
+        let light = add_light(&mut lock, "counter", |_| {});
+
+        let link_room = RType::Room.deterministic("kitchen");
+        let link_room_glight = RType::GroupedLight.deterministic(link_room.rid);
+        let room = Room {
+            children: vec![],
+            metadata: RoomMetadata::new(RoomArchetype::Kitchen, "Kitchen"),
+            services: vec![link_room_glight],
+        };
+        lock.add(&link_room, Resource::Room(room)).unwrap();
+        lock.add(
+            &link_room_glight,
+            Resource::GroupedLight(GroupedLight::new(link_room)),
+        )
+        .unwrap();
+
+        let link_zone = RType::Zone.deterministic("upstairs");
+        let link_zone_glight = RType::GroupedLight.deterministic(link_zone.rid);
+        let zone = Zone {
+            children: vec![light],
+            metadata: RoomMetadata::new(RoomArchetype::TopFloor, "Upstairs"),
+            services: vec![link_zone_glight],
+        };
+        lock.add(&link_zone, Resource::Zone(zone)).unwrap();
+        lock.add(
+            &link_zone_glight,
+            Resource::GroupedLight(GroupedLight::new(link_zone)),
+        )
+        .unwrap();
+
+        (
+            lock.get_id_v1(link_room.rid).unwrap(),
+            lock.get_id_v1(link_zone.rid).unwrap(),
+            lock.get_id_v1(light.rid).unwrap(),
+        )
+    };
+
+    let router = routes::router(state);
+    let groups = get_json(&router, &format!("/api/{}/groups", Uuid::new_v4())).await;
+
+    assert_eq!(groups[&room_id]["type"], json!("Room"));
+    assert_eq!(groups[&room_id]["class"], json!("Kitchen"));
+
+    assert_eq!(groups[&zone_id]["type"], json!("Zone"));
+    assert_eq!(groups[&zone_id]["class"], json!("Top floor"));
+    assert_eq!(groups[&zone_id]["lights"], json!([light_id]));
+
+    let zone = get_json(
+        &router,
+        &format!("/api/{}/groups/{zone_id}", Uuid::new_v4()),
+    )
+    .await;
+    assert_eq!(zone, groups[&zone_id]);
+}
+
+#[tokio::test]
+async fn short_config_is_served_to_unknown_users() {
+    let dir = common::TempDir::new("config");
+    let router = routes::router(common::appstate(&dir, common::config()));
+
+    /* apps expect the fields in the same order as a real bridge */
+    let body = get_body(&router, "/api/config").await;
+    let keys = [
+        "name",
+        "datastoreversion",
+        "swversion",
+        "apiversion",
+        "mac",
+        "bridgeid",
+        "factorynew",
+        "replacesbridgeid",
+        "modelid",
+        "starterkitid",
+    ];
+    let positions: Vec<usize> = keys
+        .iter()
+        .map(|key| body.find(&format!("\"{key}\"")).unwrap())
+        .collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "{body}");
+
+    let short: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(short.as_object().unwrap().len(), keys.len());
+
+    let bridgeid = short["bridgeid"].as_str().unwrap();
+    assert_eq!(bridgeid, bridgeid.to_uppercase());
+    assert_eq!(&bridgeid[6..10], "FFFE");
+
+    assert_eq!(get_json(&router, "/api/0/config").await, short);
+    assert_eq!(get_json(&router, "/api/nouser/config").await, short);
+
+    /* so do well-formed users that never paired */
+    let stranger = get_json(&router, &format!("/api/{}/config", Uuid::new_v4())).await;
+    assert_eq!(stranger, short);
+
+    /* only users created by pairing get the full config */
+    let (_, reply) = send(
+        &router,
+        json_request("POST", "/api", &json!({"devicetype": "app#phone"})),
+    )
+    .await;
+    let username = reply[0]["success"]["username"].as_str().unwrap();
+    let full = get_json(&router, &format!("/api/{username}/config")).await;
+    assert_eq!(full["bridgeid"], short["bridgeid"]);
+    assert_eq!(full["whitelist"][username]["name"], json!("app#phone"));
+}
+
+#[tokio::test]
+async fn portal_stubs_follow_fake_portal() {
+    for fake_portal in [false, true] {
+        let dir = common::TempDir::new("portal");
+        let mut config = common::config();
+        config.bifrost.fake_portal = fake_portal;
+        let router = routes::router(common::appstate(&dir, config));
+        let user = Uuid::new_v4();
+
+        let portalstate = get_json(&router, &format!("/api/{user}/config/portalstate")).await;
+        assert_eq!(portalstate["signedon"], json!(fake_portal));
+        let communication = if fake_portal {
+            "connected"
+        } else {
+            "disconnected"
+        };
+        assert_eq!(portalstate["communication"], json!(communication));
+
+        /* the app turning on portal services is acknowledged, with the
+         * state bifrost keeps reporting */
+        let uri = format!("/api/{user}/config");
+        let body = json!({"portalservices": true});
+        let (status, reply) = send(&router, json_request("PUT", &uri, &body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            reply,
+            json!([{"success": {"/config/portalservices": fake_portal}}])
+        );
+    }
+}
+
+#[tokio::test]
+async fn datastore_version_follows_state_changes() {
+    let dir = common::TempDir::new("datastoreversion");
+    let state = common::appstate(&dir, common::config());
+    let router = routes::router(state.clone());
+
+    let version = |config: Value| {
+        config["datastoreversion"]
+            .as_str()
+            .unwrap()
+            .parse::<u64>()
+            .unwrap()
+    };
+
+    let before = version(get_json(&router, "/api/config").await);
+    assert_eq!(version(get_json(&router, "/api/config").await), before);
+
+    add_light(&mut *state.res.lock().await, "changed", |_| {});
+    assert!(version(get_json(&router, "/api/config").await) > before);
+}
+
+async fn send(router: &axum::Router, req: Request<Body>) -> (StatusCode, Value) {
+    let resp = router.clone().oneshot(req).await.unwrap();
+    let status = resp.status();
+    let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+fn json_request(method: &str, uri: &str, body: &Value) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn resourcelinks_are_stored_and_deleted_with_recycled_links() {
+    let dir = common::TempDir::new("resourcelinks");
+    let router = routes::router(common::appstate(&dir, common::config()));
+    let base = format!("/api/{}/resourcelinks", Uuid::new_v4());
+
+    let (status, reply) = send(
+        &router,
+        json_request(
+            "POST",
+            &base,
+            &json!({"name": "Sensors", "classid": 10, "recycle": true}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(reply, json!([{"success": {"id": "1"}}]));
+
+    let links = json!(["/resourcelinks/1", "/scenes/42"]);
+    let (_, reply) = send(
+        &router,
+        json_request(
+            "POST",
+            &base,
+            &json!({"name": "Formula", "classid": 10, "links": links}),
+        ),
+    )
+    .await;
+    assert_eq!(reply, json!([{"success": {"id": "2"}}]));
+
+    let (_, reply) = send(
+        &router,
+        json_request("PUT", &format!("{base}/2"), &json!({"name": "Wake up"})),
+    )
+    .await;
+    assert_eq!(
+        reply,
+        json!([{"success": {"/resourcelinks/2/name": "Wake up"}}])
+    );
+
+    let link = get_json(&router, &format!("{base}/2")).await;
+    assert_eq!(link["name"], "Wake up");
+    assert_eq!(link["type"], "Link");
+    assert_eq!(link["links"], links);
+    assert_eq!(get_json(&router, "/admin/resourcelinks").await["2"], link);
+
+    /* the recycled link is only referenced by the deleted one, so it goes too */
+    let req = Request::delete(format!("{base}/2"))
+        .body(Body::empty())
+        .unwrap();
+    let (status, reply) = send(&router, req).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(reply, json!([{"success": "/resourcelinks/2 deleted"}]));
+    assert_eq!(get_json(&router, &base).await, json!({}));
+}
+
+#[tokio::test]
+async fn put_on_other_resources_is_not_available() {
+    let dir = common::TempDir::new("put-unavailable");
+    let router = routes::router(common::appstate(&dir, common::config()));
+    let uri = format!("/api/{}/lights/1", Uuid::new_v4());
+
+    let (status, reply) = send(&router, json_request("PUT", &uri, &json!({"on": true}))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        reply,
+        json!([{"error": {
+            "type": 4,
+            "address": "/lights/1",
+            "description": "method, PUT, not available for resource, /lights/1",
+        }}])
+    );
+}
+
+#[test]
+fn deleted_resources_are_removed_from_resourcelinks() {
+    let res = common::resources();
+    let mut res = res.try_lock().unwrap();
+    let light = add_light(&mut res, "light", |_| {});
+    let addr = format!("/lights/{}", res.get_id_v1(light.rid).unwrap());
+
+    let new: NewResourceLink = serde_json::from_value(json!({
+        "name": "Formula",
+        "classid": 1,
+        "links": [addr, "/sensors/7"],
+    }))
+    .unwrap();
+    let id = res.add_resourcelink(new.with_owner(Uuid::nil())).unwrap();
+
+    res.delete(&light).unwrap();
+
+    let link: &ApiResourceLink = res.get_resourcelink(id).unwrap();
+    assert_eq!(link.links, ["/sensors/7"]);
+}
+
+#[tokio::test]
+async fn pairing_waits_for_linkbutton() {
+    let dir = common::TempDir::new("linkbutton");
+
+    let router = || {
+        let mut config = common::config();
+        config.bifrost.require_linkbutton = true;
+        config.bifrost.linkbutton_file = Some(dir.join("linkbutton"));
+        routes::router(common::appstate(&dir, config))
+    };
+    let first = router();
+
+    let pair = || json_request("POST", "/api", &json!({"devicetype": "test#bifrost"}));
+
+    let (_, reply) = send(&first, pair()).await;
+    assert_eq!(reply[0]["error"]["type"], json!(101));
+
+    /* pressed through the admin api */
+    let (status, reply) = send(
+        &first,
+        json_request("POST", "/admin/linkbutton", &json!({})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(reply["pressed"], json!(true));
+
+    let (_, reply) = send(&first, pair()).await;
+    let username = reply[0]["success"]["username"].as_str().unwrap();
+    let config = get_json(&first, &format!("/api/{username}/config")).await;
+    assert_eq!(config["linkbutton"], json!(true));
+
+    /* a fresh instance, pressed by touching the file */
+    let second = router();
+
+    let (_, reply) = send(&second, pair()).await;
+    assert!(reply[0].get("error").is_some());
+
+    std::fs::write(dir.join("linkbutton"), "").unwrap();
+    let (_, reply) = send(&second, pair()).await;
+    assert!(reply[0]["success"]["username"].is_string(), "{reply}");
+}
+
+#[tokio::test]
+async fn offline_server_refuses_v1_changes() {
+    let dir = common::TempDir::new("offline-v1");
+    let state = common::appstate(&dir, common::config());
+
+    let mut mapper = common::mapper(state.res.clone());
+    for msg in common::bridge_announce() {
+        mapper.handle_message(&msg).await.unwrap();
+    }
+
+    let mut lock = state.res.lock().await;
+    let light = lock.get_resources_by_type(RType::Light)[0].id;
+    let id = lock.get_id_v1(light).unwrap();
+    lock.z2m_status_mut("test", "ws://unused")
+        .failed("connection reset".into());
+    drop(lock);
+
+    let req = Request::put(format!("/api/{}/lights/{id}/state", Uuid::new_v4()))
+        .header("Content-Type", "application/json")
+        .body(Body::from(json!({"on": true}).to_string()))
+        .unwrap();
+    let resp = routes::router(state).oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let error = &body[0]["error"];
+    assert_eq!(error["type"], 201);
+    assert_eq!(error["address"], format!("/lights/{id}/state"));
+    assert!(error["description"]
+        .as_str()
+        .unwrap()
+        .contains("is not connected"));
+}
@@ -0,0 +1,98 @@
+/*
+ * Tests of light and room locks: api changes are refused, z2m updates are not.
+ */
+
+mod common;
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use tokio::sync::Mutex;
+
+use bifrost::error::ApiError;
+use bifrost::hue::api::{Light, RType, ResourceLink, Room};
+use bifrost::resource::Resources;
+
+fn time(hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 6, 21, hour, minute, 0).unwrap()
+}
+
+async fn kitchen() -> (Arc<Mutex<Resources>>, ResourceLink, ResourceLink) {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+    for msg in common::bridge_announce() {
+        mapper.handle_message(&msg).await.unwrap();
+    }
+
+    let lock = res.lock().await;
+    let light = RType::Light.link_to(lock.get_resources_by_type(RType::Light)[0].id);
+    let room = RType::Room.deterministic(("test", "Kitchen"));
+    let glight = *lock
+        .get::<Room>(&room)
+        .unwrap()
+        .grouped_light_service()
+        .unwrap();
+    drop(lock);
+
+    (res, light, glight)
+}
+
+#[tokio::test]
+async fn locked_light_refuses_changes_until_expired() {
+    let (res, light, glight) = kitchen().await;
+    let mut lock = res.lock().await;
+
+    assert!(lock.check_unlocked(&light, time(12, 0)).is_ok());
+
+    lock.set_lock(light.rid, time(13, 0));
+
+    let err = lock.check_unlocked(&light, time(12, 0)).unwrap_err();
+    assert!(matches!(err, ApiError::Locked(id, until) if id == light.rid && until == time(13, 0)));
+
+    /* the room contains the locked light */
+    assert!(matches!(
+        lock.check_unlocked(&glight, time(12, 0)),
+        Err(ApiError::Locked(..))
+    ));
+
+    assert!(lock.check_unlocked(&light, time(13, 0)).is_ok());
+    assert!(lock.get_locks(time(13, 0)).is_empty());
+}
+
+#[tokio::test]
+async fn locked_room_locks_its_lights() {
+    let (res, light, _) = kitchen().await;
+    let room = RType::Room.deterministic(("test", "Kitchen"));
+    let mut lock = res.lock().await;
+
+    lock.set_lock(room.rid, time(13, 0));
+
+    let err = lock.check_unlocked(&light, time(12, 0)).unwrap_err();
+    assert!(matches!(err, ApiError::Locked(id, _) if id == room.rid));
+
+    assert!(lock.remove_lock(&room.rid));
+    assert!(!lock.remove_lock(&room.rid));
+    assert!(lock.check_unlocked(&light, time(12, 0)).is_ok());
+}
+
+#[tokio::test]
+async fn locked_light_follows_z2m_updates() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+    for msg in common::bridge_announce() {
+        mapper.handle_message(&msg).await.unwrap();
+    }
+
+    let mut lock = res.lock().await;
+    let light = RType::Light.link_to(lock.get_resources_by_type(RType::Light)[0].id);
+    lock.set_lock(light.rid, Utc::now() + Duration::hours(1));
+    drop(lock);
+
+    mapper
+        .handle_message(&common::corpus("device_light_state"))
+        .await
+        .unwrap();
+
+    let lock = res.lock().await;
+    assert!(lock.get::<Light>(&light).unwrap().on.on);
+}
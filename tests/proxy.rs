@@ -0,0 +1,85 @@
+/*
+ * Tests of client address resolution behind reverse proxies.
+ */
+
+use std::net::IpAddr;
+
+use axum::http::{HeaderMap, HeaderValue};
+
+use bifrost::server::proxy::ClientInfo;
+
+fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+fn forwarded(xff: &str, proto: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-for", HeaderValue::from_str(xff).unwrap());
+    headers.insert("x-forwarded-proto", HeaderValue::from_str(proto).unwrap());
+    headers
+}
+
+#[test]
+fn untrusted_peer_is_the_client() {
+    let headers = forwarded("10.0.0.5", "https");
+    let info = ClientInfo::resolve(ip("192.168.1.20"), &headers, &[ip("192.168.1.2")]);
+
+    assert_eq!(info.addr, ip("192.168.1.20"));
+    assert_eq!(info.proto, None);
+}
+
+#[test]
+fn trusted_proxy_reports_client() {
+    let headers = forwarded("10.0.0.5", "HTTPS");
+    let info = ClientInfo::resolve(ip("192.168.1.2"), &headers, &[ip("192.168.1.2")]);
+
+    assert_eq!(info.addr, ip("10.0.0.5"));
+    assert_eq!(info.proto.as_deref(), Some("https"));
+}
+
+#[test]
+fn spoofed_addresses_are_skipped() {
+    /* the client claims to be 1.2.3.4, and went through two trusted proxies */
+    let trusted = [ip("192.168.1.2"), ip("192.168.1.3")];
+    let headers = forwarded("1.2.3.4, 10.0.0.5, 192.168.1.3", "http");
+    let info = ClientInfo::resolve(ip("192.168.1.2"), &headers, &trusted);
+
+    assert_eq!(info.addr, ip("10.0.0.5"));
+}
+
+#[test]
+fn missing_forwarded_header_keeps_peer() {
+    let info = ClientInfo::resolve(ip("192.168.1.2"), &HeaderMap::new(), &[ip("192.168.1.2")]);
+
+    assert_eq!(info.addr, ip("192.168.1.2"));
+    assert_eq!(info.proto, None);
+}
+
+#[test]
+fn base_url_follows_forwarded_headers() {
+    let proxy = [ip("192.168.1.2")];
+    let mut headers = forwarded("10.0.0.5", "https");
+    headers.insert("host", HeaderValue::from_static("bifrost.lan:8080"));
+    headers.insert(
+        "x-forwarded-host",
+        HeaderValue::from_static("hue.example.com"),
+    );
+
+    let info = ClientInfo::resolve(ip("192.168.1.2"), &headers, &proxy);
+    assert_eq!(
+        info.base_url(Some("/bifrost")).as_deref(),
+        Some("https://hue.example.com/bifrost")
+    );
+
+    /* a prefix stripped by the proxy is part of the url */
+    headers.insert("x-forwarded-prefix", HeaderValue::from_static("/hue/"));
+    let info = ClientInfo::resolve(ip("192.168.1.2"), &headers, &proxy);
+    assert_eq!(
+        info.base_url(Some("/bifrost")).as_deref(),
+        Some("https://hue.example.com/hue")
+    );
+
+    /* without a trusted proxy, there is nothing to go by */
+    let info = ClientInfo::resolve(ip("192.168.1.20"), &headers, &proxy);
+    assert_eq!(info.base_url(None), None);
+}
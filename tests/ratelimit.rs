@@ -0,0 +1,109 @@
+/*
+ * Tests of the v1 command rate limit emulation.
+ */
+
+mod common;
+
+use std::net::{IpAddr, SocketAddr};
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use serde_json::{json, Value};
+use tokio::time::{Duration, Instant};
+use tower::{Service, ServiceExt};
+use uuid::Uuid;
+
+use bifrost::server::{self, ratelimit::RateLimiter};
+
+fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn burst_is_limited_to_rate() {
+    let mut limiter = RateLimiter::new(10);
+    let now = Instant::now();
+
+    let allowed = (0..15)
+        .filter(|_| limiter.allow(ip("10.0.0.5"), now))
+        .count();
+
+    assert_eq!(allowed, 10);
+}
+
+#[test]
+fn budget_refills_over_time() {
+    let mut limiter = RateLimiter::new(10);
+    let now = Instant::now();
+
+    for _ in 0..10 {
+        assert!(limiter.allow(ip("10.0.0.5"), now));
+    }
+    assert!(!limiter.allow(ip("10.0.0.5"), now));
+
+    let later = now + Duration::from_millis(250);
+    assert!(limiter.allow(ip("10.0.0.5"), later));
+    assert!(limiter.allow(ip("10.0.0.5"), later));
+    assert!(!limiter.allow(ip("10.0.0.5"), later));
+}
+
+#[test]
+fn clients_are_limited_separately() {
+    let mut limiter = RateLimiter::new(1);
+    let now = Instant::now();
+
+    assert!(limiter.allow(ip("10.0.0.5"), now));
+    assert!(!limiter.allow(ip("10.0.0.5"), now));
+    assert!(limiter.allow(ip("10.0.0.6"), now));
+}
+
+fn light_command(uri: &str) -> Request<Body> {
+    Request::put(uri)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json!({"on": true}).to_string()))
+        .unwrap()
+}
+
+/* the v1 error type of a reply, if any */
+async fn error_type(resp: axum::response::Response) -> Option<u64> {
+    let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let reply: Value = serde_json::from_slice(&body).unwrap_or_default();
+    reply[0]["error"]["type"].as_u64()
+}
+
+#[tokio::test]
+async fn v1_client_over_limit_gets_error_901() {
+    let dir = common::TempDir::new("ratelimit");
+    let mut config = common::config();
+    config.bifrost.v1_rate_limit = Some(2);
+    let mut make_svc = server::build_service(common::appstate(&dir, config));
+    let uri = format!("/api/{}/lights/1/state", Uuid::new_v4());
+
+    let client = make_svc
+        .call(SocketAddr::from(([10, 0, 0, 5], 40000)))
+        .await
+        .unwrap();
+
+    /* within the limit, the command goes through (to a light that is not
+     * there).. */
+    for _ in 0..2 {
+        let resp = client.clone().oneshot(light_command(&uri)).await.unwrap();
+        assert_ne!(error_type(resp).await, Some(901));
+    }
+
+    /* ..after which it is refused, like a real bridge does */
+    let resp = client.oneshot(light_command(&uri)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let reply: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(reply[0]["error"]["type"], 901);
+    assert_eq!(reply[0]["error"]["address"], "/lights/1/state");
+
+    /* other clients have their own budget */
+    let other = make_svc
+        .call(SocketAddr::from(([10, 0, 0, 6], 40000)))
+        .await
+        .unwrap();
+    let resp = other.oneshot(light_command(&uri)).await.unwrap();
+    assert_ne!(error_type(resp).await, Some(901));
+}
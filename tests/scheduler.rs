@@ -0,0 +1,234 @@
+/*
+ * Tests of scheduled settings (motion sensor sensitivity by time of day),
+ * and scheduled scene recalls.
+ */
+
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::NaiveTime;
+use serde_json::{json, Value};
+
+use bifrost::config::{MotionSensorConfig, SensitivityPeriod};
+use bifrost::hue::api::{
+    Device, DeviceArchetype, DeviceProductData, Metadata, Motion, MotionSensitivity,
+    MotionSensitivityStatus, RType, Resource, Scene, SceneMetadata,
+};
+use bifrost::model::state::AuxData;
+use bifrost::resource::Resources;
+use bifrost::scheduler::{active_period, time_passed, SceneSchedule, Scheduler};
+use bifrost::z2m::request::ClientRequest;
+
+fn time(hour: u32, min: u32) -> NaiveTime {
+    NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+}
+
+fn schedule() -> Vec<SensitivityPeriod> {
+    serde_json::from_value(json!([
+        {"at": "07:00", "sensitivity": 2},
+        {"at": "22:30", "sensitivity": 0},
+    ]))
+    .unwrap()
+}
+
+fn add_sensor(res: &mut Resources, name: &str) -> uuid::Uuid {
+    let link_device = RType::Device.deterministic(name);
+    let link_motion = RType::Motion.deterministic(name);
+    let dev = Device {
+        product_data: DeviceProductData::hue_bridge_v2(),
+        metadata: Metadata::new(DeviceArchetype::UnknownArchetype, name),
+        services: vec![link_motion],
+    };
+    let sensitivity = MotionSensitivity {
+        status: MotionSensitivityStatus::Set,
+        sensitivity: 1,
+        sensitivity_max: 2,
+    };
+    res.aux_set(&link_motion, AuxData::new().with_topic(name));
+    res.add(&link_device, Resource::Device(dev)).unwrap();
+    res.add(
+        &link_motion,
+        Resource::Motion(Motion::new(link_device, Some(sensitivity))),
+    )
+    .unwrap();
+    link_motion.rid
+}
+
+#[test]
+fn active_period_wraps_around_midnight() {
+    let schedule = schedule();
+
+    assert_eq!(
+        active_period(&schedule, time(12, 0)).unwrap().sensitivity,
+        2
+    );
+    assert_eq!(
+        active_period(&schedule, time(23, 0)).unwrap().sensitivity,
+        0
+    );
+    assert_eq!(active_period(&schedule, time(3, 0)).unwrap().sensitivity, 0);
+    assert!(active_period(&[], time(3, 0)).is_none());
+}
+
+#[tokio::test]
+async fn sensitivity_follows_schedule() {
+    let res = common::resources();
+    let id = add_sensor(&mut *res.lock().await, "Hallway sensor");
+    let mut rx = res.lock().await.z2m_channel();
+
+    let conf = MotionSensorConfig {
+        sensitivity_schedule: schedule(),
+    };
+    let mut scheduler = Scheduler::new(
+        HashMap::from([("Hallway sensor".to_string(), conf)]),
+        res.clone(),
+    );
+
+    scheduler.tick(time(23, 0)).await.unwrap();
+
+    let (_, req) = rx.try_recv().unwrap();
+    assert!(matches!(
+        *req,
+        ClientRequest::MotionSensitivity { sensitivity: 0, .. }
+    ));
+    let lock = res.lock().await;
+    let sens = lock
+        .get::<Motion>(&RType::Motion.link_to(id))
+        .unwrap()
+        .sensitivity
+        .unwrap();
+    assert_eq!(sens.sensitivity, 0);
+    assert_eq!(sens.status, MotionSensitivityStatus::Changing);
+    drop(lock);
+
+    /* changes made during a period are left alone, until the next one */
+    res.lock().await.set_motion_sensitivity(&id, 1).unwrap();
+    rx.try_recv().unwrap();
+    scheduler.tick(time(23, 30)).await.unwrap();
+    assert!(rx.try_recv().is_err());
+
+    scheduler.tick(time(7, 0)).await.unwrap();
+    let (_, req) = rx.try_recv().unwrap();
+    assert!(matches!(
+        *req,
+        ClientRequest::MotionSensitivity { sensitivity: 2, .. }
+    ));
+}
+
+#[tokio::test]
+async fn renamed_sensors_keep_their_schedule() {
+    let res = common::resources();
+    let id = add_sensor(&mut *res.lock().await, "hallway_sensor");
+    let mut rx = res.lock().await.z2m_channel();
+
+    /* renamed from the hue app, which leaves the z2m name as it was */
+    let link_device = RType::Device.deterministic("hallway_sensor");
+    res.lock()
+        .await
+        .update::<Device>(&link_device.rid, |dev| {
+            dev.metadata.name = "Hallway".into();
+        })
+        .unwrap();
+
+    let conf = MotionSensorConfig {
+        sensitivity_schedule: schedule(),
+    };
+    let mut scheduler = Scheduler::new(
+        HashMap::from([("hallway_sensor".to_string(), conf)]),
+        res.clone(),
+    );
+    scheduler.tick(time(23, 0)).await.unwrap();
+
+    let (_, req) = rx.try_recv().unwrap();
+    assert!(matches!(
+        *req,
+        ClientRequest::MotionSensitivity { sensitivity: 0, .. }
+    ));
+    let lock = res.lock().await;
+    let motion = lock.get::<Motion>(&RType::Motion.link_to(id)).unwrap();
+    assert_eq!(motion.sensitivity.unwrap().sensitivity, 0);
+}
+
+fn add_scene(res: &mut Resources, name: &str) -> uuid::Uuid {
+    let link = RType::Scene.deterministic(name);
+    let scene = Scene {
+        actions: vec![],
+        auto_dynamic: false,
+        group: RType::Room.deterministic("Living room"),
+        metadata: SceneMetadata {
+            appdata: None,
+            image: None,
+            name: name.into(),
+        },
+        palette: Value::Null,
+        speed: 0.5,
+        status: None,
+    };
+    res.add(&link, Resource::Scene(scene)).unwrap();
+    link.rid
+}
+
+#[test]
+fn time_passed_wraps_around_midnight() {
+    assert!(time_passed(time(7, 0), time(6, 59), time(7, 0)));
+    assert!(!time_passed(time(7, 0), time(7, 0), time(7, 1)));
+    assert!(time_passed(time(0, 0), time(23, 59), time(0, 1)));
+    assert!(time_passed(time(23, 59), time(23, 58), time(0, 1)));
+    assert!(!time_passed(time(12, 0), time(23, 58), time(0, 1)));
+}
+
+#[tokio::test]
+async fn scene_schedules_recall_scenes() {
+    let res = common::resources();
+    let mut rx = res.lock().await.z2m_channel();
+
+    let mut lock = res.lock().await;
+    let morning = add_scene(&mut lock, "Morning");
+    let evening = add_scene(&mut lock, "Evening");
+    let once = lock
+        .add_scene_schedule(SceneSchedule {
+            scene: morning,
+            at: time(7, 0),
+            daily: false,
+        })
+        .unwrap();
+    let daily = lock
+        .add_scene_schedule(SceneSchedule {
+            scene: evening,
+            at: time(22, 0),
+            daily: true,
+        })
+        .unwrap();
+    drop(lock);
+
+    let recalled = |rx: &mut tokio::sync::broadcast::Receiver<(_, Arc<ClientRequest>)>| {
+        let (_, req) = rx.try_recv().ok()?;
+        match *req {
+            ClientRequest::SceneRecall { scene } => Some(scene.rid),
+            _ => None,
+        }
+    };
+
+    let mut scheduler = Scheduler::new(HashMap::new(), res.clone());
+
+    /* nothing is recalled on the first tick, even if it is on time */
+    scheduler.tick(time(7, 0)).await.unwrap();
+    assert!(recalled(&mut rx).is_none());
+
+    scheduler.tick(time(21, 0)).await.unwrap();
+    scheduler.tick(time(22, 0)).await.unwrap();
+    assert_eq!(recalled(&mut rx), Some(evening));
+
+    /* the next morning, the one-time schedule runs, and is gone after */
+    scheduler.tick(time(7, 0)).await.unwrap();
+    assert_eq!(recalled(&mut rx), Some(morning));
+    assert!(!res.lock().await.get_scene_schedules().contains_key(&once));
+
+    /* deleting a scene deletes its schedules */
+    let mut lock = res.lock().await;
+    assert!(lock.get_scene_schedules().contains_key(&daily));
+    lock.delete(&RType::Scene.link_to(evening)).unwrap();
+    assert!(lock.get_scene_schedules().is_empty());
+}
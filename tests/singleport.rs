@@ -0,0 +1,79 @@
+/*
+ * Tests of serving http and https on the same port.
+ */
+
+use std::net::SocketAddr;
+
+use axum::routing::get;
+use axum::{Extension, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use camino::Utf8PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use bifrost::server::certificate;
+use bifrost::server::singleport::{SinglePortAcceptor, Transport};
+
+async fn serve(name: &str) -> SocketAddr {
+    let dir = Utf8PathBuf::try_from(std::env::temp_dir()).unwrap();
+    let certfile = dir.join(format!("bifrost-test-{name}-{}.pem", std::process::id()));
+    certificate::generate_and_save(&certfile, "00:11:22:33:44:55".parse().unwrap()).unwrap();
+    let config = RustlsConfig::from_pem_file(&certfile, &certfile)
+        .await
+        .unwrap();
+    std::fs::remove_file(&certfile).unwrap();
+
+    let app = Router::new().route("/", get(|| async { "hello" })).route(
+        "/transport",
+        get(|Extension(transport): Extension<Transport>| async move { format!("{transport:?}") }),
+    );
+    let handle = Handle::new();
+    let server = axum_server::bind("127.0.0.1:0".parse().unwrap())
+        .handle(handle.clone())
+        .acceptor(SinglePortAcceptor::new(config))
+        .serve(app.into_make_service());
+    tokio::spawn(server);
+
+    handle.listening().await.unwrap()
+}
+
+async fn exchange(addr: SocketAddr, request: &[u8]) -> Vec<u8> {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await.unwrap_or_default();
+    response
+}
+
+#[tokio::test]
+async fn plain_http_is_served() {
+    let addr = serve("plain").await;
+
+    let response = exchange(
+        addr,
+        b"GET / HTTP/1.1\r\nHost: bridge\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    let response = String::from_utf8_lossy(&response);
+
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with("hello"));
+
+    let response = exchange(
+        addr,
+        b"GET /transport HTTP/1.1\r\nHost: bridge\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert!(String::from_utf8_lossy(&response).ends_with("Plain"));
+}
+
+#[tokio::test]
+async fn tls_handshake_goes_to_rustls() {
+    let addr = serve("tls").await;
+
+    /* a (bogus) tls handshake record is answered by rustls, not by http */
+    let response = exchange(addr, &[0x16, 0x03, 0x01, 0x00, 0x04, 1, 0, 0, 0]).await;
+
+    assert!(!response.starts_with(b"HTTP"));
+}
@@ -0,0 +1,196 @@
+/*
+ * Tests of config-defined static lights, controlled by a webhook or a
+ * shell command.
+ */
+
+mod common;
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use serde_json::{json, Value};
+
+use bifrost::config::StaticLightConfig;
+use bifrost::hue::api::{Device, Light, RType};
+use bifrost::static_lights::StaticLights;
+use bifrost::z2m::request::ClientRequest;
+use bifrost::z2m::update::DeviceUpdate;
+
+fn static_config(name: &str, conf: &Value) -> HashMap<String, StaticLightConfig> {
+    HashMap::from([(
+        name.to_string(),
+        serde_json::from_value(conf.clone()).unwrap(),
+    )])
+}
+
+#[tokio::test]
+async fn static_lights_follow_capabilities() {
+    let res = common::resources();
+    let config = HashMap::from([
+        ("Relay".to_string(), StaticLightConfig::default()),
+        (
+            "Strip".to_string(),
+            serde_json::from_value(json!({"dimming": true, "color": true})).unwrap(),
+        ),
+    ]);
+
+    let mut lights = StaticLights::new(config, res.clone());
+    lights.add_resources().await.unwrap();
+
+    let lock = res.lock().await;
+    let relay = lock
+        .get::<Light>(&RType::Light.deterministic(("static", "Relay")))
+        .unwrap();
+    assert!(relay.dimming.is_none());
+    assert!(relay.color.is_none());
+
+    let strip = lock
+        .get::<Light>(&RType::Light.deterministic(("static", "Strip")))
+        .unwrap();
+    assert!(strip.dimming.is_some());
+    assert!(strip.color.is_some());
+    assert!(strip.color_temperature.is_none());
+
+    let device = lock
+        .get::<Device>(&RType::Device.deterministic(("static", "Strip")))
+        .unwrap();
+    assert_eq!(device.product_data.model_id, "static");
+}
+
+#[tokio::test]
+async fn static_light_runs_command() {
+    let dir = common::TempDir::new("static");
+    let out = dir.join("out");
+
+    let res = common::resources();
+    let config = static_config(
+        "Relay",
+        &json!({
+            "dimming": true,
+            "command": format!("echo \"$BIFROST_LIGHT $BIFROST_ON $BIFROST_BRIGHTNESS\" > {out}"),
+        }),
+    );
+    let mut lights = StaticLights::new(config, res.clone());
+    lights.add_resources().await.unwrap();
+
+    let link = RType::Light.deterministic(("static", "Relay"));
+    let upd = DeviceUpdate::default()
+        .with_state(Some(true))
+        .with_brightness(Some(127.0));
+    lights
+        .handle_request(&ClientRequest::light_update(link, upd))
+        .await
+        .unwrap();
+
+    let light = res.lock().await.get::<Light>(&link).unwrap().clone();
+    assert!(light.on.on);
+
+    let line = std::fs::read_to_string(&out).unwrap();
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    assert_eq!(fields[..2], ["Relay", "true"]);
+    let brightness: f64 = fields[2].parse().unwrap();
+    assert!((brightness - 50.0).abs() < 1.0, "{line}");
+
+    /* a failing command is reported */
+    let config = static_config("Broken", &json!({"command": "exit 3"}));
+    let mut lights = StaticLights::new(config, res.clone());
+    lights.add_resources().await.unwrap();
+
+    let link = RType::Light.deterministic(("static", "Broken"));
+    let upd = DeviceUpdate::default().with_state(Some(true));
+    let err = lights
+        .handle_request(&ClientRequest::light_update(link, upd))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Broken"), "{err}");
+}
+
+#[tokio::test]
+async fn static_light_posts_webhook() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+
+        let mut request = String::new();
+        reader.read_line(&mut request).unwrap();
+
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim_end().is_empty() {
+                break;
+            }
+            if let Some(value) = header.to_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+            .unwrap();
+
+        (request, body)
+    });
+
+    let res = common::resources();
+    let config = static_config(
+        "Blaster",
+        &json!({"webhook": format!("http://127.0.0.1:{port}/hook")}),
+    );
+    let mut lights = StaticLights::new(config, res.clone());
+    lights.add_resources().await.unwrap();
+
+    let link = RType::Light.deterministic(("static", "Blaster"));
+    let upd = DeviceUpdate::default().with_state(Some(true));
+    lights
+        .handle_request(&ClientRequest::light_update(link, upd))
+        .await
+        .unwrap();
+
+    let (request, body) = server.join().unwrap();
+    assert!(request.starts_with("POST /hook "), "{request}");
+
+    let state: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(state["name"], json!("Blaster"));
+    assert_eq!(state["on"], json!(true));
+    assert_eq!(state["brightness"], Value::Null);
+}
+
+#[tokio::test]
+async fn static_lights_follow_config_changes() {
+    let res = common::resources();
+
+    let config = HashMap::from([
+        ("Relay".to_string(), StaticLightConfig::default()),
+        ("Old".to_string(), StaticLightConfig::default()),
+    ]);
+    let mut lights = StaticLights::new(config, res.clone());
+    lights.add_resources().await.unwrap();
+
+    /* the relay gains dimming, and the old light is no longer configured */
+    let config = static_config("Relay", &json!({"dimming": true}));
+    let mut lights = StaticLights::new(config, res.clone());
+    lights.add_resources().await.unwrap();
+
+    let lock = res.lock().await;
+    let relay = lock
+        .get::<Light>(&RType::Light.deterministic(("static", "Relay")))
+        .unwrap();
+    assert!(relay.dimming.is_some());
+    assert!(relay.timed_effects.is_some());
+    assert!(relay.color.is_none());
+
+    let old_light = RType::Light.deterministic(("static", "Old"));
+    let old_device = RType::Device.deterministic(("static", "Old"));
+    assert!(lock.get::<Light>(&old_light).is_err());
+    assert!(lock.get::<Device>(&old_device).is_err());
+}
@@ -0,0 +1,222 @@
+/*
+ * Tests of the state storage backends.
+ */
+
+mod common;
+
+use serde_json::Value;
+
+use bifrost::hue::api::{RType, Resource, Room, RoomArchetype, RoomMetadata};
+use bifrost::model::state::{State, StateFormat};
+use bifrost::storage;
+
+use common::TempDir;
+
+fn room(name: &str) -> Resource {
+    Resource::Room(Room {
+        children: vec![],
+        metadata: RoomMetadata::new(RoomArchetype::Office, name),
+        services: vec![],
+    })
+}
+
+/* a state with the bridge, and a room */
+async fn sample_state() -> State {
+    let res = common::resources();
+    let mut lock = res.lock().await;
+    lock.add(&RType::Room.deterministic("stored"), room("Office"))
+        .unwrap();
+    lock.snapshot()
+}
+
+fn as_value(state: &State) -> Value {
+    serde_json::to_value(state).unwrap()
+}
+
+#[test]
+fn format_is_chosen_by_extension() {
+    for (name, format) in [
+        ("state.yaml", StateFormat::Yaml),
+        ("state", StateFormat::Yaml),
+        ("state.json", StateFormat::Json),
+        ("state.json.gz", StateFormat::JsonGz),
+    ] {
+        assert_eq!(StateFormat::from_path(name.into()), format, "{name}");
+    }
+}
+
+#[tokio::test]
+async fn format_is_detected_from_contents() {
+    let state = sample_state().await;
+
+    for format in [StateFormat::Yaml, StateFormat::Json, StateFormat::JsonGz] {
+        let data = state.to_vec(format).unwrap();
+        assert_eq!(StateFormat::detect(&data), format);
+
+        let loaded = State::from_slice(&data).unwrap();
+        assert_eq!(as_value(&loaded), as_value(&state), "{format:?}");
+    }
+}
+
+#[tokio::test]
+async fn state_files_round_trip() {
+    let dir = TempDir::new("state-formats");
+    let state = sample_state().await;
+
+    for (name, format) in [
+        ("state.yaml", StateFormat::Yaml),
+        ("state.json", StateFormat::Json),
+        ("state.json.gz", StateFormat::JsonGz),
+    ] {
+        let path = dir.join(name);
+        let mut store = storage::open(&path).unwrap();
+        assert!(store.load().unwrap().is_none());
+        assert!(store.save(&state).unwrap());
+        assert!(!store.save(&state).unwrap(), "{name}");
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(StateFormat::detect(&data), format, "{name}");
+
+        let loaded = storage::open(&path).unwrap().load().unwrap().unwrap();
+        assert_eq!(as_value(&loaded), as_value(&state), "{name}");
+    }
+}
+
+#[tokio::test]
+async fn yaml_state_is_migrated() {
+    let dir = TempDir::new("state-migrate");
+    let state = sample_state().await;
+    let yaml = dir.join("state.yaml");
+    storage::open(&yaml).unwrap().save(&state).unwrap();
+    let before = std::fs::read(&yaml).unwrap();
+
+    /* a new json state file starts from state.yaml next to it.. */
+    let path = dir.join("state.json");
+    let loaded = storage::load(&path).unwrap().unwrap();
+    assert_eq!(as_value(&loaded), as_value(&state));
+
+    /* ..which is left untouched, while the state is saved as json */
+    storage::open(&path).unwrap().save(&loaded).unwrap();
+    assert_eq!(std::fs::read(&yaml).unwrap(), before);
+    let data = std::fs::read(&path).unwrap();
+    assert_eq!(StateFormat::detect(&data), StateFormat::Json);
+    assert_eq!(
+        as_value(&storage::load(&path).unwrap().unwrap()),
+        as_value(&state)
+    );
+}
+
+#[tokio::test]
+async fn version_0_state_is_upgraded() {
+    let dir = TempDir::new("state-v0");
+    let state = sample_state().await;
+
+    /* version 0 was a list of the resources and the aux data */
+    let path = dir.join("state.yaml");
+    let v0 = serde_yml::to_string(&(&state.res, state.aux())).unwrap();
+    std::fs::write(&path, v0).unwrap();
+
+    let loaded = storage::load(&path).unwrap().unwrap();
+    assert_eq!(
+        serde_json::to_value(&loaded.res).unwrap(),
+        serde_json::to_value(&state.res).unwrap()
+    );
+    for id in state.res.keys() {
+        assert!(loaded.id_v1(id).is_some());
+    }
+
+    /* the old file is kept as a backup */
+    assert!(path.with_extension("v0.bak").is_file());
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use serde_json::json;
+
+    use bifrost::hue::api::RType;
+    use bifrost::journal::{self, JournalEntry};
+    use bifrost::storage::sqlite::SqliteStore;
+    use bifrost::storage::{self, StateStore};
+
+    use super::{as_value, room, sample_state};
+    use crate::common::TempDir;
+
+    #[tokio::test]
+    async fn state_round_trips() {
+        let dir = TempDir::new("sqlite-roundtrip");
+        let path = dir.join("state.db");
+        let state = sample_state().await;
+
+        let mut store = SqliteStore::open(&path).unwrap();
+        assert!(store.load().unwrap().is_none());
+        assert!(store.save(&state).unwrap());
+
+        /* saving the same state again writes nothing */
+        assert!(!store.save(&state).unwrap());
+        drop(store);
+
+        let loaded = storage::open(&path).unwrap().load().unwrap().unwrap();
+        assert_eq!(as_value(&loaded), as_value(&state));
+    }
+
+    #[tokio::test]
+    async fn removed_resources_are_deleted() {
+        let dir = TempDir::new("sqlite-delete");
+        let path = dir.join("state.db");
+        let mut state = sample_state().await;
+
+        let mut store = SqliteStore::open(&path).unwrap();
+        store.save(&state).unwrap();
+
+        let link = RType::Room.deterministic("stored");
+        state.remove(&link.rid).unwrap();
+        state.insert(RType::Room.deterministic("added").rid, room("Study"));
+        assert!(store.save(&state).unwrap());
+
+        /* a fresh connection sees exactly the new state */
+        let loaded = SqliteStore::open(&path).unwrap().load().unwrap().unwrap();
+        assert!(loaded.try_get(&link.rid).is_none());
+        assert_eq!(as_value(&loaded), as_value(&state));
+    }
+
+    #[tokio::test]
+    async fn state_is_migrated_from_yaml() {
+        let dir = TempDir::new("sqlite-migrate");
+        let state = sample_state().await;
+        storage::open(&dir.join("state.yaml"))
+            .unwrap()
+            .save(&state)
+            .unwrap();
+
+        /* the empty database is filled from state.yaml next to it */
+        let loaded = storage::load(&dir.join("bifrost.db")).unwrap().unwrap();
+        assert_eq!(as_value(&loaded), as_value(&state));
+    }
+
+    #[test]
+    fn journal_round_trips() {
+        let dir = TempDir::new("sqlite-journal");
+        let path = dir.join("state.db");
+        let link = RType::Room.deterministic("stored");
+
+        let entries = [
+            JournalEntry::add(link.rid, link.rtype, json!({"name": "Office"})),
+            JournalEntry::update(
+                link.rid,
+                link.rtype,
+                &json!({"name": "Office"}),
+                &json!({"name": "Study"}),
+            )
+            .unwrap(),
+            JournalEntry::delete(link.rid, link.rtype, json!({"name": "Study"})),
+        ];
+
+        let store = SqliteStore::open(&path).unwrap();
+        for entry in &entries {
+            store.append_journal(entry).unwrap();
+        }
+        drop(store);
+
+        assert_eq!(journal::read(&path).unwrap(), entries);
+    }
+}
@@ -0,0 +1,210 @@
+/*
+ * End-to-end tests of the z2m client, against a mock z2m server.
+ *
+ * These cover the whole path from z2m messages, through resource creation
+ * and updates, to the responses served by the hue apis.
+ */
+
+mod common;
+
+use std::sync::Arc;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use serde_json::Value;
+use tokio_tungstenite::tungstenite::Message;
+use tower::ServiceExt;
+
+use bifrost::hue::api::{Device, Light, RType, Room};
+use bifrost::resource::Resources;
+use bifrost::routes;
+use bifrost::z2m::request::ClientRequest;
+use bifrost::z2m::status::ConnectionState;
+use bifrost::z2m::update::DeviceUpdate;
+use bifrost::z2m::Client;
+
+use common::MockZ2m;
+
+fn find_light(res: &Resources, name: &str) -> Option<(Light, uuid::Uuid)> {
+    res.get_resources_by_type(RType::Light)
+        .into_iter()
+        .find_map(|rr| {
+            let id = rr.id;
+            Light::try_from(rr.obj)
+                .ok()
+                .filter(|light| light.metadata.name == name)
+                .map(|light| (light, id))
+        })
+}
+
+fn find_room(res: &Resources, name: &str) -> Option<Room> {
+    res.get_resources_by_type(RType::Room)
+        .into_iter()
+        .filter_map(|rr| Room::try_from(rr.obj).ok())
+        .find(|room| room.metadata.name == name)
+}
+
+#[tokio::test]
+async fn announced_devices_become_resources() {
+    let z2m = MockZ2m::with_bridge().await;
+    let res = common::resources();
+    z2m.spawn_client(common::config(), res.clone());
+
+    let room = common::wait_for(&res, |res| find_room(res, "Kitchen")).await;
+    let (light, id) = find_light(&*res.lock().await, "Kitchen ceiling").unwrap();
+
+    let lock = res.lock().await;
+    let dev = lock.get::<Device>(&light.owner).unwrap();
+    assert_eq!(dev.light_service().map(|rl| rl.rid), Some(id));
+    assert!(dev.zigbee_connectivity_service().is_some());
+
+    assert!(room.children.contains(&light.owner));
+    assert!(room.grouped_light_service().is_some());
+    assert_eq!(
+        lock.get_scenes_for_room(&RType::Room.deterministic(("test", "Kitchen")).rid)
+            .len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn device_updates_change_light_state() {
+    let z2m = MockZ2m::with_bridge().await;
+    let res = common::resources();
+    z2m.spawn_client(common::config(), res.clone());
+
+    common::wait_for(&res, |res| find_light(res, "Kitchen ceiling")).await;
+
+    z2m.send(common::corpus("device_light_state"));
+
+    /* the update is applied atomically, so wait for any part of it */
+    let light = common::wait_for(&res, |res| {
+        find_light(res, "Kitchen ceiling")
+            .map(|(light, _)| light)
+            .filter(|light| light.dimming.is_some_and(|dim| dim.brightness > 0.0))
+    })
+    .await;
+
+    assert!(light.on.on);
+    assert_eq!(light.dimming.map(|dim| dim.brightness), Some(100.0));
+    assert_eq!(light.color.map(|col| col.xy.x), Some(0.4599));
+}
+
+#[tokio::test]
+async fn light_requests_reach_z2m() {
+    let mut z2m = MockZ2m::with_bridge().await;
+    let res = common::resources();
+    z2m.spawn_client(common::config(), res.clone());
+
+    let (_, id) = common::wait_for(&res, |res| find_light(res, "Kitchen ceiling")).await;
+
+    let upd = DeviceUpdate::new().with_state(Some(true));
+    res.lock()
+        .await
+        .z2m_request(ClientRequest::light_update(RType::Light.link_to(id), upd))
+        .unwrap();
+
+    let msg = z2m.recv_topic("Kitchen ceiling/set").await;
+    assert_eq!(msg["payload"]["state"], "ON");
+}
+
+#[tokio::test]
+async fn v1_api_serves_z2m_lights() {
+    let dir = common::TempDir::new("v1-lights");
+    let config = common::config();
+    let appstate = common::appstate(&dir, config.clone());
+
+    let z2m = MockZ2m::with_bridge().await;
+    z2m.spawn_client(config, appstate.res.clone());
+    common::wait_for(&appstate.res, |res| find_room(res, "Kitchen")).await;
+
+    let username = uuid::Uuid::new_v4();
+    let req = Request::get(format!("/api/{username}/lights"))
+        .body(Body::empty())
+        .unwrap();
+    let resp = routes::router(appstate).oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let lights: Value = serde_json::from_slice(&body).unwrap();
+    let names: Vec<&str> = lights
+        .as_object()
+        .unwrap()
+        .values()
+        .filter_map(|light| light["name"].as_str())
+        .collect();
+    assert_eq!(names, ["Kitchen ceiling"]);
+}
+
+#[tokio::test]
+async fn server_status_tracks_connection() {
+    let z2m = MockZ2m::with_bridge().await;
+    let res = common::resources();
+    z2m.spawn_client(common::config(), res.clone());
+
+    /* the bridge announcement is three messages */
+    let status = common::wait_for(&res, |res| {
+        res.get_z2m_status()
+            .get("test")
+            .filter(|status| status.received >= 3)
+            .cloned()
+    })
+    .await;
+
+    assert_eq!(status.url, z2m.url);
+    assert_eq!(status.state, ConnectionState::Connected);
+    assert_eq!(status.connects, 1);
+    assert!(status.last_error.is_none());
+}
+
+#[tokio::test]
+async fn silent_server_is_checked_and_dropped() {
+    let mut z2m = MockZ2m::with_bridge().await;
+    let res = common::resources();
+    let mut server = z2m.server();
+    server.watchdog_secs = 1;
+    let client = Client::new(
+        "test".into(),
+        server,
+        Arc::new(common::config()),
+        res.clone(),
+    )
+    .unwrap();
+    tokio::spawn(client.run_forever());
+
+    /* the mock server never answers the health check */
+    let msg = z2m.recv_topic("bridge/request/health_check").await;
+    assert!(msg["payload"]["transaction"].is_string());
+
+    let error = common::wait_for(&res, |res| {
+        res.get_z2m_status()
+            .get("test")
+            .and_then(|status| status.last_error.clone())
+    })
+    .await;
+    assert!(error.contains("No messages from z2m"), "{error}");
+}
+
+#[tokio::test]
+async fn control_frames_do_not_break_the_connection() {
+    let mut z2m = MockZ2m::with_bridge().await;
+    let res = common::resources();
+    z2m.spawn_client(common::config(), res.clone());
+
+    z2m.send_frame(Message::Ping(b"alive?".to_vec()));
+    assert_eq!(z2m.recv_pong().await, b"alive?");
+
+    /* an unsolicited pong is fine too */
+    z2m.send_frame(Message::Pong(vec![]));
+
+    /* closing the websocket is a clean disconnect, not an error */
+    z2m.send_frame(Message::Close(None));
+    let status = common::wait_for(&res, |res| {
+        res.get_z2m_status()
+            .get("test")
+            .filter(|status| status.state == ConnectionState::Disconnected)
+            .cloned()
+    })
+    .await;
+    assert!(status.last_error.is_none(), "{:?}", status.last_error);
+}
@@ -0,0 +1,227 @@
+/*
+ * Tests of the z2m frontend proxy, against fake frontends.
+ */
+
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures::{SinkExt, StreamExt};
+use serde_json::json;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use tower::{Service, ServiceExt};
+
+use bifrost::server::singleport::Transport;
+use bifrost::server::{self, appstate::AppState};
+
+/* most tests talk plain http, so credentials are allowed over it */
+fn appstate(dir: &common::TempDir, port: u16) -> AppState {
+    appstate_with(dir, port, true)
+}
+
+fn appstate_with(dir: &common::TempDir, port: u16, allow_insecure: bool) -> AppState {
+    let mut config = common::config();
+    config.bifrost.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    config.z2m.servers.insert(
+        "test".to_string(),
+        serde_json::from_value(json!({
+            "url": format!("ws://127.0.0.1:{port}/api"),
+            "frontend": {
+                "username": "admin",
+                "password": "secret",
+                "allow_insecure": allow_insecure,
+            },
+        }))
+        .unwrap(),
+    );
+    common::appstate(dir, config)
+}
+
+fn auth() -> String {
+    format!("Basic {}", BASE64.encode("admin:secret"))
+}
+
+#[tokio::test]
+async fn frontend_requests_are_forwarded() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let upstream = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+
+        let mut head = vec![];
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim_end().is_empty() {
+                break;
+            }
+            head.push(line.trim_end().to_string());
+        }
+
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: text/javascript\r\n\r\nlet z2m;")
+            .unwrap();
+
+        head
+    });
+
+    let dir = common::TempDir::new("frontend");
+    let appstate = appstate(&dir, port);
+    let mut make_svc = server::build_service(appstate);
+    let svc = make_svc
+        .call(SocketAddr::from(([127, 0, 0, 1], 40000)))
+        .await
+        .unwrap();
+
+    /* credentials are required.. */
+    let req = Request::get("/z2m/test/").body(Body::empty()).unwrap();
+    let resp = svc.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert!(resp.headers().contains_key("www-authenticate"));
+
+    /* ..the frontend is served with a trailing slash.. */
+    let req = Request::get("/z2m/test")
+        .header("Authorization", auth())
+        .body(Body::empty())
+        .unwrap();
+    let resp = svc.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(resp.headers()["location"], "test/");
+
+    /* ..and only for configured servers */
+    let req = Request::get("/z2m/other/")
+        .header("Authorization", auth())
+        .body(Body::empty())
+        .unwrap();
+    let resp = svc.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let req = Request::get("/z2m/test/assets/app.js?v=1")
+        .header("Authorization", auth())
+        .body(Body::empty())
+        .unwrap();
+    let resp = svc.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "text/javascript");
+    let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(&body[..], b"let z2m;");
+
+    let head = upstream.join().unwrap();
+    assert_eq!(head[0], "GET /assets/app.js?v=1 HTTP/1.0");
+    assert!(
+        head.contains(&format!("Host: 127.0.0.1:{port}")),
+        "{head:?}"
+    );
+    assert!(
+        !head
+            .iter()
+            .any(|line| line.to_lowercase().starts_with("authorization")),
+        "{head:?}"
+    );
+
+    /* the rest of bifrost is still normalized */
+    let req = Request::get("/api-docs/").body(Body::empty()).unwrap();
+    let resp = svc.oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn frontend_websocket_is_tunneled() {
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = upstream.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        let (stream, _) = upstream.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        while let Some(Ok(msg)) = ws.next().await {
+            if msg.is_text() {
+                ws.send(msg).await.unwrap();
+            }
+        }
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let dir = common::TempDir::new("frontend-ws");
+    let svc = server::build_service(appstate(&dir, port));
+    tokio::spawn(axum_server::from_tcp(listener).serve(svc));
+
+    let mut req = format!("ws://{addr}/z2m/test/api")
+        .into_client_request()
+        .unwrap();
+    req.headers_mut()
+        .insert("Authorization", auth().parse().unwrap());
+    let (mut ws, _) = tokio::time::timeout(common::TIMEOUT, tokio_tungstenite::connect_async(req))
+        .await
+        .unwrap()
+        .unwrap();
+
+    ws.send(Message::text("ping")).await.unwrap();
+    let reply = tokio::time::timeout(common::TIMEOUT, ws.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply, Message::text("ping"));
+}
+
+#[tokio::test]
+async fn credentials_need_https() {
+    let dir = common::TempDir::new("frontend-https");
+    let mut make_svc = server::build_service(appstate_with(&dir, 1, false));
+    let svc = make_svc
+        .call(SocketAddr::from(([127, 0, 0, 1], 40000)))
+        .await
+        .unwrap();
+
+    let request = |transport: Transport| {
+        let mut req = Request::get("/z2m/test/")
+            .header("Authorization", auth())
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut().insert(transport);
+        req
+    };
+
+    /* over plain http, the credentials are not even asked for */
+    let resp = svc
+        .clone()
+        .oneshot(request(Transport::Plain))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert!(!resp.headers().contains_key("www-authenticate"));
+
+    /* over https they are accepted (and the request forwarded, to a
+     * frontend that is not there).. */
+    let resp = svc.clone().oneshot(request(Transport::Tls)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+
+    /* ..also from a trusted proxy that was reached over https */
+    let mut req = request(Transport::Plain);
+    req.headers_mut()
+        .insert("X-Forwarded-Proto", "https".parse().unwrap());
+    let resp = svc.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+
+    /* wrong credentials are still refused */
+    let mut req = request(Transport::Tls);
+    req.headers_mut().insert(
+        "Authorization",
+        format!("Basic {}", BASE64.encode("admin:secreT"))
+            .parse()
+            .unwrap(),
+    );
+    let resp = svc.oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
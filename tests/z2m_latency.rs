@@ -0,0 +1,73 @@
+/*
+ * Regression test for command latency between the api and zigbee2mqtt.
+ *
+ * A fake z2m server announces a light, after which light updates are
+ * requested while the test holds the lock on the shared resources (like a
+ * busy api request would). The updates must still reach the z2m server,
+ * which requires the client to never need the resource lock to send them.
+ */
+
+mod common;
+
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use bifrost::hue::api::RType;
+use bifrost::resource::Resources;
+use bifrost::z2m::request::ClientRequest;
+use bifrost::z2m::update::DeviceUpdate;
+
+use common::MockZ2m;
+
+/* the bridge announcement is three messages */
+const ANNOUNCED: u64 = 3;
+
+/* Wait until the client has handled everything before a message pushed
+ * now, and so is idle (and not waiting for the resource lock). `received`
+ * counts the messages pushed so far. */
+async fn sync(z2m: &MockZ2m, res: &Mutex<Resources>, received: &mut u64) {
+    z2m.send(
+        json!({"topic": "bridge/logging", "payload": {"level": "info", "message": "sync"}})
+            .to_string(),
+    );
+    *received += 1;
+    let target = *received;
+    common::wait_for(res, |res| {
+        res.get_z2m_status()
+            .get("test")
+            .filter(|status| status.received >= target)
+            .map(|_| ())
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn light_update_is_sent_while_resources_are_locked() {
+    let mut z2m = MockZ2m::with_bridge().await;
+    let res = common::resources();
+    z2m.spawn_client(common::config(), res.clone());
+
+    let light = common::wait_for(&res, |res| {
+        let lights = res.get_resources_by_type(RType::Light);
+        lights.first().map(|light| RType::Light.link_to(light.id))
+    })
+    .await;
+
+    let mut received = ANNOUNCED;
+    for n in 0..5 {
+        sync(&z2m, &res, &mut received).await;
+
+        let on = n % 2 == 0;
+        let lock = res.lock().await;
+        lock.z2m_request(ClientRequest::light_update(
+            light,
+            DeviceUpdate::new().with_state(Some(on)),
+        ))
+        .unwrap();
+
+        /* still holding the lock */
+        let msg = z2m.recv_topic("Kitchen ceiling/set").await;
+        assert_eq!(msg["payload"]["state"], if on { "ON" } else { "OFF" });
+        drop(lock);
+    }
+}
@@ -0,0 +1,2108 @@
+/*
+ * Tests of the z2m mapping layer, without any websocket.
+ *
+ * Messages from z2m are fed directly to the mapper, and everything it wants
+ * to send back is inspected from its outbox.
+ */
+
+mod common;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use bifrost::config::Z2mServer;
+use bifrost::error::ApiError;
+use bifrost::hue::api::{
+    Contact, ContactState, Device, DeviceSoftwareUpdate, Entertainment, EntertainmentSegments,
+    GroupedLight, Light, On, RType, RelativeRotary, Resource, Room, RoomArchetype, RoomMetadata,
+    RotaryAction, RotaryDirection, Scene, SceneAction, SceneActionElement, SceneMetadata,
+    SoftwareUpdateState, Tamper, TamperState, ZigbeeConnectivity, ZigbeeConnectivityStatus, Zone,
+};
+use bifrost::model::types::XY;
+use bifrost::resource::Resources;
+use bifrost::z2m::api::{IeeeAddress, RawMessage};
+use bifrost::z2m::mapper::Mapper;
+use bifrost::z2m::request::{ClientRequest, TouchlinkAction};
+use bifrost::z2m::update::DeviceUpdate;
+
+async fn announce(mapper: &mut Mapper) {
+    for msg in common::bridge_announce() {
+        mapper.handle_message(&msg).await.unwrap();
+    }
+}
+
+fn light_link(res: &Resources) -> bifrost::hue::api::ResourceLink {
+    let lights = res.get_resources_by_type(RType::Light);
+    RType::Light.link_to(lights.first().expect("no light found").id)
+}
+
+fn topics(msgs: &[RawMessage]) -> Vec<&str> {
+    msgs.iter().map(|msg| msg.topic.as_str()).collect()
+}
+
+#[tokio::test]
+async fn announce_creates_lights() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+
+    let lock = res.lock().await;
+    let light = lock.get::<Light>(&light_link(&lock)).unwrap();
+    assert_eq!(light.metadata.name, "Kitchen ceiling");
+}
+
+#[tokio::test]
+async fn room_archetype_is_guessed_from_name() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+
+    let lock = res.lock().await;
+    let archetype = |name: &str| {
+        let link = RType::Room.deterministic(("test", name));
+        lock.get::<Room>(&link).unwrap().metadata.archetype
+    };
+    assert!(matches!(archetype("Kitchen"), RoomArchetype::Kitchen));
+    assert!(matches!(
+        archetype("bifrost_hallway"),
+        RoomArchetype::Hallway
+    ));
+}
+
+#[tokio::test]
+async fn device_update_changes_light() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper
+        .handle_message(&common::corpus("device_light_state"))
+        .await
+        .unwrap();
+
+    let lock = res.lock().await;
+    let light = lock.get::<Light>(&light_link(&lock)).unwrap();
+    assert!(light.on.on);
+    assert_eq!(light.dimming.map(|dim| dim.brightness), Some(100.0));
+}
+
+#[tokio::test]
+async fn light_request_is_sent_to_device_topic() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper.take_outgoing();
+
+    let link = light_link(&*res.lock().await);
+    let upd = DeviceUpdate::new().with_state(Some(false));
+    mapper
+        .handle_request(&ClientRequest::light_update(link, upd))
+        .await
+        .unwrap();
+
+    let sent = mapper.take_outgoing();
+    assert_eq!(topics(&sent), ["Kitchen ceiling/set"]);
+    assert_eq!(sent[0].payload["state"], "OFF");
+}
+
+#[tokio::test]
+async fn request_for_unknown_light_is_dropped() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper.take_outgoing();
+
+    let link = RType::Light.deterministic("not a z2m light");
+    let upd = DeviceUpdate::new().with_state(Some(true));
+    mapper
+        .handle_request(&ClientRequest::light_update(link, upd))
+        .await
+        .unwrap();
+
+    assert!(mapper.take_outgoing().is_empty());
+}
+
+#[tokio::test]
+async fn bridge_restart_refreshes_state() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper.take_outgoing();
+
+    mapper
+        .handle_message(&common::corpus("bridge_state_offline"))
+        .await
+        .unwrap();
+    assert!(mapper.is_offline());
+
+    announce(&mut mapper).await;
+    assert!(!mapper.is_offline());
+
+    let sent = mapper.take_outgoing();
+    assert!(topics(&sent).contains(&"Kitchen ceiling/get"));
+}
+
+#[tokio::test]
+async fn requests_are_queued_while_offline() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    let link = light_link(&*res.lock().await);
+
+    mapper
+        .handle_message(&common::corpus("bridge_state_offline"))
+        .await
+        .unwrap();
+    mapper.take_outgoing();
+
+    let upd = DeviceUpdate::new().with_state(Some(true));
+    mapper
+        .submit(&ClientRequest::light_update(link, upd))
+        .await
+        .unwrap();
+    let upd = DeviceUpdate::new().with_brightness(Some(100.0));
+    mapper
+        .submit(&ClientRequest::light_update(link, upd))
+        .await
+        .unwrap();
+
+    /* both updates are combined, and nothing is sent yet */
+    assert_eq!(mapper.queued(), 1);
+    assert!(mapper.take_outgoing().is_empty());
+
+    announce(&mut mapper).await;
+    assert_eq!(mapper.queued(), 0);
+
+    let sent = mapper.take_outgoing();
+    let set: Vec<_> = sent
+        .iter()
+        .filter(|msg| msg.topic == "Kitchen ceiling/set")
+        .collect();
+    assert_eq!(set.len(), 1);
+    assert_eq!(set[0].payload["state"], "ON");
+    assert_eq!(set[0].payload["brightness"], 100.0);
+}
+
+#[tokio::test]
+async fn only_own_requests_are_queued() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    let link = light_link(&*res.lock().await);
+
+    mapper
+        .handle_message(&common::corpus("bridge_state_offline"))
+        .await
+        .unwrap();
+
+    /* a light of another z2m server */
+    let other = RType::Light.deterministic(("other", "Porch"));
+    let upd = DeviceUpdate::new().with_state(Some(true));
+    mapper
+        .submit(&ClientRequest::light_update(other, upd.clone()))
+        .await
+        .unwrap();
+    mapper
+        .submit(&ClientRequest::LightStop { device: other })
+        .await
+        .unwrap();
+    assert_eq!(mapper.queued(), 0);
+
+    mapper
+        .submit(&ClientRequest::light_update(link, upd))
+        .await
+        .unwrap();
+    assert_eq!(mapper.queued(), 1);
+}
+
+#[tokio::test]
+async fn offline_server_refuses_requests() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+
+    let mut lock = res.lock().await;
+    let light = light_link(&lock);
+    let glight = *lock
+        .get::<Room>(&RType::Room.deterministic(("test", "Kitchen")))
+        .unwrap()
+        .grouped_light_service()
+        .unwrap();
+
+    lock.z2m_status_mut("test", "ws://unused").connected();
+    assert!(lock.check_reachable(&light).is_ok());
+
+    lock.z2m_status_mut("test", "ws://unused")
+        .failed("connection reset".into());
+    assert!(matches!(
+        lock.check_reachable(&light),
+        Err(ApiError::ServerOffline(server)) if server == "test"
+    ));
+    assert!(lock.check_reachable(&glight).is_err());
+    drop(lock);
+
+    /* lights are unreachable until the connection is back */
+    mapper.disconnected().await.unwrap();
+    let lock = res.lock().await;
+    let dev = lock.get::<Light>(&light).unwrap().owner;
+    let zbc = *lock
+        .get::<bifrost::hue::api::Device>(&dev)
+        .unwrap()
+        .zigbee_connectivity_service()
+        .unwrap();
+    assert_eq!(
+        lock.get::<ZigbeeConnectivity>(&zbc).unwrap().status,
+        ZigbeeConnectivityStatus::ConnectivityIssue
+    );
+    drop(lock);
+
+    mapper.connected().await.unwrap();
+    assert_eq!(
+        res.lock()
+            .await
+            .get::<ZigbeeConnectivity>(&zbc)
+            .unwrap()
+            .status,
+        ZigbeeConnectivityStatus::Connected
+    );
+}
+
+/* make a device look like it was saved before it had a service of `rtype` */
+fn forget_service(res: &mut Resources, dev: &bifrost::hue::api::ResourceLink, rtype: RType) {
+    res.update::<Device>(&dev.rid, |dev| dev.services.retain(|rl| rl.rtype != rtype))
+        .unwrap();
+}
+
+#[tokio::test]
+async fn existing_lights_get_zigbee_connectivity_linked() {
+    let res = common::resources();
+    announce(&mut common::mapper(res.clone())).await;
+
+    let dev = {
+        let mut lock = res.lock().await;
+        let dev = lock.get::<Light>(&light_link(&lock)).unwrap().owner;
+        forget_service(&mut lock, &dev, RType::ZigbeeConnectivity);
+        dev
+    };
+
+    /* announced again, as after a restart */
+    announce(&mut common::mapper(res.clone())).await;
+
+    let lock = res.lock().await;
+    let link_zbc = *lock
+        .get::<Device>(&dev)
+        .unwrap()
+        .zigbee_connectivity_service()
+        .unwrap();
+    assert!(lock.get::<ZigbeeConnectivity>(&link_zbc).is_ok());
+}
+
+#[tokio::test]
+async fn existing_lights_get_timed_effects() {
+    let res = common::resources();
+    announce(&mut common::mapper(res.clone())).await;
+
+    let light = {
+        let mut lock = res.lock().await;
+        let light = light_link(&lock);
+        lock.update::<Light>(&light.rid, |light| light.timed_effects = None)
+            .unwrap();
+        light
+    };
+
+    /* announced again, as after a restart */
+    announce(&mut common::mapper(res.clone())).await;
+
+    let lock = res.lock().await;
+    assert!(lock.get::<Light>(&light).unwrap().timed_effects.is_some());
+}
+
+#[tokio::test]
+async fn existing_lights_get_effects() {
+    let res = common::resources();
+    announce(&mut common::mapper(res.clone())).await;
+
+    let light = {
+        let mut lock = res.lock().await;
+        let light = light_link(&lock);
+        lock.update::<Light>(&light.rid, |light| {
+            light.effects = None;
+            light.timed_effects = None;
+        })
+        .unwrap();
+        light
+    };
+
+    /* announced again, as after a restart */
+    announce(&mut common::mapper(res.clone())).await;
+
+    let lock = res.lock().await;
+    let light = lock.get::<Light>(&light).unwrap();
+    let effects = light.effects.as_ref().expect("effects not refreshed");
+    assert!(!effects.effect_values.is_empty());
+    assert!(light.timed_effects.is_some());
+}
+
+#[tokio::test]
+async fn existing_lights_get_gradient() {
+    let res = common::resources();
+
+    /* make the ceiling light a gradient light */
+    let mut devices: Value = serde_json::from_str(&common::corpus("bridge_devices")).unwrap();
+    devices["payload"][1]["definition"]["exposes"]
+        .as_array_mut()
+        .unwrap()
+        .push(json!({"type": "list", "name": "gradient", "length_max": 5}));
+    let announce = |mut mapper: Mapper| {
+        let devices = devices.to_string();
+        async move {
+            mapper
+                .handle_message(&common::corpus("bridge_state_online"))
+                .await
+                .unwrap();
+            mapper.handle_message(&devices).await.unwrap();
+        }
+    };
+
+    announce(common::mapper(res.clone())).await;
+
+    let light = {
+        let mut lock = res.lock().await;
+        let light = light_link(&lock);
+        assert!(lock.get::<Light>(&light).unwrap().gradient.is_some());
+        lock.update::<Light>(&light.rid, |light| light.gradient = None)
+            .unwrap();
+        light
+    };
+
+    /* announced again, as after a restart */
+    announce(common::mapper(res.clone())).await;
+
+    let lock = res.lock().await;
+    let gradient = lock.get::<Light>(&light).unwrap().gradient.clone();
+    assert_eq!(gradient.expect("gradient not refreshed").points_capable, 5);
+}
+
+#[tokio::test]
+async fn color_lights_get_entertainment_segments() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+
+    let lock = res.lock().await;
+    let light = light_link(&lock);
+    let dev = lock.get::<Light>(&light).unwrap().owner;
+    let link_ent = *lock
+        .get::<bifrost::hue::api::Device>(&dev)
+        .unwrap()
+        .entertainment_service()
+        .unwrap();
+
+    let ent = lock.get::<Entertainment>(&link_ent).unwrap();
+    assert_eq!(ent.renderer_reference, light);
+    assert_eq!(ent.segments.max_segments, 1);
+    assert_eq!(ent.segments.pixels(), 1);
+}
+
+#[test]
+fn entertainment_channels_map_onto_segments() {
+    let red = XY::new(0.7, 0.3);
+    let blue = XY::new(0.15, 0.06);
+
+    /* a gradient light with 5 points has a segment for each */
+    let segments = EntertainmentSegments::new(5);
+    assert_eq!(segments.segments.len(), 5);
+
+    let pixels = segments.pixel_colors(&[red, blue, red]);
+    assert_eq!(pixels, vec![red, blue, red, red, red]);
+
+    assert_eq!(segments.pixel_colors(&[blue]), vec![blue; 5]);
+    assert!(segments.pixel_colors(&[]).is_empty());
+}
+
+#[tokio::test]
+async fn grouped_light_shows_combined_color() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper
+        .handle_message(&common::corpus("device_light_state"))
+        .await
+        .unwrap();
+
+    let lock = res.lock().await;
+    let light = lock.get::<Light>(&light_link(&lock)).unwrap();
+    let room = lock
+        .get::<Room>(&RType::Room.deterministic(("test", "Kitchen")))
+        .unwrap();
+    let glight = lock
+        .get::<GroupedLight>(room.grouped_light_service().unwrap())
+        .unwrap();
+
+    /* a single light in the room, so its color is the room color */
+    let xy = glight.color.as_ref().unwrap().xy;
+    let expected = light.as_color_opt().unwrap();
+    assert!((xy.x - expected.x).abs() < 1e-9);
+    assert!((xy.y - expected.y).abs() < 1e-9);
+    assert!(glight.color_temperature.is_none());
+}
+
+#[test]
+fn color_average_is_weighted() {
+    let red = XY::new(0.7, 0.3);
+    let blue = XY::new(0.15, 0.06);
+
+    assert_eq!(XY::average([]), None);
+    assert_eq!(XY::average([(red, 0.0)]), None);
+
+    let same = XY::average([(red, 50.0)]).unwrap();
+    assert!((same.x - red.x).abs() < 1e-9 && (same.y - red.y).abs() < 1e-9);
+
+    /* a brighter light pulls the average towards its color */
+    let even = XY::average([(red, 50.0), (blue, 50.0)]).unwrap();
+    let reddish = XY::average([(red, 90.0), (blue, 10.0)]).unwrap();
+    assert!(reddish.x > even.x);
+    assert!(even.x > blue.x && even.x < red.x);
+}
+
+#[tokio::test]
+async fn bridge_device_reports_power_and_zigbee_link() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+    res.lock().await.init("001788fffe123456").unwrap();
+
+    let info: Value = serde_json::from_str(&common::corpus("bridge_info")).unwrap();
+    mapper.handle_message(&info.to_string()).await.unwrap();
+
+    let bridge_zbc = |lock: &Resources| {
+        let rr = lock.get_resources_by_type(RType::Bridge).remove(0);
+        let bridge: bifrost::hue::api::Bridge = rr.obj.try_into().unwrap();
+        let dev = lock
+            .get::<bifrost::hue::api::Device>(&bridge.owner)
+            .unwrap();
+        assert!(dev.services.iter().any(|rl| rl.rtype == RType::DevicePower));
+        let zbc = dev.zigbee_connectivity_service().unwrap();
+        lock.get::<ZigbeeConnectivity>(zbc).unwrap().clone()
+    };
+
+    let mut lock = res.lock().await;
+    let power = lock.get_resources_by_type(RType::DevicePower);
+    assert_eq!(power.len(), 1);
+
+    /* the bridge shares the network of the coordinator */
+    let coordinator = info["payload"]["coordinator"]["ieee_address"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let zbc = bridge_zbc(&lock);
+    assert_eq!(
+        zbc.mac_address.replace(':', ""),
+        coordinator.trim_start_matches("0x")
+    );
+    assert_eq!(zbc.status, ZigbeeConnectivityStatus::ConnectivityIssue);
+
+    lock.z2m_status_mut("test", "ws://unused").connected();
+    lock.refresh_bridge_connectivity().unwrap();
+    assert_eq!(
+        bridge_zbc(&lock).status,
+        ZigbeeConnectivityStatus::Connected
+    );
+
+    lock.z2m_status_mut("test", "ws://unused")
+        .failed("connection reset".into());
+    lock.refresh_bridge_connectivity().unwrap();
+    assert_eq!(
+        bridge_zbc(&lock).status,
+        ZigbeeConnectivityStatus::ConnectivityIssue
+    );
+}
+
+#[tokio::test]
+async fn publish_failure_is_recorded() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+
+    let msg = json!({
+        "topic": "bridge/logging",
+        "payload": {
+            "level": "error",
+            "message": "Publish 'set' 'scene_store' to 'Kitchen' failed: 'Error: Timeout'",
+        },
+    });
+    mapper.handle_message(&msg.to_string()).await.unwrap();
+
+    let lock = res.lock().await;
+    let failures = lock.get_z2m_failures();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].topic, "Kitchen/set");
+}
+
+#[tokio::test]
+async fn bridge_warnings_are_kept() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+
+    for (level, message) in [
+        ("info", "MQTT publish: topic 'zigbee2mqtt/Kitchen'"),
+        ("warning", "Failed to ping 'Hallway sensor'"),
+        (
+            "error",
+            "Publish 'set' 'state' to 'Kitchen' failed: 'Error: Timeout'",
+        ),
+    ] {
+        let msg = json!({
+            "topic": "bridge/logging",
+            "payload": {"level": level, "message": message},
+        });
+        mapper.handle_message(&msg.to_string()).await.unwrap();
+    }
+
+    let lock = res.lock().await;
+    let logs = lock.get_z2m_logs();
+    assert_eq!(logs.len(), 2);
+    assert_eq!(logs[0].level, "warning");
+    assert_eq!(logs[0].server, "test");
+    assert_eq!(logs[0].message, "Failed to ping 'Hallway sensor'");
+    assert_eq!(logs[1].level, "error");
+
+    /* failed publishes are still reported as request failures */
+    assert_eq!(lock.get_z2m_failures().len(), 1);
+}
+
+#[tokio::test]
+async fn bridge_response_is_correlated() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper.take_outgoing();
+
+    mapper
+        .handle_request(&ClientRequest::touchlink(None, TouchlinkAction::Scan))
+        .await
+        .unwrap();
+
+    let sent = mapper.take_outgoing();
+    assert_eq!(topics(&sent), ["bridge/request/touchlink/scan"]);
+    let transaction = sent[0].payload["transaction"].clone();
+    assert!(transaction.is_string());
+
+    /* a response to some other client is ignored */
+    let other = json!({
+        "topic": "bridge/response/touchlink/scan",
+        "payload": {"status": "error", "error": "busy", "transaction": "other-1"},
+    });
+    mapper.handle_message(&other.to_string()).await.unwrap();
+    assert!(res.lock().await.get_z2m_failures().is_empty());
+
+    let ours = json!({
+        "topic": "bridge/response/touchlink/scan",
+        "payload": {"status": "error", "error": "busy", "transaction": transaction},
+    });
+    mapper.handle_message(&ours.to_string()).await.unwrap();
+    assert_eq!(res.lock().await.get_z2m_failures().len(), 1);
+}
+
+#[tokio::test]
+async fn stale_echoes_do_not_revert_newer_commands() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper.take_outgoing();
+
+    let link = light_link(&*res.lock().await);
+    for brightness in [50.0, 200.0] {
+        let upd = DeviceUpdate::new().with_brightness(Some(brightness));
+        mapper
+            .handle_request(&ClientRequest::light_update(link, upd))
+            .await
+            .unwrap();
+    }
+
+    let echo = |state: &str, brightness: u32| {
+        json!({"topic": "Kitchen ceiling", "payload": {"state": state, "brightness": brightness}})
+            .to_string()
+    };
+    let brightness = |res: &Resources| res.get::<Light>(&link).unwrap().dimming.unwrap().brightness;
+
+    /* the echo of the first command arrives late: only the unrelated field is applied */
+    let before = brightness(&*res.lock().await);
+    mapper.handle_message(&echo("ON", 50)).await.unwrap();
+    {
+        let lock = res.lock().await;
+        assert!(lock.get::<Light>(&link).unwrap().on.on);
+        assert!((brightness(&lock) - before).abs() < f64::EPSILON);
+    }
+
+    /* the echo of the newest command is applied as usual */
+    mapper.handle_message(&echo("ON", 200)).await.unwrap();
+    assert!((brightness(&*res.lock().await) - 200.0 / 2.54).abs() < 1.0);
+}
+
+#[tokio::test]
+async fn transition_defers_state_refresh() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+
+    /* configure a default transition for the light, like z2m would report */
+    let mut info: Value = serde_json::from_str(&common::corpus("bridge_info")).unwrap();
+    info["payload"]["config"]["devices"]["0x0017880104f5a4b2"]["transition"] = json!(0.2);
+    mapper.handle_message(&info.to_string()).await.unwrap();
+    mapper.take_outgoing();
+
+    let link = light_link(&*res.lock().await);
+    let upd = DeviceUpdate::new().with_state(Some(true));
+    mapper
+        .handle_request(&ClientRequest::light_update(link, upd))
+        .await
+        .unwrap();
+    mapper
+        .handle_message(&common::corpus("device_light_state"))
+        .await
+        .unwrap();
+    mapper.take_outgoing();
+
+    /* state is not re-synced during the transition.. */
+    mapper.tick().await.unwrap();
+    assert!(mapper.take_outgoing().is_empty());
+
+    /* ..but once it is over */
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    mapper.tick().await.unwrap();
+    let sent = mapper.take_outgoing();
+    assert_eq!(topics(&sent), ["Kitchen ceiling/get"]);
+}
+
+#[tokio::test]
+async fn group_capabilities_follow_members() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    /* turn the dimmer switch into a smart plug, and add a copy of it */
+    let mut devices: Value = serde_json::from_str(&common::corpus("bridge_devices")).unwrap();
+    let mut plug = devices["payload"][2].clone();
+    plug["friendly_name"] = json!("Plug");
+    plug["ieee_address"] = json!("0x00000000000000aa");
+    plug["definition"]["exposes"] = json!([{
+        "type": "switch",
+        "features": [{
+            "type": "binary", "access": 7, "property": "state", "name": "state",
+            "label": "State", "value_on": "ON", "value_off": "OFF", "value_toggle": "TOGGLE",
+        }],
+    }]);
+    devices["payload"].as_array_mut().unwrap().push(plug);
+
+    let groups = json!({
+        "topic": "bridge/groups",
+        "payload": [
+            {
+                "friendly_name": "Plugs", "id": 10, "scenes": [],
+                "members": [{"endpoint": 1, "ieee_address": "0x00000000000000aa"}],
+            },
+            {
+                "friendly_name": "Remotes", "id": 11, "scenes": [],
+                "members": [{"endpoint": 1, "ieee_address": "0x001788010b9e1f2c"}],
+            },
+        ],
+    });
+
+    mapper
+        .handle_message(&common::corpus("bridge_state_online"))
+        .await
+        .unwrap();
+    mapper.handle_message(&devices.to_string()).await.unwrap();
+    mapper.handle_message(&groups.to_string()).await.unwrap();
+
+    /* a group of plugs can be switched, but not dimmed.. */
+    let plugs = json!({"topic": "Plugs", "payload": {"state": "ON", "brightness": 100}});
+    mapper.handle_message(&plugs.to_string()).await.unwrap();
+
+    let lock = res.lock().await;
+    let room = lock
+        .get::<Room>(&RType::Room.deterministic(("test", "Plugs")))
+        .unwrap();
+    let glight = lock.get::<GroupedLight>(&room.services[0]).unwrap();
+    assert_eq!(glight.on.map(|on| on.on), Some(true));
+    assert!(glight.dimming.is_none());
+
+    /* ..and a group of remotes is skipped entirely */
+    assert!(lock
+        .get::<Room>(&RType::Room.deterministic(("test", "Remotes")))
+        .is_err());
+}
+
+#[tokio::test]
+async fn availability_config_seeds_connectivity() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    let status = |res: &Resources, ieee: &str| {
+        let ieee: IeeeAddress = ieee.parse().unwrap();
+        let link = RType::ZigbeeConnectivity.deterministic(&ieee);
+        res.get::<ZigbeeConnectivity>(&link).unwrap().status
+    };
+
+    /* availability enabled, except for the dimmer switch */
+    let mut info: Value = serde_json::from_str(&common::corpus("bridge_info")).unwrap();
+    info["payload"]["config"]["availability"] = json!({"enabled": true});
+    info["payload"]["config"]["devices"]["0x001788010b9e1f2c"]["availability"] = json!(false);
+    mapper.handle_message(&info.to_string()).await.unwrap();
+    announce(&mut mapper).await;
+
+    let offline = json!({"topic": "Kitchen ceiling/availability", "payload": {"state": "offline"}});
+    mapper.handle_message(&offline.to_string()).await.unwrap();
+
+    /* the switch never reports availability, so is assumed reachable.. */
+    let lock = res.lock().await;
+    assert_eq!(
+        status(&lock, "0x001788010b9e1f2c"),
+        ZigbeeConnectivityStatus::Connected
+    );
+    assert_eq!(
+        status(&lock, "0x0017880104f5a4b2"),
+        ZigbeeConnectivityStatus::ConnectivityIssue
+    );
+    drop(lock);
+
+    /* ..while the light keeps its last reported status, when re-announced */
+    announce(&mut mapper).await;
+    let lock = res.lock().await;
+    assert_eq!(
+        status(&lock, "0x0017880104f5a4b2"),
+        ZigbeeConnectivityStatus::ConnectivityIssue
+    );
+}
+
+#[tokio::test]
+async fn rotary_actions_update_relative_rotary() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    /* give the dimmer switch a dial */
+    let mut devices: Value = serde_json::from_str(&common::corpus("bridge_devices")).unwrap();
+    let values = &mut devices["payload"][2]["definition"]["exposes"][1]["values"];
+    for action in ["dial_rotate_left_step", "dial_rotate_right_step"] {
+        values.as_array_mut().unwrap().push(json!(action));
+    }
+    mapper
+        .handle_message(&common::corpus("bridge_state_online"))
+        .await
+        .unwrap();
+    mapper.handle_message(&devices.to_string()).await.unwrap();
+
+    let ieee: IeeeAddress = "0x001788010b9e1f2c".parse().unwrap();
+    let link = RType::RelativeRotary.deterministic(&ieee);
+
+    let rotate = |action: &str| {
+        json!({"topic": "Hallway dimmer", "payload": {"action": action}}).to_string()
+    };
+    let report = |res: &Resources| {
+        let rotary = res.get::<RelativeRotary>(&link).unwrap();
+        rotary.relative_rotary.rotary_report.clone().unwrap()
+    };
+
+    assert!(res
+        .lock()
+        .await
+        .get::<RelativeRotary>(&link)
+        .unwrap()
+        .relative_rotary
+        .rotary_report
+        .is_none());
+
+    mapper
+        .handle_message(&rotate("dial_rotate_right_step"))
+        .await
+        .unwrap();
+    let first = report(&*res.lock().await);
+    assert_eq!(first.action, RotaryAction::Start);
+    assert_eq!(first.rotation.direction, RotaryDirection::ClockWise);
+
+    /* continued rotation in the same direction is a repeat.. */
+    mapper
+        .handle_message(&rotate("dial_rotate_right_step"))
+        .await
+        .unwrap();
+    assert_eq!(report(&*res.lock().await).action, RotaryAction::Repeat);
+
+    /* ..while changing direction starts a new rotation */
+    mapper
+        .handle_message(&rotate("dial_rotate_left_step"))
+        .await
+        .unwrap();
+    let last = report(&*res.lock().await);
+    assert_eq!(last.action, RotaryAction::Start);
+    assert_eq!(last.rotation.direction, RotaryDirection::CounterClockWise);
+}
+
+#[tokio::test]
+async fn existing_switches_get_relative_rotary_linked() {
+    let res = common::resources();
+
+    let mut devices: Value = serde_json::from_str(&common::corpus("bridge_devices")).unwrap();
+    let values = &mut devices["payload"][2]["definition"]["exposes"][1]["values"];
+    values
+        .as_array_mut()
+        .unwrap()
+        .push(json!("dial_rotate_left_step"));
+    let announce = |mut mapper: Mapper| {
+        let devices = devices.to_string();
+        async move {
+            mapper
+                .handle_message(&common::corpus("bridge_state_online"))
+                .await
+                .unwrap();
+            mapper.handle_message(&devices).await.unwrap();
+        }
+    };
+
+    announce(common::mapper(res.clone())).await;
+
+    let ieee: IeeeAddress = "0x001788010b9e1f2c".parse().unwrap();
+    let dev = RType::Device.deterministic(&ieee);
+    forget_service(&mut *res.lock().await, &dev, RType::RelativeRotary);
+
+    /* announced again, as after a restart */
+    announce(common::mapper(res.clone())).await;
+
+    let lock = res.lock().await;
+    let link_rotary = *lock
+        .get::<Device>(&dev)
+        .unwrap()
+        .relative_rotary_service()
+        .unwrap();
+    assert_eq!(link_rotary, RType::RelativeRotary.deterministic(&ieee));
+    assert!(lock.get::<RelativeRotary>(&link_rotary).is_ok());
+}
+
+#[tokio::test]
+async fn contact_sensor_reports_contact_and_tamper() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    /* turn the dimmer switch into a door sensor */
+    let binary = |name: &str| {
+        json!({
+            "type": "binary", "access": 1, "property": name, "name": name,
+            "value_on": true, "value_off": false,
+        })
+    };
+    let mut devices: Value = serde_json::from_str(&common::corpus("bridge_devices")).unwrap();
+    devices["payload"][2]["friendly_name"] = json!("Front door");
+    devices["payload"][2]["definition"]["exposes"] = json!([binary("contact"), binary("tamper")]);
+    mapper
+        .handle_message(&common::corpus("bridge_state_online"))
+        .await
+        .unwrap();
+    mapper.handle_message(&devices.to_string()).await.unwrap();
+
+    let ieee: IeeeAddress = "0x001788010b9e1f2c".parse().unwrap();
+    let link_contact = RType::Contact.deterministic(&ieee);
+    let link_tamper = RType::Tamper.deterministic(&ieee);
+
+    let msg = json!({"topic": "Front door", "payload": {"contact": false, "tamper": true}});
+    mapper.handle_message(&msg.to_string()).await.unwrap();
+
+    let lock = res.lock().await;
+    let contact = lock.get::<Contact>(&link_contact).unwrap();
+    assert_eq!(
+        contact.contact_report.as_ref().unwrap().state,
+        ContactState::NoContact
+    );
+    let tamper = lock.get::<Tamper>(&link_tamper).unwrap();
+    assert_eq!(tamper.tamper_reports.len(), 1);
+    assert_eq!(tamper.tamper_reports[0].state, TamperState::Tampered);
+    drop(lock);
+
+    /* a disabled sensor keeps its last report */
+    res.lock()
+        .await
+        .update::<Contact>(&link_contact.rid, |contact| contact.enabled = false)
+        .unwrap();
+    let msg = json!({"topic": "Front door", "payload": {"contact": true}});
+    mapper.handle_message(&msg.to_string()).await.unwrap();
+
+    let lock = res.lock().await;
+    let contact = lock.get::<Contact>(&link_contact).unwrap();
+    assert_eq!(
+        contact.contact_report.as_ref().unwrap().state,
+        ContactState::NoContact
+    );
+}
+
+#[tokio::test]
+async fn existing_sensors_get_tamper_linked() {
+    let res = common::resources();
+
+    let binary = |name: &str| {
+        json!({
+            "type": "binary", "access": 1, "property": name, "name": name,
+            "value_on": true, "value_off": false,
+        })
+    };
+    let mut devices: Value = serde_json::from_str(&common::corpus("bridge_devices")).unwrap();
+    devices["payload"][2]["friendly_name"] = json!("Front door");
+    devices["payload"][2]["definition"]["exposes"] = json!([binary("contact"), binary("tamper")]);
+    let announce = |mut mapper: Mapper| {
+        let devices = devices.to_string();
+        async move {
+            mapper
+                .handle_message(&common::corpus("bridge_state_online"))
+                .await
+                .unwrap();
+            mapper.handle_message(&devices).await.unwrap();
+        }
+    };
+
+    announce(common::mapper(res.clone())).await;
+
+    let ieee: IeeeAddress = "0x001788010b9e1f2c".parse().unwrap();
+    let dev = RType::Device.deterministic(&ieee);
+    forget_service(&mut *res.lock().await, &dev, RType::Tamper);
+
+    /* announced again, as after a restart */
+    announce(common::mapper(res.clone())).await;
+
+    let lock = res.lock().await;
+    let link_tamper = *lock.get::<Device>(&dev).unwrap().tamper_service().unwrap();
+    assert_eq!(link_tamper, RType::Tamper.deterministic(&ieee));
+    assert!(lock.get::<Tamper>(&link_tamper).is_ok());
+}
+
+#[tokio::test]
+async fn ota_progress_is_reported_as_software_update() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+    announce(&mut mapper).await;
+
+    let mut events = res.lock().await.hue_channel();
+
+    let msg = json!({"topic": "Kitchen ceiling", "payload": {
+        "update": {"state": "updating", "progress": 42.5, "remaining": 300},
+    }});
+    mapper.handle_message(&msg.to_string()).await.unwrap();
+
+    let lock = res.lock().await;
+    let owner = lock.get::<Light>(&light_link(&lock)).unwrap().owner;
+    let link_swu = RType::DeviceSoftwareUpdate.deterministic(owner.rid);
+    let swu = lock.get::<DeviceSoftwareUpdate>(&link_swu).unwrap();
+    assert_eq!(swu.owner, owner);
+    assert_eq!(swu.state, SoftwareUpdateState::Installing);
+    assert_eq!(swu.progress, Some(42.5));
+    assert_eq!(swu.remaining, Some(300));
+    drop(lock);
+
+    /* progress is streamed as an update event */
+    let mut streamed = false;
+    while let Ok(evt) = events.try_recv() {
+        let evt = serde_json::to_value(evt).unwrap();
+        streamed |= evt["data"][0]["type"] == "device_software_update"
+            && evt["data"][0]["progress"] == 42.5;
+    }
+    assert!(streamed);
+
+    /* the light state corpus reports an idle update */
+    mapper
+        .handle_message(&common::corpus("device_light_state"))
+        .await
+        .unwrap();
+
+    let lock = res.lock().await;
+    let swu = lock.get::<DeviceSoftwareUpdate>(&link_swu).unwrap();
+    assert_eq!(swu.state, SoftwareUpdateState::NoUpdate);
+    assert_eq!(swu.progress, None);
+}
+
+#[tokio::test]
+async fn devices_get_software_update_linked() {
+    let res = common::resources();
+    announce(&mut common::mapper(res.clone())).await;
+
+    let dev = {
+        let mut lock = res.lock().await;
+        let dev = lock.get::<Light>(&light_link(&lock)).unwrap().owner;
+        let link_swu = *lock
+            .get::<Device>(&dev)
+            .unwrap()
+            .software_update_service()
+            .unwrap();
+        assert_eq!(
+            lock.get::<DeviceSoftwareUpdate>(&link_swu).unwrap().owner,
+            dev
+        );
+
+        /* as saved before software updates were reported */
+        forget_service(&mut lock, &dev, RType::DeviceSoftwareUpdate);
+        lock.delete(&link_swu).unwrap();
+        dev
+    };
+
+    /* announced again, as after a restart */
+    announce(&mut common::mapper(res.clone())).await;
+
+    let lock = res.lock().await;
+    let link_swu = *lock
+        .get::<Device>(&dev)
+        .unwrap()
+        .software_update_service()
+        .unwrap();
+    assert!(lock.get::<DeviceSoftwareUpdate>(&link_swu).is_ok());
+}
+
+#[tokio::test]
+async fn scene_recall_turns_off_lights() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper.take_outgoing();
+
+    let recall = |res: &Resources, on: bool| {
+        let light = light_link(res);
+        let room = RType::Room.deterministic(("test", "Kitchen"));
+        let scene = res.get_scenes_for_room(&room.rid)[0];
+        let action = SceneActionElement {
+            target: light,
+            action: SceneAction {
+                color: None,
+                color_temperature: None,
+                dimming: None,
+                on: Some(On::new(on)),
+            },
+        };
+        (RType::Scene.link_to(scene), action)
+    };
+
+    /* the scene has the light off, so it is turned off after the recall */
+    let (link, action) = recall(&*res.lock().await, false);
+    res.lock()
+        .await
+        .update::<Scene>(&link.rid, |scene| scene.actions = vec![action])
+        .unwrap();
+    mapper
+        .handle_request(&ClientRequest::scene_recall(link))
+        .await
+        .unwrap();
+
+    let sent = mapper.take_outgoing();
+    assert_eq!(topics(&sent), ["Kitchen/set", "Kitchen ceiling/set"]);
+    assert_eq!(sent[1].payload["state"], "OFF");
+
+    /* ..but left alone, when the scene has it on */
+    let (link, action) = recall(&*res.lock().await, true);
+    res.lock()
+        .await
+        .update::<Scene>(&link.rid, |scene| scene.actions = vec![action])
+        .unwrap();
+    mapper
+        .handle_request(&ClientRequest::scene_recall(link))
+        .await
+        .unwrap();
+
+    assert_eq!(topics(&mapper.take_outgoing()), ["Kitchen/set"]);
+}
+
+#[tokio::test]
+async fn zone_scene_recall_is_sent_to_own_lights() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper.take_outgoing();
+
+    /* a zone with a light of this server, and one of another */
+    let light = light_link(&*res.lock().await);
+    let other = RType::Light.deterministic("elsewhere");
+    let link = RType::Scene.deterministic("zone scene");
+    let action = |target| SceneActionElement {
+        target,
+        action: SceneAction {
+            color: None,
+            color_temperature: None,
+            dimming: None,
+            on: Some(On::new(true)),
+        },
+    };
+    let scene = Scene {
+        actions: vec![action(light), action(other)],
+        auto_dynamic: false,
+        group: RType::Zone.deterministic("Reading corner"),
+        metadata: SceneMetadata {
+            appdata: None,
+            image: None,
+            name: "Reading".into(),
+        },
+        palette: Value::Null,
+        speed: 0.5,
+        status: None,
+    };
+    res.lock().await.add(&link, Resource::Scene(scene)).unwrap();
+
+    mapper
+        .handle_request(&ClientRequest::scene_recall(link))
+        .await
+        .unwrap();
+
+    let sent = mapper.take_outgoing();
+    assert_eq!(topics(&sent), ["Kitchen ceiling/set"]);
+    assert_eq!(sent[0].payload["state"], "ON");
+}
+
+#[tokio::test]
+async fn scene_capture_stores_current_light_state() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper
+        .handle_message(&common::corpus("device_light_state"))
+        .await
+        .unwrap();
+    mapper.take_outgoing();
+
+    let mut requests = res.lock().await.z2m_channel();
+
+    let mut lock = res.lock().await;
+    let light = light_link(&lock);
+    let room = RType::Room.deterministic(("test", "Kitchen"));
+    let link = RType::Scene.link_to(lock.get_scenes_for_room(&room.rid)[0]);
+    lock.scene_capture(&link).unwrap();
+
+    let scene = lock.get::<Scene>(&link).unwrap();
+    assert_eq!(scene.actions.len(), 1);
+    assert_eq!(scene.actions[0].target, light);
+    let action = &scene.actions[0].action;
+    assert_eq!(action.on, Some(On::new(true)));
+    let state = lock.get::<Light>(&light).unwrap();
+    assert_eq!(
+        action.color_temperature.as_ref().map(|ct| ct.mirek),
+        state.as_mirek_opt()
+    );
+    assert_eq!(
+        action.color.as_ref().map(|col| col.xy),
+        state.as_color_opt()
+    );
+    assert_eq!(
+        action.dimming.as_ref().map(|dim| dim.brightness),
+        Some(100.0)
+    );
+    drop(lock);
+
+    /* ..and the scene is stored in z2m too */
+    let (_, req) = requests.try_recv().unwrap();
+    mapper.handle_request(&req).await.unwrap();
+    let sent = mapper.take_outgoing();
+    assert_eq!(topics(&sent), ["Kitchen/set"]);
+    assert!(sent[0].payload.get("scene_store").is_some());
+
+    /* a room scene without a z2m index cannot be stored */
+    let mut lock = res.lock().await;
+    let mut aux = lock.aux_get(&link).unwrap().clone();
+    aux.index = None;
+    lock.aux_set(&link, aux);
+    let err = lock.scene_capture(&link).unwrap_err();
+    assert!(
+        matches!(err, ApiError::SceneIndexMissing(id) if id == link.rid),
+        "{err}"
+    );
+}
+
+#[tokio::test]
+async fn zone_group_follows_zone_lights() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper.take_outgoing();
+
+    let light = light_link(&*res.lock().await);
+    let zone = RType::Zone.link_to(Uuid::new_v4());
+    let name = format!("bifrost_zone_{}", zone.rid);
+
+    /* a new zone group is created, with the light as its member */
+    mapper
+        .handle_request(&ClientRequest::zone_group(zone, vec![light]))
+        .await
+        .unwrap();
+
+    let sent = mapper.take_outgoing();
+    assert_eq!(
+        topics(&sent),
+        [
+            "bridge/request/group/add",
+            "bridge/request/group/members/add"
+        ]
+    );
+    assert_eq!(sent[0].payload["friendly_name"], json!(name));
+    assert_eq!(sent[1].payload["device"], "Kitchen ceiling");
+
+    /* once announced, the group is not a room, but controls the zone */
+    let groups = json!({
+        "topic": "bridge/groups",
+        "payload": [{
+            "friendly_name": name, "id": 20, "scenes": [],
+            "members": [{"endpoint": 11, "ieee_address": "0x0017880104f5a4b2"}],
+        }],
+    });
+    mapper.handle_message(&groups.to_string()).await.unwrap();
+
+    let link_glight = RType::GroupedLight.deterministic(zone.rid);
+    res.lock()
+        .await
+        .add(
+            &link_glight,
+            Resource::GroupedLight(GroupedLight::new(zone)),
+        )
+        .unwrap();
+
+    assert!(res
+        .lock()
+        .await
+        .get::<Room>(&RType::Room.deterministic(("test", &name)))
+        .is_err());
+
+    let update = json!({"topic": name, "payload": {"state": "ON"}});
+    mapper.handle_message(&update.to_string()).await.unwrap();
+    let on = res
+        .lock()
+        .await
+        .get::<GroupedLight>(&link_glight)
+        .unwrap()
+        .on;
+    assert_eq!(on.map(|on| on.on), Some(true));
+
+    /* syncing again changes nothing, and without lights it is removed */
+    mapper
+        .handle_request(&ClientRequest::zone_group(zone, vec![light]))
+        .await
+        .unwrap();
+    assert!(mapper.take_outgoing().is_empty());
+
+    mapper
+        .handle_request(&ClientRequest::zone_group(zone, vec![]))
+        .await
+        .unwrap();
+    let sent = mapper.take_outgoing();
+    assert_eq!(topics(&sent), ["bridge/request/group/remove"]);
+    assert_eq!(sent[0].payload["id"], json!(name));
+}
+
+#[tokio::test]
+async fn light_stop_halts_transition() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper.take_outgoing();
+
+    /* without a transition in progress, there is nothing to stop */
+    let link = light_link(&*res.lock().await);
+    mapper
+        .handle_request(&ClientRequest::light_stop(link))
+        .await
+        .unwrap();
+    assert!(mapper.take_outgoing().is_empty());
+
+    let upd = DeviceUpdate::new()
+        .with_brightness(Some(254.0))
+        .with_transition(Some(600.0));
+    mapper
+        .handle_request(&ClientRequest::light_update(link, upd))
+        .await
+        .unwrap();
+    mapper.take_outgoing();
+
+    /* a long transition is halted, and the state it ended in requested */
+    mapper
+        .handle_request(&ClientRequest::light_stop(link))
+        .await
+        .unwrap();
+    let sent = mapper.take_outgoing();
+    assert_eq!(
+        topics(&sent),
+        ["Kitchen ceiling/set", "Kitchen ceiling/get"]
+    );
+    assert_eq!(sent[0].payload["brightness_move"], "stop");
+    assert_eq!(sent[0].payload["color_temp_move"], "stop");
+
+    /* ..and only once */
+    mapper
+        .handle_request(&ClientRequest::light_stop(link))
+        .await
+        .unwrap();
+    assert!(mapper.take_outgoing().is_empty());
+}
+
+#[tokio::test]
+async fn bridge_home_light_follows_all_lights() {
+    let res = common::resources();
+    res.lock().await.init("0011223344556677").unwrap();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+
+    let home = res.lock().await.get_bridge_home_light().unwrap();
+    let state = |res: &Resources| {
+        let glight = res.get::<GroupedLight>(&home).unwrap();
+        (glight.on.map(|on| on.on), glight.as_brightness_opt())
+    };
+
+    mapper
+        .handle_message(&common::corpus("device_light_state"))
+        .await
+        .unwrap();
+    assert_eq!(state(&*res.lock().await), (Some(true), Some(100.0)));
+
+    /* brightness is kept, once no light is on */
+    let off = json!({"topic": "Kitchen ceiling", "payload": {"state": "OFF"}});
+    mapper.handle_message(&off.to_string()).await.unwrap();
+    assert_eq!(state(&*res.lock().await), (Some(false), Some(100.0)));
+}
+
+#[tokio::test]
+async fn group_state_follows_repeated_changes() {
+    let res = common::resources();
+    res.lock().await.init("0011223344556677").unwrap();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+    mapper
+        .handle_message(&common::corpus("device_light_state"))
+        .await
+        .unwrap();
+
+    let home = res.lock().await.get_bridge_home_light().unwrap();
+    let room = RType::Room.deterministic(("test", "Kitchen"));
+
+    /* the groups only see the changed light, so must not drift from it */
+    for brightness in [10, 254, 77, 1, 180] {
+        let msg = json!({"topic": "Kitchen ceiling", "payload": {"state": "ON", "brightness": brightness}});
+        mapper.handle_message(&msg.to_string()).await.unwrap();
+
+        let lock = res.lock().await;
+        let light = lock.get::<Light>(&light_link(&lock)).unwrap();
+        let glight = lock.get::<GroupedLight>(&home).unwrap();
+        let expected = light.dimming.unwrap().brightness;
+        assert!((glight.as_brightness_opt().unwrap() - expected).abs() < 1e-9);
+
+        let room = lock.get::<Room>(&room).unwrap();
+        let glight = lock
+            .get::<GroupedLight>(room.grouped_light_service().unwrap())
+            .unwrap();
+        let xy = glight.color.as_ref().unwrap().xy;
+        let expected = light.as_color_opt().unwrap();
+        assert!((xy.x - expected.x).abs() < 1e-9);
+        assert!((xy.y - expected.y).abs() < 1e-9);
+    }
+
+    /* a light moved out of the room no longer counts for it */
+    let mut lock = res.lock().await;
+    let glight_link = *lock
+        .get::<Room>(&room)
+        .unwrap()
+        .grouped_light_service()
+        .unwrap();
+    let before = lock
+        .get::<GroupedLight>(&glight_link)
+        .unwrap()
+        .color
+        .clone();
+    lock.update::<Room>(&room.rid, |room| room.children.clear())
+        .unwrap();
+
+    let link = light_link(&lock);
+    let red = XY::new(0.7, 0.3);
+    lock.update::<Light>(&link.rid, |light| light.color.as_mut().unwrap().xy = red)
+        .unwrap();
+    let glight = lock.get::<GroupedLight>(&glight_link).unwrap();
+    assert_eq!(glight.color, before);
+
+    let home = lock.get::<GroupedLight>(&home).unwrap();
+    let xy = home.color.as_ref().unwrap().xy;
+    assert!((xy.x - red.x).abs() < 1e-9 && (xy.y - red.y).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn server_room_config_takes_precedence() {
+    let mut config = common::config();
+    config.rooms.insert(
+        "Kitchen".into(),
+        serde_json::from_value(json!({"name": "Global kitchen", "icon": "dining"})).unwrap(),
+    );
+
+    let server = Z2mServer {
+        url: "ws://unused".into(),
+        group_prefix: None,
+        rooms: HashMap::from([(
+            "Kitchen".into(),
+            serde_json::from_value(json!({"name": "Server kitchen"})).unwrap(),
+        )]),
+        watchdog_secs: Z2mServer::default_watchdog_secs(),
+        forward_logging: false,
+        reconcile_secs: 0,
+        frontend: None,
+    };
+
+    let res = common::resources();
+    let mut mapper = Mapper::new("test".into(), server, Arc::new(config), res.clone());
+    announce(&mut mapper).await;
+
+    let lock = res.lock().await;
+    let room = lock
+        .get::<Room>(&RType::Room.deterministic(("test", "Kitchen")))
+        .unwrap();
+    assert_eq!(room.metadata.name, "Server kitchen");
+    assert!(matches!(room.metadata.archetype, RoomArchetype::Dining));
+}
+
+#[tokio::test]
+async fn identically_named_groups_on_two_servers_are_separate_rooms() {
+    let res = common::resources();
+    let mut first = common::named_mapper("first", res.clone());
+    let mut second = common::named_mapper("second", res.clone());
+
+    announce(&mut first).await;
+    announce(&mut second).await;
+
+    let link_first = RType::Room.deterministic(("first", "Kitchen"));
+    let link_second = RType::Room.deterministic(("second", "Kitchen"));
+    assert_ne!(link_first, link_second);
+
+    let lock = res.lock().await;
+    let first_room = lock.get::<Room>(&link_first).unwrap();
+    let second_room = lock.get::<Room>(&link_second).unwrap();
+    assert_ne!(
+        first_room.grouped_light_service(),
+        second_room.grouped_light_service()
+    );
+    assert_eq!(lock.find_rooms("Kitchen").len(), 2);
+
+    /* each room has its own scenes */
+    assert!(!lock.get_scenes_for_room(&link_first.rid).is_empty());
+    assert!(lock
+        .get_scenes_for_room(&link_first.rid)
+        .iter()
+        .all(|scene| !lock.get_scenes_for_room(&link_second.rid).contains(scene)));
+}
+
+#[tokio::test]
+async fn rooms_keyed_by_name_alone_keep_their_id() {
+    let res = common::resources();
+
+    /* a room from before rooms were keyed by server */
+    let legacy = RType::Room.deterministic("Kitchen");
+    let room = Room {
+        children: vec![],
+        metadata: bifrost::hue::api::RoomMetadata::new(RoomArchetype::Kitchen, "Kitchen"),
+        services: vec![],
+    };
+    res.lock().await.add(&legacy, Resource::Room(room)).unwrap();
+
+    let mut first = common::named_mapper("first", res.clone());
+    let mut second = common::named_mapper("second", res.clone());
+    announce(&mut first).await;
+    announce(&mut second).await;
+
+    /* the first server to announce the group takes over the room, and keeps
+     * it on later announcements */
+    announce(&mut first).await;
+
+    let lock = res.lock().await;
+    let room = lock.get::<Room>(&legacy).unwrap();
+    assert!(!room.children.is_empty());
+    assert_eq!(
+        lock.aux_get(&legacy).unwrap().server.as_deref(),
+        Some("first")
+    );
+    assert!(lock
+        .get::<Room>(&RType::Room.deterministic(("first", "Kitchen")))
+        .is_err());
+    assert!(lock
+        .get::<Room>(&RType::Room.deterministic(("second", "Kitchen")))
+        .is_ok());
+}
+
+#[tokio::test]
+async fn device_leave_removes_device() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+
+    /* the light is in a zone, and in a scene of its room */
+    let mut lock = res.lock().await;
+    let light = light_link(&lock);
+    let room = RType::Room.deterministic(("test", "Kitchen"));
+    let owner = lock.get::<Light>(&light).unwrap().owner;
+    assert!(lock.get::<Room>(&room).unwrap().children.contains(&owner));
+    let scene = RType::Scene.link_to(lock.get_scenes_for_room(&room.rid)[0]);
+    let action = SceneActionElement {
+        target: light,
+        action: SceneAction {
+            color: None,
+            color_temperature: None,
+            dimming: None,
+            on: Some(On::new(true)),
+        },
+    };
+    lock.update::<Scene>(&scene.rid, |scene| scene.actions = vec![action.clone()])
+        .unwrap();
+    let aux = lock
+        .aux_get(&scene)
+        .unwrap()
+        .clone()
+        .with_actions(Some(vec![action]));
+    lock.aux_set(&scene, aux);
+    let zone = RType::Zone.deterministic("upstairs");
+    lock.add(
+        &zone,
+        Resource::Zone(Zone {
+            metadata: RoomMetadata::new(RoomArchetype::TopFloor, "Upstairs"),
+            children: vec![light],
+            services: vec![],
+        }),
+    )
+    .unwrap();
+    drop(lock);
+
+    let mut events = res.lock().await.hue_channel();
+
+    let leave = json!({
+        "topic": "bridge/event",
+        "payload": {
+            "type": "device_leave",
+            "data": {"friendly_name": "Kitchen ceiling", "ieee_address": "0x0017880104f5a4b2"},
+        },
+    });
+    mapper.handle_message(&leave.to_string()).await.unwrap();
+
+    let ieee: IeeeAddress = "0x0017880104f5a4b2".parse().unwrap();
+    let link_device = RType::Device.deterministic(&ieee);
+
+    let lock = res.lock().await;
+    assert!(lock.get_resources_by_type(RType::Light).is_empty());
+    assert!(lock.get_resource_by_id(&link_device.rid).is_err());
+    assert!(events.try_recv().is_ok());
+
+    /* ..and nothing refers to it any more */
+    assert!(!lock
+        .get::<Room>(&room)
+        .unwrap()
+        .children
+        .contains(&link_device));
+    assert!(lock.get::<Zone>(&zone).unwrap().children.is_empty());
+    assert!(lock.get::<Scene>(&scene).unwrap().actions.is_empty());
+    assert!(lock
+        .aux_get(&scene)
+        .unwrap()
+        .actions
+        .as_ref()
+        .is_some_and(Vec::is_empty));
+}
+
+#[tokio::test]
+async fn device_interview_adds_device() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    announce(&mut mapper).await;
+
+    /* a second light, of the same model as the kitchen ceiling */
+    let devices: Value = serde_json::from_str(&common::corpus("bridge_devices")).unwrap();
+    let definition = devices["payload"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|dev| dev["friendly_name"] == "Kitchen ceiling")
+        .unwrap()["definition"]
+        .clone();
+
+    let interview = json!({
+        "topic": "bridge/event",
+        "payload": {
+            "type": "device_interview",
+            "data": {
+                "friendly_name": "Porch",
+                "ieee_address": "0x0017880104f5a4b3",
+                "status": "successful",
+                "supported": true,
+                "definition": definition,
+            },
+        },
+    });
+    mapper.handle_message(&interview.to_string()).await.unwrap();
+
+    let lock = res.lock().await;
+    let names: Vec<String> = lock
+        .get_resources_by_type(RType::Light)
+        .into_iter()
+        .filter_map(|rr| match rr.obj {
+            Resource::Light(light) => Some(light.metadata.name),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(names.len(), 2);
+    assert!(names.iter().any(|name| name == "Porch"));
+}
+
+#[tokio::test]
+async fn device_interview_fills_in_product_data() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+    announce(&mut mapper).await;
+
+    /* a light listed while its interview is still running.. */
+    let mut devices: Value = serde_json::from_str(&common::corpus("bridge_devices")).unwrap();
+    let list = devices["payload"].as_array_mut().unwrap();
+    let mut porch = list
+        .iter()
+        .find(|dev| dev["friendly_name"] == "Kitchen ceiling")
+        .unwrap()
+        .clone();
+    porch["friendly_name"] = json!("Porch");
+    porch["ieee_address"] = json!("0x0017880104f5a4b3");
+    porch["manufacturer"] = Value::Null;
+    porch["interview_completed"] = json!(false);
+    porch["interviewing"] = json!(true);
+    let definition = porch["definition"].clone();
+    list.push(porch);
+    mapper.handle_message(&devices.to_string()).await.unwrap();
+
+    let ieee: IeeeAddress = "0x0017880104f5a4b3".parse().unwrap();
+    let link = RType::Device.deterministic(&ieee);
+    let product = |res: &Resources| res.get::<Device>(&link).unwrap().product_data.clone();
+    assert_eq!(product(&*res.lock().await).manufacturer_name, "<unknown>");
+
+    /* ..learns who made it when the interview succeeds */
+    let interview = json!({
+        "topic": "bridge/event",
+        "payload": {
+            "type": "device_interview",
+            "data": {
+                "friendly_name": "Porch",
+                "ieee_address": "0x0017880104f5a4b3",
+                "status": "successful",
+                "supported": true,
+                "definition": definition,
+            },
+        },
+    });
+    mapper.handle_message(&interview.to_string()).await.unwrap();
+
+    let product = product(&*res.lock().await);
+    assert_eq!(product.manufacturer_name, "Philips");
+    assert_eq!(product.product_name, "LCA001");
+}
+
+#[tokio::test]
+async fn configured_devices_get_reporting() {
+    let mut config = common::config();
+    config.reporting.devices = vec!["Kitchen ceiling".into()];
+
+    let server = Z2mServer {
+        url: "ws://unused".into(),
+        group_prefix: None,
+        rooms: HashMap::new(),
+        watchdog_secs: Z2mServer::default_watchdog_secs(),
+        forward_logging: false,
+        reconcile_secs: 0,
+        frontend: None,
+    };
+
+    let res = common::resources();
+    let mut mapper = Mapper::new("test".into(), server, Arc::new(config), res.clone());
+    announce(&mut mapper).await;
+
+    let sent = mapper.take_outgoing();
+    let reporting: Vec<&Value> = sent
+        .iter()
+        .filter(|msg| msg.topic == "bridge/request/device/configure_reporting")
+        .map(|msg| &msg.payload)
+        .collect();
+
+    /* on/off, brightness, color temperature and color */
+    assert_eq!(reporting.len(), 5);
+    assert!(reporting.iter().all(|req| req["id"] == "Kitchen ceiling"));
+    assert!(reporting.iter().all(|req| req["endpoint"] == "11"));
+    assert!(reporting
+        .iter()
+        .any(|req| req["attribute"] == "currentLevel"));
+
+    /* only once, even if the device list is announced again */
+    announce(&mut mapper).await;
+    assert!(!topics(&mapper.take_outgoing()).contains(&"bridge/request/device/configure_reporting"));
+}
+
+#[tokio::test]
+async fn light_defaults_are_applied_once() {
+    let mut config = common::config();
+    config.light_defaults = serde_json::from_value(json!({
+        "power_on_behavior": "previous",
+        "transition": 0.5,
+    }))
+    .unwrap();
+
+    let server = Z2mServer {
+        url: "ws://unused".into(),
+        group_prefix: None,
+        rooms: HashMap::new(),
+        watchdog_secs: Z2mServer::default_watchdog_secs(),
+        forward_logging: false,
+        reconcile_secs: 0,
+        frontend: None,
+    };
+
+    let res = common::resources();
+    let mut mapper = Mapper::new("test".into(), server, Arc::new(config), res.clone());
+    announce(&mut mapper).await;
+
+    let sent = mapper.take_outgoing();
+    let options = sent
+        .iter()
+        .find(|msg| msg.topic == "bridge/request/device/options")
+        .unwrap();
+    assert_eq!(options.payload["id"], "Kitchen ceiling");
+    assert_eq!(options.payload["options"], json!({"transition": 0.5}));
+
+    assert!(sent.iter().any(|msg| msg.topic == "Kitchen ceiling/set"
+        && msg.payload == json!({"power_on_behavior": "previous"})));
+
+    /* recorded, so not pushed again when the device list is announced again */
+    let lock = res.lock().await;
+    let aux = lock.aux_get(&light_link(&lock)).unwrap();
+    assert_eq!(aux.defaults.as_ref().unwrap().transition, Some(0.5));
+    drop(lock);
+
+    announce(&mut mapper).await;
+    let sent = mapper.take_outgoing();
+    assert!(!topics(&sent).contains(&"bridge/request/device/options"));
+    assert!(!topics(&sent).contains(&"Kitchen ceiling/set"));
+}
+
+fn names_mapper(res: &Arc<tokio::sync::Mutex<Resources>>, names: Value) -> Mapper {
+    let mut config = common::config();
+    config.names = serde_json::from_value(names).unwrap();
+
+    let server = Z2mServer {
+        url: "ws://unused".into(),
+        group_prefix: None,
+        rooms: HashMap::new(),
+        watchdog_secs: Z2mServer::default_watchdog_secs(),
+        forward_logging: false,
+        reconcile_secs: 0,
+        frontend: None,
+    };
+
+    Mapper::new("test".into(), server, Arc::new(config), res.clone())
+}
+
+#[tokio::test]
+async fn naming_rules_are_applied_and_reapplied() {
+    let res = common::resources();
+    let room = RType::Room.deterministic(("test", "Kitchen"));
+
+    let mut mapper = names_mapper(
+        &res,
+        json!({"strip_prefixes": ["Kitchen "], "title_case": true}),
+    );
+    announce(&mut mapper).await;
+
+    let mut lock = res.lock().await;
+    let light = lock.get::<Light>(&light_link(&lock)).unwrap().clone();
+    assert_eq!(light.metadata.name, "Ceiling");
+    let dev = lock.get::<bifrost::hue::api::Device>(&light.owner).unwrap();
+    assert_eq!(dev.metadata.name, "Ceiling");
+
+    /* renamed through the api, so later rules must leave it alone */
+    lock.update::<Room>(&room.rid, |room| room.metadata.name = "Cooking".into())
+        .unwrap();
+    drop(lock);
+
+    let mut mapper = names_mapper(
+        &res,
+        json!({"replace": [
+            {"pattern": "ceiling", "replace": "lamp"},
+            {"pattern": "^Kitchen$", "replace": "Galley"},
+        ]}),
+    );
+    announce(&mut mapper).await;
+
+    let lock = res.lock().await;
+    let light = lock.get::<Light>(&light_link(&lock)).unwrap();
+    assert_eq!(light.metadata.name, "Kitchen lamp");
+    assert_eq!(lock.get::<Room>(&room).unwrap().metadata.name, "Cooking");
+}
+
+#[tokio::test]
+async fn lights_without_stored_scene_are_found() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+    announce(&mut mapper).await;
+
+    let lock = res.lock().await;
+    let room = RType::Room.deterministic(("test", "Kitchen"));
+    let light = light_link(&lock);
+
+    /* "Kitchen ceiling" only has scene 1 ("Bright") stored */
+    let bright = RType::Scene.deterministic((room.rid, 1));
+    let relax = RType::Scene.deterministic((room.rid, 2));
+    assert!(mapper
+        .scene_missing_lights(&lock, &bright)
+        .unwrap()
+        .is_empty());
+    assert_eq!(mapper.scene_missing_lights(&lock, &relax).unwrap(), [light]);
+}
+
+#[tokio::test]
+async fn removed_scenes_are_deleted() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+    announce(&mut mapper).await;
+    mapper.take_outgoing();
+
+    let room = RType::Room.deterministic(("test", "Kitchen"));
+    let bright = RType::Scene.deterministic((room.rid, 1));
+    let relax = RType::Scene.deterministic((room.rid, 2));
+    let mut events = res.lock().await.hue_channel();
+
+    /* a scene removed through bifrost is gone without waiting for z2m */
+    mapper
+        .handle_request(&ClientRequest::scene_remove(relax))
+        .await
+        .unwrap();
+    let sent = mapper.take_outgoing();
+    assert_eq!(topics(&sent), ["Kitchen/set"]);
+    assert_eq!(sent[0].payload["scene_remove"], 2);
+    assert!(res.lock().await.get::<Scene>(&relax).is_err());
+    assert!(events.try_recv().is_ok());
+
+    /* ..and stays gone when z2m announces the groups again */
+    let groups = |groups: Value| json!({"topic": "bridge/groups", "payload": groups}).to_string();
+    let kitchen = json!([{
+        "friendly_name": "Kitchen", "id": 1, "scenes": [{"id": 1, "name": "Bright"}],
+        "members": [{"endpoint": 11, "ieee_address": "0x0017880104f5a4b2"}],
+    }]);
+    mapper.handle_message(&groups(kitchen)).await.unwrap();
+    assert!(res.lock().await.get::<Scene>(&relax).is_err());
+    assert!(res.lock().await.get::<Scene>(&bright).is_ok());
+    while events.try_recv().is_ok() {}
+
+    /* the scenes of a group removed in z2m are deleted along with it */
+    mapper.handle_message(&groups(json!([]))).await.unwrap();
+    assert!(res.lock().await.get::<Scene>(&bright).is_err());
+    assert!(events.try_recv().is_ok());
+}
+
+#[tokio::test]
+async fn scene_appdata_survives_group_announce() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+    announce(&mut mapper).await;
+
+    let room = RType::Room.deterministic(("test", "Kitchen"));
+    let bright = RType::Scene.deterministic((room.rid, 1));
+    let mut events = res.lock().await.hue_channel();
+
+    let mut lock = res.lock().await;
+    lock.scene_appdata_set(&bright, None, "shared".to_string())
+        .unwrap();
+    lock.scene_appdata_set(&bright, Some("alpha"), "alpha-data".to_string())
+        .unwrap();
+    drop(lock);
+
+    /* other clients hear about the change, without seeing the private appdata */
+    assert!(events.try_recv().is_ok());
+    let evt = serde_json::to_value(events.try_recv().unwrap()).unwrap();
+    assert_eq!(evt["type"], "update");
+    assert_eq!(evt["data"][0]["metadata"]["name"], "Bright");
+    assert!(evt["data"][0]["metadata"].get("appdata").is_none());
+
+    announce(&mut mapper).await;
+
+    let lock = res.lock().await;
+    let rec = lock.get_resource(RType::Scene, &bright.rid).unwrap();
+    let Resource::Scene(scene) = &lock.user_view(rec, Some("alpha")).obj else {
+        panic!("not a scene");
+    };
+    assert_eq!(scene.metadata.appdata.as_deref(), Some("alpha-data"));
+    let Resource::Scene(scene) = &lock.get_resource(RType::Scene, &bright.rid).unwrap().obj else {
+        panic!("not a scene");
+    };
+    assert_eq!(scene.metadata.appdata.as_deref(), Some("shared"));
+}
+
+#[tokio::test]
+async fn reconciliation_corrects_diverged_lights() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+    announce(&mut mapper).await;
+    mapper.take_outgoing();
+
+    let link = light_link(&*res.lock().await);
+    assert!(res.lock().await.get::<Light>(&link).unwrap().on.on);
+
+    mapper.reconcile().await;
+    assert_eq!(topics(&mapper.take_outgoing()), ["Kitchen ceiling/get"]);
+
+    /* the light is actually off */
+    let off = json!({"topic": "Kitchen ceiling", "payload": {"state": "OFF"}});
+    mapper.handle_message(&off.to_string()).await.unwrap();
+    assert!(!res.lock().await.get::<Light>(&link).unwrap().on.on);
+
+    /* a state that matches is only counted as checked, and states that
+     * were not asked for are not counted at all */
+    mapper.reconcile().await;
+    mapper.handle_message(&off.to_string()).await.unwrap();
+    mapper.handle_message(&off.to_string()).await.unwrap();
+
+    let lock = res.lock().await;
+    let stats = &lock.get_z2m_status()["test"].reconcile;
+    assert_eq!(stats.sweeps, 2);
+    assert_eq!(stats.checked, 2);
+    assert_eq!(stats.diverged, 1);
+    assert_eq!(stats.devices["Kitchen ceiling"], 1);
+}
+
+#[tokio::test]
+async fn room_on_restores_light_state() {
+    let res = common::resources();
+    let mut mapper = common::mapper(res.clone());
+
+    /* a second kitchen light, of the same model as the kitchen ceiling */
+    let mut devices: Value = serde_json::from_str(&common::corpus("bridge_devices")).unwrap();
+    let list = devices["payload"].as_array_mut().unwrap();
+    let mut spot = list
+        .iter()
+        .find(|dev| dev["friendly_name"] == "Kitchen ceiling")
+        .unwrap()
+        .clone();
+    spot["friendly_name"] = json!("Kitchen spot");
+    spot["ieee_address"] = json!("0x0017880106e1c3d4");
+    list.push(spot);
+
+    for msg in [
+        common::corpus("bridge_state_online"),
+        devices.to_string(),
+        common::corpus("bridge_groups"),
+    ] {
+        mapper.handle_message(&msg).await.unwrap();
+    }
+
+    let room = RType::Room.deterministic(("test", "Kitchen"));
+    let glight = res.lock().await.get::<Room>(&room).unwrap().services[0];
+    let on = DeviceUpdate::default().with_state(Some(true));
+
+    /* z2m reports the full state of a light, whether on or off */
+    let state: Value = serde_json::from_str(&common::corpus("device_light_state")).unwrap();
+    let report = |topic: &str, changes: Value| {
+        let mut payload = state["payload"].clone();
+        for (key, value) in changes.as_object().unwrap() {
+            payload[key] = value.clone();
+        }
+        json!({"topic": topic, "payload": payload}).to_string()
+    };
+    for topic in ["Kitchen ceiling", "Kitchen spot"] {
+        let msg = report(topic, json!({"state": "ON"}));
+        mapper.handle_message(&msg).await.unwrap();
+        let msg = report(topic, json!({"state": "OFF"}));
+        mapper.handle_message(&msg).await.unwrap();
+    }
+    mapper.take_outgoing();
+
+    /* both lights were left in the same state, so one group command does */
+    mapper
+        .handle_request(&ClientRequest::group_update(glight, on.clone()))
+        .await
+        .unwrap();
+    let sent = mapper.take_outgoing();
+    assert_eq!(topics(&sent), ["Kitchen/set"]);
+    assert_eq!(sent[0].payload["state"], "ON");
+    assert_eq!(sent[0].payload["brightness"], 254.0);
+
+    /* the spot is dimmed, so it gets a command of its own */
+    let msg = report("Kitchen spot", json!({"state": "OFF", "brightness": 20}));
+    mapper.handle_message(&msg).await.unwrap();
+    mapper.take_outgoing();
+
+    mapper
+        .handle_request(&ClientRequest::group_update(glight, on))
+        .await
+        .unwrap();
+    let sent = mapper.take_outgoing();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0].topic, "Kitchen/set");
+    let mut brightness: Vec<f64> = sent
+        .iter()
+        .map(|msg| msg.payload["brightness"].as_f64().unwrap())
+        .collect();
+    brightness.sort_by(f64::total_cmp);
+    assert_eq!(brightness, [20.0, 254.0]);
+    for msg in &sent {
+        assert_eq!(msg.payload["state"], "ON");
+        assert_eq!(msg.payload["color"]["x"], 0.4599);
+    }
+
+    /* anything more than turning the room on goes to the group as is */
+    let dim = DeviceUpdate::default()
+        .with_state(Some(true))
+        .with_brightness(Some(100.0));
+    mapper
+        .handle_request(&ClientRequest::group_update(glight, dim))
+        .await
+        .unwrap();
+    let sent = mapper.take_outgoing();
+    assert_eq!(topics(&sent), ["Kitchen/set"]);
+    assert_eq!(sent[0].payload["brightness"], 100.0);
+}
+
+#[test]
+fn queued_updates_merge_without_conflicts() {
+    let ct = DeviceUpdate::new()
+        .with_state(Some(true))
+        .with_color_temp(Some(300));
+    let xy = DeviceUpdate::new().with_color_xy(Some(XY::new(0.3, 0.3)));
+
+    /* a newer color replaces an older color temperature */
+    let merged = ct.merged(&xy).unwrap();
+    assert!(merged.color_temp.is_none());
+    assert!(merged.color.is_some());
+    assert!(merged.state.is_some());
+
+    /* a brightness step is applied to an older absolute brightness.. */
+    let abs = DeviceUpdate::new().with_brightness(Some(100.0));
+    let step = DeviceUpdate::new().with_brightness_step(Some(20.0));
+    let merged = abs.merged(&step).unwrap();
+    assert_eq!(merged.brightness, Some(120.0));
+    assert!(merged.brightness_step.is_none());
+
+    /* ..and replaced by a newer one */
+    let merged = step.merged(&abs).unwrap();
+    assert_eq!(merged.brightness, Some(100.0));
+    assert!(merged.brightness_step.is_none());
+
+    /* different transitions are never merged */
+    let slow = DeviceUpdate::new()
+        .with_brightness(Some(254.0))
+        .with_transition(Some(600.0));
+    assert!(abs.merged(&slow).is_none());
+}
@@ -0,0 +1,189 @@
+/*
+ * Robustness tests for parsing zigbee2mqtt messages.
+ *
+ * These run the fuzzing corpus (see fuzz/corpus) through the same parsers
+ * the z2m client uses, and then systematically mutate every value in every
+ * message. Parsing is allowed to fail on mutated input, but must never panic.
+ *
+ * For coverage-guided fuzzing (nightly only), use cargo-fuzz:
+ *
+ *   cargo fuzz run message
+ *   cargo fuzz run device_update
+ */
+
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use bifrost::z2m::api::{Message, RawMessage};
+use bifrost::z2m::update::DeviceUpdate;
+
+fn corpus(target: &str) -> Vec<(String, String)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz/corpus")
+        .join(target);
+
+    let mut files: Vec<(String, String)> = fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("cannot read corpus {}: {err}", dir.display()))
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            (name, fs::read_to_string(&path).unwrap())
+        })
+        .collect();
+
+    files.sort();
+    assert!(!files.is_empty(), "empty corpus for {target}");
+    files
+}
+
+fn parse_message(txt: &str) -> Result<(), serde_json::Error> {
+    let raw: RawMessage = serde_json::from_str(txt)?;
+    if raw.topic.starts_with("bridge/") {
+        serde_json::from_str::<Message>(txt)?;
+    } else {
+        DeviceUpdate::deserialize(&raw.payload)?;
+    }
+    Ok(())
+}
+
+fn parse_device_update(txt: &str) -> Result<(), serde_json::Error> {
+    let payload: Value = serde_json::from_str(txt)?;
+    DeviceUpdate::deserialize(&payload)?;
+    Ok(())
+}
+
+/* Replacement values, chosen to hit type confusion and range problems */
+fn replacements() -> Vec<Value> {
+    vec![
+        Value::Null,
+        json!(true),
+        json!(-1),
+        json!(u64::MAX),
+        json!(1e300),
+        json!(0.5),
+        json!(""),
+        json!("0x"),
+        json!("\u{0}\u{ffff}"),
+        json!([]),
+        json!({}),
+    ]
+}
+
+/* Produce every variant of `value` where exactly one node is changed */
+fn mutations(value: &Value) -> Vec<Value> {
+    let mut res = vec![];
+
+    for repl in replacements() {
+        if &repl != value {
+            res.push(repl);
+        }
+    }
+
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                let mut removed = items.clone();
+                removed.remove(i);
+                res.push(Value::Array(removed));
+
+                for mutated in mutations(item) {
+                    let mut items = items.clone();
+                    items[i] = mutated;
+                    res.push(Value::Array(items));
+                }
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map {
+                let mut removed = map.clone();
+                removed.remove(key);
+                res.push(Value::Object(removed));
+
+                for mutated in mutations(item) {
+                    let mut map = map.clone();
+                    map.insert(key.clone(), mutated);
+                    res.push(Value::Object(map));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    res
+}
+
+fn truncations(txt: &str) -> impl Iterator<Item = &str> {
+    (0..txt.len())
+        .step_by(7)
+        .filter(|i| txt.is_char_boundary(*i))
+        .map(|i| &txt[..i])
+}
+
+#[test]
+fn corpus_messages_parse() {
+    for (name, txt) in corpus("message") {
+        if let Err(err) = parse_message(&txt) {
+            panic!("corpus file {name} failed to parse: {err}");
+        }
+    }
+}
+
+#[test]
+fn corpus_device_updates_parse() {
+    for (name, txt) in corpus("device_update") {
+        if let Err(err) = parse_device_update(&txt) {
+            panic!("corpus file {name} failed to parse: {err}");
+        }
+    }
+}
+
+#[test]
+fn device_updates_accept_unknown_fields() {
+    for (name, txt) in corpus("device_update") {
+        let mut payload: Value = serde_json::from_str(&txt).unwrap();
+        payload["some_new_z2m_field"] = json!({"nested": [1, 2, 3]});
+        assert!(
+            DeviceUpdate::deserialize(&payload).is_ok(),
+            "unknown field rejected in {name}"
+        );
+    }
+}
+
+#[test]
+fn mutated_messages_do_not_panic() {
+    for (_name, txt) in corpus("message") {
+        let msg: Value = serde_json::from_str(&txt).unwrap();
+        for mutated in mutations(&msg["payload"]) {
+            let mut msg = msg.clone();
+            msg["payload"] = mutated;
+            let _ = parse_message(&msg.to_string());
+        }
+    }
+}
+
+#[test]
+fn mutated_device_updates_do_not_panic() {
+    for (_name, txt) in corpus("device_update") {
+        let payload: Value = serde_json::from_str(&txt).unwrap();
+        for mutated in mutations(&payload) {
+            let _ = DeviceUpdate::deserialize(&mutated);
+        }
+    }
+}
+
+#[test]
+fn truncated_input_does_not_panic() {
+    for (_name, txt) in corpus("message") {
+        for part in truncations(&txt) {
+            let _ = parse_message(part);
+        }
+    }
+    for (_name, txt) in corpus("device_update") {
+        for part in truncations(&txt) {
+            let _ = parse_device_update(part);
+        }
+    }
+}